@@ -0,0 +1,7 @@
+# adds up the numbers 1 through 10 into a0, then stores the total to memory.
+        li   t0, 10           # loop counter
+        li   a0, 0            # running total
+loop:   add  a0, a0, t0
+        addi t0, t0, -1
+        bnez t0, loop
+        sw   a0, 8(zero)
@@ -0,0 +1,1054 @@
+use crate::csr::csr_number;
+use std::collections::HashMap;
+use std::fmt;
+
+// An error found while assembling, along with the source line it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// one line of source that will turn into one or more instructions
+struct SourceLine {
+    line: usize,
+    address: u32,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+//Turns RV32I assembly into machine code, one word per instruction, to be loaded from address 0.
+// Two passes: the first finds every label's address, the second encodes the instructions.
+pub fn assemble(source: &str) -> Result<Vec<u32>, AsmError> {
+    assemble_with_labels(source).map(|(instructions, _)| instructions)
+}
+
+//same as assemble, but also hands back every label and its address.
+pub fn assemble_with_labels(source: &str) -> Result<(Vec<u32>, HashMap<String, u32>), AsmError> {
+    let mut labels: HashMap<String, u32> = HashMap::new();
+    let mut lines: Vec<SourceLine> = Vec::new();
+    let mut address: u32 = 0;
+
+    // FIRST PASS: strip comments, record labels, and figure out where each instruction lands.
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = strip_comment(raw).trim();
+
+        // a line can have any number of labels in front of the instruction
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_valid_label(label) {
+                return Err(error(line, format!("invalid label name '{}'", label)));
+            }
+            if labels.insert(label.to_owned(), address).is_some() {
+                return Err(error(line, format!("label '{}' is defined twice", label)));
+            }
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, ""),
+        };
+        let mnemonic = mnemonic.to_lowercase();
+        let operands: Vec<String> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|op| op.trim().to_owned()).collect()
+        };
+
+        // directives that dont produce anything are skipped here.
+        if matches!(
+            mnemonic.as_str(),
+            ".text" | ".globl" | ".global" | ".section" | ".align" | ".p2align"
+        ) {
+            continue;
+        }
+
+        let size = instruction_count(&mnemonic, &operands, line)?;
+        lines.push(SourceLine {
+            line,
+            address,
+            mnemonic,
+            operands,
+        });
+        address += size * 4;
+    }
+
+    // SECOND PASS: encode everything, now that label addresses are known.
+    let mut instructions = Vec::new();
+    for source_line in &lines {
+        encode_line(source_line, &labels, &mut instructions)?;
+    }
+
+    Ok((instructions, labels))
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut end = line.len();
+    for marker in ["#", "//", ";"] {
+        if let Some(pos) = line.find(marker) {
+            end = end.min(pos);
+        }
+    }
+    &line[..end]
+}
+
+fn is_valid_label(label: &str) -> bool {
+    let mut chars = label.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+//how many 32-bit words a line turns into. only pseudo-instructions can be bigger than 1.
+fn instruction_count(mnemonic: &str, operands: &[String], line: usize) -> Result<u32, AsmError> {
+    match mnemonic {
+        "li" => {
+            let imm = match operands.get(1) {
+                Some(op) => parse_immediate(op, line)?,
+                None => return Err(error(line, "li needs a register and a value".to_owned())),
+            };
+            if fits_signed(imm, 12) {
+                Ok(1)
+            } else {
+                Ok(2)
+            }
+        }
+        "la" | "call" => Ok(2),
+        _ => Ok(1),
+    }
+}
+
+fn encode_line(
+    src: &SourceLine,
+    labels: &HashMap<String, u32>,
+    out: &mut Vec<u32>,
+) -> Result<(), AsmError> {
+    let line = src.line;
+    let ops = &src.operands;
+    let m = src.mnemonic.as_str();
+
+    // reads the operand at index i, complaining if it isnt there.
+    let operand = |i: usize| -> Result<&str, AsmError> {
+        match ops.get(i) {
+            Some(op) => Ok(op.as_str()),
+            None => Err(error(line, format!("'{}' is missing operand {}", m, i + 1))),
+        }
+    };
+    let expect = |count: usize| -> Result<(), AsmError> {
+        if ops.len() != count {
+            Err(error(
+                line,
+                format!("'{}' takes {} operand(s), found {}", m, count, ops.len()),
+            ))
+        } else {
+            Ok(())
+        }
+    };
+    // resolves a branch/jump target into a PC-relative offset. numbers are already offsets.
+    let offset_to = |op: &str| -> Result<i32, AsmError> {
+        match labels.get(op) {
+            Some(target) => Ok(target.wrapping_sub(src.address) as i32),
+            None if looks_numeric(op) => parse_immediate(op, line),
+            None => Err(error(line, format!("unknown label '{}'", op))),
+        }
+    };
+    // same thing, but numbers are absolute addresses. used by la and call.
+    let distance_to = |op: &str| -> Result<i32, AsmError> {
+        match labels.get(op) {
+            Some(target) => Ok(target.wrapping_sub(src.address) as i32),
+            None if looks_numeric(op) => {
+                Ok((parse_immediate(op, line)? as u32).wrapping_sub(src.address) as i32)
+            }
+            None => Err(error(line, format!("unknown label '{}'", op))),
+        }
+    };
+
+    if let Some((funct7, funct3)) = rtype_funct(m) {
+        expect(3)?;
+        let rd = parse_register(operand(0)?, line)?;
+        let r1 = parse_register(operand(1)?, line)?;
+        let r2 = parse_register(operand(2)?, line)?;
+        out.push(encode_r(funct7, r2, r1, funct3, rd, 0b0110011));
+        return Ok(());
+    }
+
+    if let Some(funct3) = itype_arith_funct(m) {
+        expect(3)?;
+        let rd = parse_register(operand(0)?, line)?;
+        let r1 = parse_register(operand(1)?, line)?;
+        let imm = parse_immediate(operand(2)?, line)?;
+        check_signed(imm, 12, line)?;
+        out.push(encode_i(imm, r1, funct3, rd, 0b0010011));
+        return Ok(());
+    }
+
+    if let Some((funct7, funct3)) = shift_imm_funct(m) {
+        expect(3)?;
+        let rd = parse_register(operand(0)?, line)?;
+        let r1 = parse_register(operand(1)?, line)?;
+        let shamt = parse_immediate(operand(2)?, line)?;
+        if !(0..32).contains(&shamt) {
+            return Err(error(
+                line,
+                format!("shift amount {} is out of range", shamt),
+            ));
+        }
+        out.push(encode_i(
+            ((funct7 as i32) << 5) | shamt,
+            r1,
+            funct3,
+            rd,
+            0b0010011,
+        ));
+        return Ok(());
+    }
+
+    if let Some((funct12, funct3, opcode)) = unary_funct(m) {
+        expect(2)?;
+        let rd = parse_register(operand(0)?, line)?;
+        let r1 = parse_register(operand(1)?, line)?;
+        out.push(encode_i(funct12, r1, funct3, rd, opcode));
+        return Ok(());
+    }
+
+    if let Some(funct3) = load_funct(m) {
+        expect(2)?;
+        let rd = parse_register(operand(0)?, line)?;
+        let (imm, r1) = parse_memory_operand(operand(1)?, line)?;
+        check_signed(imm, 12, line)?;
+        out.push(encode_i(imm, r1, funct3, rd, 0b0000011));
+        return Ok(());
+    }
+
+    if let Some(funct3) = store_funct(m) {
+        expect(2)?;
+        let r2 = parse_register(operand(0)?, line)?;
+        let (imm, r1) = parse_memory_operand(operand(1)?, line)?;
+        check_signed(imm, 12, line)?;
+        out.push(encode_s(imm, r2, r1, funct3, 0b0100011));
+        return Ok(());
+    }
+
+    if let Some((funct5, ordering)) = atomic_funct(m) {
+        // lr.w rd, (rs1)   /   sc.w rd, rs2, (rs1)   /   amoadd.w rd, rs2, (rs1)
+        let is_lr = funct5 == 0b00010;
+        expect(if is_lr { 2 } else { 3 })?;
+        let rd = parse_register(operand(0)?, line)?;
+        let r2 = if is_lr {
+            0
+        } else {
+            parse_register(operand(1)?, line)?
+        };
+        let (offset, r1) = parse_memory_operand(operand(ops.len() - 1)?, line)?;
+        if offset != 0 {
+            return Err(error(
+                line,
+                format!(
+                    "'{}' can't have an offset, its address is just the register",
+                    m
+                ),
+            ));
+        }
+        out.push(encode_r(
+            (funct5 << 2) | ordering,
+            r2,
+            r1,
+            0b010,
+            rd,
+            0b0101111,
+        ));
+        return Ok(());
+    }
+
+    if let Some((funct7, fixed_r2, fixed_funct3, kinds)) = fp_funct(m) {
+        // fadd.s fd, fs1, fs2[, rm]   /   fsqrt.s fd, fs1[, rm]   /   fcvt.w.s rd, fs1[, rm]   /   feq.s rd, fs1, fs2
+        // kinds says which operands are FP registers (f) and which are integer ones (x).
+        let kinds = kinds.as_bytes();
+        let funct3 = match fixed_funct3 {
+            Some(funct3) => {
+                expect(kinds.len())?;
+                funct3
+            }
+            None if ops.len() == kinds.len() + 1 => {
+                parse_rounding_mode(operand(kinds.len())?, line)?
+            }
+            None => {
+                expect(kinds.len())?;
+                FP_DYNAMIC
+            }
+        };
+        let mut registers = [0u8; 3];
+        for (i, kind) in kinds.iter().enumerate() {
+            registers[i] = if *kind == b'f' {
+                parse_fp_register(operand(i)?, line)?
+            } else {
+                parse_register(operand(i)?, line)?
+            };
+        }
+        let r2 = fixed_r2.unwrap_or(registers[2]);
+        out.push(encode_r(
+            funct7,
+            r2,
+            registers[1],
+            funct3,
+            registers[0],
+            0b1010011,
+        ));
+        return Ok(());
+    }
+
+    if let Some(opcode) = fused_opcode(m) {
+        // fmadd.s fd, fs1, fs2, fs3[, rm]. R4-type: rs3 goes where funct7 would be, above fmt (0, single).
+        let funct3 = match ops.len() {
+            5 => parse_rounding_mode(operand(4)?, line)?,
+            _ => {
+                expect(4)?;
+                FP_DYNAMIC
+            }
+        };
+        let rd = parse_fp_register(operand(0)?, line)?;
+        let r1 = parse_fp_register(operand(1)?, line)?;
+        let r2 = parse_fp_register(operand(2)?, line)?;
+        let r3 = parse_fp_register(operand(3)?, line)?;
+        out.push(encode_r((r3 as u32) << 2, r2, r1, funct3, rd, opcode));
+        return Ok(());
+    }
+
+    if let Some(funct3) = branch_funct(m) {
+        expect(3)?;
+        let r1 = parse_register(operand(0)?, line)?;
+        let r2 = parse_register(operand(1)?, line)?;
+        let offset = offset_to(operand(2)?)?;
+        check_branch_offset(offset, 13, line)?;
+        out.push(encode_b(offset, r2, r1, funct3, 0b1100011));
+        return Ok(());
+    }
+
+    if let Some(funct3) = csr_funct(m) {
+        // csrrw rd, csr, rs1   /   csrrwi rd, csr, uimm
+        expect(3)?;
+        let rd = parse_register(operand(0)?, line)?;
+        let csr = parse_csr(operand(1)?, line)?;
+        let source = csr_source(funct3, operand(2)?, line)?;
+        out.push(encode_i(csr as i32, source, funct3, rd, 0b1110011));
+        return Ok(());
+    }
+
+    match m {
+        "lui" | "auipc" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let imm = parse_immediate(operand(1)?, line)?;
+            if !(0..=0xfffff).contains(&imm) && !fits_signed(imm, 20) {
+                return Err(error(
+                    line,
+                    format!("immediate {} does not fit in 20 bits", imm),
+                ));
+            }
+            let opcode = if m == "lui" { 0b0110111 } else { 0b0010111 };
+            out.push(encode_u(imm as u32, rd, opcode));
+        }
+        "jal" => {
+            // jal rd, target   or just   jal target  (links to $r1)
+            let (rd, target) = match ops.len() {
+                1 => (1, operand(0)?),
+                2 => (parse_register(operand(0)?, line)?, operand(1)?),
+                _ => return Err(error(line, "jal takes 1 or 2 operands".to_owned())),
+            };
+            let offset = offset_to(target)?;
+            check_branch_offset(offset, 21, line)?;
+            out.push(encode_j(offset, rd, 0b1101111));
+        }
+        "jalr" => {
+            // jalr rd, rs1, imm  /  jalr rd, imm(rs1)  /  jalr rs1
+            let (rd, r1, imm) = match ops.len() {
+                1 => (1, parse_register(operand(0)?, line)?, 0),
+                2 => {
+                    let (imm, r1) = parse_memory_operand(operand(1)?, line)?;
+                    (parse_register(operand(0)?, line)?, r1, imm)
+                }
+                3 => (
+                    parse_register(operand(0)?, line)?,
+                    parse_register(operand(1)?, line)?,
+                    parse_immediate(operand(2)?, line)?,
+                ),
+                _ => return Err(error(line, "jalr takes 1 to 3 operands".to_owned())),
+            };
+            check_signed(imm, 12, line)?;
+            out.push(encode_i(imm, r1, 0b000, rd, 0b1100111));
+        }
+
+        "ecall" => {
+            expect(0)?;
+            out.push(encode_i(0, 0, 0b000, 0, 0b1110011));
+        }
+        "ebreak" => {
+            expect(0)?;
+            out.push(encode_i(1, 0, 0b000, 0, 0b1110011));
+        }
+        "mret" => {
+            expect(0)?;
+            out.push(encode_i(0x302, 0, 0b000, 0, 0b1110011));
+        }
+        "wfi" => {
+            expect(0)?;
+            out.push(encode_i(0x105, 0, 0b000, 0, 0b1110011));
+        }
+
+        // PSEUDO-INSTRUCTIONS
+        "nop" => {
+            expect(0)?;
+            out.push(encode_i(0, 0, 0b000, 0, 0b0010011));
+        }
+        "li" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let imm = parse_immediate(operand(1)?, line)?;
+            push_load_immediate(rd, imm, out);
+        }
+        "la" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let offset = distance_to(operand(1)?)?;
+            // auipc + addi, with the upper part rounded so the signed lower part adds back up.
+            let upper = (offset.wrapping_add(0x800) as u32) >> 12;
+            out.push(encode_u(upper, rd, 0b0010111));
+            out.push(encode_i(lower_12(offset), rd, 0b000, rd, 0b0010011));
+        }
+        "mv" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let r1 = parse_register(operand(1)?, line)?;
+            out.push(encode_i(0, r1, 0b000, rd, 0b0010011));
+        }
+        "not" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let r1 = parse_register(operand(1)?, line)?;
+            out.push(encode_i(-1, r1, 0b100, rd, 0b0010011));
+        }
+        "neg" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let r2 = parse_register(operand(1)?, line)?;
+            out.push(encode_r(0b0100000, r2, 0, 0b000, rd, 0b0110011));
+        }
+        "beqz" | "bnez" => {
+            expect(2)?;
+            let r1 = parse_register(operand(0)?, line)?;
+            let offset = offset_to(operand(1)?)?;
+            check_branch_offset(offset, 13, line)?;
+            let funct3 = if m == "beqz" { 0b000 } else { 0b001 };
+            out.push(encode_b(offset, 0, r1, funct3, 0b1100011));
+        }
+        "j" => {
+            expect(1)?;
+            let offset = offset_to(operand(0)?)?;
+            check_branch_offset(offset, 21, line)?;
+            out.push(encode_j(offset, 0, 0b1101111));
+        }
+        "call" => {
+            expect(1)?;
+            let offset = distance_to(operand(0)?)?;
+            let upper = (offset.wrapping_add(0x800) as u32) >> 12;
+            out.push(encode_u(upper, 1, 0b0010111));
+            out.push(encode_i(lower_12(offset), 1, 0b000, 1, 0b1100111));
+        }
+        "jr" => {
+            expect(1)?;
+            let r1 = parse_register(operand(0)?, line)?;
+            out.push(encode_i(0, r1, 0b000, 0, 0b1100111));
+        }
+        "ret" => {
+            expect(0)?;
+            out.push(encode_i(0, 1, 0b000, 0, 0b1100111));
+        }
+        "csrr" => {
+            expect(2)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let csr = parse_csr(operand(1)?, line)?;
+            out.push(encode_i(csr as i32, 0, 0b010, rd, 0b1110011));
+        }
+        "csrw" | "csrs" | "csrc" | "csrwi" | "csrsi" | "csrci" => {
+            // the CSR instruction with rd = $r0
+            expect(2)?;
+            let funct3 = csr_funct(&format!("csrr{}", &m[3..])).unwrap();
+            let csr = parse_csr(operand(0)?, line)?;
+            let source = csr_source(funct3, operand(1)?, line)?;
+            out.push(encode_i(csr as i32, source, funct3, 0, 0b1110011));
+        }
+        // RV32F
+        "flw" => {
+            expect(2)?;
+            let rd = parse_fp_register(operand(0)?, line)?;
+            let (imm, r1) = parse_memory_operand(operand(1)?, line)?;
+            check_signed(imm, 12, line)?;
+            out.push(encode_i(imm, r1, 0b010, rd, 0b0000111));
+        }
+        "fsw" => {
+            expect(2)?;
+            let r2 = parse_fp_register(operand(0)?, line)?;
+            let (imm, r1) = parse_memory_operand(operand(1)?, line)?;
+            check_signed(imm, 12, line)?;
+            out.push(encode_s(imm, r2, r1, 0b010, 0b0100111));
+        }
+        "fmv.s" | "fneg.s" | "fabs.s" => {
+            // sign injection, with the register as both operands
+            expect(2)?;
+            let rd = parse_fp_register(operand(0)?, line)?;
+            let r1 = parse_fp_register(operand(1)?, line)?;
+            let funct3 = match m {
+                "fmv.s" => 0b000,
+                "fneg.s" => 0b001,
+                _ => 0b010,
+            };
+            out.push(encode_r(0b0010000, r1, r1, funct3, rd, 0b1010011));
+        }
+        "frcsr" | "frrm" | "frflags" => {
+            expect(1)?;
+            let rd = parse_register(operand(0)?, line)?;
+            out.push(encode_i(fp_csr(m) as i32, 0, 0b010, rd, 0b1110011));
+        }
+        "fscsr" | "fsrm" | "fsflags" => {
+            // fscsr rs   or   fscsr rd, rs  (swaps, handing back the old value)
+            let (rd, r1) = match ops.len() {
+                1 => (0, parse_register(operand(0)?, line)?),
+                2 => (
+                    parse_register(operand(0)?, line)?,
+                    parse_register(operand(1)?, line)?,
+                ),
+                _ => return Err(error(line, format!("{} takes 1 or 2 operands", m))),
+            };
+            out.push(encode_i(fp_csr(m) as i32, r1, 0b001, rd, 0b1110011));
+        }
+        "rdcycle" | "rdcycleh" | "rdtime" | "rdtimeh" | "rdinstret" | "rdinstreth" => {
+            expect(1)?;
+            let rd = parse_register(operand(0)?, line)?;
+            let csr = csr_number(&m[2..]).unwrap();
+            out.push(encode_i(csr as i32, 0, 0b010, rd, 0b1110011));
+        }
+
+        // DIRECTIVES
+        ".word" => {
+            expect(1)?;
+            out.push(parse_immediate(operand(0)?, line)? as u32);
+        }
+        _ => return Err(error(line, format!("unknown instruction '{}'", m))),
+    }
+
+    Ok(())
+}
+
+// li: a single addi if it fits in 12 bits, otherwise lui + addi.
+fn push_load_immediate(rd: u8, imm: i32, out: &mut Vec<u32>) {
+    if fits_signed(imm, 12) {
+        out.push(encode_i(imm, 0, 0b000, rd, 0b0010011));
+    } else {
+        let upper = (imm.wrapping_add(0x800) as u32) >> 12;
+        out.push(encode_u(upper, rd, 0b0110111));
+        out.push(encode_i(lower_12(imm), rd, 0b000, rd, 0b0010011));
+    }
+}
+
+//sign-extended lowest 12 bits of a value.
+fn lower_12(value: i32) -> i32 {
+    (value << 20) >> 20
+}
+
+// ===========================
+// Mnemonic tables
+// ===========================
+
+fn rtype_funct(m: &str) -> Option<(u32, u32)> {
+    // (funct7, funct3)
+    match m {
+        "add" => Some((0b0000000, 0b000)),
+        "sub" => Some((0b0100000, 0b000)),
+        "sll" => Some((0b0000000, 0b001)),
+        "slt" => Some((0b0000000, 0b010)),
+        "sltu" => Some((0b0000000, 0b011)),
+        "xor" => Some((0b0000000, 0b100)),
+        "srl" => Some((0b0000000, 0b101)),
+        "sra" => Some((0b0100000, 0b101)),
+        "or" => Some((0b0000000, 0b110)),
+        "and" => Some((0b0000000, 0b111)),
+        // RV32M
+        "mul" => Some((0b0000001, 0b000)),
+        "mulh" => Some((0b0000001, 0b001)),
+        "mulhsu" => Some((0b0000001, 0b010)),
+        "mulhu" => Some((0b0000001, 0b011)),
+        "div" => Some((0b0000001, 0b100)),
+        "divu" => Some((0b0000001, 0b101)),
+        "rem" => Some((0b0000001, 0b110)),
+        "remu" => Some((0b0000001, 0b111)),
+        // Zba
+        "sh1add" => Some((0b0010000, 0b010)),
+        "sh2add" => Some((0b0010000, 0b100)),
+        "sh3add" => Some((0b0010000, 0b110)),
+        // Zbb
+        "andn" => Some((0b0100000, 0b111)),
+        "orn" => Some((0b0100000, 0b110)),
+        "xnor" => Some((0b0100000, 0b100)),
+        "min" => Some((0b0000101, 0b100)),
+        "minu" => Some((0b0000101, 0b101)),
+        "max" => Some((0b0000101, 0b110)),
+        "maxu" => Some((0b0000101, 0b111)),
+        "rol" => Some((0b0110000, 0b001)),
+        "ror" => Some((0b0110000, 0b101)),
+        // Zbs
+        "bset" => Some((0b0010100, 0b001)),
+        "bclr" => Some((0b0100100, 0b001)),
+        "binv" => Some((0b0110100, 0b001)),
+        "bext" => Some((0b0100100, 0b101)),
+        _ => None,
+    }
+}
+
+fn itype_arith_funct(m: &str) -> Option<u32> {
+    match m {
+        "addi" => Some(0b000),
+        "slti" => Some(0b010),
+        "sltiu" => Some(0b011),
+        "xori" => Some(0b100),
+        "ori" => Some(0b110),
+        "andi" => Some(0b111),
+        _ => None,
+    }
+}
+
+fn shift_imm_funct(m: &str) -> Option<(u32, u32)> {
+    // (funct7, funct3)
+    match m {
+        "slli" => Some((0b0000000, 0b001)),
+        "srli" => Some((0b0000000, 0b101)),
+        "srai" => Some((0b0100000, 0b101)),
+        "rori" => Some((0b0110000, 0b101)),
+        "bseti" => Some((0b0010100, 0b001)),
+        "bclri" => Some((0b0100100, 0b001)),
+        "binvi" => Some((0b0110100, 0b001)),
+        "bexti" => Some((0b0100100, 0b101)),
+        _ => None,
+    }
+}
+
+//Zbb's instructions with just rd and rs1. the rest of the immediate (or rs2) field says which one it is.
+fn unary_funct(m: &str) -> Option<(i32, u32, u32)> {
+    // (funct12, funct3, opcode)
+    match m {
+        "clz" => Some((0x600, 0b001, 0b0010011)),
+        "ctz" => Some((0x601, 0b001, 0b0010011)),
+        "cpop" => Some((0x602, 0b001, 0b0010011)),
+        "sext.b" => Some((0x604, 0b001, 0b0010011)),
+        "sext.h" => Some((0x605, 0b001, 0b0010011)),
+        "rev8" => Some((0x698, 0b101, 0b0010011)),
+        "orc.b" => Some((0x287, 0b101, 0b0010011)),
+        "zext.h" => Some((0x080, 0b100, 0b0110011)),
+        _ => None,
+    }
+}
+
+fn load_funct(m: &str) -> Option<u32> {
+    match m {
+        "lb" => Some(0b000),
+        "lh" => Some(0b001),
+        "lw" => Some(0b010),
+        "lbu" => Some(0b100),
+        "lhu" => Some(0b101),
+        _ => None,
+    }
+}
+
+fn store_funct(m: &str) -> Option<u32> {
+    match m {
+        "sb" => Some(0b000),
+        "sh" => Some(0b001),
+        "sw" => Some(0b010),
+        _ => None,
+    }
+}
+
+fn atomic_funct(m: &str) -> Option<(u32, u32)> {
+    // (funct5, aq and rl bits). the ordering goes on the end, like amoadd.w.aqrl
+    let (base, ordering) = match m.rsplit_once('.') {
+        Some((base, "aq")) => (base, 0b10),
+        Some((base, "rl")) => (base, 0b01),
+        Some((base, "aqrl")) => (base, 0b11),
+        _ => (m, 0b00),
+    };
+    let funct5 = match base {
+        "lr.w" => 0b00010,
+        "sc.w" => 0b00011,
+        "amoswap.w" => 0b00001,
+        "amoadd.w" => 0b00000,
+        "amoxor.w" => 0b00100,
+        "amoand.w" => 0b01100,
+        "amoor.w" => 0b01000,
+        "amomin.w" => 0b10000,
+        "amomax.w" => 0b10100,
+        "amominu.w" => 0b11000,
+        "amomaxu.w" => 0b11100,
+        _ => return None,
+    };
+    Some((funct5, ordering))
+}
+
+// the rm field that means "use frm".
+const FP_DYNAMIC: u32 = 0b111;
+
+fn fp_funct(m: &str) -> Option<(u32, Option<u8>, Option<u32>, &'static str)> {
+    // (funct7, rs2 if it's fixed, funct3 if it's fixed (otherwise it's the rounding mode), the operands)
+    match m {
+        "fadd.s" => Some((0b0000000, None, None, "fff")),
+        "fsub.s" => Some((0b0000100, None, None, "fff")),
+        "fmul.s" => Some((0b0001000, None, None, "fff")),
+        "fdiv.s" => Some((0b0001100, None, None, "fff")),
+        "fsqrt.s" => Some((0b0101100, Some(0), None, "ff")),
+        "fsgnj.s" => Some((0b0010000, None, Some(0b000), "fff")),
+        "fsgnjn.s" => Some((0b0010000, None, Some(0b001), "fff")),
+        "fsgnjx.s" => Some((0b0010000, None, Some(0b010), "fff")),
+        "fmin.s" => Some((0b0010100, None, Some(0b000), "fff")),
+        "fmax.s" => Some((0b0010100, None, Some(0b001), "fff")),
+        "fcvt.w.s" => Some((0b1100000, Some(0), None, "xf")),
+        "fcvt.wu.s" => Some((0b1100000, Some(1), None, "xf")),
+        "fmv.x.w" | "fmv.x.s" => Some((0b1110000, Some(0), Some(0b000), "xf")),
+        "fclass.s" => Some((0b1110000, Some(0), Some(0b001), "xf")),
+        "feq.s" => Some((0b1010000, None, Some(0b010), "xff")),
+        "flt.s" => Some((0b1010000, None, Some(0b001), "xff")),
+        "fle.s" => Some((0b1010000, None, Some(0b000), "xff")),
+        "fcvt.s.w" => Some((0b1101000, Some(0), None, "fx")),
+        "fcvt.s.wu" => Some((0b1101000, Some(1), None, "fx")),
+        "fmv.w.x" | "fmv.s.x" => Some((0b1111000, Some(0), Some(0b000), "fx")),
+        _ => None,
+    }
+}
+
+//the CSR frcsr, fsrm and the rest read or write.
+fn fp_csr(m: &str) -> u16 {
+    match &m[2..] {
+        "csr" => csr_number("fcsr"),
+        "rm" => csr_number("frm"),
+        _ => csr_number("fflags"),
+    }
+    .unwrap()
+}
+
+fn fused_opcode(m: &str) -> Option<u32> {
+    match m {
+        "fmadd.s" => Some(0b1000011),
+        "fmsub.s" => Some(0b1000111),
+        "fnmsub.s" => Some(0b1001011),
+        "fnmadd.s" => Some(0b1001111),
+        _ => None,
+    }
+}
+
+fn branch_funct(m: &str) -> Option<u32> {
+    match m {
+        "beq" => Some(0b000),
+        "bne" => Some(0b001),
+        "blt" => Some(0b100),
+        "bge" => Some(0b101),
+        "bltu" => Some(0b110),
+        "bgeu" => Some(0b111),
+        _ => None,
+    }
+}
+
+fn csr_funct(m: &str) -> Option<u32> {
+    match m {
+        "csrrw" => Some(0b001),
+        "csrrs" => Some(0b010),
+        "csrrc" => Some(0b011),
+        "csrrwi" => Some(0b101),
+        "csrrsi" => Some(0b110),
+        "csrrci" => Some(0b111),
+        _ => None,
+    }
+}
+
+// ===========================
+// Operand parsing
+// ===========================
+
+//a CSR, by name or by its 12-bit number.
+fn parse_csr(op: &str, line: usize) -> Result<u16, AsmError> {
+    if let Some(csr) = csr_number(&op.to_lowercase()) {
+        return Ok(csr);
+    }
+    if !looks_numeric(op) {
+        return Err(error(line, format!("unknown CSR '{}'", op)));
+    }
+    let csr = parse_immediate(op, line)?;
+    if !(0..0x1000).contains(&csr) {
+        return Err(error(line, format!("CSR number {} is out of range", csr)));
+    }
+    Ok(csr as u16)
+}
+
+//the rs1 field of a CSR instruction: a register, or a 5-bit immediate for the I versions.
+fn csr_source(funct3: u32, op: &str, line: usize) -> Result<u8, AsmError> {
+    if funct3 & 0b100 == 0 {
+        return parse_register(op, line);
+    }
+    let imm = parse_immediate(op, line)?;
+    if !(0..32).contains(&imm) {
+        return Err(error(
+            line,
+            format!("CSR immediate {} does not fit in 5 bits", imm),
+        ));
+    }
+    Ok(imm as u8)
+}
+
+//accepts $rN, xN, and the ABI names (zero, ra, sp, a0...)
+pub fn parse_register(op: &str, line: usize) -> Result<u8, AsmError> {
+    let name = op.trim().to_lowercase();
+    let numbered = name
+        .strip_prefix("$r")
+        .or_else(|| name.strip_prefix('x'))
+        .and_then(|n| n.parse::<u8>().ok());
+
+    let index = match numbered {
+        Some(n) => Some(n),
+        None => match name.as_str() {
+            "zero" => Some(0),
+            "ra" => Some(1),
+            "sp" => Some(2),
+            "gp" => Some(3),
+            "tp" => Some(4),
+            "t0" => Some(5),
+            "t1" => Some(6),
+            "t2" => Some(7),
+            "s0" | "fp" => Some(8),
+            "s1" => Some(9),
+            "a0" => Some(10),
+            "a1" => Some(11),
+            "a2" => Some(12),
+            "a3" => Some(13),
+            "a4" => Some(14),
+            "a5" => Some(15),
+            "a6" => Some(16),
+            "a7" => Some(17),
+            "s2" => Some(18),
+            "s3" => Some(19),
+            "s4" => Some(20),
+            "s5" => Some(21),
+            "s6" => Some(22),
+            "s7" => Some(23),
+            "s8" => Some(24),
+            "s9" => Some(25),
+            "s10" => Some(26),
+            "s11" => Some(27),
+            "t3" => Some(28),
+            "t4" => Some(29),
+            "t5" => Some(30),
+            "t6" => Some(31),
+            _ => None,
+        },
+    };
+
+    match index {
+        Some(n) if n < 32 => Ok(n),
+        _ => Err(error(line, format!("'{}' is not a register", op.trim()))),
+    }
+}
+
+//accepts fN, $fN, and the ABI names (ft0-ft11, fs0-fs11, fa0-fa7)
+pub fn parse_fp_register(op: &str, line: usize) -> Result<u8, AsmError> {
+    let name = op.trim().to_lowercase();
+    let numbered = |prefix: &str| name.strip_prefix(prefix).and_then(|n| n.parse::<u8>().ok());
+    let index = match (
+        numbered("$f").or_else(|| numbered("f")),
+        numbered("ft"),
+        numbered("fs"),
+        numbered("fa"),
+    ) {
+        (Some(n), _, _, _) if n < 32 => Some(n),
+        (_, Some(n), _, _) if n < 8 => Some(n), //ft0-ft7 are f0-f7
+        (_, Some(n), _, _) if n < 12 => Some(n + 20), //ft8-ft11 are f28-f31
+        (_, _, Some(n), _) if n < 2 => Some(n + 8), //fs0-fs1 are f8-f9
+        (_, _, Some(n), _) if n < 12 => Some(n + 16), //fs2-fs11 are f18-f27
+        (_, _, _, Some(n)) if n < 8 => Some(n + 10), //fa0-fa7 are f10-f17
+        _ => None,
+    };
+    index.ok_or_else(|| error(line, format!("'{}' is not an FP register", op.trim())))
+}
+
+//the rounding mode operand some FP instructions can have on the end.
+fn parse_rounding_mode(op: &str, line: usize) -> Result<u32, AsmError> {
+    match op.trim().to_lowercase().as_str() {
+        "rne" => Ok(0b000),
+        "rtz" => Ok(0b001),
+        "rdn" => Ok(0b010),
+        "rup" => Ok(0b011),
+        "rmm" => Ok(0b100),
+        "dyn" => Ok(FP_DYNAMIC),
+        _ => Err(error(
+            line,
+            format!("'{}' is not a rounding mode", op.trim()),
+        )),
+    }
+}
+
+fn looks_numeric(op: &str) -> bool {
+    let op = op.trim();
+    let op = op.strip_prefix('-').unwrap_or(op);
+    op.starts_with(|c: char| c.is_ascii_digit())
+}
+
+//accepts decimal, hex (0x), and binary (0b) numbers, optionally negative.
+// hex and binary are read as raw 32-bit patterns, so 0xffffffd8 is -40. that's what display_instruction prints.
+pub fn parse_immediate(op: &str, line: usize) -> Result<i32, AsmError> {
+    let text = op.trim().replace('_', "");
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.as_str()),
+    };
+    let lower = digits.to_lowercase();
+
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok().map(|v| v as i32)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2).ok().map(|v| v as i32)
+    } else {
+        // decimal can go all the way up to u32::MAX too
+        lower
+            .parse::<i64>()
+            .ok()
+            .filter(|v| *v <= u32::MAX as i64)
+            .map(|v| v as u32 as i32)
+    };
+
+    match parsed {
+        Some(value) if negative => Ok(value.wrapping_neg()),
+        Some(value) => Ok(value),
+        None => Err(error(line, format!("'{}' is not a number", op.trim()))),
+    }
+}
+
+// parses "imm(reg)". the immediate can be left out, like "(sp)".
+fn parse_memory_operand(op: &str, line: usize) -> Result<(i32, u8), AsmError> {
+    let op = op.trim();
+    let open = op.find('(');
+    let close = op.rfind(')');
+    match (open, close) {
+        (Some(open), Some(close)) if close > open && close == op.len() - 1 => {
+            let imm_text = op[..open].trim();
+            let imm = if imm_text.is_empty() {
+                0
+            } else {
+                parse_immediate(imm_text, line)?
+            };
+            let reg = parse_register(&op[open + 1..close], line)?;
+            Ok((imm, reg))
+        }
+        _ => Err(error(
+            line,
+            format!("expected a memory operand like 8($r1), found '{}'", op),
+        )),
+    }
+}
+
+fn fits_signed(value: i32, bits: u32) -> bool {
+    let min = -(1i64 << (bits - 1));
+    let max = (1i64 << (bits - 1)) - 1;
+    (value as i64) >= min && (value as i64) <= max
+}
+
+fn check_signed(value: i32, bits: u32, line: usize) -> Result<(), AsmError> {
+    if fits_signed(value, bits) {
+        Ok(())
+    } else {
+        Err(error(
+            line,
+            format!("immediate {} does not fit in {} signed bits", value, bits),
+        ))
+    }
+}
+
+fn check_branch_offset(offset: i32, bits: u32, line: usize) -> Result<(), AsmError> {
+    if offset % 2 != 0 {
+        return Err(error(line, format!("jump offset {} is not even", offset)));
+    }
+    if !fits_signed(offset, bits) {
+        return Err(error(line, format!("jump offset {} is too far", offset)));
+    }
+    Ok(())
+}
+
+// ===========================
+// Encoders, one per instruction type
+// ===========================
+
+fn encode_r(funct7: u32, r2: u8, r1: u8, funct3: u32, rd: u8, opcode: u32) -> u32 {
+    (funct7 << 25)
+        | ((r2 as u32) << 20)
+        | ((r1 as u32) << 15)
+        | (funct3 << 12)
+        | ((rd as u32) << 7)
+        | opcode
+}
+
+fn encode_i(imm: i32, r1: u8, funct3: u32, rd: u8, opcode: u32) -> u32 {
+    (((imm as u32) & 0xfff) << 20)
+        | ((r1 as u32) << 15)
+        | (funct3 << 12)
+        | ((rd as u32) << 7)
+        | opcode
+}
+
+fn encode_s(imm: i32, r2: u8, r1: u8, funct3: u32, opcode: u32) -> u32 {
+    let imm = imm as u32;
+    (((imm >> 5) & 0b1111111) << 25)
+        | ((r2 as u32) << 20)
+        | ((r1 as u32) << 15)
+        | (funct3 << 12)
+        | ((imm & 0b11111) << 7)
+        | opcode
+}
+
+fn encode_b(imm: i32, r2: u8, r1: u8, funct3: u32, opcode: u32) -> u32 {
+    // [12] to (31), [10-5] to (30-25), [4-1] to (11-8), [11] to (7)
+    let imm = imm as u32;
+    (((imm >> 12) & 0b1) << 31)
+        | (((imm >> 5) & 0b111111) << 25)
+        | ((r2 as u32) << 20)
+        | ((r1 as u32) << 15)
+        | (funct3 << 12)
+        | (((imm >> 1) & 0b1111) << 8)
+        | (((imm >> 11) & 0b1) << 7)
+        | opcode
+}
+
+fn encode_u(imm: u32, rd: u8, opcode: u32) -> u32 {
+    ((imm & 0xfffff) << 12) | ((rd as u32) << 7) | opcode
+}
+
+fn encode_j(imm: i32, rd: u8, opcode: u32) -> u32 {
+    // [20] to (31), [10-1] to (30-21), [11] to (20), [19-12] to (19-12)
+    let imm = imm as u32;
+    (((imm >> 20) & 0b1) << 31)
+        | (((imm >> 1) & 0b1111111111) << 21)
+        | (((imm >> 11) & 0b1) << 20)
+        | (imm & 0b11111111000000000000)
+        | ((rd as u32) << 7)
+        | opcode
+}
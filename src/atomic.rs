@@ -0,0 +1,54 @@
+// RV32A: atomics, for spinlocks and counters. They're R-type instructions with their own opcode, and funct5 (the top
+// five bits of funct7) says which one it is. Their address is just rs1, with no offset, so the ALU passes it through,
+// and everything else happens in MEM:
+//  LR.W loads a word, and reserves it.
+//  SC.W stores rs2, but only if the word is still reserved. rd gets 0 if it was, and 1 if it wasn't.
+//  the AMOs load a word, work out a new one from it and rs2, and store that back, all in the one MEM cycle.
+// rd gets the word that was loaded, so as far as the hazard unit and forwarding go, they're loads.
+// There's only the one hart, and memory is always in order, so the aq and rl bits don't do anything, and nothing
+// can get in between an AMO's read and its write. The reservation is on a single word. Any SC gives it up, whether
+// it worked or not, and so does taking a trap, so a handler can't finish off an LR/SC it interrupted.
+
+pub const OPCODE_ATOMIC: u8 = 0b0101111;
+
+pub const FUNCT5_LR: u8 = 0b00010;
+pub const FUNCT5_SC: u8 = 0b00011;
+pub const FUNCT5_AMOSWAP: u8 = 0b00001;
+pub const FUNCT5_AMOADD: u8 = 0b00000;
+pub const FUNCT5_AMOXOR: u8 = 0b00100;
+pub const FUNCT5_AMOAND: u8 = 0b01100;
+pub const FUNCT5_AMOOR: u8 = 0b01000;
+pub const FUNCT5_AMOMIN: u8 = 0b10000;
+pub const FUNCT5_AMOMAX: u8 = 0b10100;
+pub const FUNCT5_AMOMINU: u8 = 0b11000;
+pub const FUNCT5_AMOMAXU: u8 = 0b11100;
+
+pub fn funct5(instruction: u32) -> u8 {
+    (instruction >> 27) as u8
+}
+
+//whether it's LR, which only reads. the rest all (try to) write.
+pub fn is_load_reserved(instruction: u32) -> bool {
+    funct5(instruction) == FUNCT5_LR
+}
+
+//What the atomic with this funct5 does to the word it loaded: what rd gets, and what gets stored back, if anything.
+// `operand` is rs2, and `reserved` is whether the word is the one LR reserved.
+pub fn atomic(funct5: u8, loaded: u32, operand: u32, reserved: bool) -> (u32, Option<u32>) {
+    let stored = match funct5 {
+        FUNCT5_LR => return (loaded, None),
+        FUNCT5_SC if reserved => return (0, Some(operand)),
+        FUNCT5_SC => return (1, None),
+        FUNCT5_AMOSWAP => operand,
+        FUNCT5_AMOADD => loaded.wrapping_add(operand),
+        FUNCT5_AMOXOR => loaded ^ operand,
+        FUNCT5_AMOAND => loaded & operand,
+        FUNCT5_AMOOR => loaded | operand,
+        FUNCT5_AMOMIN => (loaded as i32).min(operand as i32) as u32,
+        FUNCT5_AMOMAX => (loaded as i32).max(operand as i32) as u32,
+        FUNCT5_AMOMINU => loaded.min(operand),
+        FUNCT5_AMOMAXU => loaded.max(operand),
+        _ => return (0, None), //illegal, traps once it leaves EX
+    };
+    (loaded, Some(stored))
+}
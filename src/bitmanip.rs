@@ -0,0 +1,121 @@
+// Zba, Zbb and Zbs: the bit-manipulation instructions compilers use at -O2 when they're told they can.
+// They're all R-type or I-type instructions on the OP and OP-IMM opcodes, using funct7s (and, for the ones with
+// only one operand, rs2 fields) the base ISA leaves empty. The ALU does each of them in one cycle, like an add, so
+// the pipeline handles them like any other ALU instruction: forwarding, hazards and all.
+//  Zba: sh1add, sh2add, sh3add. address arithmetic, rs2 + (rs1 << n), for indexing arrays.
+//  Zbb: andn, orn, xnor, clz, ctz, cpop, min(u), max(u), sext.b, sext.h, zext.h, rol, ror(i), rev8, orc.b.
+//  Zbs: bset, bclr, binv, bext and their immediate versions, on the single bit rs2 (or shamt) says.
+
+const OPCODE_OP: u32 = 0b0110011;
+const OPCODE_OP_IMM: u32 = 0b0010011;
+
+//the bit-manipulation instruction this is, or None if it isn't one. for the disassembler, EX goes by the fields.
+pub fn bitmanip_name(instruction: u32) -> Option<&'static str> {
+    let opcode = instruction & 0b1111111;
+    let funct3 = (instruction >> 12) & 0b111;
+    let funct7 = instruction >> 25;
+    let rs2 = (instruction >> 20) & 0b11111;
+    let name = match (opcode, funct7, funct3) {
+        (OPCODE_OP, 0b0010000, 0b010) => "sh1add",
+        (OPCODE_OP, 0b0010000, 0b100) => "sh2add",
+        (OPCODE_OP, 0b0010000, 0b110) => "sh3add",
+        (OPCODE_OP, 0b0100000, 0b111) => "andn",
+        (OPCODE_OP, 0b0100000, 0b110) => "orn",
+        (OPCODE_OP, 0b0100000, 0b100) => "xnor",
+        (OPCODE_OP, 0b0000101, 0b100) => "min",
+        (OPCODE_OP, 0b0000101, 0b101) => "minu",
+        (OPCODE_OP, 0b0000101, 0b110) => "max",
+        (OPCODE_OP, 0b0000101, 0b111) => "maxu",
+        (OPCODE_OP, 0b0000100, 0b100) if rs2 == 0 => "zext.h",
+        (OPCODE_OP, 0b0110000, 0b001) => "rol",
+        (OPCODE_OP, 0b0110000, 0b101) => "ror",
+        (OPCODE_OP, 0b0010100, 0b001) => "bset",
+        (OPCODE_OP, 0b0100100, 0b001) => "bclr",
+        (OPCODE_OP, 0b0110100, 0b001) => "binv",
+        (OPCODE_OP, 0b0100100, 0b101) => "bext",
+        (OPCODE_OP_IMM, 0b0110000, 0b001) => match rs2 {
+            0b00000 => "clz",
+            0b00001 => "ctz",
+            0b00010 => "cpop",
+            0b00100 => "sext.b",
+            0b00101 => "sext.h",
+            _ => return None,
+        },
+        (OPCODE_OP_IMM, 0b0110000, 0b101) => "rori",
+        (OPCODE_OP_IMM, 0b0110100, 0b101) if rs2 == 0b11000 => "rev8",
+        (OPCODE_OP_IMM, 0b0010100, 0b101) if rs2 == 0b00111 => "orc.b",
+        (OPCODE_OP_IMM, 0b0010100, 0b001) => "bseti",
+        (OPCODE_OP_IMM, 0b0100100, 0b001) => "bclri",
+        (OPCODE_OP_IMM, 0b0110100, 0b001) => "binvi",
+        (OPCODE_OP_IMM, 0b0100100, 0b101) => "bexti",
+        _ => return None,
+    };
+    Some(name)
+}
+
+//whether it's one of these at all, for decode and EX. (by its fields, like bitmanip below. the names are for the disassembler)
+pub fn is_bitmanip(instruction: u32) -> bool {
+    result(instruction, 0, 0).is_some()
+}
+
+//the ones that only have rd and rs1. (the rest have rs2, or a shamt if they're on OP-IMM)
+pub fn is_unary(name: &str) -> bool {
+    matches!(
+        name,
+        "clz" | "ctz" | "cpop" | "sext.b" | "sext.h" | "zext.h" | "rev8" | "orc.b"
+    )
+}
+
+//The result of the bit-manipulation instruction. op1 is rs1, and op2 is rs2, or the immediate for OP-IMM ones,
+// whose low 5 bits are the shamt.
+pub fn bitmanip(instruction: u32, op1: u32, op2: u32) -> u32 {
+    result(instruction, op1, op2).unwrap_or(0) //not one. the caller checks first
+}
+
+//the same, or None if it isn't one. decoded the same way as bitmanip_name, straight from the fields.
+fn result(instruction: u32, op1: u32, op2: u32) -> Option<u32> {
+    let opcode = instruction & 0b1111111;
+    let funct3 = (instruction >> 12) & 0b111;
+    let funct7 = instruction >> 25;
+    let rs2 = (instruction >> 20) & 0b11111;
+    let shamt = op2 & 0b11111;
+    let result = match (opcode, funct7, funct3) {
+        (OPCODE_OP, 0b0010000, 0b010) => (op1 << 1).wrapping_add(op2), //SH1ADD
+        (OPCODE_OP, 0b0010000, 0b100) => (op1 << 2).wrapping_add(op2), //SH2ADD
+        (OPCODE_OP, 0b0010000, 0b110) => (op1 << 3).wrapping_add(op2), //SH3ADD
+        (OPCODE_OP, 0b0100000, 0b111) => op1 & !op2,                   //ANDN
+        (OPCODE_OP, 0b0100000, 0b110) => op1 | !op2,                   //ORN
+        (OPCODE_OP, 0b0100000, 0b100) => !(op1 ^ op2),                 //XNOR
+        (OPCODE_OP, 0b0000101, 0b100) => (op1 as i32).min(op2 as i32) as u32, //MIN
+        (OPCODE_OP, 0b0000101, 0b101) => op1.min(op2),                 //MINU
+        (OPCODE_OP, 0b0000101, 0b110) => (op1 as i32).max(op2 as i32) as u32, //MAX
+        (OPCODE_OP, 0b0000101, 0b111) => op1.max(op2),                 //MAXU
+        (OPCODE_OP, 0b0000100, 0b100) if rs2 == 0 => op1 & 0xffff,     //ZEXT.H
+        (OPCODE_OP, 0b0110000, 0b001) => op1.rotate_left(shamt),       //ROL
+        (OPCODE_OP, 0b0110000, 0b101) | (OPCODE_OP_IMM, 0b0110000, 0b101) => {
+            op1.rotate_right(shamt)
+        } //ROR, RORI
+        (OPCODE_OP, 0b0010100, 0b001) | (OPCODE_OP_IMM, 0b0010100, 0b001) => op1 | (1 << shamt), //BSET, BSETI
+        (OPCODE_OP, 0b0100100, 0b001) | (OPCODE_OP_IMM, 0b0100100, 0b001) => op1 & !(1 << shamt), //BCLR, BCLRI
+        (OPCODE_OP, 0b0110100, 0b001) | (OPCODE_OP_IMM, 0b0110100, 0b001) => op1 ^ (1 << shamt), //BINV, BINVI
+        (OPCODE_OP, 0b0100100, 0b101) | (OPCODE_OP_IMM, 0b0100100, 0b101) => (op1 >> shamt) & 1, //BEXT, BEXTI
+        (OPCODE_OP_IMM, 0b0110000, 0b001) => match rs2 {
+            0b00000 => op1.leading_zeros(),      //CLZ
+            0b00001 => op1.trailing_zeros(),     //CTZ
+            0b00010 => op1.count_ones(),         //CPOP
+            0b00100 => op1 as i8 as i32 as u32,  //SEXT.B
+            0b00101 => op1 as i16 as i32 as u32, //SEXT.H
+            _ => return None,
+        },
+        (OPCODE_OP_IMM, 0b0110100, 0b101) if rs2 == 0b11000 => op1.swap_bytes(), //REV8
+        (OPCODE_OP_IMM, 0b0010100, 0b101) if rs2 == 0b00111 => {
+            //ORC.B
+            u32::from_le_bytes(
+                op1.to_le_bytes()
+                    .map(|byte| if byte != 0 { 0xff } else { 0 }),
+            )
+        }
+        _ => return None,
+    };
+    Some(result)
+}
@@ -0,0 +1,308 @@
+// Data caches. Up to three levels of set-associative cache between the MEM stage and data memory.
+// They only keep track of which lines they hold (the tags, and whether each line is dirty). The data itself
+// always lives in memory, so the caches never change what a program does, only how long its loads and
+// stores take. A load or store finds out how long it takes when it gets to MEM, and the MEM stage stalls for
+// whatever is left after its first cycle. There's no write buffer, so write-throughs and write-backs of
+// dirty lines are all on the clock too.
+
+//How one level of cache is set up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheConfig {
+    pub size: u32,          //total bytes of data it holds
+    pub associativity: u32, //lines per set
+    pub line_size: u32,     //bytes per line
+    pub replacement: Replacement,
+    pub write_back: bool,     //false for write-through
+    pub write_allocate: bool, //if a write miss brings the line in. if not, it just gets passed on down
+    pub latency: u64,         //cycles to look in this level, hit or miss
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            size: 4096,
+            associativity: 1,
+            line_size: 16,
+            replacement: Replacement::Lru,
+            write_back: true,
+            write_allocate: true,
+            latency: 1,
+        }
+    }
+}
+
+impl CacheConfig {
+    //a cache from a list of settings like "size=32k,ways=4,line=64,policy=lru,write=back,allocate=yes,latency=2".
+    // anything left out keeps its default.
+    pub fn from_spec(spec: &str) -> Result<CacheConfig, String> {
+        let mut config = CacheConfig::default();
+        for setting in spec.split(',').filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting
+                .split_once('=')
+                .ok_or(format!("cache setting '{}' needs a value", setting))?;
+            let value = value.trim().to_lowercase();
+            let number = || parse_size(&value).ok_or(format!("bad {} '{}'", name, value));
+            match name.trim() {
+                "size" => config.size = number()?,
+                "ways" | "assoc" => config.associativity = number()?,
+                "line" => config.line_size = number()?,
+                "latency" => config.latency = number()? as u64,
+                "policy" => {
+                    config.replacement = match value.as_str() {
+                        "lru" => Replacement::Lru,
+                        "fifo" => Replacement::Fifo,
+                        "random" => Replacement::Random,
+                        _ => return Err(format!("unknown replacement policy '{}'", value)),
+                    }
+                }
+                "write" => {
+                    config.write_back = match value.as_str() {
+                        "back" => true,
+                        "through" => false,
+                        _ => {
+                            return Err(format!(
+                                "write needs to be back or through, not '{}'",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "allocate" => {
+                    config.write_allocate = match value.as_str() {
+                        "yes" | "true" => true,
+                        "no" | "false" => false,
+                        _ => {
+                            return Err(format!("allocate needs to be yes or no, not '{}'", value))
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown cache setting '{}'", name)),
+            }
+        }
+        config.check()?;
+        Ok(config)
+    }
+
+    //makes sure the sizes fit together.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() || self.line_size < 4 {
+            return Err(format!(
+                "line size {} isn't a power of two of at least 4",
+                self.line_size
+            ));
+        }
+        if self.associativity == 0 {
+            return Err("associativity can't be 0".to_owned());
+        }
+        let set_bytes = self.line_size * self.associativity;
+        if self.size < set_bytes
+            || !self.size.is_multiple_of(set_bytes)
+            || !(self.size / set_bytes).is_power_of_two()
+        {
+            return Err(format!(
+                "a {}-byte cache can't have a power-of-two number of {}-way sets of {}-byte lines",
+                self.size, self.associativity, self.line_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn sets(&self) -> u32 {
+        self.size / (self.line_size * self.associativity)
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} bytes, {}-way, {}-byte lines, {}, write-{}{}",
+            self.size,
+            self.associativity,
+            self.line_size,
+            match self.replacement {
+                Replacement::Lru => "LRU",
+                Replacement::Fifo => "FIFO",
+                Replacement::Random => "random",
+            },
+            if self.write_back { "back" } else { "through" },
+            if self.write_allocate {
+                ", write-allocate"
+            } else {
+                ""
+            }
+        )
+    }
+}
+
+//a number, optionally ending in k or m, like "32k".
+fn parse_size(text: &str) -> Option<u32> {
+    let (digits, scale) = match text.strip_suffix('k') {
+        Some(digits) => (digits, 1024),
+        None => match text.strip_suffix('m') {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (text, 1),
+        },
+    };
+    digits.parse::<u32>().ok()?.checked_mul(scale)
+}
+
+//Counters for one level of cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64, //dirty lines written back down when they got evicted
+}
+
+impl CacheStats {
+    //the fraction of accesses that hit. 0 if there weren't any.
+    pub fn hit_rate(&self) -> f64 {
+        let accesses = self.hits + self.misses;
+        if accesses == 0 {
+            0.0
+        } else {
+            self.hits as f64 / accesses as f64
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Line {
+    valid: bool,
+    dirty: bool,
+    tag: u32,
+    last_used: u64, //for LRU
+    filled: u64,    //for FIFO
+}
+
+//One level of cache.
+#[derive(Clone)]
+pub struct Cache {
+    pub config: CacheConfig,
+    pub stats: CacheStats,
+    sets: Vec<Vec<Line>>,
+    time: u64,   //goes up every access, to order the lines by
+    random: u32, //xorshift state, so random replacement still does the same thing every run
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Cache {
+        let sets =
+            vec![vec![Line::default(); config.associativity as usize]; config.sets() as usize];
+        Cache {
+            config,
+            stats: CacheStats::default(),
+            sets,
+            time: 0,
+            random: 0x2545f491,
+        }
+    }
+
+    fn set_and_tag(&self, address: u32) -> (usize, u32) {
+        let line = address / self.config.line_size;
+        let sets = self.sets.len() as u32;
+        ((line % sets) as usize, line / sets)
+    }
+
+    //looks for the line holding this address, and counts it as used if it's there.
+    fn find(&mut self, address: u32) -> Option<&mut Line> {
+        self.time += 1;
+        let time = self.time;
+        let (set, tag) = self.set_and_tag(address);
+        let line = self.sets[set]
+            .iter_mut()
+            .find(|line| line.valid && line.tag == tag)?;
+        line.last_used = time;
+        Some(line)
+    }
+
+    //brings in the line holding this address. Returns the address of the line that got thrown out for it,
+    // if that one was dirty and needs writing back.
+    fn fill(&mut self, address: u32, dirty: bool) -> Option<u32> {
+        let (set, tag) = self.set_and_tag(address);
+        let way = match self.sets[set].iter().position(|line| !line.valid) {
+            Some(empty) => empty,
+            None => self.victim(set),
+        };
+        let old = self.sets[set][way];
+        self.sets[set][way] = Line {
+            valid: true,
+            dirty,
+            tag,
+            last_used: self.time,
+            filled: self.time,
+        };
+        if old.valid && old.dirty {
+            let sets = self.sets.len() as u32;
+            Some((old.tag * sets + set as u32) * self.config.line_size)
+        } else {
+            None
+        }
+    }
+
+    //which line in a full set gets replaced.
+    fn victim(&mut self, set: usize) -> usize {
+        let lines = &self.sets[set];
+        let oldest = |key: fn(&Line) -> u64| {
+            (0..lines.len())
+                .min_by_key(|&way| key(&lines[way]))
+                .unwrap_or(0)
+        };
+        match self.config.replacement {
+            Replacement::Lru => oldest(|line| line.last_used),
+            Replacement::Fifo => oldest(|line| line.filled),
+            Replacement::Random => {
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 17;
+                self.random ^= self.random << 5;
+                self.random as usize % lines.len()
+            }
+        }
+    }
+}
+
+//A load or store going through the caches, starting at the first one in `levels`. Returns how many cycles
+// it takes. Whatever misses every level goes to data memory, which takes `memory_latency`.
+pub fn access(levels: &mut [Cache], memory_latency: u64, address: u32, write: bool) -> u64 {
+    let Some((cache, lower)) = levels.split_first_mut() else {
+        return memory_latency;
+    };
+    let mut time = cache.config.latency;
+    let write_back = cache.config.write_back;
+
+    let hit = match cache.find(address) {
+        Some(line) => {
+            line.dirty |= write && write_back;
+            true
+        }
+        None => false,
+    };
+    if hit {
+        cache.stats.hits += 1;
+        if write && !write_back {
+            time += access(lower, memory_latency, address, true);
+        }
+        return time;
+    }
+
+    cache.stats.misses += 1;
+    if write && !cache.config.write_allocate {
+        //write-no-allocate: it goes on down without stopping here.
+        return time + access(lower, memory_latency, address, true);
+    }
+    //bring the line in from the next level down, writing back whatever it replaces first.
+    if let Some(evicted) = cache.fill(address, write && write_back) {
+        cache.stats.writebacks += 1;
+        time += access(lower, memory_latency, evicted, true);
+    }
+    time += access(lower, memory_latency, address, false);
+    if write && !write_back {
+        time += access(lower, memory_latency, address, true);
+    }
+    time
+}
@@ -0,0 +1,405 @@
+use crate::assembler::{parse_immediate, parse_register};
+use crate::cache::CacheConfig;
+use crate::components::{CLINT_BASE, UART_BASE};
+use crate::config::{
+    Forwarding, Model, Predictor, DEFAULT_DIV_LATENCY, DEFAULT_FADD_LATENCY, DEFAULT_FDIV_LATENCY,
+    DEFAULT_FMUL_LATENCY, DEFAULT_MEMORY_LATENCY, DEFAULT_MUL_LATENCY, MAX_CACHE_LEVELS,
+};
+use crate::loader::Format;
+use crate::mem::MemoryMap;
+use crate::report::ReportFormat;
+
+pub const USAGE: &str = "\
+Usage: riscv-psim [command] [options] <program_file>
+
+Commands:
+    step      run the program one cycle at a time, showing the pipeline (default)
+    run       run the program to the end without any UI, then print a report
+    trace     run the program, printing one line per cycle
+    disasm    print the program's instructions as assembly
+
+Options:
+    --format <fmt>        program format: elf, asm, text, bin, ihex, memh (default: guessed)
+    --max-cycles <n>      stop after n cycles, if the program hasn't ended by then
+    --halt-at <addr>      end the program when it reaches this address or symbol
+    --tohost <addr>       end the program when it stores a non-zero value here (default: the 'tohost' symbol)
+    --reset-vector <addr> start running here instead of at the program's entry point
+    --reg <reg>=<value>   set a register before starting, e.g. --reg a0=5 (repeatable)
+    --mem <addr>=<value>  put a word into data memory before starting, e.g. --mem 0x100=42 (repeatable)
+    --forwarding <paths>  forwarding paths to use: all, none, or a list like ex-ex,mem-ex,mem-mem (default: all)
+    --model <model>       the datapath to run on: pipelined, single-cycle, multi-cycle (default: pipelined)
+    --predictor <name>    branch predictor: not-taken, btfnt, 1-bit, 2-bit, gshare (default: not-taken)
+    --btb <entries>       add a branch target buffer with this many entries
+    --early-branches      resolve branches and jumps in ID instead of EX
+    --mul-latency <n>     cycles EX takes for a multiply (default: 3)
+    --div-latency <n>     cycles EX takes for a divide or remainder (default: 32)
+    --fadd-latency <n>    cycles an FP add, subtract or conversion takes, EX and the FPU together (default: 4)
+    --fmul-latency <n>    cycles an FP multiply or fused multiply-add takes (default: 7)
+    --fdiv-latency <n>    cycles an FP divide or square root takes (default: 25)
+    --cache <settings>    add a level of data cache, e.g. size=32k,ways=4,line=64,policy=lru,write=back,allocate=yes,latency=1
+                          (repeatable: L1 first, up to L3)
+    --memory-latency <n>  cycles data memory takes when every cache misses (default: 50)
+    --icache <settings>   add an instruction cache, with the same settings as --cache
+    --icache-penalty <n>  cycles instruction memory takes when the instruction cache misses (default: 50)
+    --memory-map <map>    the memory regions, e.g. text=0x0:64k,data=0x10000000:1m,stack=0x7ff00000:1m,mmio=0xf0000000:4k
+    --uart <output>       attach a 16550 UART, sending to stdout or to a file
+    --uart-address <addr> where the UART goes (default: 0xf0000000)
+    --uart-input <file>   bytes for the UART to receive
+    --clint               attach a CLINT, for timer and software interrupts
+    --clint-address <addr>
+                          where the CLINT goes (default: 0xf2000000)
+    --display <mode>      how much 'step' shows each cycle: full, compact, none (default: full)
+    --report <fmt>        print an end-of-run report: text or json ('run' always prints one, as text by default)
+    --output <file>       write the results to a file instead of the terminal
+    -h, --help            show this message";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Run,
+    Step,
+    Disasm,
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DisplayMode {
+    Full,    //the whole pipeline diagram
+    Compact, //just the stages and the registers
+    None,
+}
+
+//everything that was asked for on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub program: String,
+    pub format: Option<Format>,
+    pub max_cycles: Option<u64>,
+    pub halt_at: Option<String>, //address or symbol name, looked up once the program is loaded
+    pub tohost: Option<String>,
+    pub reset_vector: Option<String>,
+    pub registers: Vec<(u8, u32)>, //(register index, value)
+    pub memory: Vec<(u32, u32)>,   //(byte address, word)
+    pub forwarding: Forwarding,
+    pub model: Model,
+    pub predictor: Predictor,
+    pub btb_entries: usize,
+    pub early_branches: bool,
+    pub mul_latency: u64,
+    pub div_latency: u64,
+    pub fadd_latency: u64,
+    pub fmul_latency: u64,
+    pub fdiv_latency: u64,
+    pub caches: Vec<CacheConfig>,
+    pub memory_latency: u64,
+    pub icache: Option<CacheConfig>,
+    pub icache_penalty: u64,
+    pub memory_map: MemoryMap,
+    pub uart: Option<String>, //"stdout", or a file to write to
+    pub uart_address: u32,
+    pub uart_input: Option<String>,
+    pub clint: bool,
+    pub clint_address: u32,
+    pub display: DisplayMode,
+    pub output: Option<String>,
+    pub report: Option<ReportFormat>,
+    pub help: bool,
+}
+
+//Parses the arguments that come after the program name.
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Step,
+        program: String::new(),
+        format: None,
+        max_cycles: None,
+        halt_at: None,
+        tohost: None,
+        reset_vector: None,
+        registers: Vec::new(),
+        memory: Vec::new(),
+        forwarding: Forwarding::all(),
+        model: Model::Pipelined,
+        predictor: Predictor::NotTaken,
+        btb_entries: 0,
+        early_branches: false,
+        mul_latency: DEFAULT_MUL_LATENCY,
+        div_latency: DEFAULT_DIV_LATENCY,
+        fadd_latency: DEFAULT_FADD_LATENCY,
+        fmul_latency: DEFAULT_FMUL_LATENCY,
+        fdiv_latency: DEFAULT_FDIV_LATENCY,
+        caches: Vec::new(),
+        memory_latency: DEFAULT_MEMORY_LATENCY,
+        icache: None,
+        icache_penalty: DEFAULT_MEMORY_LATENCY,
+        memory_map: MemoryMap::default(),
+        uart: None,
+        uart_address: UART_BASE,
+        uart_input: None,
+        clint: false,
+        clint_address: CLINT_BASE,
+        display: DisplayMode::Full,
+        output: None,
+        report: None,
+        help: false,
+    };
+    let mut program = None;
+    let mut rest = args;
+
+    // the command is optional, so that "riscv-psim <file>" still works like it always has.
+    if let Some(first) = rest.first() {
+        let command = match first.as_str() {
+            "run" => Some(Command::Run),
+            "step" => Some(Command::Step),
+            "disasm" => Some(Command::Disasm),
+            "trace" => Some(Command::Trace),
+            _ => None,
+        };
+        if let Some(command) = command {
+            options.command = command;
+            rest = &rest[1..];
+        }
+    }
+
+    let mut i = 0;
+    while i < rest.len() {
+        let arg = rest[i].as_str();
+
+        // options can be written "--opt value" or "--opt=value"
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_owned())),
+            _ => (arg, None),
+        };
+        let takes_value = matches!(
+            name,
+            "--format"
+                | "--max-cycles"
+                | "--halt-at"
+                | "--tohost"
+                | "--reset-vector"
+                | "--reg"
+                | "--mem"
+                | "--forwarding"
+                | "--model"
+                | "--predictor"
+                | "--btb"
+                | "--mul-latency"
+                | "--div-latency"
+                | "--fadd-latency"
+                | "--fmul-latency"
+                | "--fdiv-latency"
+                | "--cache"
+                | "--memory-latency"
+                | "--icache"
+                | "--icache-penalty"
+                | "--memory-map"
+                | "--uart"
+                | "--uart-address"
+                | "--uart-input"
+                | "--clint-address"
+                | "--display"
+                | "--output"
+                | "--report"
+        );
+        let value = if takes_value {
+            match inline_value {
+                Some(value) => value,
+                None => {
+                    i += 1;
+                    match rest.get(i) {
+                        Some(value) => value.clone(),
+                        None => return Err(format!("{} needs a value", name)),
+                    }
+                }
+            }
+        } else {
+            String::new()
+        };
+
+        match name {
+            "-h" | "--help" => options.help = true,
+            "--early-branches" => options.early_branches = true,
+            "--clint" => options.clint = true,
+            "--format" => match Format::from_name(&value) {
+                Some(format) => options.format = Some(format),
+                None => {
+                    return Err(format!(
+                        "unknown program format '{}' (expected elf, asm, text, bin, ihex or memh)",
+                        value
+                    ))
+                }
+            },
+            "--max-cycles" => match value.parse::<u64>() {
+                Ok(cycles) => options.max_cycles = Some(cycles),
+                Err(_) => return Err(format!("--max-cycles needs a number, not '{}'", value)),
+            },
+            "--halt-at" => options.halt_at = Some(value),
+            "--tohost" => options.tohost = Some(value),
+            "--reset-vector" => options.reset_vector = Some(value),
+            "--reg" => options.registers.push(parse_register_assignment(&value)?),
+            "--mem" => options.memory.push(parse_memory_assignment(&value)?),
+            "--forwarding" => match Forwarding::from_names(&value) {
+                Some(forwarding) => options.forwarding = forwarding,
+                None => {
+                    return Err(format!(
+                        "unknown forwarding paths '{}' (expected all, none, or a list of ex-ex, mem-ex and mem-mem)",
+                        value
+                    ))
+                }
+            },
+            "--model" => match Model::from_name(&value) {
+                Some(model) => options.model = model,
+                None => {
+                    return Err(format!(
+                        "unknown model '{}' (expected pipelined, single-cycle or multi-cycle)",
+                        value
+                    ))
+                }
+            },
+            "--predictor" => match Predictor::from_name(&value) {
+                Some(predictor) => options.predictor = predictor,
+                None => {
+                    return Err(format!(
+                        "unknown branch predictor '{}' (expected not-taken, btfnt, 1-bit, 2-bit or gshare)",
+                        value
+                    ))
+                }
+            },
+            "--btb" => match value.parse() {
+                Ok(entries) => options.btb_entries = entries,
+                Err(_) => return Err(format!("--btb needs a number of entries, not '{}'", value)),
+            },
+            "--mul-latency" => match value.parse() {
+                Ok(cycles) => options.mul_latency = cycles,
+                Err(_) => return Err(format!("--mul-latency needs a number, not '{}'", value)),
+            },
+            "--div-latency" => match value.parse() {
+                Ok(cycles) => options.div_latency = cycles,
+                Err(_) => return Err(format!("--div-latency needs a number, not '{}'", value)),
+            },
+            "--fadd-latency" => match value.parse() {
+                Ok(cycles) => options.fadd_latency = cycles,
+                Err(_) => return Err(format!("--fadd-latency needs a number, not '{}'", value)),
+            },
+            "--fmul-latency" => match value.parse() {
+                Ok(cycles) => options.fmul_latency = cycles,
+                Err(_) => return Err(format!("--fmul-latency needs a number, not '{}'", value)),
+            },
+            "--fdiv-latency" => match value.parse() {
+                Ok(cycles) => options.fdiv_latency = cycles,
+                Err(_) => return Err(format!("--fdiv-latency needs a number, not '{}'", value)),
+            },
+            "--cache" => {
+                if options.caches.len() == MAX_CACHE_LEVELS {
+                    return Err(format!("there can only be {} levels of cache", MAX_CACHE_LEVELS));
+                }
+                let cache = CacheConfig::from_spec(&value).map_err(|e| format!("--cache: {}", e))?;
+                options.caches.push(cache);
+            }
+            "--memory-latency" => match value.parse() {
+                Ok(cycles) => options.memory_latency = cycles,
+                Err(_) => return Err(format!("--memory-latency needs a number, not '{}'", value)),
+            },
+            "--icache" => {
+                let cache = CacheConfig::from_spec(&value).map_err(|e| format!("--icache: {}", e))?;
+                options.icache = Some(cache);
+            }
+            "--icache-penalty" => match value.parse() {
+                Ok(cycles) => options.icache_penalty = cycles,
+                Err(_) => return Err(format!("--icache-penalty needs a number, not '{}'", value)),
+            },
+            "--memory-map" => {
+                options.memory_map =
+                    MemoryMap::from_spec(&value).map_err(|e| format!("--memory-map: {}", e))?
+            }
+            "--uart" => options.uart = Some(value),
+            "--uart-address" => {
+                options.uart_address = parse_immediate(&value, 0).map_err(|e| e.message)? as u32
+            }
+            "--uart-input" => options.uart_input = Some(value),
+            "--clint-address" => {
+                options.clint_address = parse_immediate(&value, 0).map_err(|e| e.message)? as u32
+            }
+            "--display" => {
+                options.display = match value.as_str() {
+                    "full" => DisplayMode::Full,
+                    "compact" => DisplayMode::Compact,
+                    "none" => DisplayMode::None,
+                    _ => {
+                        return Err(format!(
+                            "unknown display mode '{}' (expected full, compact or none)",
+                            value
+                        ))
+                    }
+                }
+            }
+            "--output" => options.output = Some(value),
+            "--report" => match ReportFormat::from_name(&value) {
+                Some(format) => options.report = Some(format),
+                None => {
+                    return Err(format!(
+                        "unknown report format '{}' (expected text or json)",
+                        value
+                    ))
+                }
+            },
+            _ if name.starts_with('-') && name.len() > 1 => {
+                return Err(format!("unknown option '{}'", name))
+            }
+            _ => {
+                if program.is_some() {
+                    return Err("only one program file can be given".to_owned());
+                }
+                program = Some(arg.to_owned());
+            }
+        }
+        i += 1;
+    }
+
+    if options.uart.is_none() && (options.uart_input.is_some() || options.uart_address != UART_BASE)
+    {
+        return Err("--uart-address and --uart-input need --uart".to_owned());
+    }
+    if !options.clint && options.clint_address != CLINT_BASE {
+        return Err("--clint-address needs --clint".to_owned());
+    }
+
+    if options.command == Command::Run && options.report.is_none() {
+        options.report = Some(ReportFormat::Text);
+    }
+
+    match program {
+        Some(program) => options.program = program,
+        None if options.help => {}
+        None => return Err("no program file given".to_owned()),
+    }
+    Ok(options)
+}
+
+// "a0=5"
+fn parse_register_assignment(text: &str) -> Result<(u8, u32), String> {
+    let (reg, value) = match text.split_once('=') {
+        Some(parts) => parts,
+        None => return Err(format!("--reg expects <reg>=<value>, not '{}'", text)),
+    };
+    let reg = parse_register(reg, 0).map_err(|e| e.message)?;
+    if reg == 0 {
+        return Err("$r0 is always zero, it can't be set".to_owned());
+    }
+    let value = parse_immediate(value, 0).map_err(|e| e.message)?;
+    Ok((reg, value as u32))
+}
+
+// "0x100=42"
+fn parse_memory_assignment(text: &str) -> Result<(u32, u32), String> {
+    let (address, value) = match text.split_once('=') {
+        Some(parts) => parts,
+        None => return Err(format!("--mem expects <addr>=<value>, not '{}'", text)),
+    };
+    let address = parse_immediate(address, 0).map_err(|e| e.message)? as u32;
+    if !address.is_multiple_of(4) {
+        return Err(format!("--mem address {:#x} is not word-aligned", address));
+    }
+    let value = parse_immediate(value, 0).map_err(|e| e.message)?;
+    Ok((address, value as u32))
+}
@@ -1,24 +1,42 @@
 mod decode;
 mod execute;
 mod fetch;
+mod fpu;
 mod memory;
 mod writeback;
 
+pub use crate::atomic::*;
+pub use crate::bitmanip::*;
+pub use crate::cache::*;
+pub use crate::compressed::*;
+pub use crate::config::{Forwarding, Latencies, MemoryConfig, Model, Predictor};
+pub use crate::csr::*;
+pub use crate::device::*;
+pub use crate::float::*;
 pub use crate::isa::isa;
 pub use crate::isa::isa::InstrT;
+pub use crate::mem::*;
+pub use crate::muldiv::*;
+pub use crate::predictor::*;
+pub use crate::report::Stats;
+pub use crate::syscall::*;
+pub use crate::termination::*;
+pub use crate::trap::*;
 pub use decode::*;
 pub use execute::*;
 pub use fetch::*;
+pub use fpu::*;
 pub use memory::*;
-use std::collections::hash_map::*;
 pub use writeback::*;
 
 //this struct holds the Stateful components of the CPU:
 //  Each pipeline latch
 //  Program Counter
-//  Instruction Memory
-//  Register Memory
-//  Data Memory
+//  Memory, for instructions and data both, and the bus the devices are on
+//  Register Memory, for the integer and the FP registers
+//  the FPU's pipeline
+//  Control and Status Registers
+//  and the run statistics, and how/whether the program has ended
 
 #[derive(Clone)]
 pub struct Registers {
@@ -29,9 +47,36 @@ pub struct Registers {
 
     pub pc: u32,
 
-    pub instr_mem: Vec<u32>,
+    pub mem: Memory,
+    pub memory_map: MemoryMap,
+    pub text_end: u32, //one past the program's last instruction
+    pub bus: Bus,
     pub reg_mem: Vec<u32>,
-    pub data_mem: HashMap<u32, u32>,
+    pub fp_reg_mem: Vec<u32>, //f0-f31, as bits. (see float.rs)
+    pub csr: CsrFile,
+    pub forwarding: Forwarding, //which forwarding paths are switched on
+    pub model: Model,           //pipelined, or one instruction at a time
+    pub predictor: Box<dyn BranchPredictor>,
+    pub btb: Option<Btb>,
+    pub early_branches: bool, //resolve branches and jumps in ID, instead of EX
+    pub caches: Vec<Cache>,   //the data caches, L1 first
+    pub memory_latency: u64,  //cycles for data memory, when the caches miss
+    pub mem_wait: u64,        //cycles the load or store in MEM still needs
+    pub icache: Option<Cache>,
+    pub icache_miss_penalty: u64, //cycles for instruction memory, when the instruction cache misses
+    pub fetch_wait: u64,          //cycles the fetch going on in IF still needs
+    pub fetch_address: Option<u32>, //the address IF has already started fetching, until it goes into IF-ID
+    pub latencies: Latencies,
+    pub ex_wait: u64,     //cycles the multiply or divide in EX still needs
+    pub ex_started: bool, //EX has already started on the instruction in ID-EX
+    pub fpu: Fpu,
+    pub reservation: Option<u32>, //the word LR.W reserved, until an SC or a trap gives it up
+
+    pub stats: Stats,
+    pub termination: Termination,
+    pub stop: Option<StopReason>, //set once the program has ended. the pipeline then drains.
+    pub interrupt_raised: Option<u64>, //the cycle an enabled interrupt started pending, until it's taken
+    pub syscalls: Box<dyn SyscallHandler>,
 }
 
 //this structs holds all the wiring of each stage
@@ -48,12 +93,74 @@ pub struct Logic {
 }
 
 impl Registers {
+    //a CPU with this in its memory, and everything else zeroed.
+    pub fn new(mem: Memory) -> Registers {
+        Registers {
+            ifid: IFIDLatch::default(),
+            idex: IDEXLatch::default(),
+            exmem: EXMEMLatch::default(),
+            memwb: MEMWBLatch::default(),
+
+            pc: 0,
+
+            mem,
+            memory_map: MemoryMap::default(),
+            text_end: 0,
+            bus: Bus::new(),
+            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
+            fp_reg_mem: vec![0; 32],
+            csr: CsrFile::default(),
+            forwarding: Forwarding::all(),
+            model: Model::Pipelined,
+            predictor: Box::new(StaticNotTaken),
+            btb: None,
+            early_branches: false,
+            caches: Vec::new(),
+            memory_latency: 0,
+            mem_wait: 0,
+            icache: None,
+            icache_miss_penalty: 0,
+            fetch_wait: 0,
+            fetch_address: None,
+            latencies: Latencies::default(),
+            ex_wait: 0,
+            ex_started: false,
+            fpu: Fpu::default(),
+            reservation: None,
+
+            stats: Stats::default(),
+            termination: Termination::default(),
+            stop: None,
+            interrupt_raised: None,
+            syscalls: Box::new(NewlibSyscalls::new(0)),
+        }
+    }
+
+    //a register by its index in the pipeline: x0-x31 are 0-31, and f0-f31 are 32-63.
+    pub fn register(&self, index: u8) -> u32 {
+        if index >= FP_REGISTERS {
+            self.fp_reg_mem[(index - FP_REGISTERS) as usize]
+        } else {
+            self.reg_mem[index as usize]
+        }
+    }
+
     fn update(&mut self, logic: &Logic) {
         let old_rd = self.memwb.rd_index;
 
+        // the FPU moves along with MEM. whatever comes out of it goes into EX-MEM, below.
+        let fpu_done = if self.exmem.mem_stall != 1 {
+            self.fpu.advance()
+        } else {
+            None
+        };
+
         // MEM-WB Latch
-        // Pass only if not stall!
-        if self.memwb.wb_stall != 1 {
+        // Pass only if not stall! a bubble goes in instead, if there's supposed to be one.
+        if self.memwb.wb_stall == 2 {
+            self.memwb.bubble();
+        } else if self.memwb.wb_stall != 1 {
+            self.memwb.base_pc = self.exmem.base_pc;
             self.memwb.added_pc = self.exmem.added_pc;
             self.memwb.alu_output = self.exmem.alu_output;
             self.memwb.mem_data_out = logic.memory.mem_data_out;
@@ -62,139 +169,58 @@ impl Registers {
             self.memwb.opcode = self.exmem.opcode;
 
             self.memwb.instruction = self.exmem.instruction;
+
+            // a load from a device only really happens now, as it leaves MEM. (see device.rs)
+            if matches!(self.exmem.opcode, 0b0000011 | OPCODE_LOAD_FP)
+                && self.memory_map.is_mmio(self.exmem.alu_output)
+            {
+                let size = 1 << (self.exmem.funct3 & 0b11);
+                self.bus.read_done(self.exmem.alu_output, size);
+            }
         }
 
         //println!("Old RD:{}", old_rd);
 
-        // Data Memory
-
-        //insert code for Data Mem write here
-        //check if instr. is a stor,
-        if self.exmem.opcode == 0b0100011 {
-            //println!("Storing value to Data Mem!");
-            //use these to find the right address to pull from\
-            if self.exmem.alu_output == 0 {
-                panic!("Instruction tried storing value to data memory address 0.")
-            }
-            let which_word = (self.exmem.alu_output) / 4;
-            let align = self.exmem.alu_output % 4;
-
+        // Memory. Stores write to it here.
+        //stores to address 0, misaligned stores and ones outside the memory map trap before they get here. (see trap.rs)
+        if matches!(self.exmem.opcode, 0b0100011 | OPCODE_STORE_FP) {
             //check if MEM-MEM forwarding is needed! if yes, perform it and flip the bit off.
             if logic.memory.memmem_fwd {
                 self.exmem.mem_data_in = logic.memory.memmem_data;
             }
-
-            /*println!(
-                "whichword: {:?} , Align: {:?}, Funct3: {:?} to-store: {:#034b}",
-                which_word, align, self.exmem.funct3, self.exmem.mem_data_in
-            );*/
-            if self.data_mem.contains_key(&which_word) {
-                if self.exmem.funct3 == 0b000 {
-                    //Store Byte
-                    self.data_mem.insert(
-                        which_word,
-                        match align {
-                            0 => {
-                                (self.data_mem.get(&which_word).unwrap()
-                                    & 0b11111111111111111111111100000000)
-                                    + (self.exmem.mem_data_in & 0b11111111)
-                            }
-                            1 => {
-                                (self.data_mem.get(&which_word).unwrap()
-                                    & 0b11111111111111110000000011111111)
-                                    + ((self.exmem.mem_data_in & 0b11111111) << 8)
-                            }
-                            2 => {
-                                (self.data_mem.get(&which_word).unwrap()
-                                    & 0b11111111000000001111111111111111)
-                                    + ((self.exmem.mem_data_in & 0b11111111) << 16)
-                            }
-                            3 => {
-                                (self.data_mem.get(&which_word).unwrap()
-                                    & 0b00000000111111111111111111111111)
-                                    + ((self.exmem.mem_data_in & 0b11111111) << 24)
-                            }
-                            _ => panic!("align value is larger than 3!"),
-                        },
-                    );
-                } else if self.exmem.funct3 == 0b001 {
-                    //Store Half-Word
-                    self.data_mem.insert(
-                        which_word,
-                        match align {
-                            0 => {
-                                (self.data_mem.get(&which_word).unwrap()
-                                    & 0b11111111111111110000000000000000)
-                                    + (self.exmem.mem_data_in & 0b1111111111111111)
-                            }
-                            1 => panic!("Misaligned store value!"),
-                            2 => {
-                                (self.data_mem.get(&which_word).unwrap()
-                                    & 0b00000000000000001111111111111111)
-                                    + ((self.exmem.mem_data_in & 0b1111111111111111) << 16)
-                            }
-                            3 => panic!("Misaligned store value!"),
-                            _ => panic!("Align value is larger thna 3!"),
-                        },
-                    );
-                } else if self.exmem.funct3 == 0b010 {
-                    //Store Word
-                    if align > 3 {
-                        panic!("align value is larger than 3!")
-                    }
-                    if align > 0 {
-                        panic!("Misaligned store value!")
-                    }
-
-                    self.data_mem.insert(which_word, self.exmem.mem_data_in);
-                } else {
-                    panic!("Invalid funct3 on a Store instruction!")
-                }
+            // the lowest two bits of funct3 are the size: SB, SH, SW.
+            let address = self.exmem.alu_output;
+            let size = 1 << (self.exmem.funct3 & 0b11);
+            //stores to the MMIO regions go to the devices instead.
+            if self.memory_map.is_mmio(address) {
+                self.bus.write(address, size, self.exmem.mem_data_in);
             } else {
-                //if there, the value is 0, must be initialized!!
-                if self.exmem.funct3 == 0b000 {
-                    //Store Byte
-                    self.data_mem.insert(
-                        which_word,
-                        match align {
-                            0 => self.exmem.mem_data_in & 0b11111111,
-                            1 => (self.exmem.mem_data_in & 0b11111111) << 8,
-                            2 => (self.exmem.mem_data_in & 0b11111111) << 16,
-                            3 => (self.exmem.mem_data_in & 0b11111111) << 24,
-                            _ => panic!("Align value is more than 3!"),
-                        },
-                    );
-                } else if self.exmem.funct3 == 0b001 {
-                    //Store Half-Word
-                    self.data_mem.insert(
-                        which_word,
-                        match align {
-                            0 => self.exmem.mem_data_in & 0b1111111111111111,
-                            1 => panic!("Misaligned store value!"),
-                            2 => (self.exmem.mem_data_in & 0b1111111111111111) << 16,
-                            3 => panic!("Misaligned store value!"),
-                            _ => panic!("Align value is larger thna 3!"),
-                        },
-                    );
-                } else if self.exmem.funct3 == 0b010 {
-                    //Store Word
-                    if align > 3 {
-                        panic!("align value is larger than 3!")
-                    }
-                    if align > 0 {
-                        panic!("Misaligned store value!")
-                    }
+                self.mem.write(address, size, self.exmem.mem_data_in);
+            }
+            if self.memwb.wb_stall == 0 {
+                self.memwb.store_data = self.exmem.mem_data_in & (u32::MAX >> (32 - 8 * size));
+            }
+        }
 
-                    self.data_mem.insert(which_word, self.exmem.mem_data_in);
-                } else {
-                    panic!("Invalid funct3 on a Store instruction!")
-                }
+        // and atomics finish their read-modify-write, once they're leaving MEM. (see atomic.rs)
+        if self.exmem.opcode == OPCODE_ATOMIC && self.memwb.wb_stall == 0 {
+            let address = self.exmem.alu_output;
+            if let Some(value) = logic.memory.amo_write {
+                self.mem.write_word(address, value);
+            }
+            match funct5(self.exmem.instruction) {
+                FUNCT5_LR => self.reservation = Some(address),
+                FUNCT5_SC => self.reservation = None,
+                _ => {}
             }
         }
 
         //EX-MEM Latch
-        //only update if not stalled!
-        if self.exmem.mem_stall != 1 {
+        //only update if not stalled! a bubble goes in instead, if there's supposed to be one.
+        if self.exmem.mem_stall == 2 {
+            self.exmem.bubble();
+        } else if self.exmem.mem_stall != 1 {
+            self.exmem.base_pc = self.idex.base_pc;
             self.exmem.added_pc = self.idex.added_pc;
             self.exmem.alu_output = logic.execute.alu_output;
             self.exmem.mem_data_in = logic.execute.formux_r2;
@@ -205,11 +231,32 @@ impl Registers {
             self.exmem.funct3 = self.idex.funct3;
 
             self.exmem.instruction = self.idex.instruction;
+            self.exmem.fflags = logic.execute.fflags;
+            self.exmem.from_fpu = false;
+
+            // FP adds, multiplies and divides go into the FPU instead, and MEM gets a bubble. (see fpu.rs)
+            if let Some((unit, latency)) = fpu_unit(self, self.idex.instruction) {
+                self.fpu.issue(FpuEntry {
+                    latch: self.exmem,
+                    unit,
+                    stage: 2,
+                    latency,
+                });
+                self.exmem.bubble();
+            }
+        }
+        // (EX was held for it, if there was anything else for EX-MEM. see fpu_collision)
+        if let Some(done) = fpu_done {
+            self.exmem = done.latch;
+            self.exmem.from_fpu = true;
         }
 
         // ID-EX Latch
-        //only update if not  stalled!
-        if self.idex.ex_stall != 1 {
+        //only update if not  stalled! a bubble goes in instead, if there's supposed to be one.
+        if self.idex.ex_stall == 2 {
+            self.idex.bubble();
+            self.ex_started = false;
+        } else if self.idex.ex_stall != 1 {
             self.idex.base_pc = self.ifid.base_pc;
             self.idex.added_pc = self.ifid.added_pc;
 
@@ -223,21 +270,37 @@ impl Registers {
             self.idex.funct7 = logic.decode.decode_funct7;
             self.idex.r2_index = logic.decode.decode_r2;
             self.idex.r1_index = logic.decode.decode_r1;
+            self.idex.r3_index = logic.decode.decode_r3;
+            self.idex.r3_data = logic.decode.regmem_r3;
 
-            self.idex.instruction = self.ifid.instruction;
+            self.idex.instruction = logic.decode.expanded;
+            self.idex.predicted_pc = self.ifid.predicted_pc;
+            self.ex_started = false;
+        } else {
+            // held in EX behind a cache miss, or while it multiplies or divides. Whatever got forwarded to it has to be kept,
+            // since the instruction it came from might move on and stop forwarding it.
+            self.idex.r1_data = logic.execute.formux_r1;
+            self.idex.r2_data = logic.execute.formux_r2;
+            self.idex.r3_data = logic.execute.formux_r3;
         }
 
         // Register Memory. Write to it.
-        assert!(old_rd < 0b100000); //Register indices are always 5 bits or less.
-        if (old_rd != 0) {
+        assert!(old_rd < 2 * FP_REGISTERS); //Register indices are always 5 bits or less, +32 for the FP ones.
+        if old_rd >= FP_REGISTERS {
+            self.fp_reg_mem[(old_rd - FP_REGISTERS) as usize] = logic.writeback.wb_data;
+        } else if (old_rd != 0) {
             self.reg_mem[old_rd as usize] = logic.writeback.wb_data;
         }
 
         // IF-ID latch. Transfer
-        if self.ifid.id_stall != 1 {
+        if self.ifid.id_stall == 2 {
+            self.ifid.bubble();
+        } else if self.ifid.id_stall != 1 {
             self.ifid.base_pc = self.pc;
             self.ifid.added_pc = logic.fetch.pcadder_out;
             self.ifid.instruction = logic.fetch.instruction_out;
+            self.ifid.predicted_pc = logic.fetch.pcmux_out;
+            self.fetch_address = None;
         }
 
         // Program Counter. simply updates itself, unless stalling
@@ -258,11 +321,14 @@ impl Logic {
             //JAL, JALR, store (pc+4) into RD
             self.writeback.wb_used = 2;
             self.writeback.wb_data = state.memwb.added_pc;
-        } else if state.memwb.opcode == 0b0000011 {
-            // LB, LH, LW, LBU, LHU. The load instructions all load the memory read data into RD
+        } else if matches!(
+            state.memwb.opcode,
+            0b0000011 | OPCODE_ATOMIC | OPCODE_LOAD_FP
+        ) {
+            // LB, LH, LW, LBU, LHU. The load instructions all load the memory read data into RD. so do the atomics, and FLW
             self.writeback.wb_used = 1;
             self.writeback.wb_data = state.memwb.mem_data_out;
-        } else if state.memwb.opcode == 0b1100011 || state.memwb.opcode == 0b0100011 {
+        } else if matches!(state.memwb.opcode, 0b1100011 | 0b0100011 | OPCODE_STORE_FP) {
             // Branches & Stores. These write nothing to RD at all!
             self.writeback.wb_used = 3;
             self.writeback.wb_data = 0xdeadbeef; //special value representing null
@@ -292,12 +358,13 @@ impl Logic {
             self.execute.formux_r1 = state.idex.r1_data;
         } else {
             // Forwarding might be needed. Check RD index of instructions further along.
-            if state.idex.r1_index == state.exmem.rd_index {
+            // (paths that are switched off never match. the hazard unit stalled until the register file had it instead)
+            if state.forwarding.ex_ex && state.idex.r1_index == state.exmem.rd_index {
                 //need to EX-EX forward!
                 //println!("EX-EX forward!");
                 self.execute.r1_forwarded = 1;
-                self.execute.formux_r1 = state.exmem.alu_output;
-            } else if state.idex.r1_index == state.memwb.rd_index {
+                self.execute.formux_r1 = exmem_result(state);
+            } else if state.forwarding.mem_ex && state.idex.r1_index == state.memwb.rd_index {
                 //need to MEM-EX forward!
                 //println!("EX-MEM Forward!");
                 self.execute.r1_forwarded = 2;
@@ -323,15 +390,15 @@ impl Logic {
         } else {
             // Forwarding might be needed. Check RD index of instructions further along.
             //println!("r2:{}  rd:{}", state.idex.r2_index, state.memwb.rd_index);
-            if state.idex.r2_index == state.exmem.rd_index {
+            if state.forwarding.ex_ex && state.idex.r2_index == state.exmem.rd_index {
                 //need to EX-EX forward!
                 self.execute.r2_forwarded = 1;
-                self.execute.formux_r2 = state.exmem.alu_output;
-            } else if state.idex.r2_index == state.memwb.rd_index {
+                self.execute.formux_r2 = exmem_result(state);
+            } else if state.forwarding.mem_ex && state.idex.r2_index == state.memwb.rd_index {
                 //need to MEM-EX forward!
                 //println!("yes!");
                 self.execute.r2_forwarded = 2;
-                self.execute.formux_r2 = state.memwb.alu_output;
+                self.execute.formux_r2 = self.writeback.wb_data;
             } else {
                 // no forwarding needed!
                 self.execute.r2_forwarded = 0;
@@ -339,6 +406,21 @@ impl Logic {
             }
         }
 
+        // and R3, which only the fused multiply-adds have. it's always an FP register, so it's never 0 when there is one.
+        if state.idex.r3_index == 0 {
+            self.execute.r3_forwarded = 0;
+            self.execute.formux_r3 = state.idex.r3_data;
+        } else if state.forwarding.ex_ex && state.idex.r3_index == state.exmem.rd_index {
+            self.execute.r3_forwarded = 1;
+            self.execute.formux_r3 = exmem_result(state);
+        } else if state.forwarding.mem_ex && state.idex.r3_index == state.memwb.rd_index {
+            self.execute.r3_forwarded = 2;
+            self.execute.formux_r3 = self.writeback.wb_data;
+        } else {
+            self.execute.r3_forwarded = 0;
+            self.execute.formux_r3 = state.idex.r3_data;
+        }
+
         // R1-PC Multiplexor.
         // Decides if Operand 1 is the R1 value or the Program Count.
         // Uses Instruction Type to decide.
@@ -369,15 +451,11 @@ impl Logic {
         // while ALU calculates jump address.
 
         // Checks based off the 3bit funct3-code and R1 & R2, if the instruction is a Branch, whether a branch happens or not.
-        self.execute.branch_taken = match state.idex.funct3 {
-            0b000 => self.execute.formux_r1 == self.execute.formux_r2, //BEQ
-            0b001 => self.execute.formux_r1 != self.execute.formux_r2, //BNE
-            0b100 => (self.execute.formux_r1 as i32) < (self.execute.formux_r2 as i32), //BLT
-            0b101 => (self.execute.formux_r1 as i32) > (self.execute.formux_r2 as i32), //BGE
-            0b110 => self.execute.formux_r1 < self.execute.formux_r2,  //BLTU
-            0b111 => self.execute.formux_r1 > self.execute.formux_r2,  //BGEU
-            _ => false, //not a branching instruction.
-        };
+        self.execute.branch_taken = branch_comparator(
+            state.idex.funct3,
+            self.execute.formux_r1,
+            self.execute.formux_r2,
+        );
 
         if state.idex.opcode != 0b1100011 {
             self.execute.branch_taken = false;
@@ -391,6 +469,20 @@ impl Logic {
             "Out: {}",
             ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32
         ); */
+        // the FP part of the ALU. for the FP adds, multiplies and divides, this is only the first stage of the FPU. (see fpu.rs)
+        let (fp_result, fp_flags) = if is_float(state.idex.opcode) {
+            fp_execute(
+                state.idex.instruction,
+                self.execute.op1,
+                self.execute.op2,
+                self.execute.formux_r3,
+                state.csr.frm(),
+            )
+        } else {
+            (0, 0)
+        };
+        self.execute.fflags = fp_flags;
+
         self.execute.alu_output = match state.idex.opcode {
             0b0110111 => self.execute.op2, //LUI, just put in immediate as is
             0b0010111 => self.execute.op1 + self.execute.op2, //AUIPC, add PC and  shifted Imm, store in RD
@@ -402,6 +494,14 @@ impl Logic {
             0b1100011 => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, //Branches. Perform signed addition between PC and Imm to figure out new PC.
             0b0000011 => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, // LB/LH/LW, add R1 and Imm offset to get source memory address.
             0b0100011 => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, // SB/SH/SW, add R1 and Imm offset to get destination memory address. R2 goes straight to Mem (thru EX-MEM latch).
+            OPCODE_LOAD_FP => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, //FLW, like LW
+            OPCODE_STORE_FP => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, //FSW, like SW
+            opcode if is_float(opcode) => fp_result, //everything else in RV32F. (see float.rs)
+            OPCODE_ATOMIC => self.execute.op1, //LR, SC and the AMOs. the address is just R1, and R2 goes to Mem like a store's.
+            0b0010011 | 0b0110011 if is_bitmanip(state.idex.instruction) => {
+                bitmanip(state.idex.instruction, self.execute.op1, self.execute.op2)
+                //Zba, Zbb and Zbs. (see bitmanip.rs)
+            }
             0b0010011 => match state.idex.funct3 {
                 //Register-Immediate instructions
                 0b000 => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, //ADDI, siggned add R1 and Imm
@@ -422,29 +522,26 @@ impl Logic {
                 0b100 => self.execute.op1 ^ self.execute.op2, //XORI, bitwise exclusive-or on R1 and Imm.
                 0b110 => self.execute.op1 | self.execute.op2, //ORI, bitwise or on R1 and Imm.
                 0b111 => self.execute.op1 & self.execute.op2, //ANDI, bitwise and on R1 and Imm.
-                0b001 => {
-                    if self.execute.op2 > 31 {
-                        panic!()
-                    } else {
-                        self.execute.op1 << self.execute.op2
-                    }
-                } // SLLI, shift R1 left by  shamt ([4-0] of Imm) bits.
+                0b001 => self.execute.op1 << (self.execute.op2 & 0b11111), // SLLI, shift R1 left by  shamt ([4-0] of Imm) bits.
                 0b101 => match self.execute.op2 >> 5 {
                     0b0000000 => self.execute.op1 >> self.execute.op2, //SRLI, shift R1 right logically by shamt bits
                     0b0100000 => {
                         ((self.execute.op1 as i32) >> ((self.execute.op2 - 0b010000000000) as i32))
                             as u32
                     } //SRAI, shift R1 right arithmetically by shamt bits
-                    _ => panic!("Invalid upper Imm. bits for Right Shift Instruction!"),
+                    _ => 0, //illegal, traps once it leaves EX
                 },
-                _ => panic!("funct3-code is bigger than 3 bits! this shouldnt happen!!!"),
+                _ => 0, //can't happen, funct3 is 3 bits
             },
+            0b0110011 if state.idex.funct7 == FUNCT7_MULDIV => {
+                muldiv(state.idex.funct3, self.execute.op1, self.execute.op2) //MUL, DIV, REM and the rest. (see muldiv.rs)
+            }
             0b0110011 => match state.idex.funct3 {
                 //Register-Register instructions
                 0b000 => match state.idex.funct7 {
                     0b0000000 => ((self.execute.op1 as i32) + (self.execute.op2 as i32)) as u32, //ADD
                     0b0100000 => ((self.execute.op1 as i32) - (self.execute.op2 as i32)) as u32, //SUB
-                    _ => panic!("Invalid funct7 for ADD/SUB instruction"),
+                    _ => 0, //illegal, traps once it leaves EX
                 },
                 0b001 => self.execute.op1 << (self.execute.op2 & 0b11111), //SLL, shift left logical. Shift R1 left by the lowest 5 bits of R2
                 0b010 => {
@@ -465,85 +562,183 @@ impl Logic {
                 0b101 => match state.idex.funct7 {
                     0b0000000 => self.execute.op1 >> (self.execute.op2 & 0b11111), //SRL, shift right logical. Shift R1 logically right by the lowest 5 bits or R2
                     0b0100000 => ((self.execute.op1 as i32) >> (self.execute.op2 & 0b11111)) as u32, //SRA, shift right arithmetic.
-                    _ => panic!("Invalid upper Imm. bits for Right Shift Instruction!"),
+                    _ => 0, //illegal, traps once it leaves EX
                 },
                 0b110 => self.execute.op1 | self.execute.op2, //OR, bitwise or
                 0b111 => self.execute.op1 & self.execute.op2, //AND, bitwise and
-                _ => panic!("funct3-code is bigger than 3 bits! this shouldnt happen!!!"),
+                _ => 0,                                       //can't happen, funct3 is 3 bits
             },
-            0 => 0, //NOP Instruction. Does nothing.
-            _ => panic!("Invalid or Unimplemented Instruction!"),
+            0b1110011 => match state.idex.funct3 {
+                //System instructions. They get handled once they leave EX, all the ALU does is pass the operand along.
+                0b001..=0b011 => self.execute.formux_r1, //CSRRW, CSRRS, CSRRC use rs1
+                0b101..=0b111 => state.idex.r1_index as u32, //CSRRWI, CSRRSI, CSRRCI have a 5-bit immediate there instead
+                _ => 0,                                      //ECALL, EBREAK, MRET, WFI
+            },
+            0b0001111 => 0, //FENCE. memory is always in order here, so it does nothing.
+            0 => 0,         //NOP Instruction. Does nothing.
+            _ => 0, //Illegal instruction. It traps once it leaves EX, so the output doesn't matter.
         };
 
         //=================================
         // IF Stage
         //=================================
 
-        //must update PCAdder first.
-        self.fetch.pcadder_out = state.pc + 4;
+        //must update PCAdder first. it adds 2 for a compressed instruction, and 4 for the rest. (see compressed.rs)
+        let word = state.mem.read_word(state.pc);
+        self.fetch.pcadder_out = state.pc + instruction_size(word);
 
         //PCMux: First, check if opcode FROM EX STAGE is Jump, Branching, or neither
         if (state.idex.opcode == 0b1101111 || state.idex.opcode == 0b1100111) {
             self.fetch.jumped = true;
-            self.fetch.pcmux_out = self.execute.alu_output; //initiates jump by setting PC to result of address adition
         } else if (state.idex.opcode == 0b1100011 && self.execute.branch_taken) {
             //if branch taken!
             self.fetch.jumped = true;
-            self.fetch.pcmux_out = self.execute.alu_output; //initiates jump
         } else {
             //if not branch, or branch not taken
             self.fetch.jumped = false;
-            self.fetch.pcmux_out = self.fetch.pcadder_out;
         }
+        //then check if that's where the IF stage went after fetching it. if not, the PC gets put right.
+        // with early branches that's already been worked out for the instr. in ID, one cycle sooner.
+        let early = state.early_branches && state.model == Model::Pipelined;
+        let (resolved, next_pc, predicted_pc) = if early {
+            let resolved = early_branch(state, self);
+            self.decode.branch_resolved = resolved.is_some();
+            let (taken, target) = resolved.unwrap_or((false, 0));
+            self.decode.branch_taken = taken;
+            self.decode.branch_target = target;
+            let next_pc = if taken { target } else { state.ifid.added_pc };
+            (resolved.is_some(), next_pc, state.ifid.predicted_pc)
+        } else {
+            let next_pc = if self.fetch.jumped {
+                self.execute.alu_output //the jump's target, from the address addition
+            } else {
+                state.idex.added_pc
+            };
+            (
+                state.idex.instruction != 0,
+                next_pc,
+                state.idex.predicted_pc,
+            )
+        };
+        self.fetch.redirected = resolved && next_pc != predicted_pc;
+        self.fetch.pcmux_out = if self.fetch.redirected {
+            next_pc
+        } else {
+            self.fetch.pcadder_out
+        };
 
-        if ((state.pc / 4) as usize) >= state.instr_mem.len() {
+        if state.stop.is_some() {
+            //the program has ended. stop fetching, and let whatever is left drain out.
+            self.fetch.jumped = false;
+            self.fetch.redirected = false;
+            self.fetch.pcmux_out = state.pc;
+            self.fetch.instruction_out = 0;
+        } else if state.model != Model::Pipelined && !pipeline_empty(state) {
+            //the non-pipelined models only start the next instruction once the last one is completely done.
+            // a jump or branch in EX still gets to change the PC, it just isn't fetched from until then.
+            if !self.fetch.redirected {
+                self.fetch.pcmux_out = state.pc;
+            }
+            self.fetch.instruction_out = 0;
+        } else if state.pc >= state.text_end {
             //if reached end of program... put in NOPs to let the previous instructions finish.
             self.fetch.instruction_out = 0;
         } else {
-            self.fetch.instruction_out = state.instr_mem[(state.pc / 4) as usize];
+            //a compressed instruction is only the lower half of the word. a 32-bit one can start halfway into a word.
+            self.fetch.instruction_out = if instruction_size(word) == 2 {
+                word & 0xffff
+            } else {
+                word
+            };
+            //branches and jumps predicted taken go straight to their target. (the pipeline is the only
+            // model with anything to gain from it. the others don't fetch until the branch is done anyway.)
+            if !self.fetch.redirected && state.model == Model::Pipelined {
+                let instruction = expand(self.fetch.instruction_out);
+                if let Some(target) = predict(state, state.pc, instruction) {
+                    self.fetch.pcmux_out = target;
+                }
+            }
         }
 
         //==============================
         // ID Stage
         //==============================
 
+        // Expander
+        //compressed instructions get turned into the 32-bit instruction they stand for, first thing.
+        let instruction = expand(state.ifid.instruction);
+        self.decode.expanded = instruction;
+
         // Decoder
         //gets the opcode, r1 index, r2 index, and destination register index out of the instruction, even if they end up being unused.
         //uses bit-wise AND operation on a mask in order to get the desired bits, dividing to rem
 
         // need to get lowest 7 bits out, just use a mask to get (6-0)
-        self.decode.decode_opcode = (state.ifid.instruction & 0b1111111) as u8;
+        self.decode.decode_opcode = (instruction & 0b1111111) as u8;
         // need to get bits (19-15) out. use mask, then shift right all the zero'd bits
-        self.decode.decode_r1 = ((state.ifid.instruction & 0b11111000000000000000) >> 15) as u8;
+        self.decode.decode_r1 = ((instruction & 0b11111000000000000000) >> 15) as u8;
         // need to get bits (24-20) out.
-        self.decode.decode_r2 =
-            ((state.ifid.instruction & 0b1111100000000000000000000) >> 20) as u8;
+        self.decode.decode_r2 = ((instruction & 0b1111100000000000000000000) >> 20) as u8;
         // need to get bits (11-7) out... unless B or S, those have no rd
         let instr_type: InstrT = isa::get_instruction_type(self.decode.decode_opcode);
-        if matches!(instr_type, InstrT::Stype) || matches!(instr_type, InstrT::Stype) {
+        if matches!(instr_type, InstrT::Stype) || matches!(instr_type, InstrT::Btype) {
             //This operation has no register output. discard write to $r0
             self.decode.decode_rd = 0;
         } else {
             //isn't S or B type, needs an rd.
-            self.decode.decode_rd = ((state.ifid.instruction & 0b111110000000) >> 7) as u8;
+            self.decode.decode_rd = ((instruction & 0b111110000000) >> 7) as u8;
         }
 
         // need to get the bits (14-12) out.
-        self.decode.decode_funct3 = ((state.ifid.instruction & 0b111000000000000) >> 12) as u8;
+        self.decode.decode_funct3 = ((instruction & 0b111000000000000) >> 12) as u8;
         //need to get the bits (31-25) out.
         self.decode.decode_funct7 =
-            ((state.ifid.instruction & 0b11111110000000000000000000000000) >> 25) as u8;
+            ((instruction & 0b11111110000000000000000000000000) >> 25) as u8;
+
+        // the FP registers are 32-63 from here on, so nothing else has to know which file a register is in. (see float.rs)
+        // an rs2 that isn't there at all is 0, so nothing waits on it.
+        if let Some((rd_fp, r1_fp, r2_fp)) = fp_registers(instruction) {
+            if rd_fp {
+                self.decode.decode_rd += FP_REGISTERS;
+            }
+            if r1_fp {
+                self.decode.decode_r1 += FP_REGISTERS;
+            }
+            match r2_fp {
+                Some(true) => self.decode.decode_r2 += FP_REGISTERS,
+                Some(false) => {}
+                None => self.decode.decode_r2 = 0,
+            }
+        }
+        // the fused multiply-adds have an rs3, in bits (31-27).
+        self.decode.decode_r3 = if is_fused(self.decode.decode_opcode) {
+            FP_REGISTERS + (instruction >> 27) as u8
+        } else {
+            0
+        };
 
         // Register Memory: Read.
-        assert!(self.decode.decode_r1 < 32 && self.decode.decode_r2 < 32);
-        self.decode.regmem_r1 = state.reg_mem[self.decode.decode_r1 as usize];
-        self.decode.regmem_r2 = state.reg_mem[self.decode.decode_r2 as usize];
+        assert!(
+            self.decode.decode_r1 < 2 * FP_REGISTERS && self.decode.decode_r2 < 2 * FP_REGISTERS
+        );
+        self.decode.regmem_r1 = state.register(self.decode.decode_r1);
+        self.decode.regmem_r2 = state.register(self.decode.decode_r2);
+        self.decode.regmem_r3 = state.register(self.decode.decode_r3);
 
         //secret forwarding! takes care of small data hazard that wouldn't happen in-model.
-        if self.decode.decode_r1 == state.memwb.rd_index {
-            self.decode.regmem_r1 = self.writeback.wb_data;
-        } else if self.decode.decode_r2 == state.memwb.rd_index {
-            self.decode.regmem_r2 = self.writeback.wb_data;
+        // $r0 is never written, so it never gets forwarded. both registers can need it at once.
+        // (this is the register file writing in the first half of the cycle and reading in the second,
+        // so it stays on even when the forwarding paths are off.)
+        if state.memwb.rd_index != 0 {
+            if self.decode.decode_r1 == state.memwb.rd_index {
+                self.decode.regmem_r1 = self.writeback.wb_data;
+            }
+            if self.decode.decode_r2 == state.memwb.rd_index {
+                self.decode.regmem_r2 = self.writeback.wb_data;
+            }
+            if self.decode.decode_r3 == state.memwb.rd_index {
+                self.decode.regmem_r3 = self.writeback.wb_data;
+            }
         }
 
         // Immediates Decoder
@@ -552,23 +747,21 @@ impl Logic {
             self.decode.immediates = 0; //Outputs a useless value. R-Type has no immediates.
         } else if matches!(instr_type, InstrT::Itype) {
             //in this one, simply take the 31st thru 12th bits! they're already where they want to be.
-            self.decode.immediates = ((state.ifid.instruction as i32) >> 20) as u32;
+            self.decode.immediates = ((instruction as i32) >> 20) as u32;
         } else if matches!(instr_type, InstrT::Stype) {
             //(31-25) goes to [11-5],  (11-7) goes to [4-0]. do each separately, then bitwise OR
 
             //                       the (31-25) is converted to signed so that it does an arithmetic right shift
-            self.decode.immediates =
-                ((((state.ifid.instruction & 0b11111110000000000000000000000000) as i32) >> 20)
-                    as u32)
-                    | ((state.ifid.instruction & 0b111110000000) >> 7);
+            self.decode.immediates = ((((instruction & 0b11111110000000000000000000000000) as i32)
+                >> 20) as u32)
+                | ((instruction & 0b111110000000) >> 7);
         } else if matches!(instr_type, InstrT::Btype) {
             //A (31) to [12] ,B (30-25) to [10-5], C (11-8) to [4-1], D (7) to [11]
-            let imm_a: u32 = (((state.ifid.instruction & 0b10000000000000000000000000000000)
-                as i32)
-                >> 19) as u32;
-            let imm_b: u32 = (state.ifid.instruction & 0b01111110000000000000000000000000) >> 20;
-            let imm_c: u32 = (state.ifid.instruction & 0b00000000000000000000111100000000) >> 7;
-            let imm_d: u32 = (state.ifid.instruction & 0b00000000000000000000000010000000) << 4;
+            let imm_a: u32 =
+                (((instruction & 0b10000000000000000000000000000000) as i32) >> 19) as u32;
+            let imm_b: u32 = (instruction & 0b01111110000000000000000000000000) >> 20;
+            let imm_c: u32 = (instruction & 0b00000000000000000000111100000000) >> 7;
+            let imm_d: u32 = (instruction & 0b00000000000000000000000010000000) << 4;
             //println!("{:#b}",imm_a);
             //println!("{:#b}",imm_b);
             //println!("{:#b}",imm_c);
@@ -579,13 +772,13 @@ impl Logic {
         //the wonky shifting just sign-extends the 12-bit Imm preemptively
         } else if matches!(instr_type, InstrT::Utype) {
             //(31-12) goes to [31-12]... so just mask the rest!
-            self.decode.immediates = state.ifid.instruction & 0b11111111111111111111000000000000;
+            self.decode.immediates = instruction & 0b11111111111111111111000000000000;
         } else {
             //only J-type left!  E  (31) to [20], F  (30-21) to [10-1], G  (20) to [11],  H  (19-12) to [19-12]
-            let imm_e: u32 = (state.ifid.instruction & 0b10000000000000000000000000000000) >> 11;
-            let imm_f: u32 = (state.ifid.instruction & 0b01111111111000000000000000000000) >> 20;
-            let imm_g: u32 = (state.ifid.instruction & 0b00000000000100000000000000000000) >> 9;
-            let imm_h: u32 = state.ifid.instruction & 0b00000000000011111111000000000000;
+            let imm_e: u32 = (instruction & 0b10000000000000000000000000000000) >> 11;
+            let imm_f: u32 = (instruction & 0b01111111111000000000000000000000) >> 20;
+            let imm_g: u32 = (instruction & 0b00000000000100000000000000000000) >> 9;
+            let imm_h: u32 = instruction & 0b00000000000011111111000000000000;
             //println!("{:#b}", imm_e);
             //println!("{:#b}", imm_f);
             //println!("{:#b}", imm_g);
@@ -600,124 +793,52 @@ impl Logic {
         // MEM Stage
         // =========================
 
-        //Reading Data Memory is the only thing that happens in this stage.
-        //check if instruction is a load.
-        if state.exmem.opcode == 0b0000011 {
-            let which_word = (state.exmem.alu_output) / 4;
-            let align = state.exmem.alu_output % 4;
-            if state.data_mem.contains_key(&which_word) {
-                if state.exmem.funct3 == 0b000 {
-                    //Load Byte, need to sign extend.
-                    self.memory.mem_data_out = match align {
-                        0 => {
-                            ((((state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000000000000000000011111111)
-                                as i32)
-                                << 24)
-                                >> 24) as u32
-                        }
-                        1 => {
-                            ((((state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000000000001111111100000000)
-                                as i32)
-                                << 16)
-                                >> 24) as u32
-                        }
-                        2 => {
-                            ((((state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000111111110000000000000000)
-                                as i32)
-                                << 8)
-                                >> 24) as u32
-                        }
-                        3 => {
-                            (((state.data_mem.get(&which_word).unwrap()
-                                & 0b11111111000000000000000000000000)
-                                as i32)
-                                >> 24) as u32
-                        }
-                        _ => panic!("Align is greater than 3!"),
-                    }
-                } else if state.exmem.funct3 == 0b001 {
-                    //Load Half-Word, need to sign extend.
-                    self.memory.mem_data_out = match align {
-                        0 => {
-                            ((((state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000000000001111111111111111)
-                                as i32)
-                                << 16)
-                                >> 16) as u32
-                        }
-                        1 => panic!("Misaligned Load!"),
-                        2 => {
-                            (((state.data_mem.get(&which_word).unwrap()
-                                & 0b11111111111111110000000000000000)
-                                as i32)
-                                >> 16) as u32
-                        }
-                        3 => panic!("Misaligned Load!"),
-                        _ => panic!("Align is greater than 3!"),
-                    }
-                } else if state.exmem.funct3 == 0b010 {
-                    //Load Word.
-                    if align > 3 {
-                        panic!("Align is greater than 3!")
-                    }
-                    if align < 0 {
-                        panic!("Misaligned Load!")
-                    }
-                    self.memory.mem_data_out = *state.data_mem.get(&which_word).unwrap();
-                } else if state.exmem.funct3 == 0b100 {
-                    //Load Byte Unsigned. No sign extend
-                    self.memory.mem_data_out = match align {
-                        0 => {
-                            (state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000000000000000000011111111)
-                        }
-                        1 => {
-                            (state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000000000001111111100000000)
-                                >> 8
-                        }
-                        2 => {
-                            (state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000111111110000000000000000)
-                                >> 16
-                        }
-                        3 => {
-                            (state.data_mem.get(&which_word).unwrap()
-                                & 0b11111111000000000000000000000000)
-                                >> 24
-                        }
-                        _ => panic!("Align is greater than 3!"),
-                    }
-                } else if state.exmem.funct3 == 0b101 {
-                    //Load Half Word Unsigned. No sign extend.
-                    self.memory.mem_data_out = match align {
-                        0 => {
-                            (state.data_mem.get(&which_word).unwrap()
-                                & 0b00000000000000001111111111111111)
-                        }
-                        1 => panic!("Misaligned Load!"),
-                        2 => {
-                            (state.data_mem.get(&which_word).unwrap()
-                                & 0b11111111111111110000000000000000)
-                                >> 16
-                        }
-                        3 => panic!("Misaligned Load!"),
-                        _ => panic!("Align is greater than 3!"),
-                    }
+        //Reading Memory is the only thing that happens in this stage.
+        //check if instruction is a load. (or an atomic, which reads and then maybe writes.)
+        self.memory.amo_write = None;
+        if state.exmem.opcode == OPCODE_ATOMIC {
+            // atomics always go to a word of memory. ones that are misaligned or aimed at a device trap instead.
+            let address = state.exmem.alu_output;
+            let (result, write) = atomic(
+                funct5(state.exmem.instruction),
+                state.mem.read_word(address),
+                state.exmem.mem_data_in,
+                state.reservation == Some(address),
+            );
+            self.memory.mem_data_out = result;
+            self.memory.amo_write = write;
+        } else if matches!(state.exmem.opcode, 0b0000011 | OPCODE_LOAD_FP) {
+            let address = state.exmem.alu_output;
+            //loads from the MMIO regions come from the devices instead.
+            let read = |size| {
+                if state.memory_map.is_mmio(address) {
+                    state.bus.read(address, size)
                 } else {
-                    panic!("Invalid funct3 for a Load Instruction!")
+                    state.mem.read(address, size)
                 }
-            } else {
-                //if this value has never been accessed before, it is trivially zero!
-                self.memory.mem_data_out = 0;
-            }
-
+            };
+            // misaligned loads trap once they leave EX, so what they load doesn't matter.
+            self.memory.mem_data_out = match state.exmem.funct3 {
+                0b000 => read(1) as i8 as u32,  //Load Byte, sign extended
+                0b001 => read(2) as i16 as u32, //Load Half-Word, sign extended
+                0b010 => read(4),               //Load Word
+                0b100 => read(1),               //Load Byte Unsigned
+                0b101 => read(2),               //Load Half-Word Unsigned
+                _ => 0, //Invalid funct3, an illegal instruction. It traps once it leaves EX.
+            };
+        }
+        if matches!(
+            state.exmem.opcode,
+            0b0000011 | OPCODE_ATOMIC | OPCODE_LOAD_FP
+        ) {
             //read next instr in EX-stage; check if MEM-MEM forwarding will be needed next cycle.
             // if the next instruction is a Store AND it stores from the same register that this instr. loads to...
-            if state.idex.opcode == 0b0100011 && state.exmem.rd_index == state.idex.r2_index {
+            // ($r0 never gets written, like with `amoadd.w zero, ...`, so storing it doesn't need anything forwarded.)
+            if state.forwarding.mem_mem
+                && matches!(state.idex.opcode, 0b0100011 | OPCODE_STORE_FP)
+                && state.exmem.rd_index != 0
+                && state.exmem.rd_index == state.idex.r2_index
+            {
                 self.memory.memmem_fwd = true;
                 self.memory.memmem_data = self.memory.mem_data_out;
                 self.memory.memmem_timer = 1;
@@ -739,69 +860,399 @@ impl Logic {
 }
 
 pub fn step(state: &mut Registers, logic: &mut Logic) {
-    //Checks if Stalling or Bubbling is needed
-    //Check if a jump is performed! those always need a stall on the NEXT step
-    let must_jump_stall_next = logic.fetch.jumped;
-
-    //if there's a Load in EX stage and an ALU instruction in the ID stage, using the same register...
+    //Check if a jump is performed! the instruction that goes into EX-MEM now is the one that did it.
+    let jumped = logic.fetch.jumped;
 
+    count_stats(state, logic);
+    train_predictor(state, logic);
     state.update(logic);
 
-    //always do this inbetween!!!that way the right instructions get stalled/bubbled, but their logic is not allowed to propagate.
-    if must_jump_stall_next {
-        //If there is a jump, need to bubble IF and ID of now... so the ID and EX of next step!
-        logic.pc_stall = false;
-        state.ifid.id_stall = 2; //bubble flag  on
-        state.ifid.bubble();
+    //the stall and bubble flags were for that clock edge, and it's happened now.
+    logic.pc_stall = false;
+    state.ifid.id_stall = 0;
+    state.idex.ex_stall = 0;
+    state.exmem.mem_stall = 0;
+    state.memwb.wb_stall = 0;
 
+    logic.update(state);
+
+    //interrupts, traps, ECALLs, CSR instructions, and instructions that end the program and throw away the ones behind them,
+    // change the pipeline. so the wires need redoing. traps go first, since a faulting instruction does nothing else.
+    update_interrupts(state);
+    let handled_trap = handle_traps(state, jumped);
+    accrue_fflags(state);
+    let handled_system = handle_csr(state) | handle_ecall(state, logic);
+    if check_termination(state) || handled_trap || handled_system {
+        logic.update(state);
+    }
+    let memory_stall = memory_timing(state);
+    let execute_stall = execute_timing(state) || fpu_collision(state);
+    let fetch_stall = fetch_timing(state);
+
+    //Checks if Stalling or Bubbling is needed on the NEXT clock edge.
+    if memory_stall {
+        //a load or store that's still waiting on the caches holds MEM, and everything behind it. WB gets a bubble.
+        logic.pc_stall = true;
+        state.ifid.id_stall = 1;
+        state.idex.ex_stall = 1;
+        state.exmem.mem_stall = 1;
+        state.memwb.wb_stall = 2;
+        add_bubbles(state, 1);
+        return;
+    }
+    if execute_stall {
+        //a multiply or divide that isn't done yet holds EX, and everything behind it. MEM gets a bubble.
+        // (so does anything that would get to MEM at the same time as an FP instruction coming out of the FPU.)
+        // with early branches, a branch waiting in ID doesn't redirect anything until it can move on.
+        logic.pc_stall = true;
+        state.ifid.id_stall = 1;
+        state.idex.ex_stall = 1;
+        state.exmem.mem_stall = 2;
+        add_bubbles(state, 1);
+        return;
+    }
+    // (with one instruction at a time there's never anything behind a jump, or anything for it to wait on.)
+    if state.model != Model::Pipelined {
+        if fetch_stall {
+            stall_fetch(state, logic);
+        }
+        return;
+    }
+    // with early branches, the jump or branch is the instr. in ID. if it has to wait for a register,
+    // the redirect doesn't happen, and it gets resolved again once it's done waiting.
+    let hazard = check_data_hazard(state, logic);
+    if logic.fetch.redirected && state.early_branches && !hazard {
+        //If a jump or branch in ID was mispredicted, only the instruction in IF is on the wrong path.
+        state.ifid.id_stall = 2; //bubble flag on
+        state.fetch_address = None; //a fetch still waiting on the instruction cache is abandoned
+        add_bubbles(state, 1);
+    } else if logic.fetch.redirected && !state.early_branches {
+        //If a jump or branch was mispredicted, the instructions in IF and ID are on the wrong path. bubble them both.
+        state.ifid.id_stall = 2; //bubble flag  on
         state.idex.ex_stall = 2; //bubble flag on
-        state.idex.bubble();
+        state.fetch_address = None;
+        add_bubbles(state, 2);
+    } else if hazard {
+        //If there is a data hazard (like LOAD-ALU), need to stall IF and ID,  and bubble EX.
+        // this gets checked again every cycle, until the value can be forwarded or read from the register file.
+        logic.pc_stall = true; //freezes the PC
+        state.ifid.id_stall = 1; //stall flag on
+        state.idex.ex_stall = 2; //bubble flag on
+        add_bubbles(state, 1);
+    } else if fetch_stall {
+        stall_fetch(state, logic);
+    }
+}
 
-        state.exmem.mem_stall = 0;
-        state.memwb.wb_stall = 0;
-    /* } else if must_load_alu_stall {
-    //If there is a load-ALU stall, need to stall IF and ID,  and bubble EX.
-    logic.pc_stall = true; //freezes the PC
-    state.ifid.id_stall = 1; //stall flag on
-    state.idex.ex_stall = 2; //bubble flag on
-    state.idex.bubble();
+//an instruction cache miss. the PC waits, and ID gets bubbles until the instruction is there.
+// (the rest of the pipeline keeps going.)
+fn stall_fetch(state: &mut Registers, logic: &mut Logic) {
+    logic.pc_stall = true;
+    state.ifid.id_stall = 2;
+    add_bubbles(state, 1);
+}
 
-    state.exmem.mem_stall = 0;
-    state.memwb.wb_stall = 0; */
-    } else {
-        logic.pc_stall = false;
-        state.ifid.id_stall = 0;
-        state.idex.ex_stall = 0;
-        state.exmem.mem_stall = 0;
-        state.memwb.wb_stall = 0;
+//Starts the cache access for a load or store that just got to MEM, or counts down the one that's going on.
+// Returns true if it needs MEM for another cycle.
+fn memory_timing(state: &mut Registers) -> bool {
+    if state.mem_wait > 0 {
+        state.mem_wait -= 1;
+    } else if !state.caches.is_empty()
+        && matches!(
+            state.exmem.opcode,
+            0b0000011 | 0b0100011 | OPCODE_ATOMIC | OPCODE_LOAD_FP | OPCODE_STORE_FP
+        )
+        && !state.memory_map.is_mmio(state.exmem.alu_output)
+    {
+        //(MMIO isn't cached.) atomics other than LR count as writes, since the line has to be there to write to.
+        let write = matches!(state.exmem.opcode, 0b0100011 | OPCODE_STORE_FP)
+            || (state.exmem.opcode == OPCODE_ATOMIC && !is_load_reserved(state.exmem.instruction));
+        let address = state.exmem.alu_output;
+        let cycles = access(&mut state.caches, state.memory_latency, address, write);
+        state.mem_wait = cycles.saturating_sub(1); //the first cycle is the normal MEM cycle
     }
-
-    logic.update(state);
+    state.mem_wait > 0
 }
 
-//checks if a LOAD-ALU hazard is going to happen.
-pub fn check_load_alu(state: &mut Registers, logic: &mut Logic) -> bool {
-    //EX instr. needs to be a load.
-    if state.idex.opcode != 0b0000011 {
+//Starts the multiply or divide that just got to EX, or counts down the one that's going on.
+// Returns true if it needs EX for another cycle. (the single-cycle model's one cycle covers all of it.)
+fn execute_timing(state: &mut Registers) -> bool {
+    if !is_muldiv(state.idex.opcode, state.idex.funct7) || state.model == Model::SingleCycle {
+        state.ex_wait = 0;
         return false;
     }
-    //ID instr. needs to use the ALU. NOP does not.
-    if logic.decode.decode_opcode == 0 {
-        return false;
+    if !state.ex_started {
+        let latency = if is_divide(state.idex.funct3) {
+            state.latencies.div
+        } else {
+            state.latencies.mul
+        };
+        state.ex_started = true;
+        state.ex_wait = latency.saturating_sub(1); //the first cycle is the normal EX cycle
+    } else if state.ex_wait > 0 {
+        state.ex_wait -= 1;
     }
+    state.ex_wait > 0
+}
 
-    //if the $r being loaded to is $r0 for some reason, this isnt needed.
-    if state.idex.rd_index == 0 {
+//Starts the instruction cache access for the instruction IF is fetching, or counts down the one that's going on.
+// Returns true if IF needs another cycle to get it. (the single-cycle model's one cycle covers any miss.)
+fn fetch_timing(state: &mut Registers) -> bool {
+    let fetching = state.stop.is_none()
+        && (state.model == Model::Pipelined || pipeline_empty(state))
+        && state.pc < state.text_end;
+    let Some(icache) = &mut state.icache else {
+        return false;
+    };
+    if !fetching {
+        state.fetch_wait = 0;
         return false;
     }
+    if state.fetch_address != Some(state.pc) {
+        let mut cycles = access(
+            std::slice::from_mut(icache),
+            state.icache_miss_penalty,
+            state.pc,
+            false,
+        );
+        // a 32-bit instruction that starts in the last two bytes of a line needs the next line too.
+        let last = state.pc + instruction_size(state.mem.read_word(state.pc)) - 1;
+        if last / icache.config.line_size != state.pc / icache.config.line_size {
+            cycles += access(
+                std::slice::from_mut(icache),
+                state.icache_miss_penalty,
+                last,
+                false,
+            );
+        }
+        state.fetch_address = Some(state.pc);
+        state.fetch_wait = cycles.saturating_sub(1); //the first cycle is the normal IF cycle
+    } else if state.fetch_wait > 0 {
+        state.fetch_wait -= 1;
+    }
+    if state.model == Model::SingleCycle {
+        state.fetch_wait = 0;
+    }
+    state.fetch_wait > 0
+}
+
+//a register's value, including the write the instruction in MEM-WB is about to make.
+pub fn read_register(state: &Registers, logic: &Logic, index: u8) -> u32 {
+    if index != 0 && state.memwb.rd_index == index {
+        logic.writeback.wb_data
+    } else {
+        state.reg_mem[index as usize]
+    }
+}
+
+//Compares R1 and R2 for a branch, by its funct3. false if it isn't a branching funct3.
+pub fn branch_comparator(funct3: u8, r1: u32, r2: u32) -> bool {
+    match funct3 {
+        0b000 => r1 == r2,                   //BEQ
+        0b001 => r1 != r2,                   //BNE
+        0b100 => (r1 as i32) < (r2 as i32),  //BLT
+        0b101 => (r1 as i32) >= (r2 as i32), //BGE
+        0b110 => r1 < r2,                    //BLTU
+        0b111 => r1 >= r2,                   //BGEU
+        _ => false,                          //not a branching instruction.
+    }
+}
+
+//The early branch unit: a comparator and target adder in ID, for when branches are resolved there.
+// Returns whether the branch or jump in IF-ID is taken and where it goes, or None if it isn't one,
+// or if it needs a register that isn't ready yet (the hazard unit is stalling it until it is).
+// The registers come from the register file, or get forwarded from EX-MEM and MEM-WB into ID.
+pub fn early_branch(state: &Registers, logic: &Logic) -> Option<(bool, u32)> {
+    let instr = expand(state.ifid.instruction);
+    let pc = state.ifid.base_pc;
+    let r1 = ((instr >> 15) & 0b11111) as u8;
+    let r2 = ((instr >> 20) & 0b11111) as u8;
+    match instr & 0b1111111 {
+        0b1101111 => Some((true, pc.wrapping_add(target_offset(instr)))), //JAL
+        0b1100111 => {
+            //JALR
+            let offset = ((instr as i32) >> 20) as u32;
+            Some((
+                true,
+                id_operand(state, logic, r1)?.wrapping_add(offset) & !1,
+            ))
+        }
+        0b1100011 => {
+            let funct3 = ((instr >> 12) & 0b111) as u8;
+            let taken = branch_comparator(
+                funct3,
+                id_operand(state, logic, r1)?,
+                id_operand(state, logic, r2)?,
+            );
+            Some((taken, pc.wrapping_add(target_offset(instr))))
+        }
+        _ => None,
+    }
+}
 
-    //if the $r being loaded to isnt being used in the next instr. this isnt needed.
-    if state.idex.rd_index != logic.decode.decode_r1
-        && state.idex.rd_index != logic.decode.decode_r2
+//a register's value for the early branch unit, or None if it isn't available in ID yet.
+fn id_operand(state: &Registers, logic: &Logic, index: u8) -> Option<u32> {
+    if index == 0 {
+        Some(0)
+    } else if state.idex.rd_index == index
+        || (index == 10 && state.idex.instruction == ECALL)
+        || state.fpu.writes(index).is_some()
     {
-        return false;
+        None //still being worked out in EX, or the FPU. (or a syscall's return value, see below)
+    } else if state.exmem.rd_index == index {
+        //EX-MEM to ID forwarding, which is the EX-EX path's wire, so it's only there if that is.
+        // a load's value doesn't exist until the end of MEM, though. (nor an atomic's)
+        if !state.forwarding.ex_ex
+            || state.exmem.opcode == 0b0000011
+            || state.exmem.opcode == OPCODE_ATOMIC
+        {
+            None
+        } else {
+            Some(exmem_result(state))
+        }
+    } else {
+        Some(read_register(state, logic, index))
+    }
+}
+
+//what the instruction in EX-MEM is going to write to its rd: the ALU's result, or the return address for jumps.
+pub fn exmem_result(state: &Registers) -> u32 {
+    if state.exmem.opcode == 0b1101111 || state.exmem.opcode == 0b1100111 {
+        state.exmem.added_pc
+    } else {
+        state.exmem.alu_output
+    }
+}
+
+//bookkeeping for the cycle that is about to happen. call before the state updates.
+fn count_stats(state: &mut Registers, logic: &Logic) {
+    if is_cycle(state) {
+        state.stats.cycles += 1;
+        state.csr.mcycle = state.csr.mcycle.wrapping_add(1);
+        state.bus.tick();
+    }
+    if logic.pc_stall {
+        state.stats.stall_cycles += 1;
+    }
+
+    //whatever is in the MEM-WB latch gets written back this cycle. bubbles are all zeroes.
+    if state.memwb.instruction != 0 {
+        state.stats.retired += 1;
+        state.csr.minstret = state.csr.minstret.wrapping_add(1);
+    }
+
+    //loads, stores and atomics in the MEM stage
+    if matches!(
+        state.exmem.opcode,
+        0b0000011 | 0b0100011 | OPCODE_ATOMIC | OPCODE_LOAD_FP | OPCODE_STORE_FP
+    ) {
+        state.stats.touched.insert(state.exmem.alu_output / 4);
+    }
+}
+
+//Whether the step that is about to happen is a clock cycle of the model being run.
+// The non-pipelined models use the same stages, with only one instruction in them at a time, so each step
+// is the one stage that instruction is in (or IF, when the pipeline is empty and the next one is being fetched).
+// The single-cycle model does all of them in one cycle, and the multi-cycle one skips the stages it doesn't need.
+fn is_cycle(state: &Registers) -> bool {
+    let fetching = pipeline_empty(state) && state.stop.is_none() && state.pc < state.text_end;
+    match state.model {
+        Model::Pipelined => true,
+        Model::SingleCycle => fetching,
+        Model::MultiCycle => {
+            let needs_mem = |opcode| {
+                matches!(
+                    opcode,
+                    0b0000011 | 0b0100011 | OPCODE_ATOMIC | OPCODE_LOAD_FP | OPCODE_STORE_FP
+                )
+            };
+            //stores and branches don't write rd
+            let needs_wb = |opcode| !matches!(opcode, 0b0100011 | 0b1100011 | OPCODE_STORE_FP);
+            fetching
+                || state.ifid.instruction != 0
+                || state.idex.instruction != 0
+                || !state.fpu.is_empty() //the FP instruction's cycles after EX
+                || (state.exmem.instruction != 0 && needs_mem(state.exmem.opcode))
+                || (state.memwb.instruction != 0 && needs_wb(state.memwb.opcode))
+        }
     }
+}
 
-    // if all above are true, then Load-ALU is needed!
-    return true;
+//counts bubbles put into the pipeline. The non-pipelined models never have anything behind
+// the current instruction to throw away, so they don't have any.
+pub fn add_bubbles(state: &mut Registers, count: u64) {
+    if state.model == Model::Pipelined {
+        state.stats.bubbles += count;
+    }
+}
+
+//lets the wiring catch up to the current state without clocking anything.
+// needed before the very first step, so the PC mux starts out pointing at the right place.
+// The first fetch starts then too, so it goes through the instruction cache like the rest.
+pub fn settle(state: &mut Registers, logic: &mut Logic) {
+    logic.update(state);
+    if fetch_timing(state) {
+        stall_fetch(state, logic);
+    }
+}
+
+//The hazard detection unit. Checks if the instruction in ID would get to EX before a value it reads
+// can be forwarded to it, in which case it has to wait in ID.
+// With every forwarding path on, that only happens right behind a load: a LOAD-ALU hazard.
+// (or behind an FP instruction that's still in the FPU, which adds hazards of its own. see fpu.rs)
+pub fn check_data_hazard(state: &Registers, logic: &Logic) -> bool {
+    //only the registers the ID instr. actually reads count. the r2 bits of an I-type are its immediate.
+    let (uses_r1, uses_r2) = match isa::get_instruction_type(logic.decode.decode_opcode) {
+        InstrT::Rtype | InstrT::Btype | InstrT::Stype => (true, true),
+        InstrT::Itype => (true, false),
+        _ => (false, false),
+    };
+    let is_store = matches!(logic.decode.decode_opcode, 0b0100011 | OPCODE_STORE_FP);
+    let forwarding = state.forwarding;
+    let idex_to_fpu = fpu_unit(state, state.idex.instruction).is_some();
+
+    let must_wait = |reg: u8, store_data: bool| {
+        if reg == 0 {
+            false
+        } else if state.idex.rd_index == reg && idex_to_fpu {
+            //written by an FP instr. in EX that goes into the FPU next, so it won't be in MEM for a while.
+            true
+        } else if let Some(remaining) = state.fpu.writes(reg) {
+            //written by an FP instr. in the FPU. it can be forwarded EX-EX, once it's coming out as this one goes into EX.
+            remaining > 1 || !forwarding.ex_ex
+        } else if reg == 10 && state.idex.instruction == ECALL {
+            //an ECALL in EX. it doesn't have an rd until it's handled in EX-MEM (see syscall.rs), but then it writes
+            // the syscall's return value to a0 like an ALU instr. would, so it gets forwarded the same way.
+            !forwarding.ex_ex
+        } else if state.idex.rd_index == reg {
+            //written by the instr. in EX, which will be in MEM by the time this one is in EX.
+            if matches!(
+                state.idex.opcode,
+                0b0000011 | OPCODE_ATOMIC | OPCODE_LOAD_FP
+            ) {
+                // a load's (or atomic's) value only exists after MEM. the exception is a store's data, which isn't needed
+                // until the store's own MEM stage, and MEM-MEM forwarding can get it there.
+                !(store_data && forwarding.mem_mem)
+            } else {
+                !forwarding.ex_ex
+            }
+        } else if state.exmem.rd_index == reg {
+            //written by the instr. in MEM, which will be in WB by then.
+            !forwarding.mem_ex
+        } else {
+            //the instr. in WB writes the register file before ID reads it.
+            false
+        }
+    };
+    // a branch resolved in ID needs its registers a whole stage sooner, so it also waits on anything
+    // still in EX, and on loads still in MEM.
+    let early_wait = state.early_branches
+        && state.model == Model::Pipelined
+        && matches!(logic.decode.decode_opcode, 0b1100011 | 0b1100111)
+        && early_branch(state, logic).is_none();
+    early_wait
+        || (uses_r1 && must_wait(logic.decode.decode_r1, false))
+        || (uses_r2 && must_wait(logic.decode.decode_r2, is_store))
+        || must_wait(logic.decode.decode_r3, false)
+        || fpu_hazard(state, logic)
 }
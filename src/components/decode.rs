@@ -6,6 +6,7 @@ pub struct IFIDLatch {
     pub base_pc: u32,
     pub added_pc: u32,
     pub instruction: u32,
+    pub predicted_pc: u32, //where the IF stage went next, after fetching this
 
     pub id_stall: u8, //stall, bubble, or neither?
 }
@@ -15,6 +16,7 @@ impl IFIDLatch {
         self.base_pc = 0;
         self.added_pc = 0;
         self.instruction = 0;
+        self.predicted_pc = 0;
     }
 }
 
@@ -23,13 +25,21 @@ impl IFIDLatch {
 pub struct IDLogic {
     pub decode_r1: u8,
     pub decode_r2: u8,
+    pub decode_r3: u8, //only the fused multiply-adds have one. 0 for the rest
     pub decode_opcode: u8,
     pub decode_rd: u8,
     pub decode_funct3: u8,
     pub decode_funct7: u8,
+    pub expanded: u32, //the instruction, or the 32-bit one a compressed instruction stands for. (see compressed.rs)
 
     pub regmem_r1: u32,
     pub regmem_r2: u32,
+    pub regmem_r3: u32,
 
     pub immediates: u32,
+
+    //the early branch unit, only used when branches are resolved in ID.
+    pub branch_resolved: bool, //the instr. in ID is a branch or jump, and its registers were ready
+    pub branch_taken: bool,
+    pub branch_target: u32,
 }
@@ -16,9 +16,12 @@ pub struct IDEXLatch {
     pub funct7: u8,
     pub r2_index: u8,
     pub r1_index: u8,
+    pub r3_index: u8, //the fused multiply-adds have a third operand. (see float.rs)
+    pub r3_data: u32,
 
     pub instruction: u32,
-    pub ex_stall: u8, //Stall or Bubble?
+    pub predicted_pc: u32, //where the IF stage went next, after fetching this
+    pub ex_stall: u8,      //Stall or Bubble?
 }
 
 impl IDEXLatch {
@@ -34,7 +37,10 @@ impl IDEXLatch {
         self.funct3 = 0;
         self.r1_index = 0;
         self.r2_index = 0;
+        self.r3_index = 0;
+        self.r3_data = 0;
         self.instruction = 0;
+        self.predicted_pc = 0;
     }
 }
 
@@ -43,14 +49,17 @@ impl IDEXLatch {
 pub struct EXLogic {
     pub formux_r1: u32,
     pub formux_r2: u32,
+    pub formux_r3: u32,
     pub op1: u32,
     pub op2: u32,
     pub alu_output: u32,
+    pub fflags: u8, //what an FP instruction raised
 
     pub branch_taken: bool,
     //these are just used to visually display the multiplexor.
     pub r1_forwarded: u8, //0 - no forwarding. 1 - EX-EX, 2 - MEM-EX
     pub r2_forwarded: u8,
+    pub r3_forwarded: u8,
     pub pc_used: bool,
     pub imm_used: bool,
 }
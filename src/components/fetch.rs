@@ -7,6 +7,8 @@ pub struct IFLogic {
     pub instruction_out: u32,
     pub pcadder_out: u32,
 
-    //used to visually display the PCmux
+    //the instruction in EX jumped or took its branch. also used to visually display the PCmux
     pub jumped: bool,
+    //the instruction in EX didn't go where the IF stage predicted, so the PC is being put right.
+    pub redirected: bool,
 }
@@ -0,0 +1,147 @@
+use crate::components::*;
+
+// The FPU: the rest of an FP add, multiply or divide, after EX. (see float.rs)
+// EX works the result out, and the instruction then spends the rest of its latency in here instead of going to MEM.
+// The adder and multiplier are pipelined, so a new instruction can go into each of them every cycle,
+// but the divider isn't, and a divide or square root has to wait for the one before it to come out.
+// While it's in here, everything behind it carries on, so it can come out after younger instructions. That makes
+// hazards the 5-stage pipeline never had. The hazard unit holds an instruction in ID while:
+//  it reads a register an FP instruction in here hasn't worked out yet (RAW). EX-EX forwarding picks it up
+//   as it comes out.
+//  it writes a register an FP instruction in here still has to write, which would then overwrite it (WAW).
+//  it would get to MEM in the same cycle as one coming out of here. there's only the one EX-MEM latch (structural).
+//  it's a divide, and the divider is busy (structural).
+//  it's a SYSTEM instruction, which has to see fflags and the registers with everything before it done.
+// Nothing in here can trap, so letting them finish late doesn't make traps imprecise.
+
+//an instruction in the FPU. the latch is what goes into EX-MEM once it's done.
+#[derive(Clone, Copy)]
+pub struct FpuEntry {
+    pub latch: EXMEMLatch,
+    pub unit: FpUnit,
+    pub stage: u64,   //which of its cycles it's on. EX was the first
+    pub latency: u64, //cycles it takes, EX included
+}
+
+impl FpuEntry {
+    //how many clock edges until it goes into EX-MEM.
+    pub fn remaining(&self) -> u64 {
+        self.latency - self.stage + 1
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Fpu {
+    pub entries: Vec<FpuEntry>, //oldest first
+}
+
+impl Fpu {
+    //moves everything along a cycle. Returns the instruction that's done, if there is one.
+    // (the hazard unit made sure there's never more than one.)
+    pub fn advance(&mut self) -> Option<FpuEntry> {
+        let done = self
+            .entries
+            .iter()
+            .position(|entry| entry.remaining() == 1)
+            .map(|index| self.entries.remove(index));
+        for entry in &mut self.entries {
+            entry.stage += 1;
+        }
+        done
+    }
+
+    pub fn issue(&mut self, entry: FpuEntry) {
+        self.entries.push(entry);
+    }
+
+    //if an instruction in here writes this register, how many clock edges until it comes out.
+    pub fn writes(&self, register: u8) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|entry| register != 0 && entry.latch.rd_index == register)
+            .map(|entry| entry.remaining())
+    }
+
+    //whether the divider will still be busy in the next cycle.
+    pub fn divider_busy(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.unit == FpUnit::Divider && entry.remaining() > 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+//Which FPU unit the instruction goes through after EX, and its latency, or None if it's done after EX.
+// That's the case for the quick ones, and any with latencies of 1, and illegal ones (which have to get to EX-MEM
+// to trap). The single-cycle model's one cycle covers all of it.
+pub fn fpu_unit(state: &Registers, instruction: u32) -> Option<(FpUnit, u64)> {
+    let unit = unit(instruction)?;
+    let latency = match unit {
+        FpUnit::Adder => state.latencies.fadd,
+        FpUnit::Multiplier => state.latencies.fmul,
+        FpUnit::Divider => state.latencies.fdiv,
+    };
+    let frm = state.csr.frm();
+    let legal = isa::is_legal(instruction) && rounding_mode(instruction, frm).is_some();
+    (latency > 1 && legal && state.model != Model::SingleCycle).then_some((unit, latency))
+}
+
+//the hazards the FPU adds, for the instruction in ID. (the RAW ones are in check_data_hazard)
+pub fn fpu_hazard(state: &Registers, logic: &Logic) -> bool {
+    let instruction = logic.decode.expanded;
+    let rd = logic.decode.decode_rd;
+    let in_ex = fpu_unit(state, state.idex.instruction);
+
+    // WAW: something in the FPU, or about to go in, writes the same register.
+    let overwritten = rd != 0
+        && (state.fpu.writes(rd).is_some() || (in_ex.is_some() && state.idex.rd_index == rd));
+
+    // the EX-MEM latch, on the clock edge this one would get there. after the one into ID-EX, that's however long EX takes.
+    let mine = fpu_unit(state, instruction);
+    let ex_cycles = if let Some((_, latency)) = mine {
+        latency
+    } else if is_muldiv(logic.decode.decode_opcode, logic.decode.decode_funct7) {
+        if is_divide(logic.decode.decode_funct3) {
+            state.latencies.div.max(1)
+        } else {
+            state.latencies.mul.max(1)
+        }
+    } else {
+        1
+    };
+    // (a bubble, or the all-zero NOP past the end of the program, has nothing to put there, so it can go.)
+    let arrival = 1 + ex_cycles;
+    let taken = instruction != 0
+        && (state
+            .fpu
+            .entries
+            .iter()
+            .any(|entry| entry.remaining() == arrival)
+            || matches!(in_ex, Some((_, latency)) if latency == arrival));
+
+    let divider = matches!(mine, Some((FpUnit::Divider, _)))
+        && (state.fpu.divider_busy() || matches!(in_ex, Some((FpUnit::Divider, _))));
+
+    let system =
+        logic.decode.decode_opcode == 0b1110011 && (!state.fpu.is_empty() || in_ex.is_some());
+
+    overwritten || taken || divider || system
+}
+
+//Whether the instruction in EX would go into EX-MEM on the same clock edge as one coming out of the FPU.
+// fpu_hazard doesn't let one leave ID if that would happen, but a cache miss holds the FPU, along with MEM, while
+// a multiply or divide in EX keeps counting down. Then it's done a cycle sooner than fpu_hazard thought, and it
+// has to wait in EX one more cycle instead.
+pub fn fpu_collision(state: &Registers) -> bool {
+    state.idex.instruction != 0
+        && fpu_unit(state, state.idex.instruction).is_none()
+        && state.fpu.entries.iter().any(|entry| entry.remaining() == 1)
+}
+
+//the flags the FP instruction that just got into the EX-MEM latch raised go into fflags.
+pub fn accrue_fflags(state: &mut Registers) {
+    state.csr.fcsr |= state.exmem.fflags as u32;
+}
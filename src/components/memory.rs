@@ -3,6 +3,7 @@ use crate::components::*;
 //the EX-MEM Latch
 #[derive(Clone, Copy, Default)]
 pub struct EXMEMLatch {
+    pub base_pc: u32, //address of the instruction
     pub added_pc: u32,
     pub alu_output: u32,
     pub mem_data_in: u32,
@@ -12,11 +13,14 @@ pub struct EXMEMLatch {
     pub funct3: u8,
 
     pub instruction: u32,
-    pub mem_stall: u8, //stall or bubble?
+    pub fflags: u8,     //the FP exception flags it raised. (see float.rs)
+    pub from_fpu: bool, //it's just come out of the FPU, after younger instructions. (see fpu.rs)
+    pub mem_stall: u8,  //stall or bubble?
 }
 
 impl EXMEMLatch {
     pub fn bubble(&mut self) {
+        self.base_pc = 0;
         self.added_pc = 0;
         self.alu_output = 0xdeadbeef; //cannot write to Data Mem address 0, so just in case.
         self.mem_data_in = 0;
@@ -25,6 +29,8 @@ impl EXMEMLatch {
         self.funct3 = 0;
 
         self.instruction = 0;
+        self.fflags = 0;
+        self.from_fpu = false;
     }
 }
 
@@ -32,6 +38,7 @@ impl EXMEMLatch {
 #[derive(Clone, Default)]
 pub struct MEMLogic {
     pub mem_data_out: u32,
+    pub amo_write: Option<u32>, //what the atomic in MEM stores back, if anything
 
     pub memmem_fwd: bool,
     pub memmem_data: u32,
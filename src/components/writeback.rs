@@ -2,9 +2,11 @@ use crate::components::*;
 
 #[derive(Clone, Copy, Default)]
 pub struct MEMWBLatch {
+    pub base_pc: u32, //address of the instruction
     pub added_pc: u32,
     pub alu_output: u32,
     pub mem_data_out: u32,
+    pub store_data: u32, //what a store wrote, for tohost. a store to a device can't just be read back
     pub rd_index: u8,
 
     pub opcode: u8,
@@ -15,9 +17,11 @@ pub struct MEMWBLatch {
 
 impl MEMWBLatch {
     pub fn bubble(&mut self) {
+        self.base_pc = 0;
         self.added_pc = 0;
         self.alu_output = 0;
         self.mem_data_out = 0;
+        self.store_data = 0;
         self.rd_index = 0;
         self.opcode = 0;
         self.instruction = 0;
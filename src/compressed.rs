@@ -0,0 +1,229 @@
+// RV32C: compressed instructions. They're 16 bits long, and any instruction whose lowest two bits aren't 11 is one.
+// Each one is just a shorter way of writing a 32-bit instruction, so IF fetches 2 or 4 bytes at a time (the PC only
+// has to be a multiple of 2 now, so a 32-bit instruction can be split across two words, or two cache lines), and ID
+// expands compressed ones into the 32-bit instruction they stand for. From then on, nothing can tell the difference.
+// The registers written rd', rs1' and rs2' are 3 bits, for x8-x15, the ones that get used the most.
+// An all-zero parcel is illegal in the spec, but here it does nothing, like a zero word always has.
+
+//whether an instruction (or the word it starts) is a compressed one.
+pub fn is_compressed(instruction: u32) -> bool {
+    instruction & 0b11 != 0b11
+}
+
+//how many bytes the instruction at the start of `word` takes up. a zero word is still one 4-byte NOP.
+pub fn instruction_size(word: u32) -> u32 {
+    if word != 0 && is_compressed(word) {
+        2
+    } else {
+        4
+    }
+}
+
+//What decode sees: compressed instructions expanded, and anything else as it is.
+// A parcel that isn't a compressed instruction stays as it is too, and is illegal once it gets past EX.
+pub fn expand(instruction: u32) -> u32 {
+    if instruction != 0 && is_compressed(instruction) {
+        match decompress(instruction as u16) {
+            Some((_, expanded)) => expanded,
+            None => instruction,
+        }
+    } else {
+        instruction
+    }
+}
+
+//The name of a compressed instruction, and the 32-bit instruction it stands for. None if it isn't one.
+// (the floating-point ones are the loads and stores of f8-f15, or of any FP register off the stack pointer.)
+pub fn decompress(parcel: u16) -> Option<(&'static str, u32)> {
+    let c = parcel as u32;
+    let bit = |n: u32| (c >> n) & 1;
+    let bits = |high: u32, low: u32| (c >> low) & ((1 << (high - low + 1)) - 1);
+    let funct3 = bits(15, 13);
+    let rd = bits(11, 7); //also rs1
+    let rs2 = bits(6, 2);
+    let rd_short = 8 + bits(4, 2); //rd' and rs2'
+    let rs1_short = 8 + bits(9, 7); //rs1', and rd' for the ALU ones
+
+    // the 6-bit immediate most of them have: imm[5] is bit 12, and imm[4:0] is bits 6:2. sign-extended.
+    let imm6 = sign_extend((bit(12) << 5) | rs2, 6);
+    // the offsets of C.J and C.JAL, and of C.BEQZ and C.BNEZ.
+    let jump_offset = sign_extend(
+        (bit(12) << 11)
+            | (bit(11) << 4)
+            | (bits(10, 9) << 8)
+            | (bit(8) << 10)
+            | (bit(7) << 6)
+            | (bit(6) << 7)
+            | (bits(5, 3) << 1)
+            | (bit(2) << 5),
+        12,
+    );
+    let branch_offset = sign_extend(
+        (bit(12) << 8)
+            | (bits(11, 10) << 3)
+            | (bits(6, 5) << 6)
+            | (bits(4, 3) << 1)
+            | (bit(2) << 5),
+        9,
+    );
+
+    let expanded = match (c & 0b11, funct3) {
+        // quadrant 0
+        (0b00, 0b000) => {
+            let imm = (bits(12, 11) << 4) | (bits(10, 7) << 6) | (bit(6) << 2) | (bit(5) << 3);
+            if imm == 0 {
+                return None; //(this is the all-zero parcel, too)
+            }
+            ("c.addi4spn", encode_i(imm, 2, 0b000, rd_short, 0b0010011))
+        }
+        (0b00, 0b010) => {
+            let imm = (bits(12, 10) << 3) | (bit(6) << 2) | (bit(5) << 6);
+            ("c.lw", encode_i(imm, rs1_short, 0b010, rd_short, 0b0000011))
+        }
+        (0b00, 0b110) => {
+            let imm = (bits(12, 10) << 3) | (bit(6) << 2) | (bit(5) << 6);
+            ("c.sw", encode_s(imm, rd_short, rs1_short, 0b010, 0b0100011))
+        }
+        (0b00, 0b011) => {
+            let imm = (bits(12, 10) << 3) | (bit(6) << 2) | (bit(5) << 6);
+            (
+                "c.flw",
+                encode_i(imm, rs1_short, 0b010, rd_short, 0b0000111),
+            )
+        }
+        (0b00, 0b111) => {
+            let imm = (bits(12, 10) << 3) | (bit(6) << 2) | (bit(5) << 6);
+            (
+                "c.fsw",
+                encode_s(imm, rd_short, rs1_short, 0b010, 0b0100111),
+            )
+        }
+
+        // quadrant 1
+        (0b01, 0b000) if rd == 0 => ("c.nop", encode_i(imm6, 0, 0b000, 0, 0b0010011)),
+        (0b01, 0b000) => ("c.addi", encode_i(imm6, rd, 0b000, rd, 0b0010011)),
+        (0b01, 0b001) => ("c.jal", encode_j(jump_offset, 1)),
+        (0b01, 0b010) => ("c.li", encode_i(imm6, 0, 0b000, rd, 0b0010011)),
+        (0b01, 0b011) if rd == 2 => {
+            let imm = sign_extend(
+                (bit(12) << 9) | (bit(6) << 4) | (bit(5) << 6) | (bits(4, 3) << 7) | (bit(2) << 5),
+                10,
+            );
+            if imm == 0 {
+                return None;
+            }
+            ("c.addi16sp", encode_i(imm, 2, 0b000, 2, 0b0010011))
+        }
+        (0b01, 0b011) => {
+            if imm6 == 0 {
+                return None;
+            }
+            ("c.lui", ((imm6 & 0xfffff) << 12) | (rd << 7) | 0b0110111)
+        }
+        (0b01, 0b100) => match bits(11, 10) {
+            // on RV32 the shift amount is only 5 bits, so bit 12 has to be 0.
+            0b00 if bit(12) == 0 => (
+                "c.srli",
+                encode_i(rs2, rs1_short, 0b101, rs1_short, 0b0010011),
+            ),
+            0b01 if bit(12) == 0 => {
+                let imm = (0b0100000 << 5) | rs2;
+                (
+                    "c.srai",
+                    encode_i(imm, rs1_short, 0b101, rs1_short, 0b0010011),
+                )
+            }
+            0b10 => (
+                "c.andi",
+                encode_i(imm6, rs1_short, 0b111, rs1_short, 0b0010011),
+            ),
+            0b11 if bit(12) == 0 => {
+                let (name, funct7, funct3) = match bits(6, 5) {
+                    0b00 => ("c.sub", 0b0100000, 0b000),
+                    0b01 => ("c.xor", 0, 0b100),
+                    0b10 => ("c.or", 0, 0b110),
+                    _ => ("c.and", 0, 0b111),
+                };
+                (
+                    name,
+                    encode_r(funct7, rd_short, rs1_short, funct3, rs1_short),
+                )
+            }
+            _ => return None,
+        },
+        (0b01, 0b101) => ("c.j", encode_j(jump_offset, 0)),
+        (0b01, 0b110) => ("c.beqz", encode_b(branch_offset, 0, rs1_short, 0b000)),
+        (0b01, 0b111) => ("c.bnez", encode_b(branch_offset, 0, rs1_short, 0b001)),
+
+        // quadrant 2
+        (0b10, 0b000) if bit(12) == 0 => ("c.slli", encode_i(rs2, rd, 0b001, rd, 0b0010011)),
+        (0b10, 0b010) if rd != 0 => {
+            let imm = (bit(12) << 5) | (bits(6, 4) << 2) | (bits(3, 2) << 6);
+            ("c.lwsp", encode_i(imm, 2, 0b010, rd, 0b0000011))
+        }
+        (0b10, 0b100) => match (bit(12), rd, rs2) {
+            (0, 0, 0) => return None,
+            (0, _, 0) => ("c.jr", encode_i(0, rd, 0b000, 0, 0b1100111)),
+            (0, _, _) => ("c.mv", encode_r(0, rs2, 0, 0b000, rd)),
+            (_, 0, 0) => ("c.ebreak", 0x00100073),
+            (_, _, 0) => ("c.jalr", encode_i(0, rd, 0b000, 1, 0b1100111)),
+            (_, _, _) => ("c.add", encode_r(0, rs2, rd, 0b000, rd)),
+        },
+        (0b10, 0b110) => {
+            let imm = (bits(12, 9) << 2) | (bits(8, 7) << 6);
+            ("c.swsp", encode_s(imm, rs2, 2, 0b010, 0b0100011))
+        }
+        (0b10, 0b011) => {
+            let imm = (bit(12) << 5) | (bits(6, 4) << 2) | (bits(3, 2) << 6);
+            ("c.flwsp", encode_i(imm, 2, 0b010, rd, 0b0000111))
+        }
+        (0b10, 0b111) => {
+            let imm = (bits(12, 9) << 2) | (bits(8, 7) << 6);
+            ("c.fswsp", encode_s(imm, rs2, 2, 0b010, 0b0100111))
+        }
+        _ => return None,
+    };
+    Some(expanded)
+}
+
+//sign-extends the lowest `width` bits.
+fn sign_extend(value: u32, width: u32) -> u32 {
+    (((value << (32 - width)) as i32) >> (32 - width)) as u32
+}
+
+fn encode_r(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+    (funct7 << 25) | (rs2 << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | 0b0110011
+}
+
+fn encode_i(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm << 20) | (rs1 << 15) | (funct3 << 12) | (rd << 7) | opcode
+}
+
+fn encode_s(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    ((imm >> 5) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | ((imm & 0b11111) << 7)
+        | opcode
+}
+
+fn encode_b(offset: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    (((offset >> 12) & 1) << 31)
+        | (((offset >> 5) & 0b111111) << 25)
+        | (rs2 << 20)
+        | (rs1 << 15)
+        | (funct3 << 12)
+        | (((offset >> 1) & 0b1111) << 8)
+        | (((offset >> 11) & 1) << 7)
+        | 0b1100011
+}
+
+fn encode_j(offset: u32, rd: u32) -> u32 {
+    (((offset >> 20) & 1) << 31)
+        | (((offset >> 1) & 0x3ff) << 21)
+        | (((offset >> 11) & 1) << 20)
+        | (((offset >> 12) & 0xff) << 12)
+        | (rd << 7)
+        | 0b1101111
+}
@@ -0,0 +1,196 @@
+use crate::cache::CacheConfig;
+use crate::mem::MemoryMap;
+use crate::predictor::*;
+
+//How a Simulator is set up: everything about the run that isn't the program itself.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub halt_address: Option<u32>, //stop once the instruction at this address is reached
+    pub tohost: Option<u32>, //riscv-tests style exit word. if None, the program's "tohost" symbol is used
+    pub max_cycles: Option<u64>, //the watchdog. None runs for as long as it takes
+    pub forwarding: Forwarding,
+    pub model: Model,
+    pub predictor: Predictor,
+    pub btb_entries: usize,   //0 for no branch target buffer
+    pub early_branches: bool, //resolve branches in ID instead of EX. only the pipeline has stages to save
+    pub memory: MemoryConfig,
+    pub latencies: Latencies,
+    pub reset_vector: Option<u32>, //where the pc starts. if None, the program's entry point
+}
+
+//Where everything goes in memory, the caches, and how long memory takes when they miss.
+// With no caches, every fetch, load and store takes just the one cycle, like it always used to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryConfig {
+    pub map: MemoryMap,
+    pub caches: Vec<CacheConfig>, //the data caches, L1 first. up to three of them
+    pub memory_latency: u64,
+    pub icache: Option<CacheConfig>, //the instruction cache
+    pub icache_miss_penalty: u64, //cycles instruction memory takes when the instruction cache misses
+}
+
+pub const DEFAULT_MEMORY_LATENCY: u64 = 50;
+pub const MAX_CACHE_LEVELS: usize = 3;
+
+impl Default for MemoryConfig {
+    fn default() -> MemoryConfig {
+        MemoryConfig {
+            map: MemoryMap::default(),
+            caches: Vec::new(),
+            memory_latency: DEFAULT_MEMORY_LATENCY,
+            icache: None,
+            icache_miss_penalty: DEFAULT_MEMORY_LATENCY,
+        }
+    }
+}
+
+//How many cycles EX takes for the instructions that take more than one. (see muldiv.rs)
+// The FP ones spend the cycles after the first in the FPU instead, so they don't hold EX. (see fpu.rs)
+// Anything not here takes one, and so does anything set to 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Latencies {
+    pub mul: u64,  //MUL, MULH, MULHSU, MULHU
+    pub div: u64,  //DIV, DIVU, REM, REMU
+    pub fadd: u64, //FADD.S, FSUB.S, and the conversions between floats and integers
+    pub fmul: u64, //FMUL.S and the fused multiply-adds
+    pub fdiv: u64, //FDIV.S and FSQRT.S
+}
+
+pub const DEFAULT_MUL_LATENCY: u64 = 3;
+pub const DEFAULT_DIV_LATENCY: u64 = 32; //a bit a cycle
+
+// the FP ones are the MIPS R4000's, as Hennessy & Patterson use them.
+pub const DEFAULT_FADD_LATENCY: u64 = 4;
+pub const DEFAULT_FMUL_LATENCY: u64 = 7;
+pub const DEFAULT_FDIV_LATENCY: u64 = 25;
+
+impl Default for Latencies {
+    fn default() -> Latencies {
+        Latencies {
+            mul: DEFAULT_MUL_LATENCY,
+            div: DEFAULT_DIV_LATENCY,
+            fadd: DEFAULT_FADD_LATENCY,
+            fmul: DEFAULT_FMUL_LATENCY,
+            fdiv: DEFAULT_FDIV_LATENCY,
+        }
+    }
+}
+
+//Which datapath runs the program. They all share the same stages and memories, and get the same results;
+// only how many instructions are in flight at once, and what counts as a cycle, is different.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Model {
+    #[default]
+    Pipelined, //the 5-stage pipeline, with up to 5 instructions in flight
+    SingleCycle, //one instruction at a time, each taking one (very long) cycle
+    MultiCycle,  //one instruction at a time, one cycle per stage it actually needs
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name.to_lowercase().as_str() {
+            "pipelined" | "pipeline" => Some(Model::Pipelined),
+            "single-cycle" | "single" => Some(Model::SingleCycle),
+            "multi-cycle" | "multi" => Some(Model::MultiCycle),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Pipelined => "pipelined",
+            Model::SingleCycle => "single-cycle",
+            Model::MultiCycle => "multi-cycle",
+        }
+    }
+}
+
+//Which forwarding paths the pipeline has. Without one, the hazard unit stalls instead,
+// until the value can come another way (or from the register file).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Forwarding {
+    pub ex_ex: bool,   //EX-MEM latch's ALU result, back into EX
+    pub mem_ex: bool,  //the value being written back, into EX
+    pub mem_mem: bool, //a loaded value, straight into the store right behind the load
+}
+
+impl Default for Forwarding {
+    fn default() -> Forwarding {
+        Forwarding::all()
+    }
+}
+
+impl Forwarding {
+    pub fn all() -> Forwarding {
+        Forwarding {
+            ex_ex: true,
+            mem_ex: true,
+            mem_mem: true,
+        }
+    }
+
+    pub fn none() -> Forwarding {
+        Forwarding {
+            ex_ex: false,
+            mem_ex: false,
+            mem_mem: false,
+        }
+    }
+
+    //"all", "none", or a comma-separated list of paths, like "ex-ex,mem-mem".
+    pub fn from_names(text: &str) -> Option<Forwarding> {
+        match text {
+            "all" => return Some(Forwarding::all()),
+            "none" => return Some(Forwarding::none()),
+            _ => {}
+        }
+        let mut forwarding = Forwarding::none();
+        for name in text.split(',') {
+            match name.trim().to_lowercase().as_str() {
+                "ex-ex" => forwarding.ex_ex = true,
+                "mem-ex" => forwarding.mem_ex = true,
+                "mem-mem" => forwarding.mem_mem = true,
+                _ => return None,
+            }
+        }
+        Some(forwarding)
+    }
+}
+
+//The built-in branch predictors. (Simulator::set_branch_predictor takes any other BranchPredictor.)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Predictor {
+    #[default]
+    NotTaken,
+    BackwardTaken,
+    OneBit,
+    TwoBit,
+    Gshare,
+}
+
+//how big the built-in predictors' tables are.
+pub const PREDICTOR_ENTRIES: usize = 1024;
+pub const GSHARE_HISTORY_BITS: u32 = 10;
+
+impl Predictor {
+    pub fn from_name(name: &str) -> Option<Predictor> {
+        match name.to_lowercase().as_str() {
+            "not-taken" | "static" => Some(Predictor::NotTaken),
+            "btfnt" => Some(Predictor::BackwardTaken),
+            "1-bit" | "1bit" => Some(Predictor::OneBit),
+            "2-bit" | "2bit" => Some(Predictor::TwoBit),
+            "gshare" => Some(Predictor::Gshare),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn BranchPredictor> {
+        match self {
+            Predictor::NotTaken => Box::new(StaticNotTaken),
+            Predictor::BackwardTaken => Box::new(BackwardTaken),
+            Predictor::OneBit => Box::new(OneBit::new(PREDICTOR_ENTRIES)),
+            Predictor::TwoBit => Box::new(TwoBit::new(PREDICTOR_ENTRIES)),
+            Predictor::Gshare => Box::new(Gshare::new(PREDICTOR_ENTRIES, GSHARE_HISTORY_BITS)),
+        }
+    }
+}
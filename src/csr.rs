@@ -0,0 +1,234 @@
+use crate::components::*;
+
+// Zicsr: the Control and Status Registers, and the instructions that read and write them.
+// CSR instructions get done once they make it past EX, in program order with ECALL and friends.
+// Everything older has been through MEM by then, and nothing younger has, so there's no need to stall.
+// The ALU passes the rs1 value (or the 5-bit immediate) through, and the old CSR value goes back to rd
+// through the normal writeback path, where forwarding picks it up like any other result.
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+pub const MCYCLEH: u16 = 0xb80;
+pub const MINSTRETH: u16 = 0xb82;
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const CYCLEH: u16 = 0xc80;
+pub const TIMEH: u16 = 0xc81;
+pub const INSTRETH: u16 = 0xc82;
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+
+// mstatus bits
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_MPP: u32 = 0b11 << 11; //always machine mode, it's the only one there is
+
+// the interrupts, as bits of mip. mie enables them with the same bits.
+pub const MIP_MSIP: u32 = 1 << 3; //software
+pub const MIP_MTIP: u32 = 1 << 7; //timer
+pub const MIP_MEIP: u32 = 1 << 11; //external
+
+const MISA_RV32IMAFC: u32 = (1 << 30) | (1 << 12) | (1 << 8) | (1 << 5) | (1 << 2) | 1; //MXL = 32 bits, I, M, F, C, A
+
+// fcsr is frm, the rounding mode, above fflags, the exception flags. (see float.rs)
+const FCSR_FLAGS: u32 = 0b11111;
+const FCSR_FRM_SHIFT: u32 = 5;
+
+//All of the CSRs.
+#[derive(Clone, Copy)]
+pub struct CsrFile {
+    pub mstatus: u32,
+    pub misa: u32,
+    pub mie: u32,
+    pub mtvec: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub mip: u32,  //whatever the devices are raising. (see device.rs)
+    pub fcsr: u32, //frm and fflags. mstatus.FS isn't there, the FPU is always on
+
+    pub mcycle: u64,   //goes up every cycle
+    pub minstret: u64, //goes up every time an instruction finishes WB
+}
+
+impl Default for CsrFile {
+    fn default() -> CsrFile {
+        CsrFile {
+            mstatus: MSTATUS_MPP,
+            misa: MISA_RV32IMAFC,
+            mie: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mip: 0,
+            fcsr: 0,
+            mcycle: 0,
+            minstret: 0,
+        }
+    }
+}
+
+impl CsrFile {
+    //the value of a CSR, or None if there is no such CSR.
+    // there's no separate clock, so time is the cycle count.
+    pub fn read(&self, csr: u16) -> Option<u32> {
+        Some(match csr {
+            FFLAGS => self.fcsr & FCSR_FLAGS,
+            FRM => self.fcsr >> FCSR_FRM_SHIFT,
+            FCSR => self.fcsr,
+            MSTATUS => self.mstatus,
+            MISA => self.misa,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            MCYCLE | CYCLE | TIME => self.mcycle as u32,
+            MCYCLEH | CYCLEH | TIMEH => (self.mcycle >> 32) as u32,
+            MINSTRET | INSTRET => self.minstret as u32,
+            MINSTRETH | INSTRETH => (self.minstret >> 32) as u32,
+            MVENDORID | MARCHID | MIMPID | MHARTID => 0,
+            _ => return None,
+        })
+    }
+
+    //writes a CSR. Returns false if it doesn't exist or is read-only.
+    // bits that can't be changed just ignore whatever gets written to them.
+    pub fn write(&mut self, csr: u16, value: u32) -> bool {
+        if is_read_only(csr) {
+            return false;
+        }
+        match csr {
+            FFLAGS => self.fcsr = (self.fcsr & !FCSR_FLAGS) | (value & FCSR_FLAGS),
+            FRM => self.fcsr = (self.fcsr & FCSR_FLAGS) | ((value & 0b111) << FCSR_FRM_SHIFT),
+            FCSR => self.fcsr = value & 0xff,
+            MSTATUS => {
+                self.mstatus = (value & (MSTATUS_MIE | MSTATUS_MPIE)) | MSTATUS_MPP;
+            }
+            MISA => {} //can't turn extensions on or off
+            MIE => self.mie = value & (MIP_MSIP | MIP_MTIP | MIP_MEIP),
+            MTVEC => self.mtvec = value & !0b10, //mode 0 (direct) and 1 (vectored) only
+            MSCRATCH => self.mscratch = value,
+            MEPC => self.mepc = value & !0b1, //(instructions can start on any 2-byte boundary)
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            MIP => {} //the devices set these bits, and clearing them has to go through the devices too
+            MCYCLE => self.mcycle = (self.mcycle & !0xffffffff) | value as u64,
+            MCYCLEH => self.mcycle = (self.mcycle & 0xffffffff) | ((value as u64) << 32),
+            MINSTRET => self.minstret = (self.minstret & !0xffffffff) | value as u64,
+            MINSTRETH => self.minstret = (self.minstret & 0xffffffff) | ((value as u64) << 32),
+            _ => return false,
+        }
+        true
+    }
+
+    //the dynamic rounding mode.
+    pub fn frm(&self) -> u8 {
+        (self.fcsr >> FCSR_FRM_SHIFT) as u8
+    }
+}
+
+//the top two bits of a CSR's address say if it's read-only.
+pub fn is_read_only(csr: u16) -> bool {
+    csr >> 10 == 0b11
+}
+
+//the name of a CSR, for the assembler and for display.
+pub const CSR_NAMES: [(&str, u16); 26] = [
+    ("fflags", FFLAGS),
+    ("frm", FRM),
+    ("fcsr", FCSR),
+    ("mstatus", MSTATUS),
+    ("misa", MISA),
+    ("mie", MIE),
+    ("mtvec", MTVEC),
+    ("mscratch", MSCRATCH),
+    ("mepc", MEPC),
+    ("mcause", MCAUSE),
+    ("mtval", MTVAL),
+    ("mip", MIP),
+    ("mcycle", MCYCLE),
+    ("minstret", MINSTRET),
+    ("mcycleh", MCYCLEH),
+    ("minstreth", MINSTRETH),
+    ("cycle", CYCLE),
+    ("time", TIME),
+    ("instret", INSTRET),
+    ("cycleh", CYCLEH),
+    ("timeh", TIMEH),
+    ("instreth", INSTRETH),
+    ("mvendorid", MVENDORID),
+    ("marchid", MARCHID),
+    ("mimpid", MIMPID),
+    ("mhartid", MHARTID),
+];
+
+pub fn csr_name(csr: u16) -> Option<&'static str> {
+    CSR_NAMES
+        .iter()
+        .find(|(_, number)| *number == csr)
+        .map(|(name, _)| *name)
+}
+
+pub fn csr_number(name: &str) -> Option<u16> {
+    CSR_NAMES
+        .iter()
+        .find(|(csr_name, _)| *csr_name == name)
+        .map(|(_, number)| *number)
+}
+
+//Does the CSR instruction that just got into the EX-MEM latch. Returns true if there was one,
+// since its result then needs to get to the wires.
+pub fn handle_csr(state: &mut Registers) -> bool {
+    let instr = state.exmem.instruction;
+    let funct3 = (instr >> 12) & 0b111;
+    if instr & 0b1111111 != 0b1110011 || funct3 == 0 || funct3 == 0b100 || state.stop.is_some() {
+        return false;
+    }
+
+    let csr = (instr >> 20) as u16;
+    let rs1 = (instr >> 15) & 0b11111; //also the immediate, for the I versions
+    let source = state.exmem.alu_output; //rs1's value or the immediate, passed through the ALU
+
+    // minstret hasn't counted the instruction in MEM-WB yet, but it's older than this one.
+    let pending = (state.memwb.instruction != 0) as u64;
+    state.csr.minstret = state.csr.minstret.wrapping_add(pending);
+
+    let old = state.csr.read(csr).unwrap_or(0);
+    let new = match funct3 & 0b11 {
+        0b01 => Some(source),                    //CSRRW(I): always writes
+        0b10 if rs1 != 0 => Some(old | source),  //CSRRS(I): only writes if there's something to set
+        0b11 if rs1 != 0 => Some(old & !source), //CSRRC(I): or to clear
+        _ => None,
+    };
+    if let Some(new) = new {
+        // the next instruction reads what was written, so this one's own retirement doesn't count.
+        if state.csr.write(csr, new) && (csr == MINSTRET || csr == MINSTRETH) {
+            state.csr.minstret = state.csr.minstret.wrapping_sub(1);
+        }
+    }
+
+    state.csr.minstret = state.csr.minstret.wrapping_sub(pending);
+    state.exmem.alu_output = old;
+    true
+}
@@ -0,0 +1,123 @@
+mod clint;
+mod uart;
+
+pub use clint::*;
+pub use uart::*;
+
+// Memory-mapped devices. Loads and stores to an MMIO region of the memory map don't go to memory,
+// they go over the bus to whichever device is attached at that address. (With none there, loads get 0
+// and stores go nowhere.) They happen in MEM like any other load or store, just never through the caches.
+// Devices can also raise interrupts. Whatever they're raising between them is what's in mip. (see trap.rs)
+
+//Anything that can be attached to the bus.
+pub trait Device {
+    //for the report and error messages.
+    fn name(&self) -> String;
+    //how many bytes of address space it takes up, starting at the address it's attached at.
+    fn size(&self) -> u32;
+    //what a load of `size` bytes from `offset` gets. This can't change anything,
+    // since the MEM stage's wires can get worked out more than once a cycle.
+    fn read(&self, offset: u32, size: u32) -> u32;
+    //a load from `offset` actually happened, on the clock edge it left MEM.
+    // for devices where reading does something, like taking a byte out of a FIFO.
+    fn read_done(&mut self, _offset: u32, _size: u32) {}
+    //a store of the lowest `size` bytes of `value` to `offset`, on the clock edge it leaves MEM.
+    fn write(&mut self, offset: u32, size: u32, value: u32);
+    //a clock cycle has gone by.
+    fn tick(&mut self) {}
+    //the interrupts it's raising right now, as mip bits. (see csr.rs)
+    fn interrupts(&self) -> u32 {
+        0
+    }
+    //needed so the CPU can still be cloned for snapshots.
+    fn clone_box(&self) -> Box<dyn Device>;
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Box<dyn Device> {
+        self.clone_box()
+    }
+}
+
+//The devices, and where each one is attached.
+#[derive(Clone, Default)]
+pub struct Bus {
+    devices: Vec<(u32, Box<dyn Device>)>, //(base address, device)
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus::default()
+    }
+
+    //attaches a device at `base`, as long as it doesn't overlap one that's already there.
+    pub fn attach(&mut self, base: u32, device: Box<dyn Device>) -> Result<(), String> {
+        let end = base as u64 + device.size() as u64;
+        if device.size() == 0 || end > 1 << 32 {
+            return Err(format!(
+                "the {} at {:#x} doesn't fit in the address space",
+                device.name(),
+                base
+            ));
+        }
+        if let Some((other_base, other)) = self.devices.iter().find(|(other_base, other)| {
+            (base as u64) < *other_base as u64 + other.size() as u64 && (*other_base as u64) < end
+        }) {
+            return Err(format!(
+                "the {} at {:#x} overlaps the {} at {:#x}",
+                device.name(),
+                base,
+                other.name(),
+                other_base
+            ));
+        }
+        self.devices.push((base, device));
+        Ok(())
+    }
+
+    //(base address, device) for everything attached, in the order it was attached.
+    pub fn devices(&self) -> impl Iterator<Item = (u32, &dyn Device)> {
+        self.devices
+            .iter()
+            .map(|(base, device)| (*base, device.as_ref()))
+    }
+
+    //which device (by index) is at `address`, and how far into it the address is.
+    fn find(&self, address: u32) -> Option<(usize, u32)> {
+        self.devices
+            .iter()
+            .position(|(base, device)| address >= *base && address - base < device.size())
+            .map(|index| (index, address - self.devices[index].0))
+    }
+
+    pub fn read(&self, address: u32, size: u32) -> u32 {
+        match self.find(address) {
+            Some((index, offset)) => self.devices[index].1.read(offset, size),
+            None => 0,
+        }
+    }
+
+    pub fn read_done(&mut self, address: u32, size: u32) {
+        if let Some((index, offset)) = self.find(address) {
+            self.devices[index].1.read_done(offset, size);
+        }
+    }
+
+    pub fn write(&mut self, address: u32, size: u32, value: u32) {
+        if let Some((index, offset)) = self.find(address) {
+            self.devices[index].1.write(offset, size, value);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for (_, device) in &mut self.devices {
+            device.tick();
+        }
+    }
+
+    pub fn interrupts(&self) -> u32 {
+        self.devices
+            .iter()
+            .fold(0, |raised, (_, device)| raised | device.interrupts())
+    }
+}
@@ -0,0 +1,111 @@
+use crate::csr::{MIP_MSIP, MIP_MTIP};
+use crate::device::Device;
+
+// A CLINT (core-local interruptor), laid out like SiFive's and QEMU's:
+//  0x0000  msip      bit 0 raises the software interrupt, until it's cleared again
+//  0x4000  mtimecmp  64 bits. the timer interrupt is raised for as long as mtime >= mtimecmp
+//  0xbff8  mtime     64 bits. goes up once a cycle, and can be written
+// The 64-bit registers are read and written a word at a time, low word first.
+// mtimecmp starts out as big as it goes, so the timer stays quiet until a program sets it.
+
+pub const CLINT_BASE: u32 = 0xf200_0000; //where the command line attaches it, unless told otherwise
+pub const CLINT_SIZE: u32 = 0x10000;
+
+pub const CLINT_MSIP: u32 = 0x0000;
+pub const CLINT_MTIMECMP: u32 = 0x4000;
+pub const CLINT_MTIME: u32 = 0xbff8;
+
+#[derive(Clone)]
+pub struct Clint {
+    pub msip: bool,
+    pub mtimecmp: u64,
+    pub mtime: u64,
+}
+
+impl Default for Clint {
+    fn default() -> Clint {
+        Clint {
+            msip: false,
+            mtimecmp: u64::MAX,
+            mtime: 0,
+        }
+    }
+}
+
+impl Clint {
+    pub fn new() -> Clint {
+        Clint::default()
+    }
+
+    //the whole word at a (word-aligned) offset.
+    fn word(&self, offset: u32) -> u32 {
+        match offset {
+            CLINT_MSIP => self.msip as u32,
+            CLINT_MTIMECMP => self.mtimecmp as u32,
+            0x4004 => (self.mtimecmp >> 32) as u32,
+            CLINT_MTIME => self.mtime as u32,
+            0xbffc => (self.mtime >> 32) as u32,
+            _ => 0,
+        }
+    }
+
+    fn set_word(&mut self, offset: u32, value: u32) {
+        let low = |old: u64| (old & !0xffff_ffff) | value as u64;
+        let high = |old: u64| (old & 0xffff_ffff) | (value as u64) << 32;
+        match offset {
+            CLINT_MSIP => self.msip = value & 1 != 0,
+            CLINT_MTIMECMP => self.mtimecmp = low(self.mtimecmp),
+            0x4004 => self.mtimecmp = high(self.mtimecmp),
+            CLINT_MTIME => self.mtime = low(self.mtime),
+            0xbffc => self.mtime = high(self.mtime),
+            _ => {}
+        }
+    }
+}
+
+//which bits of its word a `size`-byte access at `offset` covers.
+fn mask(offset: u32, size: u32) -> u32 {
+    let bits = if size >= 4 { !0 } else { (1 << (8 * size)) - 1 };
+    bits << (8 * (offset & 3))
+}
+
+impl Device for Clint {
+    fn name(&self) -> String {
+        "CLINT".to_owned()
+    }
+
+    fn size(&self) -> u32 {
+        CLINT_SIZE
+    }
+
+    fn read(&self, offset: u32, size: u32) -> u32 {
+        (self.word(offset & !3) & mask(offset, size)) >> (8 * (offset & 3))
+    }
+
+    fn write(&mut self, offset: u32, size: u32, value: u32) {
+        let mask = mask(offset, size);
+        let old = self.word(offset & !3);
+        self.set_word(
+            offset & !3,
+            (old & !mask) | ((value << (8 * (offset & 3))) & mask),
+        );
+    }
+
+    fn tick(&mut self) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    fn interrupts(&self) -> u32 {
+        let software = if self.msip { MIP_MSIP } else { 0 };
+        let timer = if self.mtime >= self.mtimecmp {
+            MIP_MTIP
+        } else {
+            0
+        };
+        software | timer
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,150 @@
+use crate::device::Device;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// A 16550-style UART, for programs to print text with.
+// Its registers are a byte each, at offsets 0-7 (a wider load or store only gets the one at its address):
+//  0  RBR (read) the next received byte / THR (write) send a byte. DLL instead, while LCR.DLAB is set
+//  1  IER interrupt enables. DLM instead, while LCR.DLAB is set
+//  2  IIR (read) which interrupt is pending / FCR (write) FIFO control
+//  3  LCR line control        4  MCR modem control
+//  5  LSR line status         6  MSR modem status
+//  7  SCR scratch
+// Sending is instant, so LSR always says the transmitter is empty, and a program polling THRE never has to wait.
+// Bytes to receive come from a buffer given up front. It never raises interrupts, so IER can be written
+// but nothing ever happens, and IIR always says none are pending.
+
+pub const UART_BASE: u32 = 0xf000_0000; //where the command line attaches it, unless told otherwise
+pub const UART_SIZE: u32 = 8;
+
+pub const UART_RBR: u32 = 0;
+pub const UART_THR: u32 = 0;
+pub const UART_IER: u32 = 1;
+pub const UART_IIR: u32 = 2;
+pub const UART_FCR: u32 = 2;
+pub const UART_LCR: u32 = 3;
+pub const UART_MCR: u32 = 4;
+pub const UART_LSR: u32 = 5;
+pub const UART_MSR: u32 = 6;
+pub const UART_SCR: u32 = 7;
+
+pub const LCR_DLAB: u8 = 0x80; //the divisor latch takes over offsets 0 and 1
+pub const LSR_DATA_READY: u8 = 0x01;
+pub const LSR_THR_EMPTY: u8 = 0x20;
+pub const LSR_TX_EMPTY: u8 = 0x40;
+const FCR_FIFO_ENABLE: u8 = 0x01;
+const IIR_NONE_PENDING: u8 = 0x01;
+const IIR_FIFOS_ON: u8 = 0xc0;
+
+#[derive(Clone)]
+pub struct Uart {
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    input: VecDeque<u8>,
+    output: Rc<RefCell<dyn Write>>, //shared, so snapshots of the CPU all write to the same place
+}
+
+impl Uart {
+    //sends to `output`, and has `input` waiting to be received.
+    pub fn new(output: Rc<RefCell<dyn Write>>, input: &[u8]) -> Uart {
+        Uart {
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            input: input.iter().copied().collect(),
+            output,
+        }
+    }
+
+    //one that sends to the real stdout.
+    pub fn stdout() -> Uart {
+        Uart::new(Rc::new(RefCell::new(io::stdout())), &[])
+    }
+
+    //everything sent gets collected into the returned buffer instead.
+    pub fn captured(input: &[u8]) -> (Uart, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        (Uart::new(output.clone(), input), output)
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    fn line_status(&self) -> u8 {
+        let ready = if self.input.is_empty() {
+            0
+        } else {
+            LSR_DATA_READY
+        };
+        ready | LSR_THR_EMPTY | LSR_TX_EMPTY
+    }
+
+    fn send(&mut self, byte: u8) {
+        let mut output = self.output.borrow_mut();
+        // a program can't do anything about the terminal going away, so errors are dropped.
+        let _ = output.write_all(&[byte]).and_then(|_| output.flush());
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> String {
+        "16550 UART".to_owned()
+    }
+
+    fn size(&self) -> u32 {
+        UART_SIZE
+    }
+
+    fn read(&self, offset: u32, _size: u32) -> u32 {
+        let value = match offset {
+            UART_RBR if self.dlab() => self.divisor as u8,
+            UART_RBR => self.input.front().copied().unwrap_or(0),
+            UART_IER if self.dlab() => (self.divisor >> 8) as u8,
+            UART_IER => self.ier,
+            UART_IIR if self.fcr & FCR_FIFO_ENABLE != 0 => IIR_FIFOS_ON | IIR_NONE_PENDING,
+            UART_IIR => IIR_NONE_PENDING,
+            UART_LCR => self.lcr,
+            UART_MCR => self.mcr,
+            UART_LSR => self.line_status(),
+            UART_SCR => self.scr,
+            _ => 0, //MSR: there's no modem
+        };
+        value as u32
+    }
+
+    fn read_done(&mut self, offset: u32, _size: u32) {
+        if offset == UART_RBR && !self.dlab() {
+            self.input.pop_front();
+        }
+    }
+
+    fn write(&mut self, offset: u32, _size: u32, value: u32) {
+        let byte = value as u8;
+        match offset {
+            UART_THR if self.dlab() => self.divisor = (self.divisor & 0xff00) | byte as u16,
+            UART_THR => self.send(byte),
+            UART_IER if self.dlab() => self.divisor = (self.divisor & 0x00ff) | (byte as u16) << 8,
+            UART_IER => self.ier = byte & 0x0f,
+            // the input counts as still arriving, so clearing the receive FIFO doesn't throw it away.
+            UART_FCR => self.fcr = byte & FCR_FIFO_ENABLE,
+            UART_LCR => self.lcr = byte,
+            UART_MCR => self.mcr = byte & 0x1f,
+            UART_SCR => self.scr = byte,
+            _ => {} //LSR and MSR are read-only
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,469 @@
+// RV32F: single-precision floating point. There are 32 more registers, f0-f31, and fcsr, which holds the rounding
+// mode (frm) and the exception flags (fflags). Inside the pipeline the FP registers are numbered 32-63, after the
+// integer ones, so forwarding and the hazard unit can tell an f5 from an x5 without knowing anything else about them.
+// FLW and FSW are a load and a store like any other. The rest work their results out in EX, but the adds, multiplies
+// and divides then go through the FPU for as many cycles as the config says before getting to MEM. (see fpu.rs)
+// Nothing traps except for illegal instructions. Invalid operations, dividing by zero, overflow, underflow
+// and inexact results just set flags in fflags, which stay set until the program clears them.
+// The arithmetic is done in f64, which is exact for products, along with which way the f64 result is off
+// from the exact one. That's enough to round it to f32 correctly in every rounding mode.
+
+pub const OPCODE_LOAD_FP: u8 = 0b0000111;
+pub const OPCODE_STORE_FP: u8 = 0b0100111;
+pub const OPCODE_OP_FP: u8 = 0b1010011;
+pub const OPCODE_FMADD: u8 = 0b1000011;
+pub const OPCODE_FMSUB: u8 = 0b1000111;
+pub const OPCODE_FNMSUB: u8 = 0b1001011;
+pub const OPCODE_FNMADD: u8 = 0b1001111;
+
+//where the FP registers start, in the pipeline's register indices.
+pub const FP_REGISTERS: u8 = 32;
+
+// rounding modes, from an instruction's rm field (funct3) or frm
+pub const RNE: u8 = 0; //to nearest, ties to even
+pub const RTZ: u8 = 1; //towards zero
+pub const RDN: u8 = 2; //down
+pub const RUP: u8 = 3; //up
+pub const RMM: u8 = 4; //to nearest, ties away from zero
+pub const DYN: u8 = 7; //whatever frm says
+
+// the exception flags, as bits of fflags
+pub const NX: u8 = 1; //inexact
+pub const UF: u8 = 2; //underflow
+pub const OF: u8 = 4; //overflow
+pub const DZ: u8 = 8; //divide by zero
+pub const NV: u8 = 16; //invalid operation
+
+//every NaN an operation makes is this one.
+pub const CANONICAL_NAN: u32 = 0x7fc00000;
+
+const SIGN: u32 = 1 << 31;
+const QUIET: u32 = 1 << 22;
+
+//the part of the FPU an instruction goes through after EX.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FpUnit {
+    Adder,      //adds, subtracts, and conversions to and from integers
+    Multiplier, //multiplies, and the fused multiply-adds
+    Divider,    //divides and square roots. it isn't pipelined
+}
+
+pub fn is_float(opcode: u8) -> bool {
+    opcode == OPCODE_OP_FP || is_fused(opcode)
+}
+
+pub fn is_fused(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        OPCODE_FMADD | OPCODE_FMSUB | OPCODE_FNMSUB | OPCODE_FNMADD
+    )
+}
+
+fn funct7(instruction: u32) -> u32 {
+    instruction >> 25
+}
+
+fn funct3(instruction: u32) -> u8 {
+    ((instruction >> 12) & 0b111) as u8
+}
+
+fn rs2(instruction: u32) -> u32 {
+    (instruction >> 20) & 0b11111
+}
+
+//which unit the instruction needs after EX, or None if EX is all it takes: compares, min/max, sign injection, moves.
+pub fn unit(instruction: u32) -> Option<FpUnit> {
+    let opcode = (instruction & 0b1111111) as u8;
+    if is_fused(opcode) {
+        return Some(FpUnit::Multiplier);
+    }
+    if opcode != OPCODE_OP_FP {
+        return None;
+    }
+    match funct7(instruction) {
+        0x00 | 0x04 | 0x60 | 0x68 => Some(FpUnit::Adder), //FADD, FSUB, FCVT.W[U].S, FCVT.S.W[U]
+        0x08 => Some(FpUnit::Multiplier),                 //FMUL
+        0x0c | 0x2c => Some(FpUnit::Divider),             //FDIV, FSQRT
+        _ => None,
+    }
+}
+
+//which of the instruction's registers are FP ones: (rd, rs1, rs2), or None if it isn't an RV32F instruction.
+// rs2 is None when the instruction has no rs2, and those bits are part of the opcode instead.
+pub fn fp_registers(instruction: u32) -> Option<(bool, bool, Option<bool>)> {
+    let opcode = (instruction & 0b1111111) as u8;
+    match opcode {
+        OPCODE_LOAD_FP => Some((true, false, None)),
+        OPCODE_STORE_FP => Some((false, false, Some(true))),
+        _ if is_fused(opcode) => Some((true, true, Some(true))),
+        OPCODE_OP_FP => Some(match funct7(instruction) {
+            0x2c => (true, true, None),         //FSQRT
+            0x50 => (false, true, Some(true)),  //FEQ, FLT, FLE
+            0x60 | 0x70 => (false, true, None), //FCVT.W[U].S, FMV.X.W, FCLASS
+            0x68 | 0x78 => (true, false, None), //FCVT.S.W[U], FMV.W.X
+            _ => (true, true, Some(true)),
+        }),
+        _ => None,
+    }
+}
+
+//the instructions that round, and so have an rm field instead of a funct3.
+pub fn uses_rounding_mode(instruction: u32) -> bool {
+    let opcode = (instruction & 0b1111111) as u8;
+    is_fused(opcode)
+        || (opcode == OPCODE_OP_FP
+            && matches!(
+                funct7(instruction),
+                0x00 | 0x04 | 0x08 | 0x0c | 0x2c | 0x60 | 0x68
+            ))
+}
+
+//the rounding mode an instruction uses, or None if it's one of the reserved ones.
+pub fn rounding_mode(instruction: u32, frm: u8) -> Option<u8> {
+    let rm = match funct3(instruction) {
+        DYN => frm,
+        rm => rm,
+    };
+    (rm <= RMM).then_some(rm)
+}
+
+//checks every field of an RV32F instruction. (a dynamic rounding mode also needs a valid frm. see trap.rs)
+pub fn is_legal(instruction: u32) -> bool {
+    let opcode = (instruction & 0b1111111) as u8;
+    let funct3 = funct3(instruction);
+    let rm = !matches!(funct3, 0b101 | 0b110);
+    match opcode {
+        OPCODE_LOAD_FP | OPCODE_STORE_FP => funct3 == 0b010, //FLW, FSW
+        _ if is_fused(opcode) => (instruction >> 25) & 0b11 == 0 && rm, //fmt 0 is single precision
+        OPCODE_OP_FP => match funct7(instruction) {
+            0x00 | 0x04 | 0x08 | 0x0c => rm,
+            0x2c => rs2(instruction) == 0 && rm,
+            0x10 | 0x50 => funct3 <= 0b010,
+            0x14 => funct3 <= 0b001,
+            0x60 | 0x68 => rs2(instruction) <= 1 && rm,
+            0x70 => rs2(instruction) == 0 && funct3 <= 0b001,
+            0x78 => rs2(instruction) == 0 && funct3 == 0,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+//The result of an OP-FP or fused multiply-add instruction, and the flags it raises.
+// a, b and c are rs1, rs2 and rs3: FP registers as bits, or integer registers for the ones that read those.
+// frm is for the instructions with a dynamic rounding mode.
+pub fn fp_execute(instruction: u32, a: u32, b: u32, c: u32, frm: u8) -> (u32, u8) {
+    let opcode = (instruction & 0b1111111) as u8;
+    let funct3 = funct3(instruction);
+    let rm = if uses_rounding_mode(instruction) {
+        rounding_mode(instruction, frm)
+    } else {
+        Some(RNE) //(funct3 picks the operation instead)
+    };
+    let Some(rm) = rm else {
+        return (0, 0); //a reserved rounding mode is illegal. it traps once it leaves EX
+    };
+    let (x, y, z) = (f32::from_bits(a), f32::from_bits(b), f32::from_bits(c));
+    match opcode {
+        OPCODE_FMADD => fused(x, y, z, rm),
+        OPCODE_FMSUB => fused(x, y, -z, rm),
+        OPCODE_FNMSUB => fused(-x, y, z, rm),
+        OPCODE_FNMADD => fused(-x, y, -z, rm),
+        OPCODE_OP_FP => match funct7(instruction) {
+            0x00 => add(x, y, rm),  //FADD.S
+            0x04 => add(x, -y, rm), //FSUB.S
+            0x08 => multiply(x, y, rm),
+            0x0c => divide(x, y, rm),
+            0x2c => square_root(x, rm),
+            0x10 => match funct3 {
+                0b000 => ((a & !SIGN) | (b & SIGN), 0), //FSGNJ.S, which is also FMV.S
+                0b001 => ((a & !SIGN) | (!b & SIGN), 0), //FSGNJN.S, also FNEG.S
+                0b010 => (a ^ (b & SIGN), 0),           //FSGNJX.S, also FABS.S
+                _ => (0, 0),
+            },
+            0x14 => min_max(x, y, funct3 == 0b001), //FMIN.S, FMAX.S
+            0x50 => compare(funct3, x, y),
+            0x60 => to_integer(x, rs2(instruction) == 0, rm), //FCVT.W.S, FCVT.WU.S
+            0x68 if rs2(instruction) == 0 => round(a as i32 as f64, 0.0, rm), //FCVT.S.W
+            0x68 => round(a as f64, 0.0, rm),                 //FCVT.S.WU
+            0x70 if funct3 == 0 => (a, 0),                    //FMV.X.W, the bits as they are
+            0x70 => (classify(x), 0),                         //FCLASS.S
+            0x78 => (a, 0),                                   //FMV.W.X
+            _ => (0, 0),                                      //illegal, traps once it leaves EX
+        },
+        _ => (0, 0),
+    }
+}
+
+fn is_signaling(x: f32) -> bool {
+    x.is_nan() && x.to_bits() & QUIET == 0
+}
+
+//any operation on a NaN gives the canonical NaN. signaling ones are invalid operations too.
+fn nan(operands: &[f32]) -> (u32, u8) {
+    let invalid = operands.iter().any(|x| is_signaling(*x));
+    (CANONICAL_NAN, if invalid { NV } else { 0 })
+}
+
+//the error of an f64 addition, exactly: a + b is sum + the error. (Knuth's TwoSum)
+fn two_sum_error(a: f64, b: f64, sum: f64) -> f64 {
+    if !sum.is_finite() {
+        return 0.0;
+    }
+    let b_part = sum - a;
+    let a_part = sum - b_part;
+    (a - a_part) + (b - b_part)
+}
+
+fn add(x: f32, y: f32, rm: u8) -> (u32, u8) {
+    if x.is_nan() || y.is_nan() {
+        return nan(&[x, y]);
+    }
+    if x.is_infinite() && y.is_infinite() && x.is_sign_negative() != y.is_sign_negative() {
+        return (CANONICAL_NAN, NV); //inf - inf
+    }
+    sum(x as f64, y as f64, rm)
+}
+
+//rounds a + b, where both are exact.
+fn sum(a: f64, b: f64, rm: u8) -> (u32, u8) {
+    let mut sum = a + b;
+    // an exact zero is +0, unless both were -0. except when rounding down, where it's -0 unless both were +0.
+    if sum == 0.0 && rm == RDN && (a.is_sign_negative() || b.is_sign_negative()) {
+        sum = -0.0;
+    }
+    round(sum, two_sum_error(a, b, sum), rm)
+}
+
+fn multiply(x: f32, y: f32, rm: u8) -> (u32, u8) {
+    if x.is_nan() || y.is_nan() {
+        return nan(&[x, y]);
+    }
+    if (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite()) {
+        return (CANONICAL_NAN, NV);
+    }
+    round(x as f64 * y as f64, 0.0, rm) //24 bits times 24 bits fits in an f64 exactly
+}
+
+fn fused(x: f32, y: f32, z: f32, rm: u8) -> (u32, u8) {
+    // inf * 0 is invalid even when z is a quiet NaN.
+    if (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite()) {
+        return (CANONICAL_NAN, NV);
+    }
+    if x.is_nan() || y.is_nan() || z.is_nan() {
+        return nan(&[x, y, z]);
+    }
+    let product = x as f64 * y as f64;
+    if product.is_infinite()
+        && z.is_infinite()
+        && product.is_sign_negative() != z.is_sign_negative()
+    {
+        return (CANONICAL_NAN, NV);
+    }
+    sum(product, z as f64, rm)
+}
+
+fn divide(x: f32, y: f32, rm: u8) -> (u32, u8) {
+    if x.is_nan() || y.is_nan() {
+        return nan(&[x, y]);
+    }
+    if (x == 0.0 && y == 0.0) || (x.is_infinite() && y.is_infinite()) {
+        return (CANONICAL_NAN, NV);
+    }
+    if y == 0.0 && x.is_finite() {
+        return ((x / y).to_bits(), DZ); //a correctly signed infinity
+    }
+    let (x, y) = (x as f64, y as f64);
+    let quotient = x / y;
+    // the remainder says which way the quotient is off. it's exact, so this is too.
+    let error = if quotient.is_finite() && quotient != 0.0 {
+        (-quotient).mul_add(y, x) * y.signum()
+    } else {
+        0.0
+    };
+    round(quotient, error, rm)
+}
+
+fn square_root(x: f32, rm: u8) -> (u32, u8) {
+    if x.is_nan() {
+        return nan(&[x]);
+    }
+    if x < 0.0 {
+        return (CANONICAL_NAN, NV); //(the square root of -0 is -0)
+    }
+    let x = x as f64;
+    let root = x.sqrt();
+    let error = if root.is_finite() && root != 0.0 {
+        (-root).mul_add(root, x)
+    } else {
+        0.0
+    };
+    round(root, error, rm)
+}
+
+fn min_max(x: f32, y: f32, max: bool) -> (u32, u8) {
+    let flags = if is_signaling(x) || is_signaling(y) {
+        NV
+    } else {
+        0
+    };
+    // a NaN only comes out if both are. -0 counts as less than +0.
+    let result = if x.is_nan() && y.is_nan() {
+        CANONICAL_NAN
+    } else if x.is_nan() {
+        y.to_bits()
+    } else if y.is_nan() || (x == y && x.is_sign_negative() != max) || (x != y && (x < y) != max) {
+        x.to_bits()
+    } else {
+        y.to_bits()
+    };
+    (result, flags)
+}
+
+//FEQ.S only complains about signaling NaNs. FLT.S and FLE.S complain about any NaN.
+fn compare(funct3: u8, x: f32, y: f32) -> (u32, u8) {
+    let (result, signaling) = match funct3 {
+        0b010 => (x == y, false), //FEQ.S
+        0b001 => (x < y, true),   //FLT.S
+        _ => (x <= y, true),      //FLE.S
+    };
+    let invalid = if signaling {
+        x.is_nan() || y.is_nan()
+    } else {
+        is_signaling(x) || is_signaling(y)
+    };
+    (result as u32, if invalid { NV } else { 0 })
+}
+
+//FCVT.W.S and FCVT.WU.S. out of range values (and NaNs) saturate, and are invalid instead of inexact.
+fn to_integer(x: f32, signed: bool, rm: u8) -> (u32, u8) {
+    let (min, max) = if signed {
+        (i32::MIN as f64, i32::MAX as f64)
+    } else {
+        (0.0, u32::MAX as f64)
+    };
+    let value = x as f64;
+    let rounded = match rm {
+        RTZ => value.trunc(),
+        RDN => value.floor(),
+        RUP => value.ceil(),
+        RMM => value.round(),
+        _ => value.round_ties_even(),
+    };
+    let saturated = |value: f64| {
+        if signed {
+            value as i32 as u32
+        } else {
+            value as u32
+        }
+    };
+    if x.is_nan() || rounded > max {
+        (saturated(max), NV)
+    } else if rounded < min {
+        (saturated(min), NV)
+    } else {
+        (saturated(rounded), if rounded != value { NX } else { 0 })
+    }
+}
+
+//FCLASS.S: one bit, for what kind of number it is.
+fn classify(x: f32) -> u32 {
+    let negative = x.is_sign_negative();
+    let bit = if x.is_nan() {
+        if is_signaling(x) {
+            8
+        } else {
+            9
+        }
+    } else if x.is_infinite() {
+        if negative {
+            0
+        } else {
+            7
+        }
+    } else if x == 0.0 {
+        if negative {
+            3
+        } else {
+            4
+        }
+    } else if x.is_subnormal() {
+        if negative {
+            2
+        } else {
+            5
+        }
+    } else if negative {
+        1
+    } else {
+        6
+    };
+    1 << bit
+}
+
+//Rounds an f64 to f32, and says which flags that raises. `error` is which way the exact value is from `value`,
+// for when it's exactly on an f32 or halfway between two. (only its sign matters)
+// Underflow is checked after rounding, like RISC-V does it: when the result would have been
+// smaller than the smallest normal number even with an unbounded exponent.
+pub fn round(value: f64, error: f64, rm: u8) -> (u32, u8) {
+    let result = nearest(value, error, rm);
+    if result as f64 == value && error == 0.0 {
+        return (result.to_bits(), 0);
+    }
+    let mut flags = NX;
+    if result.is_infinite() || value.abs() >= 2f64.powi(128) {
+        flags |= OF;
+    }
+    // scaling it up by 2^64 is exact, and keeps it well clear of the subnormals.
+    if nearest(value * 2f64.powi(64), error, rm).abs() < 2f32.powi(-126 + 64) {
+        flags |= UF;
+    }
+    (result.to_bits(), flags)
+}
+
+//the f32 that value + error rounds to.
+fn nearest(value: f64, error: f64, rm: u8) -> f32 {
+    let closest = value as f32; //(rounds to nearest, ties to even)
+    if closest as f64 == value && error == 0.0 {
+        return closest;
+    }
+    let (below, above) = if (closest as f64) < value || (closest as f64 == value && error > 0.0) {
+        (closest, closest.next_up())
+    } else {
+        (closest.next_down(), closest)
+    };
+    // for finding the halfway point, infinity is where the next number would be.
+    let unbounded = |x: f32| {
+        if x.is_infinite() {
+            2f64.powi(128).copysign(x as f64)
+        } else {
+            x as f64
+        }
+    };
+    let halfway = (unbounded(below) + unbounded(above)) / 2.0;
+    let side = if value != halfway {
+        value - halfway
+    } else {
+        error
+    };
+    let away = if above.abs() > below.abs() {
+        above
+    } else {
+        below
+    };
+    let towards_zero = if above.abs() < below.abs() {
+        above
+    } else {
+        below
+    };
+    match rm {
+        RTZ => towards_zero,
+        RDN => below,
+        RUP => above,
+        _ if side > 0.0 => above,
+        _ if side < 0.0 => below,
+        RMM => away,
+        _ if above.to_bits() & 1 == 0 => above, //RNE, ties to even
+        _ => below,
+    }
+}
@@ -7,24 +7,79 @@ pub mod isa {
         Btype,
         Utype,
         Jtype,
+        Illegal, //not an instruction at all. it traps once it gets past EX.
     }
 
     pub fn get_instruction_type(opcode: u8) -> InstrT {
         //takes in the opcode of an instruction, and returns what instruction type it is.
         match opcode {
-            0b0110111 => InstrT::Utype, //LUI
-            0b0010111 => InstrT::Utype, //AUIPC
-            0b1101111 => InstrT::Jtype, //JAL
-            0b1100111 => InstrT::Itype, //JALR
+            0b0110111 => InstrT::Utype,                                     //LUI
+            0b0010111 => InstrT::Utype,                                     //AUIPC
+            0b1101111 => InstrT::Jtype,                                     //JAL
+            0b1100111 => InstrT::Itype,                                     //JALR
             0b1100011 => InstrT::Btype, //BEQ, BNE, BLT, BGE, BLTU, BGEU
             0b0000011 => InstrT::Itype, //LB, LH, LW, LBU, LHU
             0b0100011 => InstrT::Stype, //SB, SH, SW
-            0b0010011 => InstrT::Itype, //ADDI, SLTI, SLTIU, XORI, ORI, ANDI, SLLI, SRLI, SRAI
-            0b0110011 => InstrT::Rtype, //ADD, SUB, SLL, SLT, SLTU, XOR, SRL, SRA, OR, AND
+            0b0010011 => InstrT::Itype, //ADDI, SLTI, SLTIU, XORI, ORI, ANDI, SLLI, SRLI, SRAI, and Zbb/Zbs's CLZ, RORI, BSETI...
+            0b0110011 => InstrT::Rtype, //ADD, SUB, SLL, SLT, SLTU, XOR, SRL, SRA, OR, AND, RV32M's MUL, DIV..., and Zba/Zbb/Zbs's SH1ADD, ANDN, BSET...
+            0b0101111 => InstrT::Rtype, //RV32A's LR.W, SC.W, AMOSWAP.W, AMOADD.W...
+            0b0000111 => InstrT::Itype, //RV32F's FLW
+            0b0100111 => InstrT::Stype, //FSW
+            0b1010011 => InstrT::Rtype, //FADD.S, FMUL.S, FCVT.W.S, FEQ.S...
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => InstrT::Rtype, //FMADD.S, FMSUB.S, FNMSUB.S, FNMADD.S. (R4-type)
             0b0001111 => InstrT::Itype, //FENCE, FENCE.I  (not usre about these two? I dont understand them)
             0b1110011 => InstrT::Itype, //ECALL, EBREAK, CSRRW, CSRRS, CSRRC, CSRRWI, CSRRSI, CSRRCI
             0b0000000 => InstrT::Rtype, //NOP doesnt really have immediates, or any value. so it doesnt matter
-            _ => panic!("Invalid instruction opcode encountered!"),
+            _ => InstrT::Illegal,
+        }
+    }
+
+    pub const MRET: u32 = 0x30200073;
+    pub const WFI: u32 = 0x10500073;
+
+    //checks every field of an instruction, not just the opcode. anything false here is an illegal instruction.
+    // whether a CSR exists gets checked separately, since that depends on the CSR file.
+    pub fn is_legal(instruction: u32) -> bool {
+        let funct3 = (instruction >> 12) & 0b111;
+        let funct7 = instruction >> 25;
+        match instruction & 0b1111111 {
+            0b0110111 | 0b0010111 | 0b1101111 => true, //LUI, AUIPC, JAL
+            0b1100111 => funct3 == 0b000,              //JALR
+            0b1100011 => funct3 != 0b010 && funct3 != 0b011, //branches
+            0b0000011 => matches!(funct3, 0b000 | 0b001 | 0b010 | 0b100 | 0b101), //loads
+            0b0100011 => funct3 <= 0b010,              //stores
+            0b0010011 if crate::bitmanip::is_bitmanip(instruction) => true, //Zbb, Zbs
+            0b0010011 => match funct3 {
+                0b001 => funct7 == 0,                        //SLLI
+                0b101 => funct7 == 0 || funct7 == 0b0100000, //SRLI, SRAI
+                _ => true,
+            },
+            0b0110011 => {
+                funct7 == 0
+                    || funct7 == 0b0000001 //RV32M: multiply and divide
+                    || (funct7 == 0b0100000 && (funct3 == 0b000 || funct3 == 0b101))
+                    || crate::bitmanip::is_bitmanip(instruction) //Zba, Zbb, Zbs
+            }
+            0b0101111 => {
+                //RV32A, by funct5. LR has no rs2. (aq and rl can be anything)
+                let rs2 = (instruction >> 20) & 0b11111;
+                funct3 == 0b010
+                    && match funct7 >> 2 {
+                        0b00010 => rs2 == 0,
+                        0b00011 | 0b00001 | 0b00000 | 0b00100 | 0b01100 | 0b01000 => true,
+                        0b10000 | 0b10100 | 0b11000 | 0b11100 => true,
+                        _ => false,
+                    }
+            }
+            0b0000111 | 0b0100111 | 0b1010011 => crate::float::is_legal(instruction), //RV32F
+            0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 => crate::float::is_legal(instruction),
+            0b0001111 => funct3 <= 0b001, //FENCE, FENCE.I. there are no caches to keep in sync (yet)
+            0b1110011 => match funct3 {
+                0b000 => matches!(instruction, 0x00000073 | 0x00100073 | MRET | WFI),
+                0b100 => false,
+                _ => true, //the CSR instructions
+            },
+            _ => instruction == 0, //a bubble
         }
     }
 }
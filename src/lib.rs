@@ -0,0 +1,25 @@
+// The simulator itself. The binary in main.rs is just a command-line front end for it.
+
+pub mod assembler;
+pub mod atomic;
+pub mod bitmanip;
+pub mod cache;
+pub mod components;
+pub mod compressed;
+pub mod config;
+pub mod csr;
+pub mod device;
+pub mod float;
+pub mod isa;
+pub mod loader;
+pub mod mem;
+pub mod muldiv;
+pub mod predictor;
+pub mod report;
+pub mod simulator;
+pub mod syscall;
+pub mod termination;
+pub mod trap;
+
+pub use config::Config;
+pub use simulator::Simulator;
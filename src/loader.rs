@@ -0,0 +1,229 @@
+mod elf;
+mod image;
+
+pub use crate::mem::Memory;
+pub use elf::*;
+pub use image::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+//Where the stack starts for programs that expect the environment to set up $sp (compiled C does),
+// if the memory map doesn't have a stack region.
+pub const DEFAULT_STACK_TOP: u32 = 0x7ffffff0;
+
+// Everything needed to set up the CPU for a program:
+//  what goes into memory, where the code ends, and where to start.
+#[derive(Clone, Default)]
+pub struct Program {
+    pub memory: Memory,  //code and data both, at the addresses they get loaded to
+    pub text_start: u32, //the first instruction. (not always where it starts running, which is the entry)
+    pub text_end: u32, //one past the last instruction. the program has run off the end once the pc gets here
+    pub end: u32,      //one past the last byte of anything that got loaded
+    pub entry: u32,    //initial pc
+    pub stack_top: Option<u32>, //initial $sp, if the program needs one
+    pub symbols: BTreeMap<u32, String>, //address -> name
+}
+
+impl Program {
+    //wraps a plain list of instructions starting at address 0, like the binary-text format.
+    pub fn from_instructions(instructions: Vec<u32>) -> Program {
+        let mut memory = Memory::new();
+        for (index, instr) in instructions.iter().enumerate() {
+            memory.write_word(index as u32 * 4, *instr);
+        }
+        let end = instructions.len() as u32 * 4;
+        Program {
+            memory,
+            text_end: end,
+            end,
+            ..Program::default()
+        }
+    }
+
+    //the words from the start of the code up to the end of it.
+    pub fn instructions(&self) -> Vec<u32> {
+        (self.text_start..self.text_end)
+            .step_by(4)
+            .map(|address| self.memory.read_word(address))
+            .collect()
+    }
+
+    //finds the address of a symbol by its name
+    pub fn symbol_address(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(_, sym)| sym.as_str() == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    //names an address after the closest symbol at or below it, like "main+0x8"
+    pub fn symbolize(&self, address: u32) -> Option<String> {
+        let (base, name) = self.symbols.range(..=address).next_back()?;
+        if *base == address {
+            Some(name.clone())
+        } else {
+            Some(format!("{}+{:#x}", name, address - base))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadError {
+    pub message: String,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn error(message: &str) -> LoadError {
+    LoadError {
+        message: message.to_owned(),
+    }
+}
+
+// Biggest raw binary image we're willing to load. (or ELF section or segment)
+const MAX_IMAGE_SIZE: u32 = 0x1000_0000;
+
+// =======================
+// Picking a loader
+// =======================
+
+//All of the program file formats that can be loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Elf,
+    Assembly,
+    BinaryText, //one 32-bit binary string per line
+    RawBinary,
+    IntelHex,
+    MemHex, //Verilog $readmemh
+}
+
+impl Format {
+    //the names accepted by --format
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "elf" => Some(Format::Elf),
+            "asm" | "s" | "assembly" => Some(Format::Assembly),
+            "text" | "bintext" => Some(Format::BinaryText),
+            "bin" | "raw" => Some(Format::RawBinary),
+            "ihex" | "hex" => Some(Format::IntelHex),
+            "memh" | "readmemh" => Some(Format::MemHex),
+            _ => None,
+        }
+    }
+}
+
+//Guesses a file's format. Magic numbers win, then the file extension, then what the text looks like.
+pub fn detect_format(path: &str, bytes: &[u8]) -> Format {
+    if is_elf(bytes) {
+        return Format::Elf;
+    }
+
+    let extension = match path.rsplit_once('.') {
+        Some((_, ext)) if !ext.contains('/') => ext.to_lowercase(),
+        _ => String::new(),
+    };
+    let text = std::str::from_utf8(bytes).ok();
+    let first_line = text
+        .and_then(|t| t.lines().map(|l| l.trim()).find(|l| !l.is_empty()))
+        .unwrap_or("");
+
+    match extension.as_str() {
+        "s" | "asm" => return Format::Assembly,
+        "bin" => return Format::RawBinary,
+        "ihex" | "ihx" => return Format::IntelHex,
+        "mem" | "memh" | "vmem" => return Format::MemHex,
+        "hex" => {
+            // .hex gets used for both, Intel HEX always starts its records with a colon.
+            if first_line.starts_with(':') {
+                return Format::IntelHex;
+            } else {
+                return Format::MemHex;
+            }
+        }
+        _ => {}
+    }
+
+    match text {
+        None => Format::RawBinary, //not text at all
+        Some(text) => {
+            let binary_lines = text
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .all(|l| l.len() == 32 && l.chars().all(|c| c == '0' || c == '1'));
+            if binary_lines {
+                Format::BinaryText
+            } else if first_line.starts_with(':') {
+                Format::IntelHex
+            } else if first_line.starts_with('@')
+                || first_line.starts_with("//")
+                || first_line
+                    .split_whitespace()
+                    .all(|t| t.chars().all(|c| c.is_ascii_hexdigit() || c == '_'))
+            {
+                Format::MemHex
+            } else {
+                Format::Assembly
+            }
+        }
+    }
+}
+
+//Loads a program file. If no format is given, it gets guessed by detect_format.
+pub fn load_file(path: &str, format: Option<Format>) -> Result<Program, LoadError> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(error(&format!("could not read {}: {}", path, e))),
+    };
+
+    let format = format.unwrap_or_else(|| detect_format(path, &bytes));
+    let text = String::from_utf8_lossy(&bytes);
+
+    let result = match format {
+        Format::Elf => load_elf(&bytes),
+        Format::Assembly => match crate::assembler::assemble_with_labels(&text) {
+            Ok((instructions, labels)) => Ok(Program {
+                symbols: labels
+                    .into_iter()
+                    .map(|(name, addr)| (addr, name))
+                    .collect(),
+                ..Program::from_instructions(instructions)
+            }),
+            Err(e) => Err(error(&e.to_string())),
+        },
+        Format::BinaryText => load_binary_text(&text),
+        Format::RawBinary => load_raw_binary(&bytes),
+        Format::IntelHex => load_intel_hex(&text),
+        Format::MemHex => load_memh(&text),
+    };
+
+    // say which file went wrong
+    result.map_err(|e| error(&format!("{}: {}", path, e)))
+}
+
+//the original program format: one instruction per line, written out as 32 binary digits.
+pub fn load_binary_text(text: &str) -> Result<Program, LoadError> {
+    let mut instructions = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match u32::from_str_radix(line, 2) {
+            Ok(instr) => instructions.push(instr),
+            Err(_) => {
+                return Err(error(&format!(
+                    "line {}: '{}' is not a 32-bit binary number",
+                    index + 1,
+                    line
+                )))
+            }
+        }
+    }
+    Ok(Program::from_instructions(instructions))
+}
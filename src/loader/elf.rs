@@ -0,0 +1,217 @@
+use crate::loader::*;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u32 = 0x2;
+const SHF_EXECINSTR: u32 = 0x4;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 0x1;
+
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_NOTYPE: u8 = 0;
+
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[0..4] == ELF_MAGIC
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, LoadError> {
+    match bytes.get(offset..offset + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]])),
+        None => Err(error("ELF file is truncated")),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, LoadError> {
+    match bytes.get(offset..offset + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+        None => Err(error("ELF file is truncated")),
+    }
+}
+
+fn slice(bytes: &[u8], offset: u32, size: u32) -> Result<&[u8], LoadError> {
+    let start = offset as usize;
+    let end = start + size as usize;
+    match bytes.get(start..end) {
+        Some(b) => Ok(b),
+        None => Err(error("ELF section runs past the end of the file")),
+    }
+}
+
+// one section header, only the fields we need.
+struct Section {
+    kind: u32,
+    flags: u32,
+    addr: u32,
+    offset: u32,
+    size: u32,
+    link: u32,
+}
+
+//Loads a little-endian ELF32 RISC-V executable.
+// All of the allocated sections (.text, .data, .rodata, .bss, ...) go into memory where they were linked to,
+// the end of the executable ones is where the code ends, and the entry point becomes the starting pc.
+pub fn load_elf(bytes: &[u8]) -> Result<Program, LoadError> {
+    if !is_elf(bytes) {
+        return Err(error("not an ELF file"));
+    }
+    if bytes.len() < 52 {
+        return Err(error("ELF file is truncated"));
+    }
+    if bytes[4] != ELFCLASS32 {
+        return Err(error("only 32-bit ELF files are supported"));
+    }
+    if bytes[5] != ELFDATA2LSB {
+        return Err(error("only little-endian ELF files are supported"));
+    }
+    if read_u16(bytes, 18)? != EM_RISCV {
+        return Err(error("ELF file is not for RISC-V"));
+    }
+    if read_u16(bytes, 16)? != ET_EXEC {
+        return Err(error("ELF file is not an executable (was it linked?)"));
+    }
+
+    let entry = read_u32(bytes, 24)?;
+    let phoff = read_u32(bytes, 28)? as usize;
+    let shoff = read_u32(bytes, 32)? as usize;
+    let phentsize = read_u16(bytes, 42)? as usize;
+    let phnum = read_u16(bytes, 44)? as usize;
+    let shentsize = read_u16(bytes, 46)? as usize;
+    let shnum = read_u16(bytes, 48)? as usize;
+
+    let mut sections = Vec::new();
+    if shoff != 0 {
+        for i in 0..shnum {
+            let at = shoff + i * shentsize;
+            sections.push(Section {
+                kind: read_u32(bytes, at + 4)?,
+                flags: read_u32(bytes, at + 8)?,
+                addr: read_u32(bytes, at + 12)?,
+                offset: read_u32(bytes, at + 16)?,
+                size: read_u32(bytes, at + 20)?,
+                link: read_u32(bytes, at + 24)?,
+            });
+        }
+    }
+
+    let mut memory = Memory::new();
+    let mut text_start = None;
+    let mut text_end = None;
+    let mut end = 0;
+    //puts a section or segment's bytes into memory. `contents` is zero-padded out to `size`.
+    // the sizes come straight from the file, so a bad one could have us writing zeroes for a long time.
+    let mut load = |address: u32, size: u32, contents: &[u8], executable: bool| {
+        if size > MAX_IMAGE_SIZE {
+            return Err(error("ELF section or segment is too big"));
+        }
+        if address as u64 + size as u64 > 1 << 32 {
+            return Err(error("ELF section or segment runs past the top of memory"));
+        }
+        for i in 0..size {
+            let byte = contents.get(i as usize).copied().unwrap_or(0);
+            memory.write_byte(address.wrapping_add(i), byte);
+        }
+        let last = address.saturating_add(size);
+        if executable {
+            text_start = Some(text_start.map_or(address, |start: u32| start.min(address)));
+            text_end = text_end.max(Some(last));
+        }
+        end = end.max(last);
+        Ok(())
+    };
+
+    let allocated: Vec<&Section> = sections
+        .iter()
+        .filter(|s| s.flags & SHF_ALLOC != 0 && s.size > 0)
+        .collect();
+
+    if !allocated.is_empty() {
+        for section in allocated {
+            let executable = section.flags & SHF_EXECINSTR != 0;
+            if section.kind == SHT_NOBITS {
+                // .bss. takes no room in the file, just zeroes.
+                load(section.addr, section.size, &[], executable)?;
+            } else {
+                let contents = slice(bytes, section.offset, section.size)?;
+                load(section.addr, section.size, contents, executable)?;
+            }
+        }
+    } else {
+        // stripped of section headers, fall back on the loadable segments.
+        for i in 0..phnum {
+            let at = phoff + i * phentsize;
+            if read_u32(bytes, at)? != PT_LOAD {
+                continue;
+            }
+            let offset = read_u32(bytes, at + 4)?;
+            let vaddr = read_u32(bytes, at + 8)?;
+            let filesz = read_u32(bytes, at + 16)?;
+            let memsz = read_u32(bytes, at + 20)?;
+            let flags = read_u32(bytes, at + 24)?;
+
+            let contents = slice(bytes, offset, filesz)?;
+            load(vaddr, memsz, contents, flags & PF_X != 0)?;
+        }
+    }
+
+    let (Some(text_start), Some(text_end)) = (text_start, text_end) else {
+        return Err(error("ELF file has no executable code"));
+    };
+
+    Ok(Program {
+        memory,
+        text_start,
+        text_end,
+        end,
+        entry,
+        stack_top: Some(DEFAULT_STACK_TOP),
+        symbols: read_symbols(bytes, &sections)?,
+    })
+}
+
+//pulls the function and variable names out of .symtab
+fn read_symbols(bytes: &[u8], sections: &[Section]) -> Result<BTreeMap<u32, String>, LoadError> {
+    let mut symbols = BTreeMap::new();
+
+    for symtab in sections.iter().filter(|s| s.kind == SHT_SYMTAB) {
+        let strtab = match sections.get(symtab.link as usize) {
+            Some(s) => slice(bytes, s.offset, s.size)?,
+            None => continue,
+        };
+        let table = slice(bytes, symtab.offset, symtab.size)?;
+
+        // each symbol is 16 bytes: name, value, size, info, other, section index
+        for entry in table.chunks_exact(16) {
+            let name = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+            let value = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            let kind = entry[12] & 0xf;
+            let section_index = u16::from_le_bytes([entry[14], entry[15]]);
+
+            if name == 0 || section_index == 0 {
+                continue; // unnamed, or undefined
+            }
+            if kind != STT_FUNC && kind != STT_OBJECT && kind != STT_NOTYPE {
+                continue;
+            }
+            let text = match strtab.get(name..) {
+                Some(rest) => rest.split(|b| *b == 0).next().unwrap_or(&[]),
+                None => continue,
+            };
+            let text = String::from_utf8_lossy(text).into_owned();
+            // local labels like ".L3" just clutter things up.
+            if text.starts_with(".L") || text.starts_with('$') {
+                continue;
+            }
+            symbols.entry(value).or_insert(text);
+        }
+    }
+
+    Ok(symbols)
+}
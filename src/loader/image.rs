@@ -0,0 +1,218 @@
+use crate::loader::*;
+
+// Flat memory images, the kind RTL testbenches get fed.
+// None of these say which bytes are code and which are data, so the whole image counts as code,
+// from its first word to its last. Programs can still load constants that were placed next to their code.
+// They start running at that first word too, unless the image has a start address of its own (only Intel HEX can).
+
+fn program_from_image(bytes: &BTreeMap<u32, u8>, entry: Option<u32>) -> Result<Program, LoadError> {
+    let mut memory = Memory::new();
+    for (address, byte) in bytes {
+        memory.write_byte(*address, *byte);
+    }
+    let start = match bytes.keys().next() {
+        Some(first) => first & !3,
+        None => 0,
+    };
+    let end = match bytes.keys().next_back() {
+        Some(last) => (last / 4 + 1).saturating_mul(4),
+        None => 0,
+    };
+
+    Ok(Program {
+        memory,
+        text_start: start,
+        text_end: end,
+        end,
+        entry: entry.unwrap_or(start),
+        ..Program::default()
+    })
+}
+
+//A raw .bin dump: the bytes of memory in order, starting at address 0.
+pub fn load_raw_binary(bytes: &[u8]) -> Result<Program, LoadError> {
+    if bytes.len() as u64 > MAX_IMAGE_SIZE as u64 {
+        return Err(error("binary image is too big"));
+    }
+    let image: BTreeMap<u32, u8> = bytes
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| (i as u32, *byte))
+        .collect();
+    program_from_image(&image, None)
+}
+
+//Intel HEX. Each line is a record:  :LLAAAATT<data>CC
+//  LL byte count, AAAA address, TT record type, CC checksum.
+pub fn load_intel_hex(text: &str) -> Result<Program, LoadError> {
+    let mut image: BTreeMap<u32, u8> = BTreeMap::new();
+    let mut upper_address: u32 = 0; //set by the extended address records
+    let mut entry: Option<u32> = None; //set by the start address records
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let record = match line.strip_prefix(':') {
+            Some(record) => record,
+            None => return Err(line_error(line_no, "record does not start with ':'")),
+        };
+        if record.len() % 2 != 0 || record.len() < 10 {
+            return Err(line_error(line_no, "record is the wrong length"));
+        }
+
+        let mut fields = Vec::new();
+        for i in (0..record.len()).step_by(2) {
+            match u8::from_str_radix(&record[i..i + 2], 16) {
+                Ok(byte) => fields.push(byte),
+                Err(_) => return Err(line_error(line_no, "record has a non-hex character")),
+            }
+        }
+
+        // all of the bytes in a record, checksum included, add up to 0.
+        let sum = fields.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if sum != 0 {
+            return Err(line_error(line_no, "bad checksum"));
+        }
+
+        let count = fields[0] as usize;
+        if fields.len() != count + 5 {
+            return Err(line_error(line_no, "byte count does not match the record"));
+        }
+        let offset = ((fields[1] as u32) << 8) | fields[2] as u32;
+        let kind = fields[3];
+        let data = &fields[4..4 + count];
+
+        match kind {
+            0x00 => {
+                // data
+                for (i, byte) in data.iter().enumerate() {
+                    match upper_address.checked_add(offset + i as u32) {
+                        Some(address) => image.insert(address, *byte),
+                        None => {
+                            return Err(line_error(line_no, "data runs past the top of memory"))
+                        }
+                    };
+                }
+            }
+            0x01 => break, // end of file
+            0x02 if count == 2 => {
+                // extended segment address, the 8086 way: segment * 16
+                upper_address = (((data[0] as u32) << 8) | data[1] as u32) << 4;
+            }
+            0x03 if count == 4 => {
+                // start segment address, CS:IP
+                let cs = ((data[0] as u32) << 8) | data[1] as u32;
+                let ip = ((data[2] as u32) << 8) | data[3] as u32;
+                entry = Some((cs << 4) + ip);
+            }
+            0x04 if count == 2 => {
+                // extended linear address, the upper 16 bits
+                upper_address = (((data[0] as u32) << 8) | data[1] as u32) << 16;
+            }
+            0x05 if count == 4 => {
+                // start linear address
+                entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+            }
+            _ => return Err(line_error(line_no, "unknown or malformed record type")),
+        }
+    }
+
+    program_from_image(&image, entry)
+}
+
+//Verilog $readmemh files: whitespace-separated hex values, with "@addr" to jump around
+// and // or /* */ comments. Values of 2 digits or less mean the memory is byte-wide,
+// otherwise each value is one 32-bit word. "@addr" counts in those same units.
+pub fn load_memh(text: &str) -> Result<Program, LoadError> {
+    let mut tokens: Vec<(usize, String)> = Vec::new();
+    let mut in_block_comment = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let mut rest = line;
+        let mut kept = String::new();
+        loop {
+            if in_block_comment {
+                match rest.find("*/") {
+                    Some(end) => {
+                        rest = &rest[end + 2..];
+                        in_block_comment = false;
+                    }
+                    None => break,
+                }
+            } else {
+                let line_comment = rest.find("//");
+                let block_comment = rest.find("/*");
+                match (line_comment, block_comment) {
+                    (Some(l), Some(b)) if b < l => {
+                        kept.push_str(&rest[..b]);
+                        kept.push(' ');
+                        rest = &rest[b + 2..];
+                        in_block_comment = true;
+                    }
+                    (None, Some(b)) => {
+                        kept.push_str(&rest[..b]);
+                        kept.push(' ');
+                        rest = &rest[b + 2..];
+                        in_block_comment = true;
+                    }
+                    (Some(l), _) => {
+                        kept.push_str(&rest[..l]);
+                        break;
+                    }
+                    (None, None) => {
+                        kept.push_str(rest);
+                        break;
+                    }
+                }
+            }
+        }
+        for token in kept.split_whitespace() {
+            tokens.push((index + 1, token.replace('_', "")));
+        }
+    }
+
+    let byte_wide = tokens
+        .iter()
+        .filter(|(_, t)| !t.starts_with('@'))
+        .all(|(_, t)| t.len() <= 2);
+    let unit: u32 = if byte_wide { 1 } else { 4 };
+
+    let mut image: BTreeMap<u32, u8> = BTreeMap::new();
+    let mut address: u64 = 0; // in units, not bytes. (it can end up one past the top of memory)
+
+    for (line_no, token) in tokens {
+        if let Some(addr) = token.strip_prefix('@') {
+            address = match u32::from_str_radix(addr, 16) {
+                Ok(addr) => addr as u64,
+                Err(_) => return Err(line_error(line_no, "bad @address")),
+            };
+            continue;
+        }
+        let value = match u32::from_str_radix(&token, 16) {
+            Ok(value) => value,
+            Err(_) => {
+                return Err(line_error(
+                    line_no,
+                    &format!("'{}' is not a hex value", token),
+                ))
+            }
+        };
+        let byte_address = address * unit as u64;
+        if byte_address + unit as u64 > 1 << 32 {
+            return Err(line_error(line_no, "value is past the top of memory"));
+        }
+        for (i, byte) in value.to_le_bytes().iter().take(unit as usize).enumerate() {
+            image.insert(byte_address as u32 + i as u32, *byte);
+        }
+        address += 1;
+    }
+
+    program_from_image(&image, None)
+}
+
+fn line_error(line: usize, message: &str) -> LoadError {
+    error(&format!("line {}: {}", line, message))
+}
@@ -1,131 +1,357 @@
+use std::cell::RefCell;
 use std::env;
-use std::fs::read_to_string;
-use std::io;
-use std::time::Duration;
-use std::{hash::Hash, io::stdin, thread::sleep};
+use std::fs::{self, File};
+use std::io::{self, stdin, Write};
+use std::process;
+use std::rc::Rc;
 
-use components::*;
-use isa::isa::get_instruction_type;
+use cli::{Command, DisplayMode, Options};
+use loader::Program;
+use riscv_psim::components::*;
+use riscv_psim::*;
 
-pub mod components;
-pub mod isa;
-
-// a "snapshot", or backup, of the state of a CPU in a given cycle.
-struct Snapshot {
-    state: Registers,
-    logic: Logic,
-}
+mod cli;
 
 fn main() {
-    use crate::components::*;
-    use std::collections::hash_map::*;
-
     //get commandline arguments
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        panic!("The program needs 1 filename, and just 1, as its argument.");
+    let options = match cli::parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    match run_command(&options) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
     }
-    //now args[1] is the filename...
-    println!("{}", args[1]);
+}
+
+//Does whatever the command line asked for. Returns the exit code.
+fn run_command(options: &Options) -> Result<i32, String> {
+    let program = loader::load_file(&options.program, options.format).map_err(|e| e.to_string())?;
 
-    // code for obtaining instructions here. Not sure how to do it... from a file, maybe?
+    let mut out: Box<dyn Write> = match &options.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => return Err(format!("could not create {}: {}", path, e)),
+        },
+        None => Box::new(io::stdout()),
+    };
 
-    let mut instructions = Vec::new();
+    if options.command == Command::Disasm {
+        disassemble(&program, &mut out).map_err(|e| e.to_string())?;
+        return Ok(0);
+    }
 
-    for line in read_to_string(&args[1]).unwrap().lines() {
-        instructions.push(u32::from_str_radix(line, 2).unwrap());
+    if options.command == Command::Step {
+        println!("{}", options.program);
+        if let Some(name) = program.symbolize(program.entry) {
+            println!("Entry point: {:#010x} <{}>", program.entry, name);
+        }
     }
 
-    /*let instructions = Vec::<u32>::from([
-        0b00000000000100000000000010010011,
-        0b00000000001000000000000100010011,
-        0b00000000011000010000000110010011,
-        0b00000000001000000000001000010011,
-        0b00000000000100000000001010010011,
-    ]);*/
+    let mut sim = setup_simulator(program, options)?;
+
+    let settings = RunSettings {
+        interactive: options.command == Command::Step,
+        display: match options.command {
+            Command::Step => options.display,
+            _ => DisplayMode::None,
+        },
+        trace: options.command == Command::Trace,
+    };
+
+    run_with(&mut sim, &settings, &mut out)
+        .map_err(|e| format!("could not write output: {}", e))?;
 
-    //CPU SETUP: Initializes the state and logic structs.s
-    let mut state = Registers {
-        ifid: IFIDLatch::default(),
-        idex: IDEXLatch::default(),
-        exmem: EXMEMLatch::default(),
-        memwb: MEMWBLatch::default(),
+    if let Some(format) = options.report {
+        write!(out, "{}", sim.report(format)).map_err(|e| e.to_string())?;
+    }
 
-        pc: 0,
+    let reason = sim.stop_reason().unwrap_or(StopReason::Quit);
+    match reason {
+        StopReason::CycleLimit(cycles) => eprintln!(
+            "Stopped after {} cycles without finishing (--max-cycles).",
+            cycles
+        ),
+        StopReason::UnhandledTrap(trap) => eprintln!("Unhandled trap: {}.", trap.describe()),
+        _ => {}
+    }
+    Ok(reason.exit_code())
+}
 
-        instr_mem: instructions,
-        reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-        data_mem: HashMap::new(),
+//builds the simulator for a program, with any registers and memory the user asked to preload.
+fn setup_simulator(program: Program, options: &Options) -> Result<Simulator, String> {
+    let halt_address = match &options.halt_at {
+        Some(text) => Some(resolve_address(&program, text)?),
+        None => None,
+    };
+    let tohost = match &options.tohost {
+        Some(text) => Some(resolve_address(&program, text)?),
+        None => None, //the simulator looks for a "tohost" symbol itself
     };
+    let reset_vector = match &options.reset_vector {
+        Some(text) => Some(resolve_address(&program, text)?),
+        None => None,
+    };
+
+    let mut sim = Simulator::new(Config {
+        halt_address,
+        tohost,
+        max_cycles: options.max_cycles,
+        forwarding: options.forwarding,
+        model: options.model,
+        predictor: options.predictor,
+        btb_entries: options.btb_entries,
+        early_branches: options.early_branches,
+        memory: MemoryConfig {
+            map: options.memory_map.clone(),
+            caches: options.caches.clone(),
+            memory_latency: options.memory_latency,
+            icache: options.icache.clone(),
+            icache_miss_penalty: options.icache_penalty,
+        },
+        latencies: Latencies {
+            mul: options.mul_latency,
+            div: options.div_latency,
+            fadd: options.fadd_latency,
+            fmul: options.fmul_latency,
+            fdiv: options.fdiv_latency,
+        },
+        reset_vector,
+    });
+    sim.load_program(program);
+    for (reg, value) in &options.registers {
+        sim.set_register(*reg, *value);
+    }
+    for (address, value) in &options.memory {
+        sim.write_word(*address, *value);
+    }
+    if let Some(output) = &options.uart {
+        let output: Rc<RefCell<dyn Write>> = match output.as_str() {
+            "stdout" => Rc::new(RefCell::new(io::stdout())),
+            path => match File::create(path) {
+                Ok(file) => Rc::new(RefCell::new(file)),
+                Err(e) => return Err(format!("could not create {}: {}", path, e)),
+            },
+        };
+        let input = match &options.uart_input {
+            Some(path) => fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))?,
+            None => Vec::new(),
+        };
+        sim.attach_device(options.uart_address, Box::new(Uart::new(output, &input)))?;
+    }
+    if options.clint {
+        sim.attach_device(options.clint_address, Box::new(Clint::new()))?;
+    }
+    Ok(sim)
+}
 
-    let mut logic = Logic::default();
+//an address given on the command line, either as a number or as one of the program's symbols.
+fn resolve_address(program: &Program, text: &str) -> Result<u32, String> {
+    if let Some(address) = program.symbol_address(text) {
+        return Ok(address);
+    }
+    match assembler::parse_immediate(text, 0) {
+        Ok(address) => Ok(address as u32),
+        Err(_) => Err(format!("'{}' is not an address or a symbol", text)),
+    }
+}
 
-    run_program(&mut state, &mut logic, true);
+// how a program should be run
+struct RunSettings {
+    interactive: bool, //wait for the user between cycles
+    display: DisplayMode,
+    trace: bool, //write one line per cycle to the output
 }
 
-//Actually runs the program in the simulated CPU
-fn run_program(state: &mut Registers, logic: &mut Logic, interactive: bool) {
+fn run_with(sim: &mut Simulator, settings: &RunSettings, out: &mut dyn Write) -> io::Result<()> {
     //ADDITIONAL SETUP:
 
     //a vector of snapshots to make rewinding possible.
-    let mut backups: Vec<Snapshot> = Vec::new();
-
-    //when a program is <eop_buffer> instructions past the last instruction, the program is done executing.
-    let eop_buffer = 6;
-    let eop_program_count: u32 = ((state.instr_mem.len() as u32) + eop_buffer) * 4;
+    let mut backups: Vec<Simulator> = Vec::new();
 
-    let mut end_of_program = false;
     let mut step_count = 0;
 
     //used for getting user input
     let user_input = &mut String::new();
     let stdin = stdin();
 
-    while !(end_of_program) {
+    //runs until the program ends itself (see termination.rs), the watchdog goes off, or the user quits.
+    while !sim.is_over() {
         //make backup, if needed.
 
         if backups.len() < step_count {
-            backups.push(Snapshot {
-                state: state.clone(),
-                logic: logic.clone(),
-            });
+            backups.push(sim.clone());
         }
 
-        display_cpu(&state, &logic);
+        match settings.display {
+            DisplayMode::Full => display_cpu(sim.state(), sim.logic()),
+            DisplayMode::Compact => display_compact(sim.state(), sim.logic(), step_count),
+            DisplayMode::None => {}
+        }
+        if settings.trace {
+            write_trace_line(sim.state(), sim.logic(), step_count, out)?;
+        }
 
         //get user input for next step.
         user_input.clear();
-        if interactive {
-            println!("What now? [n - next cycle, b - prev. cycle]:");
-            stdin.read_line(user_input);
+        if settings.interactive {
+            println!("What now? [n - next cycle, b - prev. cycle, q - quit]:");
+            if stdin.read_line(user_input)? == 0 {
+                sim.quit(); //no more input coming
+                break;
+            }
         } else {
             //if this is a test, dont get user input, just go to next step every time.
             user_input.push_str("n\n");
         }
-        println!("{}", user_input);
+        if settings.display != DisplayMode::None {
+            println!("{}", user_input);
+        }
 
         if user_input == "n\n" {
-            //if n, proceed to next step.
-            step(state, logic);
+            //if n, proceed to next step. (the watchdog is in there too)
+            sim.step();
 
             step_count += 1;
-            //check if program is over
-            //println!("PC: {}", state.pc);
-            if state.pc >= eop_program_count {
-                end_of_program = true;
-            }
         } else if user_input == "b\n" {
             //load backup, go one step back!!
-            if step_count == 1 {
+            if step_count <= 1 {
                 println!("Can't go back any further!");
             } else {
                 step_count -= 1;
-                *state = backups[step_count - 1].state.clone();
-                *logic = backups[step_count - 1].logic.clone();
+                *sim = backups[step_count - 1].clone();
+            }
+        } else if user_input == "q\n" {
+            sim.quit();
+            break;
+        }
+    }
+    Ok(())
+}
+
+//prints the program's instructions as assembly, one per line, with symbol names where there are any.
+// compressed instructions take up 2 bytes, and the rest 4.
+fn disassemble(program: &Program, out: &mut dyn Write) -> io::Result<()> {
+    let mut address = program.text_start;
+    while address < program.text_end {
+        let word = program.memory.read_word(address);
+        let size = instruction_size(word);
+        let (instr, bits) = if size == 2 {
+            (word & 0xffff, format!("{:04x}", word & 0xffff))
+        } else {
+            (word, format!("{:08x}", word))
+        };
+        if let Some(name) = program.symbols.get(&address) {
+            writeln!(out, "\n{:08x} <{}>:", address, name)?;
+        }
+        let assembly = display_instruction(&instr);
+        writeln!(
+            out,
+            "{:8x}:  {:<8}  {}",
+            address,
+            bits,
+            assembly.trim_end_matches('-')
+        )?;
+        address += size;
+    }
+    Ok(())
+}
+
+//one line per cycle: which instruction is in each stage.
+fn write_trace_line(
+    state: &Registers,
+    logic: &Logic,
+    cycle: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let stage = |instr: &u32| {
+        let assembly = display_instruction(instr);
+        format!("{:<25}", assembly.trim_end_matches('-'))
+    };
+    writeln!(
+        out,
+        "{:>6}  pc={:08x}  IF: {} ID: {} EX: {} MEM: {} WB: {}{}",
+        cycle,
+        state.pc,
+        stage(&logic.fetch.instruction_out),
+        stage(&state.ifid.instruction),
+        stage(&state.idex.instruction),
+        stage(&state.exmem.instruction),
+        stage(&state.memwb.instruction),
+        display_fpu(state)
+    )
+}
+
+//what's in the FPU, if anything: each instruction, with its unit and which of its cycles it's on. (A2 is the adder's 2nd)
+fn display_fpu(state: &Registers) -> String {
+    let mut text = String::new();
+    for entry in &state.fpu.entries {
+        let unit = match entry.unit {
+            FpUnit::Adder => 'A',
+            FpUnit::Multiplier => 'M',
+            FpUnit::Divider => 'D',
+        };
+        let assembly = display_instruction(&entry.latch.instruction);
+        text += &format!(
+            "  FPU {}{}: {}",
+            unit,
+            entry.stage,
+            assembly.trim_end_matches('-')
+        );
+    }
+    text
+}
+
+//the final contents of the register memory, 8 to a line. the FP registers too, once anything has used them.
+fn write_registers(state: &Registers, out: &mut dyn Write) -> io::Result<()> {
+    for r in 0..32 {
+        write!(out, "$r{:#02}: {:#010x}   ", r, state.reg_mem[r])?;
+        if (r + 1) % 8 == 0 {
+            writeln!(out)?;
+        }
+    }
+    if state.fp_reg_mem.iter().any(|value| *value != 0) {
+        for r in 0..32 {
+            write!(out, "$f{:#02}: {:#010x}   ", r, state.fp_reg_mem[r])?;
+            if (r + 1) % 8 == 0 {
+                writeln!(out)?;
             }
         }
     }
+    Ok(())
+}
+
+//A smaller alternative to display_cpu: just what's in each stage, and the registers.
+fn display_compact(state: &Registers, logic: &Logic, cycle: usize) {
+    println!(
+        "CYCLE {}   PC: {:#010x}   {}",
+        cycle,
+        state.pc,
+        if logic.pc_stall { "STALL" } else { "" }
+    );
+    println!(
+        "IF:{} ID:{} EX:{} MEM:{} WB:{}{}",
+        display_instruction(&logic.fetch.instruction_out),
+        display_instruction(&state.ifid.instruction),
+        display_instruction(&state.idex.instruction),
+        display_instruction(&state.exmem.instruction),
+        display_instruction(&state.memwb.instruction),
+        display_fpu(state)
+    );
+    let _ = write_registers(state, &mut io::stdout());
 }
 
 //Displays the current state of the CPU in an ASCII-based UI
@@ -170,9 +396,9 @@ fn display_cpu(state: &Registers, logic: &Logic) {
     println!("│ │ PC           │  ┌────────┐     │     │                         │ │  ┌─────────────────┐      │     │ ├────MEM-EX──►│\x1b[93m{}\x1b[0m  │        │  ├─┐         │  │     │   │                  │  Out│ │ │ Mux.  │ │", if logic.execute.r1_forwarded == 2 {"►"} else {" "});
     println!("│ │ Mux.         │  │ Instr. │     │     │   ┌───────┐             │ │  │  Register Mem.  │      │     │ │             │ R1├─────┬─►│\x1b[93m{}\x1b[0m │ │         │  │     │   │                  │     │ │ │ ┌─\\   │ │", if !logic.execute.pc_used {"►"} else {" "});
     println!("│ │ ┌─\\     ┌──┐ │  │  Mem.  │     │     │ ┌►│Decoder├─\x1b[92m$r{:#02}\x1b[0m──────┐ │ └─►│Wb data      Reg1├─┬───►│R1   ├───\x1b[92m{:#010x}\x1b[0m─►│\x1b[93m{}\x1b[0m  │     │  └─/  ▼         │  │     │   │                  │     │ │ └►│\x1b[93m{}\x1b[0m │  │ │", logic.decode.decode_r1, state.idex.r1_data, if logic.execute.r1_forwarded == 0 {"►"} else {" "}, if logic.writeback.wb_used == 2 {"►"} else {" "});
-    println!("│ └►│\x1b[93m{}\x1b[0m │    │PC│ │  │        │     │     │ │ │       │           │ │    │                 │ │    │ Data│ │             │   │     │     ┌─────────┐ │  │     │   │                  │     │ │   │  │  │ │", if !logic.fetch.jumped {"►"} else {" "});
+    println!("│ └►│\x1b[93m{}\x1b[0m │    │PC│ │  │        │     │     │ │ │       │           │ │    │                 │ │    │ Data│ │             │   │     │     ┌─────────┐ │  │     │   │                  │     │ │   │  │  │ │", if logic.fetch.pcmux_out == logic.fetch.pcadder_out {"►"} else {" "});
     println!("│   │  ├─┬─►│  ├─┼─►│addr    │     │     │ │ │       ├─\x1b[92m$r{:#02}\x1b[0m────┐ │ └───►│Wb idx           │ │    │     │ │ ┌───EX-EX──►└{}─/      │     │Op1      │ │  │     │   │  ┌──────────┐    │     │ └─┬►│\x1b[93m{}\x1b[0m ├──┘ │", logic.decode.decode_r2, if logic.execute.r1_forwarded == 1 {"\x1b[93m►\x1b[0m"} else {"─"}, if logic.writeback.wb_used == 0 {"►"} else {" "});
-    println!("└──►│\x1b[93m{}\x1b[0m │ │  │  │ │  │     ins├─┬──►│instr├─┤ │       │         │ │      │                 │ │    │     │ │ │                     │     │         │ │  │     │   │  │ DATA MEM.│    │     │   │ │  │    │", if logic.fetch.jumped {"►"} else {" "});
+    println!("└──►│\x1b[93m{}\x1b[0m │ │  │  │ │  │     ins├─┬──►│instr├─┤ │       │         │ │      │                 │ │    │     │ │ │                     │     │         │ │  │     │   │  │ DATA MEM.│    │     │   │ │  │    │", if logic.fetch.pcmux_out != logic.fetch.pcadder_out {"►"} else {" "});
     println!("    └─/  │  └──┘ │  └────────┘ │   │     │ │ │ opcode├───┐     │ └─────►│R1 idx           │ │    │     │ └──────MEM-EX──►┌{}─\\    │     │   ALU   │ │  │     │   │  │      Read├─┬─►│Mem  ├─┬──►│\x1b[93m{}\x1b[0m │    │", if logic.execute.r2_forwarded == 2 {"►"} else {"─"}, if logic.writeback.wb_used == 1 {"\x1b[93m►\x1b[0m"} else {" "});
     println!("         │ {} │             │   │     │ │ │       │   │     │        │                 │ │    │     │   │             │   │   │     │      Out├─┼─►│ALU  ├───┼──┤Addr   Out│ │  │  Out│ │ │ └─/     │", if logic.pc_stall { "STALL" } else {"PASS "});
     println!("         │       │             │   │     │ │ │ rd idx├─┐ │     └───────►│R2 idx       Reg2├───┬─►│R2   ├─────\x1b[92m{:#010x}\x1b[0m─►│\x1b[93m{}\x1b[0m  │   │     │Op2      │ │  │  Out│   │  │          │ │  │     │ │ │         │", state.idex.r2_data, if logic.execute.r2_forwarded == 0 {"►"} else {" "});
@@ -196,14 +422,12 @@ fn display_cpu(state: &Registers, logic: &Logic) {
     println!("                                    {}                                                         {}    │                                           {}    │                   {}               ", match state.ifid.id_stall {0 => "PASS ", 1 => "STALL", _ => "BUBBL"}, match state.idex.ex_stall {0 => "PASS ", 1 => "STALL", _ => "BUBBL"}, match state.exmem.mem_stall {0 => "PASS ", 1 => "STALL", _ => "BUBBL"}, match state.memwb.wb_stall {0 => "PASS ", 1 => "STALL", _ => "BUBBL"});
     println!("                                                                                                           └─\x1b[92m{:#010x}\x1b[0m─────────────────────────────────────────┘                                      ", state.exmem.alu_output);
 
+    if !state.fpu.is_empty() {
+        println!("{}", display_fpu(state).trim_start());
+    }
     println!("");
     println!("*********************************************REGISTER MEMORY*********************************************************************************************************************************************");
-    for r in 0..32 {
-        print!("$r{:#02}: {:#010x}   ", r, state.reg_mem[r]);
-        if (r + 1) % 8 == 0 {
-            print!("\n");
-        }
-    }
+    let _ = write_registers(state, &mut io::stdout());
 }
 
 fn display_instruction(instr: &u32) -> String {
@@ -215,20 +439,24 @@ fn display_instruction(instr: &u32) -> String {
     let r1 = ((instr & 0b11111000000000000000) >> 15);
     let r2 = ((instr & 0b1111100000000000000000000) >> 20);
 
-    let typ = get_instruction_type(opcode as u8);
-
     let mut assembly: String = "".to_string();
 
     //Note: the immediates of LUI and AUIPC are the only ones to be unsigned, since they get pushed all the way to the top of their output.
-    if opcode == 0b0110111 {
+    if *instr != 0 && is_compressed(*instr) {
+        //RV32C. (see compressed.rs)
+        assembly = match display_compressed(*instr as u16) {
+            Some(assembly) => assembly,
+            None => return unknown_instruction(instr),
+        };
+    } else if opcode == 0b0110111 {
         //LUI
-        let imm = (instr & 0b11111111111111111111) >> 12;
+        let imm = instr >> 12;
         assembly = "lui $r".to_owned() + &rd.to_string() + ", " + &format!("{:#x}", imm);
         //         6                     2               2       7
         // 17 chars at most
     } else if opcode == 0b0010111 {
         //AUIPC
-        let imm = (instr & 0b11111111111111111111) >> 12;
+        let imm = instr >> 12;
         assembly = "auipc $r".to_owned() + &rd.to_string() + ", " + &format!("{:#x}", imm);
         // 19 at most
     } else if opcode == 0b1101111 {
@@ -273,7 +501,7 @@ fn display_instruction(instr: &u32) -> String {
             0b101 => "bge",
             0b110 => "bltu",
             0b111 => "bgeu",
-            _ => panic!("Invalid funct3 for a branch! {:b}", instr),
+            _ => return unknown_instruction(instr),
         };
         assembly = instr_name.to_owned()
             + " $r"
@@ -292,7 +520,7 @@ fn display_instruction(instr: &u32) -> String {
             0b010 => "lw",
             0b100 => "lbu",
             0b101 => "lhu",
-            _ => panic!("Invalid funct3 for a load! {:b}", instr),
+            _ => return unknown_instruction(instr),
         };
         assembly = instr_name.to_owned()
             + " $r"
@@ -312,7 +540,7 @@ fn display_instruction(instr: &u32) -> String {
             0b000 => "sb",
             0b001 => "sh",
             0b010 => "sw",
-            _ => panic!("Invalid funct3 for a store! {:b}", instr),
+            _ => return unknown_instruction(instr),
         };
 
         assembly = instr_name.to_owned()
@@ -323,6 +551,15 @@ fn display_instruction(instr: &u32) -> String {
             + "($r"
             + &r1.to_string()
             + ")";
+    } else if let Some(instr_name) = bitmanip_name(*instr) {
+        //Zba, Zbb and Zbs. the OP-IMM ones that aren't unary have a shamt where rs2 would be. (see bitmanip.rs)
+        assembly = if is_unary(instr_name) {
+            format!("{} $r{}, $r{}", instr_name, rd, r1)
+        } else if opcode == 0b0010011 {
+            format!("{} $r{}, $r{}, {}", instr_name, rd, r1, r2)
+        } else {
+            format!("{} $r{}, $r{}, $r{}", instr_name, rd, r1, r2)
+        };
     } else if opcode == 0b0010011 {
         // Register-Immediate Operation! Look at funct3 to figure which.
         let imm = ((*instr as i32) >> 20);
@@ -342,7 +579,7 @@ fn display_instruction(instr: &u32) -> String {
                 0b0100000 => "srai",
                 _ => "wrsh!!",
             },
-            _ => panic!("Invalid funct3 for an R-I op! {:b}", instr),
+            _ => return unknown_instruction(instr),
         };
 
         if funct3 == 0b001 || funct3 == 0b101 {
@@ -368,10 +605,21 @@ fn display_instruction(instr: &u32) -> String {
         //Register-Register Instructions! Look at funct3/7 to see which.
         // No immediates.
         let instr_name = match funct3 {
+            _ if funct7 == 0b0000001 => match funct3 {
+                //RV32M
+                0b000 => "mul",
+                0b001 => "mulh",
+                0b010 => "mulhsu",
+                0b011 => "mulhu",
+                0b100 => "div",
+                0b101 => "divu",
+                0b110 => "rem",
+                _ => "remu",
+            },
             0b000 => match funct7 {
                 0b0000000 => "add",
                 0b0100000 => "sub",
-                _ => panic!("invalid funct7 for add/sub! {:b}", instr),
+                _ => return unknown_instruction(instr),
             },
             0b001 => "sll",
             0b010 => "slt",
@@ -380,11 +628,11 @@ fn display_instruction(instr: &u32) -> String {
             0b101 => match funct7 {
                 0b0000000 => "srl",
                 0b0100000 => "sra",
-                _ => panic!("invalid funct7 for srl/a! {:b}", instr),
+                _ => return unknown_instruction(instr),
             },
             0b110 => "or",
             0b111 => "and",
-            _ => panic!("Invalid funct3 for an R-R op! {:b}", instr),
+            _ => return unknown_instruction(instr),
         };
 
         assembly = instr_name.to_owned()
@@ -394,11 +642,69 @@ fn display_instruction(instr: &u32) -> String {
             + &r1.to_string()
             + ", $r"
             + &r2.to_string();
+    } else if opcode == 0b0101111 {
+        //RV32A atomics. funct5 says which, and the last two bits of funct7 are aq and rl.
+        let instr_name = match funct7 >> 2 {
+            0b00010 => "lr.w",
+            0b00011 => "sc.w",
+            0b00001 => "amoswap.w",
+            0b00000 => "amoadd.w",
+            0b00100 => "amoxor.w",
+            0b01100 => "amoand.w",
+            0b01000 => "amoor.w",
+            0b10000 => "amomin.w",
+            0b10100 => "amomax.w",
+            0b11000 => "amominu.w",
+            0b11100 => "amomaxu.w",
+            _ => return unknown_instruction(instr),
+        };
+        if funct3 != 0b010 {
+            return unknown_instruction(instr);
+        }
+        let ordering = match funct7 & 0b11 {
+            0b10 => ".aq",
+            0b01 => ".rl",
+            0b11 => ".aqrl",
+            _ => "",
+        };
+        assembly = if instr_name == "lr.w" {
+            format!("lr.w{} $r{}, ($r{})", ordering, rd, r1)
+        } else {
+            format!("{}{} $r{}, $r{}, ($r{})", instr_name, ordering, rd, r2, r1)
+        };
+    } else if opcode == 0b1110011 {
+        //System instructions
+        let csr = (instr >> 20) as u16;
+        let csr = match csr::csr_name(csr) {
+            Some(name) => name.to_owned(),
+            None => format!("{:#x}", csr),
+        };
+        assembly = match funct3 {
+            0b000 => match instr {
+                0x00000073 => "ecall".to_owned(),
+                0x00100073 => "ebreak".to_owned(),
+                0x30200073 => "mret".to_owned(),
+                0x10500073 => "wfi".to_owned(),
+                _ => return unknown_instruction(instr),
+            },
+            0b001 => format!("csrrw $r{}, {}, $r{}", rd, csr, r1),
+            0b010 => format!("csrrs $r{}, {}, $r{}", rd, csr, r1),
+            0b011 => format!("csrrc $r{}, {}, $r{}", rd, csr, r1),
+            0b101 => format!("csrrwi $r{}, {}, {}", rd, csr, r1),
+            0b110 => format!("csrrsi $r{}, {}, {}", rd, csr, r1),
+            0b111 => format!("csrrci $r{}, {}, {}", rd, csr, r1),
+            _ => return unknown_instruction(instr),
+        };
+    } else if float::fp_registers(*instr).is_some() {
+        //RV32F. (see float.rs)
+        assembly = match display_float(*instr) {
+            Some(assembly) => assembly,
+            None => return unknown_instruction(instr),
+        };
     } else if opcode == 0 {
         assembly = "nop".to_owned();
     } else {
-        assembly = "".to_owned();
-        panic!("Invalid instruction opcode! {:b}", instr);
+        return unknown_instruction(instr);
     }
     while assembly.len() < 25 {
         assembly += "-";
@@ -406,6 +712,109 @@ fn display_instruction(instr: &u32) -> String {
     return assembly;
 }
 
+//a compressed instruction, by its own name. the operands come out of the instruction it stands for.
+fn display_compressed(parcel: u16) -> Option<String> {
+    let (name, expanded) = compressed::decompress(parcel)?;
+    let rd = (expanded >> 7) & 0b11111;
+    let r1 = (expanded >> 15) & 0b11111;
+    let r2 = (expanded >> 20) & 0b11111;
+    let imm = (expanded as i32) >> 20; //I-type
+    let store_imm = ((expanded as i32) >> 25 << 5) | ((expanded >> 7) & 0b11111) as i32;
+    let offset = target_offset(expanded) as i32; //jumps and branches
+    Some(match name {
+        "c.nop" | "c.ebreak" => name.to_owned(),
+        "c.addi4spn" => format!("{} $r{}, $r2, {:#x}", name, rd, imm),
+        "c.lw" | "c.lwsp" => format!("{} $r{}, {:#x}($r{})", name, rd, imm, r1),
+        "c.sw" | "c.swsp" => format!("{} $r{}, {:#x}($r{})", name, r2, store_imm, r1),
+        "c.flw" | "c.flwsp" => format!("{} $f{}, {:#x}($r{})", name, rd, imm, r1),
+        "c.fsw" | "c.fswsp" => format!("{} $f{}, {:#x}($r{})", name, r2, store_imm, r1),
+        "c.slli" | "c.srli" | "c.srai" => format!("{} $r{}, {}", name, rd, r2),
+        "c.lui" => format!("{} $r{}, {:#x}", name, rd, expanded >> 12),
+        "c.j" | "c.jal" => format!("{} {:#x}", name, offset),
+        "c.beqz" | "c.bnez" => format!("{} $r{}, {:#x}", name, r1, offset),
+        "c.jr" | "c.jalr" => format!("{} $r{}", name, r1),
+        "c.mv" | "c.add" | "c.sub" | "c.xor" | "c.or" | "c.and" => {
+            format!("{} $r{}, $r{}", name, rd, r2)
+        }
+        _ => format!("{} $r{}, {:#x}", name, rd, imm), //c.addi, c.li, c.addi16sp, c.andi
+    })
+}
+
+//an RV32F instruction. FP registers are $fN. the rounding mode only gets shown when it isn't the dynamic one.
+fn display_float(instr: u32) -> Option<String> {
+    if !float::is_legal(instr) {
+        return None;
+    }
+    let (rd_fp, r1_fp, r2_fp) = float::fp_registers(instr)?;
+    let register = |fp: bool, index: u32| format!("${}{}", if fp { "f" } else { "r" }, index);
+    let rd = register(rd_fp, (instr >> 7) & 0b11111);
+    let r1 = register(r1_fp, (instr >> 15) & 0b11111);
+    let r2 = (instr >> 20) & 0b11111;
+    let funct3 = (instr >> 12) & 0b111;
+    let rm = match funct3 {
+        _ if !float::uses_rounding_mode(instr) => "",
+        0b000 => ", rne",
+        0b001 => ", rtz",
+        0b010 => ", rdn",
+        0b011 => ", rup",
+        0b100 => ", rmm",
+        _ => "",
+    };
+    let opcode = (instr & 0b1111111) as u8;
+    let name = match opcode {
+        float::OPCODE_LOAD_FP => {
+            let imm = (instr as i32) >> 20;
+            return Some(format!("flw {}, {:#x}({})", rd, imm, r1));
+        }
+        float::OPCODE_STORE_FP => {
+            let imm = ((instr as i32) >> 25 << 5) | ((instr >> 7) & 0b11111) as i32;
+            return Some(format!("fsw $f{}, {:#x}({})", r2, imm, r1));
+        }
+        float::OPCODE_FMADD => "fmadd.s",
+        float::OPCODE_FMSUB => "fmsub.s",
+        float::OPCODE_FNMSUB => "fnmsub.s",
+        float::OPCODE_FNMADD => "fnmadd.s",
+        _ => match (instr >> 25, funct3, r2) {
+            (0x00, _, _) => "fadd.s",
+            (0x04, _, _) => "fsub.s",
+            (0x08, _, _) => "fmul.s",
+            (0x0c, _, _) => "fdiv.s",
+            (0x2c, _, _) => "fsqrt.s",
+            (0x10, 0b000, _) => "fsgnj.s",
+            (0x10, 0b001, _) => "fsgnjn.s",
+            (0x10, _, _) => "fsgnjx.s",
+            (0x14, 0b000, _) => "fmin.s",
+            (0x14, _, _) => "fmax.s",
+            (0x50, 0b010, _) => "feq.s",
+            (0x50, 0b001, _) => "flt.s",
+            (0x50, _, _) => "fle.s",
+            (0x60, _, 0) => "fcvt.w.s",
+            (0x60, _, _) => "fcvt.wu.s",
+            (0x68, _, 0) => "fcvt.s.w",
+            (0x68, _, _) => "fcvt.s.wu",
+            (0x70, 0b000, _) => "fmv.x.w",
+            (0x70, _, _) => "fclass.s",
+            _ => "fmv.w.x",
+        },
+    };
+    Some(if float::is_fused(opcode) {
+        format!("{} {}, {}, $f{}, $f{}{}", name, rd, r1, r2, instr >> 27, rm)
+    } else if let Some(r2_fp) = r2_fp {
+        format!("{} {}, {}, {}{}", name, rd, r1, register(r2_fp, r2), rm)
+    } else {
+        format!("{} {}, {}{}", name, rd, r1, rm)
+    })
+}
+
+//anything that isn't a known instruction gets shown as a raw data word.
+fn unknown_instruction(instr: &u32) -> String {
+    let mut assembly = format!(".word {:#010x}", instr);
+    while assembly.len() < 25 {
+        assembly += "-";
+    }
+    assembly
+}
+
 /*
 
 fn make_backup() {
@@ -422,13 +831,35 @@ fn step_forward() {
 
 */
 
-// also need to do...
-// code assembler
+//Runs a list of instructions from address 0 to the end, with the default setup. Most tests just want this.
+#[cfg(test)]
+fn run_instructions(instructions: Vec<u32>) -> Simulator {
+    let mut sim = Simulator::new(Config::default());
+    sim.load_program(Program::from_instructions(instructions));
+    sim.run();
+    sim
+}
+
+//The same, but assembling the program first, and with whatever setup the test wants.
+#[cfg(test)]
+fn run_source(source: &str, config: Config) -> Simulator {
+    let mut sim = Simulator::new(config);
+    sim.load_program(Program::from_instructions(
+        assembler::assemble(source).unwrap(),
+    ));
+    sim.run();
+    sim
+}
+
+//The encoding of one instruction.
+#[cfg(test)]
+fn asm(text: &str) -> u32 {
+    assembler::assemble(text).unwrap()[0]
+}
 
 #[cfg(test)]
 pub mod instr_tests {
     use crate::*;
-    use std::collections::hash_map::*;
 
     #[test]
     fn smoke_test() {
@@ -442,33 +873,17 @@ pub mod instr_tests {
             0b00000000000100000000001010010011, //addi $r5, $r0, 1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -486,32 +901,16 @@ pub mod instr_tests {
             0b11111111111111111111001010110111, //lui $r5, 0b1111111...
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
         // rd = imm << 12
-        assert_eq!(state.reg_mem[0], (0 as u32) << 12);
-        assert_eq!(state.reg_mem[1], (1 as u32) << 12);
-        assert_eq!(state.reg_mem[2], (2 as u32) << 12);
-        assert_eq!(state.reg_mem[3], (8 as u32) << 12);
-        assert_eq!(state.reg_mem[4], (2 as u32) << 12);
-        assert_eq!(state.reg_mem[5], (0b11111111111111111111 as u32) << 12);
+        assert_eq!(sim.registers()[0], (0 as u32) << 12);
+        assert_eq!(sim.registers()[1], (1 as u32) << 12);
+        assert_eq!(sim.registers()[2], (2 as u32) << 12);
+        assert_eq!(sim.registers()[3], (8 as u32) << 12);
+        assert_eq!(sim.registers()[4], (2 as u32) << 12);
+        assert_eq!(sim.registers()[5], (0b11111111111111111111 as u32) << 12);
     }
 
     #[test]
@@ -528,32 +927,16 @@ pub mod instr_tests {
             0b00000000000000000000001010010111, //auipc $r5, 0
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
         // rd = (imm << 12) + PC
-        assert_eq!(state.reg_mem[0], ((0 as u32) << 12));
-        assert_eq!(state.reg_mem[1], ((8 as u32) << 12)); //PC = 0
-        assert_eq!(state.reg_mem[2], ((4 as u32) << 12) + 4); //PC = 4
-        assert_eq!(state.reg_mem[3], ((8 as u32) << 12) + 8); //PC = 8
-        assert_eq!(state.reg_mem[4], ((4 as u32) << 12) + 12); //PC = 12
-        assert_eq!(state.reg_mem[5], ((0 as u32) << 12) + 16); //PC = 16
+        assert_eq!(sim.registers()[0], ((0 as u32) << 12));
+        assert_eq!(sim.registers()[1], ((8 as u32) << 12)); //PC = 0
+        assert_eq!(sim.registers()[2], ((4 as u32) << 12) + 4); //PC = 4
+        assert_eq!(sim.registers()[3], ((8 as u32) << 12) + 8); //PC = 8
+        assert_eq!(sim.registers()[4], ((4 as u32) << 12) + 12); //PC = 12
+        assert_eq!(sim.registers()[5], ((0 as u32) << 12) + 16); //PC = 16
     }
 
     #[test]
@@ -576,34 +959,18 @@ pub mod instr_tests {
         ]);
         //instructions 16-28 should get skipped
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 16);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 16);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -625,34 +992,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //36: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 16);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 16);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -680,34 +1031,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //56: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 56);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 56);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -734,34 +1069,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //56: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 56);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 56);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -788,34 +1107,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //56: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 56);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 56);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -842,34 +1145,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //56: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 56);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 56);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -896,34 +1183,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //56: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], (0b11111111111111111111 as u32) << 12);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 56);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], (0b11111111111111111111 as u32) << 12);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 56);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -950,34 +1221,18 @@ pub mod instr_tests {
             0b00000000000000000000000000000000, //56: nop
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], (0b11111111111111111111 as u32) << 12);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 56);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], (0b11111111111111111111 as u32) << 12);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 56);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -996,28 +1251,12 @@ pub mod instr_tests {
             0b00000000000100000010010000100011, //sw $r1, 8($r0)
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.data_mem[&2], 0b11111111111111111111000000000000);
+        assert_eq!(sim.read_word(8), 0b11111111111111111111000000000000);
         for i in 2..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1038,28 +1277,12 @@ pub mod instr_tests {
             0b00000000001000000001010000100011, //sh $r2, 8($r0)
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.data_mem[&2], 0b11111111111111110101000000000000);
+        assert_eq!(sim.read_word(8), 0b11111111111111110101000000000000);
         for i in 3..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1082,28 +1305,12 @@ pub mod instr_tests {
             0b00000000001100000000010000100011, //sb $r3, 8($r0)
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.data_mem[&2], 0b11111111111111110101000000001000);
+        assert_eq!(sim.read_word(8), 0b11111111111111110101000000001000);
         for i in 4..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1137,36 +1344,26 @@ pub mod instr_tests {
             0b00000001100000000000000010000011, //lb 24($r0), $r1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 8);
-        assert_eq!(state.reg_mem[6], 0b01010000);
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 8);
+        assert_eq!(sim.registers()[6], 0b01010000);
         //not unsigned, so the following 2 need to sign-extend
-        assert_eq!(state.reg_mem[7], (((0b11111111 << 24) as i32) >> 24) as u32);
-        assert_eq!(state.reg_mem[8], (((0b11111111 << 24) as i32) >> 24) as u32);
+        assert_eq!(
+            sim.registers()[7],
+            (((0b11111111 << 24) as i32) >> 24) as u32
+        );
+        assert_eq!(
+            sim.registers()[8],
+            (((0b11111111 << 24) as i32) >> 24) as u32
+        );
         for i in 9..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1198,37 +1395,21 @@ pub mod instr_tests {
             0b00000001100000000001000010000011, //lh 24($r0), $r1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 0b0101000000001000);
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 0b0101000000001000);
         //not unsigned, so the following needs to sign-extend
         assert_eq!(
-            state.reg_mem[7],
+            sim.registers()[7],
             (((0b1111111111111111 << 16) as i32) >> 16) as u32
         );
         for i in 9..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1261,32 +1442,16 @@ pub mod instr_tests {
             0b00000001100000000010000010000011, //lw 24($r0), $r1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 0b11111111111111110101000000001000);
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 0b11111111111111110101000000001000);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1320,36 +1485,20 @@ pub mod instr_tests {
             0b00000001100000000100000010000011, //lbu 24($r0), $r1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 8);
-        assert_eq!(state.reg_mem[6], 0b01010000);
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 8);
+        assert_eq!(sim.registers()[6], 0b01010000);
         //unsigned, so the following should NOT be sign-extended
-        assert_eq!(state.reg_mem[7], 0b11111111);
-        assert_eq!(state.reg_mem[8], 0b11111111);
+        assert_eq!(sim.registers()[7], 0b11111111);
+        assert_eq!(sim.registers()[8], 0b11111111);
         for i in 9..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1381,34 +1530,18 @@ pub mod instr_tests {
             0b00000001100000000101000010000011, //lh 24($r0), $r1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 0b0101000000001000);
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 0b0101000000001000);
         //unsigned, so the following needs to NOT sign-extend
-        assert_eq!(state.reg_mem[7], 0b1111111111111111);
+        assert_eq!(sim.registers()[7], 0b1111111111111111);
         for i in 8..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1426,35 +1559,17 @@ pub mod instr_tests {
             0b00000000000100000000001010010011, //addi $r5, $r0, 1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1483,43 +1598,25 @@ pub mod instr_tests {
             0b00000000010000110010011000010011, //slti $r12, $r6, 4
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 0b11111111111111111111 << 12);
-        assert_eq!(state.reg_mem[7], 1);
-        assert_eq!(state.reg_mem[8], 1);
-        assert_eq!(state.reg_mem[9], 0);
-        assert_eq!(state.reg_mem[10], 1);
-        assert_eq!(state.reg_mem[11], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 0b11111111111111111111 << 12);
+        assert_eq!(sim.registers()[7], 1);
+        assert_eq!(sim.registers()[8], 1);
+        assert_eq!(sim.registers()[9], 0);
+        assert_eq!(sim.registers()[10], 1);
+        assert_eq!(sim.registers()[11], 1);
         //This is signed,  so $r6 should be read as a negative number. This IS less than!
-        assert_eq!(state.reg_mem[12], 1);
+        assert_eq!(sim.registers()[12], 1);
         for i in 13..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1548,43 +1645,25 @@ pub mod instr_tests {
             0b00000000010000110011011000010011, //sltiu $r12, $r6, 4
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 0b11111111111111111111 << 12);
-        assert_eq!(state.reg_mem[7], 1);
-        assert_eq!(state.reg_mem[8], 1);
-        assert_eq!(state.reg_mem[9], 0);
-        assert_eq!(state.reg_mem[10], 1);
-        assert_eq!(state.reg_mem[11], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 0b11111111111111111111 << 12);
+        assert_eq!(sim.registers()[7], 1);
+        assert_eq!(sim.registers()[8], 1);
+        assert_eq!(sim.registers()[9], 0);
+        assert_eq!(sim.registers()[10], 1);
+        assert_eq!(sim.registers()[11], 1);
         //This is unsigned,  so $r6 should be read as a huge positive number. This IS NOT less than!
-        assert_eq!(state.reg_mem[12], 0);
+        assert_eq!(sim.registers()[12], 0);
         for i in 13..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1603,36 +1682,18 @@ pub mod instr_tests {
             0b01111111111100011100001100010011, //xori $r6, $r3, 0b011111111111
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0b011111111110);
-        assert_eq!(state.reg_mem[5], 0b011111111101);
-        assert_eq!(state.reg_mem[6], 0b011111110111);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0b011111111110);
+        assert_eq!(sim.registers()[5], 0b011111111101);
+        assert_eq!(sim.registers()[6], 0b011111110111);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1654,36 +1715,18 @@ pub mod instr_tests {
             0b01111111000100011110001100010011, //ori $r6, $r3, 0b011111110001
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0b011111110001);
-        assert_eq!(state.reg_mem[5], 0b011111110011);
-        assert_eq!(state.reg_mem[6], 0b011111111001);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0b011111110001);
+        assert_eq!(sim.registers()[5], 0b011111110011);
+        assert_eq!(sim.registers()[6], 0b011111111001);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1705,36 +1748,18 @@ pub mod instr_tests {
             0b01111110111100011111001100010011, //andi $r6, $r3, 0b011111101111
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 17);
-        assert_eq!(state.reg_mem[2], 18);
-        assert_eq!(state.reg_mem[3], 24);
-        assert_eq!(state.reg_mem[4], 1);
-        assert_eq!(state.reg_mem[5], 2);
-        assert_eq!(state.reg_mem[6], 8);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 17);
+        assert_eq!(sim.registers()[2], 18);
+        assert_eq!(sim.registers()[3], 24);
+        assert_eq!(sim.registers()[4], 1);
+        assert_eq!(sim.registers()[5], 2);
+        assert_eq!(sim.registers()[6], 8);
         for i in 7..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1755,35 +1780,17 @@ pub mod instr_tests {
             0b00000001111100001001001010010011, //slli $r5, $r1, 31
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 0b010101010101);
-        assert_eq!(state.reg_mem[2], 0b0101010101010);
-        assert_eq!(state.reg_mem[3], 0b01010101010100);
-        assert_eq!(state.reg_mem[4], 0b010101010101000);
-        assert_eq!(state.reg_mem[5], 0b10000000000000000000000000000000);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 0b010101010101);
+        assert_eq!(sim.registers()[2], 0b0101010101010);
+        assert_eq!(sim.registers()[3], 0b01010101010100);
+        assert_eq!(sim.registers()[4], 0b010101010101000);
+        assert_eq!(sim.registers()[5], 0b10000000000000000000000000000000);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1805,35 +1812,19 @@ pub mod instr_tests {
             0b00000001111100001101001010010011, //srli $r5, $r1, 31
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
+        let sim = run_instructions(instructions);
 
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
-
-        //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 0b10010010010010010011 << 12);
-        assert_eq!(state.reg_mem[2], 0b10010010010010010011 << 11);
-        assert_eq!(state.reg_mem[3], 0b10010010010010010011 << 10);
-        assert_eq!(state.reg_mem[4], 0b10010010010010010011 << 9);
-        assert_eq!(state.reg_mem[5], 1);
-        for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
-        }
-    }
+        //Checks for output correctness.
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 0b10010010010010010011 << 12);
+        assert_eq!(sim.registers()[2], 0b10010010010010010011 << 11);
+        assert_eq!(sim.registers()[3], 0b10010010010010010011 << 10);
+        assert_eq!(sim.registers()[4], 0b10010010010010010011 << 9);
+        assert_eq!(sim.registers()[5], 1);
+        for i in 6..32 {
+            assert_eq!(sim.registers()[i], 0);
+        }
+    }
 
     #[test]
     fn srai() {
@@ -1858,39 +1849,23 @@ pub mod instr_tests {
             0b01000001111100010101010100010011, //srai $r10, $r2, 31
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 0b10010010010010010011 << 12);
-        assert_eq!(state.reg_mem[2], 0b01010010010010010011 << 12);
-        assert_eq!(state.reg_mem[3], 0b110010010010010010011 << 11);
-        assert_eq!(state.reg_mem[4], 0b1110010010010010010011 << 10);
-        assert_eq!(state.reg_mem[5], 0b11110010010010010010011 << 9);
-        assert_eq!(state.reg_mem[6], 0b11111111111111111111111111111111);
-        assert_eq!(state.reg_mem[7], 0b01010010010010010011 << 11);
-        assert_eq!(state.reg_mem[8], 0b01010010010010010011 << 10);
-        assert_eq!(state.reg_mem[9], 0b01010010010010010011 << 9);
-        assert_eq!(state.reg_mem[10], 0);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 0b10010010010010010011 << 12);
+        assert_eq!(sim.registers()[2], 0b01010010010010010011 << 12);
+        assert_eq!(sim.registers()[3], 0b110010010010010010011 << 11);
+        assert_eq!(sim.registers()[4], 0b1110010010010010010011 << 10);
+        assert_eq!(sim.registers()[5], 0b11110010010010010010011 << 9);
+        assert_eq!(sim.registers()[6], 0b11111111111111111111111111111111);
+        assert_eq!(sim.registers()[7], 0b01010010010010010011 << 11);
+        assert_eq!(sim.registers()[8], 0b01010010010010010011 << 10);
+        assert_eq!(sim.registers()[9], 0b01010010010010010011 << 9);
+        assert_eq!(sim.registers()[10], 0);
 
         for i in 11..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1908,33 +1883,15 @@ pub mod instr_tests {
             0b00000000001000001000000110110011, //add $r3, $r1, $r2
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 3);
-        assert_eq!(state.reg_mem[2], 6);
-        assert_eq!(state.reg_mem[3], 9);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 3);
+        assert_eq!(sim.registers()[2], 6);
+        assert_eq!(sim.registers()[3], 9);
         for i in 4..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -1953,34 +1910,16 @@ pub mod instr_tests {
             0b01000000001000001000001000110011, //sub $r4, $r1, $r2
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 3);
-        assert_eq!(state.reg_mem[2], 6);
-        assert_eq!(state.reg_mem[3], 3);
-        assert_eq!(state.reg_mem[4], (i32::from(-3)) as u32);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 3);
+        assert_eq!(sim.registers()[2], 6);
+        assert_eq!(sim.registers()[3], 3);
+        assert_eq!(sim.registers()[4], (i32::from(-3)) as u32);
         for i in 5..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2005,39 +1944,21 @@ pub mod instr_tests {
             0b00000000100100001001001010110011, //sll $r5, $r1, $r9
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 0b010101010101);
-        assert_eq!(state.reg_mem[2], 0b0101010101010);
-        assert_eq!(state.reg_mem[3], 0b01010101010100);
-        assert_eq!(state.reg_mem[4], 0b010101010101000);
-        assert_eq!(state.reg_mem[5], 0b10000000000000000000000000000000);
-        assert_eq!(state.reg_mem[6], 1);
-        assert_eq!(state.reg_mem[7], 2);
-        assert_eq!(state.reg_mem[8], 3);
-        assert_eq!(state.reg_mem[9], 31);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 0b010101010101);
+        assert_eq!(sim.registers()[2], 0b0101010101010);
+        assert_eq!(sim.registers()[3], 0b01010101010100);
+        assert_eq!(sim.registers()[4], 0b010101010101000);
+        assert_eq!(sim.registers()[5], 0b10000000000000000000000000000000);
+        assert_eq!(sim.registers()[6], 1);
+        assert_eq!(sim.registers()[7], 2);
+        assert_eq!(sim.registers()[8], 3);
+        assert_eq!(sim.registers()[9], 31);
         for i in 10..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2067,44 +1988,26 @@ pub mod instr_tests {
             0b00000000110100110010011000110011, //slt $r12, $r6, $r13
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 0b11111111111111111111 << 12);
-        assert_eq!(state.reg_mem[7], 1);
-        assert_eq!(state.reg_mem[8], 1);
-        assert_eq!(state.reg_mem[9], 0);
-        assert_eq!(state.reg_mem[10], 1);
-        assert_eq!(state.reg_mem[11], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 0b11111111111111111111 << 12);
+        assert_eq!(sim.registers()[7], 1);
+        assert_eq!(sim.registers()[8], 1);
+        assert_eq!(sim.registers()[9], 0);
+        assert_eq!(sim.registers()[10], 1);
+        assert_eq!(sim.registers()[11], 1);
         //This is signed,  so $r6 should be read as a negative number. This IS less than!
-        assert_eq!(state.reg_mem[12], 1);
-        assert_eq!(state.reg_mem[13], 4);
+        assert_eq!(sim.registers()[12], 1);
+        assert_eq!(sim.registers()[13], 4);
         for i in 14..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2134,44 +2037,26 @@ pub mod instr_tests {
             0b00000000110100110011011000110011, //sltu $r12, $r6, $r13
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 0b11111111111111111111 << 12);
-        assert_eq!(state.reg_mem[7], 1);
-        assert_eq!(state.reg_mem[8], 1);
-        assert_eq!(state.reg_mem[9], 0);
-        assert_eq!(state.reg_mem[10], 1);
-        assert_eq!(state.reg_mem[11], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 0b11111111111111111111 << 12);
+        assert_eq!(sim.registers()[7], 1);
+        assert_eq!(sim.registers()[8], 1);
+        assert_eq!(sim.registers()[9], 0);
+        assert_eq!(sim.registers()[10], 1);
+        assert_eq!(sim.registers()[11], 1);
         //This is unsigned,  so $r6 should be read as a big positive number. This IS NOT less than!
-        assert_eq!(state.reg_mem[12], 0);
-        assert_eq!(state.reg_mem[13], 4);
+        assert_eq!(sim.registers()[12], 0);
+        assert_eq!(sim.registers()[13], 4);
         for i in 14..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2191,37 +2076,19 @@ pub mod instr_tests {
             0b00000000011100011100001100110011, //xor $r6, $r3, 0b011111111111
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0b011111111110);
-        assert_eq!(state.reg_mem[5], 0b011111111101);
-        assert_eq!(state.reg_mem[6], 0b011111110111);
-        assert_eq!(state.reg_mem[7], 0b011111111111);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0b011111111110);
+        assert_eq!(sim.registers()[5], 0b011111111101);
+        assert_eq!(sim.registers()[6], 0b011111110111);
+        assert_eq!(sim.registers()[7], 0b011111111111);
         for i in 8..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2243,37 +2110,21 @@ pub mod instr_tests {
             0b00000000100100001101001010110011, //srl $r5, $r1, $r9
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 0b10010010010010010011 << 12);
-        assert_eq!(state.reg_mem[2], 0b10010010010010010011 << 11);
-        assert_eq!(state.reg_mem[3], 0b10010010010010010011 << 10);
-        assert_eq!(state.reg_mem[4], 0b10010010010010010011 << 9);
-        assert_eq!(state.reg_mem[5], 1);
-        assert_eq!(state.reg_mem[6], 1);
-        assert_eq!(state.reg_mem[7], 2);
-        assert_eq!(state.reg_mem[8], 3);
-        assert_eq!(state.reg_mem[9], 31);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 0b10010010010010010011 << 12);
+        assert_eq!(sim.registers()[2], 0b10010010010010010011 << 11);
+        assert_eq!(sim.registers()[3], 0b10010010010010010011 << 10);
+        assert_eq!(sim.registers()[4], 0b10010010010010010011 << 9);
+        assert_eq!(sim.registers()[5], 1);
+        assert_eq!(sim.registers()[6], 1);
+        assert_eq!(sim.registers()[7], 2);
+        assert_eq!(sim.registers()[8], 3);
+        assert_eq!(sim.registers()[9], 31);
         for i in 10..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2300,43 +2151,27 @@ pub mod instr_tests {
             0b01000000111000010101010100110011, //srai $r10, $r2, $r14
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 0b10010010010010010011 << 12);
-        assert_eq!(state.reg_mem[2], 0b01010010010010010011 << 12);
-        assert_eq!(state.reg_mem[3], 0b110010010010010010011 << 11);
-        assert_eq!(state.reg_mem[4], 0b1110010010010010010011 << 10);
-        assert_eq!(state.reg_mem[5], 0b11110010010010010010011 << 9);
-        assert_eq!(state.reg_mem[6], 0b11111111111111111111111111111111);
-        assert_eq!(state.reg_mem[7], 0b01010010010010010011 << 11);
-        assert_eq!(state.reg_mem[8], 0b01010010010010010011 << 10);
-        assert_eq!(state.reg_mem[9], 0b01010010010010010011 << 9);
-        assert_eq!(state.reg_mem[10], 0);
-        assert_eq!(state.reg_mem[11], 1);
-        assert_eq!(state.reg_mem[12], 2);
-        assert_eq!(state.reg_mem[13], 3);
-        assert_eq!(state.reg_mem[14], 31);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 0b10010010010010010011 << 12);
+        assert_eq!(sim.registers()[2], 0b01010010010010010011 << 12);
+        assert_eq!(sim.registers()[3], 0b110010010010010010011 << 11);
+        assert_eq!(sim.registers()[4], 0b1110010010010010010011 << 10);
+        assert_eq!(sim.registers()[5], 0b11110010010010010010011 << 9);
+        assert_eq!(sim.registers()[6], 0b11111111111111111111111111111111);
+        assert_eq!(sim.registers()[7], 0b01010010010010010011 << 11);
+        assert_eq!(sim.registers()[8], 0b01010010010010010011 << 10);
+        assert_eq!(sim.registers()[9], 0b01010010010010010011 << 9);
+        assert_eq!(sim.registers()[10], 0);
+        assert_eq!(sim.registers()[11], 1);
+        assert_eq!(sim.registers()[12], 2);
+        assert_eq!(sim.registers()[13], 3);
+        assert_eq!(sim.registers()[14], 31);
 
         for i in 15..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2356,37 +2191,19 @@ pub mod instr_tests {
             0b00000000011100011110001100110011, //or $r6, $r3, $r7
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0b011111110001);
-        assert_eq!(state.reg_mem[5], 0b011111110011);
-        assert_eq!(state.reg_mem[6], 0b011111111001);
-        assert_eq!(state.reg_mem[7], 0b011111110001);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0b011111110001);
+        assert_eq!(sim.registers()[5], 0b011111110011);
+        assert_eq!(sim.registers()[6], 0b011111111001);
+        assert_eq!(sim.registers()[7], 0b011111110001);
         for i in 8..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2406,37 +2223,19 @@ pub mod instr_tests {
             0b00000000011100011111001100110011, //and $r6, $r3, $r7
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 17);
-        assert_eq!(state.reg_mem[2], 18);
-        assert_eq!(state.reg_mem[3], 24);
-        assert_eq!(state.reg_mem[4], 1);
-        assert_eq!(state.reg_mem[5], 2);
-        assert_eq!(state.reg_mem[6], 8);
-        assert_eq!(state.reg_mem[7], 0b011111101111);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 17);
+        assert_eq!(sim.registers()[2], 18);
+        assert_eq!(sim.registers()[3], 24);
+        assert_eq!(sim.registers()[4], 1);
+        assert_eq!(sim.registers()[5], 2);
+        assert_eq!(sim.registers()[6], 8);
+        assert_eq!(sim.registers()[7], 0b011111101111);
         for i in 8..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2458,35 +2257,19 @@ pub mod instr_tests {
             0b00000000010100000010011000100011, //sw $r1, 12($r0)
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.data_mem[&2], 0b01010101010101010101000000000000);
-        assert_eq!(state.data_mem[&3], 0b01010101010101010101000000000000);
-
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.read_word(8), 0b01010101010101010101000000000000);
+        assert_eq!(sim.read_word(12), 0b01010101010101010101000000000000);
+
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], (0b01010101010101010101 as u32) << 12);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2504,35 +2287,17 @@ pub mod instr_tests {
             0b00000000000100000000001010010011, //addi $r5, $r0, 1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
@@ -2551,39 +2316,39 @@ pub mod instr_tests {
             0b00000000000100000000001010010011, //addi $r5, $r0, 1
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
-
-            pc: 0,
-
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
-
-        let mut logic = Logic::default();
-
-        //ADDITIONAL SETUP:
-
-        run_program(&mut state, &mut logic, false);
+        let sim = run_instructions(instructions);
 
         //Checks for output correctness.
-        assert_eq!(state.reg_mem[0], 0);
-        assert_eq!(state.reg_mem[1], 1);
-        assert_eq!(state.reg_mem[2], 2);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 2);
-        assert_eq!(state.reg_mem[5], 1);
+        assert_eq!(sim.registers()[0], 0);
+        assert_eq!(sim.registers()[1], 1);
+        assert_eq!(sim.registers()[2], 2);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 2);
+        assert_eq!(sim.registers()[5], 1);
         for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+            assert_eq!(sim.registers()[i], 0);
         }
     }
 
-    /*#[test]
+    #[test]
+    fn r0_is_not_forwarded() {
+        // a store in WB has no rd, so it looks like it writes $r0. the addi reading $r0 three
+        // instructions later must still see 0, not the store's junk writeback value.
+        let instructions = Vec::<u32>::from([
+            0b00000000011000000000000100010011, //addi $r2, $r0, 6
+            0b00000000001000000010010000100011, //sw $r2, 8($r0)
+            0b00000000000000000000000000010011, //nop
+            0b00000000000000000000000000010011, //nop
+            0b00000000001100000000001100010011, //addi $r6, $r0, 3
+        ]);
+
+        let sim = run_instructions(instructions);
+
+        assert_eq!(sim.read_word(8), 6);
+        assert_eq!(sim.registers()[6], 3);
+    }
+
+    #[test]
     fn load_alu_hazard() {
         //Tests for LOAD-ALU hazards.
         // The last two instruction, a LW followed by a SW targeting the same register, should result in a stall followed by a MEM-EX Fwd.
@@ -2599,34 +2364,4159 @@ pub mod instr_tests {
             0b00000000100000101000001010010011, //addi $r5, $r5, 8
         ]);
 
-        //CPU SETUP: Initializes the state and logic structs.
-        let mut state = Registers {
-            ifid: IFIDLatch::default(),
-            idex: IDEXLatch::default(),
-            exmem: EXMEMLatch::default(),
-            memwb: MEMWBLatch::default(),
+        let sim = run_instructions(instructions);
 
-            pc: 0,
+        //Checks for output correctness.
+        assert_eq!(sim.read_word(8), 0b01010101010101010101000000000000);
 
-            instr_mem: instructions,
-            reg_mem: vec![0; 32], //makes a vector of 32 zeroes.
-            data_mem: HashMap::new(),
-        };
+        assert_eq!(sim.registers()[1], 0);
+        assert_eq!(sim.registers()[2], (0b01010101010101010101 as u32) << 12);
+        assert_eq!(sim.registers()[3], 8);
+        assert_eq!(sim.registers()[4], 0);
+        assert_eq!(sim.registers()[5], 0b01010101010101010101000000001000);
+        for i in 6..32 {
+            assert_eq!(sim.registers()[i], 0);
+        }
+        assert_eq!(sim.stats().stall_cycles, 1);
+    }
+}
 
-        let mut logic = Logic::default();
+#[cfg(test)]
+pub mod asm_tests {
+    use crate::assembler::*;
+    use crate::*;
 
-        run_program(&mut state, &mut logic, false);
+    #[test]
+    fn matches_hand_encoding() {
+        // the same program as smoke_test, written as assembly with a mix of register names.
+        let instructions = assemble(
+            "addi $r1, $r0, 1
+             addi x2, zero, 2
+             addi gp, sp, 6     # $r3 = $r2 + 6
+             addi tp, x0, 0x2
+             addi t0, $r0, 0b1",
+        )
+        .unwrap();
 
-        //Checks for output correctness.
-        assert_eq!(state.data_mem[&2], 0b01010101010101010101000000000000);
+        assert_eq!(
+            instructions,
+            Vec::<u32>::from([
+                0b00000000000100000000000010010011,
+                0b00000000001000000000000100010011,
+                0b00000000011000010000000110010011,
+                0b00000000001000000000001000010011,
+                0b00000000000100000000001010010011,
+            ])
+        );
+    }
 
-        assert_eq!(state.reg_mem[1], 0);
-        assert_eq!(state.reg_mem[2], (0b01010101010101010101 as u32) << 12);
-        assert_eq!(state.reg_mem[3], 8);
-        assert_eq!(state.reg_mem[4], 0);
-        assert_eq!(state.reg_mem[5], 0b01010101010101010101000000001000);
-        for i in 6..32 {
-            assert_eq!(state.reg_mem[i], 0);
+    #[test]
+    fn labels_and_branches() {
+        // the beq test program, with labels instead of hand-counted offsets.
+        let instructions = assemble(
+            "       addi $r1, $r0, 1
+                    addi $r2, $r0, 2
+                    addi $r3, $r0, 3
+            back:   nop
+                    nop
+                    nop
+                    beq $r4, $r2, done
+                    nop
+                    nop
+                    nop
+                    addi $r4, $r0, 2
+                    addi $r5, $r0, 1
+                    nop
+                    jal $r6, back
+            done:   nop",
+        )
+        .unwrap();
+
+        assert_eq!(instructions[6], 0b00000010001000100000000001100011);
+        assert_eq!(instructions[13], 0b11111101100111111111001101101111);
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        // whatever display_instruction prints should assemble back into the same instruction.
+        let instructions = Vec::<u32>::from([
+            0b11111111111111111111000010110111, //lui $r1, 0xfffff
+            0b00000000000000001000000010010111, //auipc $r1, 8
+            0b00000001100000011000001101100111, //jalr $r6, $r3, 24
+            0b11111101100111111111001101101111, //jal $r6, -40
+            0b00000010010000001110000001100011, //bltu $r1, $r4, 32
+            0b00000000100000000000001010000011, //lb $r5, 8($r0)
+            0b00000000001000000001010000100011, //sh $r2, 8($r0)
+            0b01000000001100001101001010010011, //srai $r5, $r1, 3
+            0b11111111111100001100001010010011, //xori $r5, $r1, -1
+            0b01000000001000001000001010110011, //sub $r5, $r1, $r2
+        ]);
+
+        for instr in instructions {
+            let text = display_instruction(&instr);
+            let text = text.trim_end_matches('-');
+            assert_eq!(assemble(text).unwrap(), vec![instr], "{}", text);
+        }
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let instructions = assemble(
+            "li a0, 5
+             li a1, 0x12345678
+             mv a2, a1",
+        )
+        .unwrap();
+        // li with a big value turns into lui + addi, so 4 instructions in all.
+        assert_eq!(instructions.len(), 4);
+
+        let sim = run_instructions(instructions);
+
+        assert_eq!(sim.registers()[10], 5);
+        assert_eq!(sim.registers()[11], 0x12345678);
+        assert_eq!(sim.registers()[12], 0x12345678);
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let err = assemble("addi $r1, $r0, 1\n\nfoo $r1, $r2").unwrap_err();
+        assert_eq!(err.line, 3);
+
+        let err = assemble("nop\nbeq $r1, $r2, nowhere").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("nowhere"));
+
+        let err = assemble("addi $r1, $r0, 5000").unwrap_err();
+        assert_eq!(err.line, 1);
+
+        let err = assemble("lw $r1, 4").unwrap_err();
+        assert_eq!(err.line, 1);
+
+        let err = assemble("add $r1, $r2, $r32").unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+}
+
+#[cfg(test)]
+pub mod elf_tests {
+    use crate::assembler::assemble;
+    use crate::loader::*;
+    use crate::*;
+
+    // builds a tiny linked ELF32 executable by hand: .text, .data, .bss, and a symbol table.
+    fn build_elf(
+        text: &[u32],
+        text_addr: u32,
+        data: &[u8],
+        data_addr: u32,
+        bss_size: u32,
+    ) -> Vec<u8> {
+        let text_bytes: Vec<u8> = text.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let strtab = b"\0_start\0value\0".to_vec();
+        let mut symtab = vec![0u8; 16]; //symbol 0 is always empty
+        for (name, value, kind, section) in [(1u32, text_addr, 2u8, 1u16), (8, data_addr, 1, 2)] {
+            symtab.extend_from_slice(&name.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&0u32.to_le_bytes());
+            symtab.push(0x10 | kind); //global
+            symtab.push(0);
+            symtab.extend_from_slice(&section.to_le_bytes());
+        }
+
+        let text_off = 52u32;
+        let data_off = text_off + text_bytes.len() as u32;
+        let symtab_off = data_off + data.len() as u32;
+        let strtab_off = symtab_off + symtab.len() as u32;
+        let shoff = strtab_off + strtab.len() as u32;
+
+        let mut elf = vec![0x7f, b'E', b'L', b'F', 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        elf.extend_from_slice(&2u16.to_le_bytes()); //ET_EXEC
+        elf.extend_from_slice(&243u16.to_le_bytes()); //EM_RISCV
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&text_addr.to_le_bytes()); //entry
+        elf.extend_from_slice(&0u32.to_le_bytes()); //phoff
+        elf.extend_from_slice(&shoff.to_le_bytes());
+        elf.extend_from_slice(&0u32.to_le_bytes()); //flags
+        for half in [52u16, 32, 0, 40, 6, 0] {
+            elf.extend_from_slice(&half.to_le_bytes());
+        }
+        elf.extend_from_slice(&text_bytes);
+        elf.extend_from_slice(data);
+        elf.extend_from_slice(&symtab);
+        elf.extend_from_slice(&strtab);
+
+        // name, type, flags, addr, offset, size, link, info, align, entsize
+        let sections = [
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            [
+                0,
+                1,
+                0x6,
+                text_addr,
+                text_off,
+                text_bytes.len() as u32,
+                0,
+                0,
+                4,
+                0,
+            ],
+            [
+                0,
+                1,
+                0x3,
+                data_addr,
+                data_off,
+                data.len() as u32,
+                0,
+                0,
+                4,
+                0,
+            ],
+            [
+                0,
+                8,
+                0x3,
+                data_addr.wrapping_add(0x100),
+                0,
+                bss_size,
+                0,
+                0,
+                4,
+                0,
+            ],
+            [0, 2, 0, 0, symtab_off, symtab.len() as u32, 5, 1, 4, 16],
+            [0, 3, 0, 0, strtab_off, strtab.len() as u32, 0, 0, 1, 0],
+        ];
+        for section in sections {
+            for field in section {
+                elf.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        elf
+    }
+
+    #[test]
+    fn loads_sections_and_entry() {
+        let text = assemble(
+            "lui  a0, 0x2
+             lw   a1, 0(a0)
+             lbu  a2, 5(a0)
+             nop
+             add  a3, a2, a1
+             addi a4, sp, 0",
+        )
+        .unwrap();
+        let elf = build_elf(&text, 0x100, &[0x78, 0x56, 0x34, 0x12, 0, 0x7f], 0x2000, 16);
+
+        let program = load_elf(&elf).unwrap();
+        assert_eq!(program.entry, 0x100);
+        assert_eq!(program.text_end, 0x100 + 6 * 4);
+        assert_eq!(program.memory.read_word(0x100), text[0]);
+        assert_eq!(program.memory.read_word(0x2000), 0x12345678);
+        assert_eq!(program.memory.read_word(0x2004), 0x7f00);
+        assert_eq!(program.memory.read_word(0x2100), 0); //.bss
+        assert_eq!(program.symbol_address("value"), Some(0x2000));
+        assert_eq!(program.symbolize(0x108), Some("_start+0x8".to_owned()));
+
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(program);
+        sim.run();
+
+        assert_eq!(sim.registers()[10], 0x2000);
+        assert_eq!(sim.registers()[11], 0x12345678);
+        assert_eq!(sim.registers()[12], 0x7f);
+        assert_eq!(sim.registers()[13], 0x12345678 + 0x7f);
+        assert_eq!(sim.registers()[14], DEFAULT_STACK_TOP);
+    }
+
+    #[test]
+    fn linked_at_0x80000000() {
+        // where riscv-tests and bare-metal linker scripts put everything. it's mapped, and code can run there.
+        let text = assemble(
+            "lui  a0, 0x80001
+             li   a1, 3
+      loop:  lw   a2, 0(a0)
+             add  a2, a2, a1
+             sw   a2, 0(a0)
+             addi a1, a1, -1
+             bnez a1, loop
+             jal  ra, done
+             li   a3, 1
+      done:  lw   a4, 0(a0)",
+        )
+        .unwrap();
+        let elf = build_elf(&text, 0x8000_0000, &[10, 0, 0, 0], 0x8000_1000, 0);
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(load_elf(&elf).unwrap());
+        sim.run();
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.register(14), 10 + 3 + 2 + 1);
+        assert_eq!(sim.register(13), 0);
+        assert_eq!(sim.read_word(0x8000_1000), 16);
+    }
+
+    #[test]
+    fn disassembles_from_the_start_of_text() {
+        // nothing below .text gets listed, however high up it's linked.
+        let text = assemble("li a0, 1\n li a1, 2").unwrap();
+        for base in [0x10000, 0x8000_0000] {
+            let program =
+                load_elf(&build_elf(&text, base, &[1, 0, 0, 0], base + 0x1000, 0)).unwrap();
+            assert_eq!(program.text_start, base);
+            assert_eq!(program.instructions(), text);
+            let mut out = Vec::new();
+            disassemble(&program, &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert_eq!(out.lines().filter(|line| line.contains(':')).count(), 3); //the symbol, and the two instructions
+            assert!(out.contains(&format!("{:08x} <_start>:", base)));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let text = assemble("nop").unwrap();
+        let mut elf = build_elf(&text, 0, &[], 0x1000, 0);
+        elf[18] = 62; //x86-64
+        assert!(load_elf(&elf).is_err());
+
+        let mut elf = build_elf(&text, 0, &[], 0x1000, 0);
+        elf[4] = 2; //64-bit
+        assert!(load_elf(&elf).is_err());
+
+        assert!(load_elf(b"not an elf at all").is_err());
+        assert!(load_elf(&build_elf(&text, 0, &[], 0x1000, 0)[..60]).is_err());
+
+        // sizes that would take forever to fill in, or that go past the top of memory.
+        assert!(load_elf(&build_elf(&text, 0, &[], 0x1000, 0xffff_ffff)).is_err());
+        assert!(load_elf(&build_elf(&text, 0, &[1, 2, 3, 4], 0xffff_fffe, 0)).is_err());
+        assert!(load_elf(&build_elf(&text, 0, &[1, 2, 3, 4], 0xffff_fffc, 0)).is_ok());
+    }
+}
+
+#[cfg(test)]
+pub mod image_tests {
+    use crate::loader::*;
+    use crate::*;
+
+    #[test]
+    fn intel_hex() {
+        // two instructions at 0, a data word at 0x10010 through an extended linear address, and an entry point.
+        let program = load_intel_hex(
+            ":08000000930010001301200021
+             :020000040001F9
+             :0400100078563412D8
+             :0400000500000004F3
+             :00000001FF",
+        )
+        .unwrap();
+        assert_eq!(
+            program.memory.read_word(0),
+            0b00000000000100000000000010010011
+        );
+        assert_eq!(
+            program.memory.read_word(4),
+            0b00000000001000000000000100010011
+        );
+        assert_eq!(program.memory.read_word(0x10010), 0x12345678);
+        assert_eq!(program.entry, 4);
+
+        let bad_checksum = load_intel_hex(":08000000930010001301200022");
+        assert!(bad_checksum.err().unwrap().message.contains("line 1"));
+
+        // the last byte of memory is fine, but one more would wrap around to 0.
+        let program = load_intel_hex(":02000004FFFFFC\n:01FFFF00AA57\n:00000001FF").unwrap();
+        assert_eq!(program.memory.read_byte(0xffff_ffff), 0xaa);
+        let wraps = load_intel_hex(":02000004FFFFFC\n:02FFFF00AABB9B\n:00000001FF");
+        assert!(wraps.err().unwrap().message.contains("line 2"));
+    }
+
+    #[test]
+    fn readmemh_words_and_bytes() {
+        let program = load_memh(
+            "// the smoke test program
+             00100093 00200113
+             @4 /* skip ahead to word 4 */ deadbeef",
+        )
+        .unwrap();
+        assert_eq!(program.memory.read_word(0), 0x00100093);
+        assert_eq!(program.memory.read_word(4), 0x00200113);
+        assert_eq!(program.memory.read_word(16), 0xdeadbeef);
+        assert_eq!(program.memory.read_word(16), 0xdeadbeef);
+
+        // byte-wide memories list each byte on its own, little-endian.
+        let program = load_memh("93 00 10 00\n@8\n78 56 34 12").unwrap();
+        assert_eq!(program.memory.read_word(0), 0x00100093);
+        assert_eq!(program.memory.read_word(8), 0x12345678);
+
+        assert!(load_memh("00100093 zzzz").is_err());
+
+        // same at the top of memory.
+        assert!(load_memh("@ffffffff 01").is_ok());
+        assert!(load_memh("@ffffffff 01 02").is_err());
+        assert!(load_memh("@3fffffff deadbeef").is_ok());
+        assert!(load_memh("@40000000 deadbeef").is_err());
+    }
+
+    #[test]
+    fn starts_at_the_first_byte() {
+        // li a0, 5; ebreak, loaded at 0x80000000 with nothing saying where to start.
+        let images = [
+            load_intel_hex(":0200000480007A\n:0800000013055000730010000D\n:00000001FF"),
+            load_memh("@20000000 00500513 00100073"),
+            load_memh("@80000000 13 05 50 00 73 00 10 00"),
+        ];
+        for image in images {
+            let program = image.unwrap();
+            assert_eq!(program.entry, 0x8000_0000);
+            let mut sim = Simulator::new(Config::default());
+            sim.load_program(program);
+            sim.run();
+            assert_eq!(sim.stop_reason(), Some(StopReason::Ebreak));
+            assert_eq!(sim.registers()[10], 5);
         }
-    }*/
+    }
+
+    #[test]
+    fn raw_binary() {
+        let program = load_raw_binary(&[0x93, 0x00, 0x10, 0x00, 0x13, 0x01, 0x20]).unwrap();
+        assert_eq!(program.instructions(), vec![0x00100093, 0x00200113]);
+        assert_eq!(program.entry, 0);
+    }
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(detect_format("a.elf", b"\x7fELF\x01\x01"), Format::Elf);
+        assert_eq!(detect_format("prog.s", b"nop"), Format::Assembly);
+        assert_eq!(detect_format("prog.bin", b"00100093"), Format::RawBinary);
+        assert_eq!(detect_format("prog.hex", b":00000001FF"), Format::IntelHex);
+        assert_eq!(detect_format("prog.hex", b"00100093"), Format::MemHex);
+        assert_eq!(
+            detect_format("prog.txt", b"00000000000100000000000010010011\n"),
+            Format::BinaryText
+        );
+        assert_eq!(detect_format("prog", b":00000001FF"), Format::IntelHex);
+        assert_eq!(detect_format("prog", b"@0 00100093"), Format::MemHex);
+        assert_eq!(
+            detect_format("prog", &[0x93, 0x00, 0xff, 0xfe]),
+            Format::RawBinary
+        );
+        assert_eq!(detect_format("prog", b"addi a0, a0, 1"), Format::Assembly);
+
+        assert_eq!(Format::from_name("ihex"), Some(Format::IntelHex));
+        assert_eq!(Format::from_name("readmemh"), Some(Format::MemHex));
+        assert_eq!(Format::from_name("nope"), None);
+    }
+}
+
+#[cfg(test)]
+pub mod cli_tests {
+    use crate::cli::*;
+    use crate::config::{Forwarding, Model, Predictor};
+    use crate::loader::Format;
+    use crate::mem::MemoryMap;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(|s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn plain_file_still_steps() {
+        let options = parse_args(&args("prog.txt")).unwrap();
+        assert_eq!(options.command, Command::Step);
+        assert_eq!(options.program, "prog.txt");
+        assert_eq!(options.display, DisplayMode::Full);
+        assert_eq!(options.max_cycles, None);
+    }
+
+    #[test]
+    fn commands_and_options() {
+        let options = parse_args(&args(
+            "run --format=asm --max-cycles 500 --reg a0=5 --reg x11=-1 --mem 0x100=0x2a --output out.txt prog.s",
+        ))
+        .unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.format, Some(Format::Assembly));
+        assert_eq!(options.max_cycles, Some(500));
+        assert_eq!(options.registers, vec![(10, 5), (11, 0xffffffff)]);
+        assert_eq!(options.memory, vec![(0x100, 42)]);
+        assert_eq!(options.output, Some("out.txt".to_owned()));
+
+        let options = parse_args(&args("trace --display compact prog.s")).unwrap();
+        assert_eq!(options.command, Command::Trace);
+        assert_eq!(options.display, DisplayMode::Compact);
+        assert_eq!(options.forwarding, Forwarding::all());
+
+        let options = parse_args(&args("run --forwarding ex-ex,mem-mem prog.s")).unwrap();
+        assert!(
+            options.forwarding.ex_ex && !options.forwarding.mem_ex && options.forwarding.mem_mem
+        );
+        let options = parse_args(&args("run --forwarding=none prog.s")).unwrap();
+        assert_eq!(options.forwarding, Forwarding::none());
+        assert_eq!(options.model, Model::Pipelined);
+        let options = parse_args(&args("run --model multi-cycle prog.s")).unwrap();
+        assert_eq!(options.model, Model::MultiCycle);
+        assert_eq!(options.predictor, Predictor::NotTaken);
+        let options = parse_args(&args("run --predictor 2-bit --btb 64 prog.s")).unwrap();
+        assert_eq!(options.predictor, Predictor::TwoBit);
+        assert_eq!(options.btb_entries, 64);
+        assert!(!options.early_branches);
+        assert!(
+            parse_args(&args("run --early-branches prog.s"))
+                .unwrap()
+                .early_branches
+        );
+        let options = parse_args(&args(
+            "run --cache size=1k,ways=2 --cache size=8k,latency=4 --memory-latency 20 prog.s",
+        ))
+        .unwrap();
+        assert_eq!(options.caches.len(), 2);
+        assert_eq!(options.caches[0].size, 1024);
+        assert_eq!(options.caches[0].associativity, 2);
+        assert_eq!(options.caches[1].latency, 4);
+        assert_eq!(options.memory_latency, 20);
+        assert_eq!(options.icache, None);
+        let options = parse_args(&args(
+            "run --icache size=2k,line=32 --icache-penalty 8 prog.s",
+        ))
+        .unwrap();
+        assert_eq!(options.icache.unwrap().line_size, 32);
+        assert_eq!(options.icache_penalty, 8);
+        assert_eq!(options.memory_map, MemoryMap::default());
+        let options = parse_args(&args(
+            "run --memory-map text=0:64k,stack=0x80000:4k --reset-vector _start prog.s",
+        ))
+        .unwrap();
+        assert_eq!(options.memory_map.regions.len(), 2);
+        assert_eq!(options.memory_map.stack_top(), Some(0x80ff0));
+        assert_eq!(options.reset_vector, Some("_start".to_owned()));
+
+        assert!(parse_args(&args("--help")).unwrap().help);
+    }
+
+    #[test]
+    fn bad_arguments() {
+        assert!(parse_args(&args("run")).is_err());
+        assert!(parse_args(&args("run a.s b.s")).is_err());
+        assert!(parse_args(&args("--bogus prog.s")).is_err());
+        assert!(parse_args(&args("--format pdf prog.s")).is_err());
+        assert!(parse_args(&args("--max-cycles lots prog.s")).is_err());
+        assert!(parse_args(&args("prog.s --max-cycles")).is_err());
+        assert!(parse_args(&args("--reg zero=1 prog.s")).is_err());
+        assert!(parse_args(&args("--reg a0 prog.s")).is_err());
+        assert!(parse_args(&args("--mem 0x102=1 prog.s")).is_err());
+        assert!(parse_args(&args("--display loud prog.s")).is_err());
+        assert!(parse_args(&args("--forwarding wb-ex prog.s")).is_err());
+        assert!(parse_args(&args("--predictor perceptron prog.s")).is_err());
+        assert!(parse_args(&args("--btb lots prog.s")).is_err());
+        assert!(parse_args(&args("--cache size=100 prog.s")).is_err());
+        assert!(parse_args(&args("--cache colour=blue prog.s")).is_err());
+        assert!(parse_args(&args(
+            "--cache size=1k --cache size=2k --cache size=4k --cache size=8k prog.s"
+        ))
+        .is_err());
+        assert!(parse_args(&args("--memory-latency slow prog.s")).is_err());
+        assert!(parse_args(&args("--icache ways=0 prog.s")).is_err());
+        assert!(parse_args(&args("--icache-penalty slow prog.s")).is_err());
+        assert!(parse_args(&args("--memory-map text=0:4k,data=0:4k prog.s")).is_err());
+        assert!(parse_args(&args("--memory-map heap=0:4k prog.s")).is_err());
+    }
+
+    #[test]
+    fn uart_options() {
+        let options =
+            parse_args(&args("run --uart out.txt --uart-address 0xf0001000 prog.s")).unwrap();
+        assert_eq!(options.uart, Some("out.txt".to_owned()));
+        assert_eq!(options.uart_address, 0xf0001000);
+        assert_eq!(options.uart_input, None);
+        let options = parse_args(&args("run --uart=stdout --uart-input in.txt prog.s")).unwrap();
+        assert_eq!(options.uart_address, 0xf0000000);
+        assert_eq!(options.uart_input, Some("in.txt".to_owned()));
+        assert!(parse_args(&args("run --uart-input in.txt prog.s")).is_err());
+        assert!(parse_args(&args("run --uart stdout --uart-address nowhere prog.s")).is_err());
+
+        let options = parse_args(&args("run --clint prog.s")).unwrap();
+        assert!(options.clint);
+        assert_eq!(options.clint_address, 0xf2000000);
+        let options = parse_args(&args("run --clint --clint-address 0xf0010000 prog.s")).unwrap();
+        assert_eq!(options.clint_address, 0xf0010000);
+        assert!(parse_args(&args("run --clint-address 0xf0010000 prog.s")).is_err());
+    }
+
+    #[test]
+    fn latency_options() {
+        let options = parse_args(&args("run prog.s")).unwrap();
+        assert_eq!((options.mul_latency, options.div_latency), (3, 32));
+        let options = parse_args(&args("run --mul-latency 1 --div-latency=8 prog.s")).unwrap();
+        assert_eq!((options.mul_latency, options.div_latency), (1, 8));
+        assert!(parse_args(&args("run --div-latency slow prog.s")).is_err());
+
+        let options = parse_args(&args("run prog.s")).unwrap();
+        let fp = (
+            options.fadd_latency,
+            options.fmul_latency,
+            options.fdiv_latency,
+        );
+        assert_eq!(fp, (4, 7, 25));
+        let options = parse_args(&args(
+            "run --fadd-latency 2 --fmul-latency=3 --fdiv-latency 9 prog.s",
+        ))
+        .unwrap();
+        let fp = (
+            options.fadd_latency,
+            options.fmul_latency,
+            options.fdiv_latency,
+        );
+        assert_eq!(fp, (2, 3, 9));
+        assert!(parse_args(&args("run --fmul-latency lots prog.s")).is_err());
+    }
+}
+
+#[cfg(test)]
+pub mod report_tests {
+    use crate::assembler::*;
+    use crate::report::*;
+    use crate::*;
+
+    #[test]
+    fn counts_a_loop() {
+        let instructions = assemble(
+            "        li   t0, 10
+                     li   a0, 0
+             loop:   add  a0, a0, t0
+                     addi t0, t0, -1
+                     bnez t0, loop
+                     sw   a0, 8(zero)",
+        )
+        .unwrap();
+        let sim = run_instructions(instructions);
+
+        assert_eq!(sim.registers()[10], 55);
+        assert_eq!(sim.stats().retired, 2 + 10 * 3 + 1);
+        assert_eq!(sim.stats().bubbles, 9 * 2); //the branch is taken 9 times
+        assert_eq!(sim.stats().stall_cycles, 0);
+        assert!(sim.stats().cycles > sim.stats().retired);
+        assert_eq!(
+            sim.stats().touched.iter().copied().collect::<Vec<u32>>(),
+            vec![2]
+        );
+
+        let text = sim.report(ReportFormat::Text);
+        assert!(text.contains("stopped by:           end of program"));
+        assert!(text.contains("instructions retired: 33"));
+        assert!(text.contains("0x00000008: 0x00000037"));
+
+        let json = sim.report(ReportFormat::Json);
+        assert!(json.starts_with("{\"stop_reason\": \"end of program\", \"exit_code\": 0, "));
+        assert!(json.contains(&format!("\"cycles\": {}, ", sim.stats().cycles)));
+        assert!(json.contains("\"retired\": 33"));
+        assert!(json.contains("\"memory\": {\"0x00000008\": 55}"));
+    }
+}
+
+#[cfg(test)]
+pub mod termination_tests {
+    use crate::*;
+
+    #[test]
+    fn ecall_exit() {
+        // a7 is still in MEM-WB when the ECALL gets checked, so this also checks that it gets forwarded.
+        let sim = run_source(
+            "li a0, 7
+             li a7, 93
+             ecall
+             li a0, 99",
+            Config::default(),
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::Exit(7)));
+        assert_eq!(sim.stop_reason().unwrap().exit_code(), 7);
+        assert_eq!(sim.registers()[10], 7); //the instruction after the ECALL never ran
+        assert_eq!(sim.stats().retired, 3);
+
+        //any other ECALL is ignored for now
+        let sim = run_source(
+            "li a7, 64
+             ecall
+             li a1, 5",
+            Config::default(),
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.registers()[11], 5);
+    }
+
+    #[test]
+    fn ebreak() {
+        let sim = run_source(
+            "li a0, 1
+             ebreak
+             li a0, 2",
+            Config::default(),
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::Ebreak));
+        assert_eq!(sim.registers()[10], 1);
+    }
+
+    #[test]
+    fn halt_address() {
+        // loops backwards, so the old "some distance past the end" check wouldn't work here.
+        let source = "        li   t0, 10
+                              li   a0, 0
+                      loop:   add  a0, a0, t0
+                              addi t0, t0, -1
+                              bnez t0, loop
+                              sw   a0, 8(zero)
+                      done:   li   a1, 1
+                              j    done";
+        let sim = run_source(
+            source,
+            Config {
+                halt_address: Some(0x18),
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::HaltAddress(0x18)));
+        assert_eq!(sim.registers()[10], 55);
+        assert_eq!(sim.read_word(8), 55);
+        assert_eq!(sim.registers()[11], 0); //the instruction at the halt address doesn't run
+
+        //the halt address can be past the last instruction
+        let sim = run_source(
+            "li a0, 1",
+            Config {
+                halt_address: Some(4),
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::HaltAddress(4)));
+        assert_eq!(sim.registers()[10], 1);
+    }
+
+    #[test]
+    fn tohost() {
+        let sim = run_source(
+            "sw   zero, 0x100(zero)
+             li   t0, 1
+             sw   t0, 0x100(zero)
+             li   a0, 5",
+            Config {
+                tohost: Some(0x100),
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::ToHost(1)));
+        assert_eq!(sim.stop_reason().unwrap().exit_code(), 0);
+        assert_eq!(sim.registers()[10], 0);
+
+        // in an MMIO region, where the store goes to the bus instead of memory. (nothing is there to take it)
+        let sim = run_source(
+            "li   t1, 0xf0001000
+             sw   zero, 0(t1)
+             li   t0, 7
+             sw   t0, 0(t1)
+             li   a0, 5",
+            Config {
+                tohost: Some(0xf000_1000),
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::ToHost(7)));
+        assert_eq!(sim.registers()[10], 0);
+    }
+
+    #[test]
+    fn watchdog_and_running_off_the_end() {
+        let sim = run_source(
+            "loop: j loop",
+            Config {
+                max_cycles: Some(100),
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::CycleLimit(100)));
+        assert_eq!(sim.stop_reason().unwrap().exit_code(), EXIT_CYCLE_LIMIT);
+
+        //jumping way past the end of the program
+        let sim = run_source(
+            "li a0, 1
+             j  0x100
+             li a0, 2",
+            Config {
+                max_cycles: Some(1000),
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.registers()[10], 1);
+    }
+}
+
+#[cfg(test)]
+pub mod syscall_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    #[test]
+    fn write_read_and_exit() {
+        // prints "hi\n" from data memory, reads 2 bytes of input after it, then exits with what write returned.
+        let instructions = assemble(
+            "li a0, 1
+             li a1, 0x100
+             li a2, 3
+             li a7, 64
+             ecall
+             mv s0, a0
+             li a0, 0
+             li a1, 0x104
+             li a2, 2
+             li a7, 63
+             ecall
+             mv s1, a0
+             lbu s2, 0x105(zero)
+             mv a0, s0
+             li a7, 93
+             ecall
+             li a0, 99",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(Program::from_instructions(instructions));
+        sim.write_word(0x100, u32::from_le_bytes(*b"hi\n\0"));
+        let (syscalls, output) = NewlibSyscalls::captured(0x1000, b"xyz");
+        sim.set_syscall_handler(Box::new(syscalls));
+        sim.run();
+
+        assert_eq!(output.borrow().as_slice(), b"hi\n");
+        assert_eq!(sim.registers()[8], 3); //s0: bytes written
+        assert_eq!(sim.registers()[9], 2); //s1: bytes read
+        assert_eq!(sim.read_word(0x104), u32::from_le_bytes(*b"xy\0\0"));
+        assert_eq!(sim.registers()[18], b'y' as u32); //s2, loaded right after the read
+        assert_eq!(sim.stop_reason(), Some(StopReason::Exit(3)));
+        assert_eq!(sim.registers()[10], 3);
+    }
+
+    #[test]
+    fn brk_and_unknown_calls() {
+        let instructions = assemble(
+            "li a0, 0
+             li a7, 214
+             ecall
+             mv s0, a0
+             addi a0, a0, 0x100
+             ecall
+             mv s1, a0
+             li a7, 12345
+             ecall
+             mv s2, a0
+             li a0, 8
+             li a7, 64
+             ecall",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(Program::from_instructions(instructions));
+        let (syscalls, _) = NewlibSyscalls::captured(0x2000, b"");
+        sim.set_syscall_handler(Box::new(syscalls));
+        sim.run();
+
+        assert_eq!(sim.registers()[8], 0x2000);
+        assert_eq!(sim.registers()[9], 0x2100);
+        assert_eq!(sim.registers()[18] as i32, -38); //ENOSYS
+        assert_eq!(sim.registers()[10] as i32, -9); //EBADF, there is no file 8
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+    }
+
+    #[test]
+    fn huge_counts_come_back_short() {
+        let instructions = assemble(
+            "li a0, 1
+             li a1, 0x100
+             li a2, -1
+             li a7, 64
+             ecall
+             mv s0, a0
+             li a0, 0
+             li a2, -1
+             li a7, 63
+             ecall
+             mv s1, a0",
+        )
+        .unwrap();
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(Program::from_instructions(instructions));
+        let (syscalls, output) = NewlibSyscalls::captured(0x1000, b"xyz");
+        sim.set_syscall_handler(Box::new(syscalls));
+        sim.run();
+
+        assert_eq!(output.borrow().len(), MAX_TRANSFER as usize);
+        assert_eq!(sim.registers()[8], MAX_TRANSFER);
+        assert_eq!(sim.registers()[9], 3);
+    }
+
+    #[test]
+    fn return_value_with_any_forwarding() {
+        // a0 is only written once the ECALL is in EX-MEM, so the instructions right behind it have to wait for it
+        // (or a branch on it) the same as they would behind an ALU instruction.
+        let source = "li a0, 1
+                      li a1, 0x100
+                      li a2, 3
+                      li a7, 64
+                      ecall
+                      addi s0, a0, 10
+                      li a7, 214
+                      li a0, 0
+                      ecall
+                      beq a0, zero, done
+                      li s1, 1
+                      done: nop";
+        for forwarding in [
+            "all",
+            "none",
+            "ex-ex",
+            "mem-ex",
+            "mem-mem",
+            "mem-ex,mem-mem",
+        ] {
+            for early_branches in [false, true] {
+                let mut sim = Simulator::new(Config {
+                    forwarding: Forwarding::from_names(forwarding).unwrap(),
+                    early_branches,
+                    ..Config::default()
+                });
+                sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+                let (syscalls, output) = NewlibSyscalls::captured(0x2000, b"");
+                sim.set_syscall_handler(Box::new(syscalls));
+                sim.run();
+
+                assert_eq!(output.borrow().len(), 3, "{}", forwarding);
+                assert_eq!(sim.registers()[8], 13, "{} {}", forwarding, early_branches);
+                assert_eq!(sim.registers()[9], 1, "{} {}", forwarding, early_branches);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod csr_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str) -> Simulator {
+        run_instructions(assemble(source).unwrap())
+    }
+
+    #[test]
+    fn matches_hand_encoding() {
+        let instructions = assemble(
+            "csrrw a0, mscratch, a1
+             csrrsi a2, mstatus, 8
+             csrc mepc, t0
+             csrr a3, minstret
+             rdcycle s0
+             csrwi mtvec, 4",
+        )
+        .unwrap();
+        assert_eq!(
+            instructions,
+            vec![0x34059573, 0x30046673, 0x3412b073, 0xb02026f3, 0xc0002473, 0x30525073]
+        );
+        assert!(assemble("csrr a0, nosuchcsr").is_err());
+        assert!(assemble("csrrwi a0, mstatus, 32").is_err());
+    }
+
+    #[test]
+    fn read_modify_write() {
+        let sim = run("li   a1, 0x0f0
+                         csrrw a0, mscratch, a1
+                         addi a2, a0, 1
+                         csrrs a3, mscratch, a0
+                         li   t0, 0x030
+                         csrrc a4, mscratch, t0
+                         csrrsi a5, mscratch, 1
+                         csrrci a6, mscratch, 0
+                         csrr s0, mscratch
+                         csrw mstatus, a1
+                         csrr s1, mstatus
+                         csrw cycle, a1
+                         csrr s2, mhartid");
+
+        assert_eq!(sim.registers()[10], 0); //mscratch started out as 0
+        assert_eq!(sim.registers()[12], 1); //the old value gets forwarded right away
+        assert_eq!(sim.registers()[13], 0x0f0);
+        assert_eq!(sim.registers()[14], 0x0f0);
+        assert_eq!(sim.registers()[15], 0x0c0);
+        assert_eq!(sim.registers()[16], 0x0c1);
+        assert_eq!(sim.registers()[8], 0x0c1);
+        assert_eq!(sim.state().csr.mscratch, 0x0c1);
+        // only MIE and MPIE can be changed, and MPP is always machine mode
+        assert_eq!(sim.registers()[9], MSTATUS_MPIE | MSTATUS_MPP);
+        assert_eq!(sim.registers()[18], 0);
+    }
+
+    #[test]
+    fn counters() {
+        let sim = run("rdinstret s0
+                         li   a0, 1
+                         rdinstret s1
+                         nop
+                         nop
+                         rdinstret s2
+                         rdcycle t0
+                         nop
+                         rdcycle t1
+                         li   t2, 100
+                         csrw minstret, t2
+                         rdinstret s3");
+
+        assert_eq!(sim.registers()[8], 0);
+        assert_eq!(sim.registers()[9], 2); //the li is still in MEM-WB, but it counts
+        assert_eq!(sim.registers()[18], 5);
+        assert_eq!(sim.registers()[6] - sim.registers()[5], 2);
+        assert_eq!(sim.registers()[19], 100); //the write counts, not the instruction that did it
+        assert_eq!(sim.state().csr.minstret, sim.stats().retired - 11 + 100);
+        assert_eq!(sim.state().csr.mcycle, sim.stats().cycles);
+    }
+}
+
+#[cfg(test)]
+pub mod trap_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str) -> Simulator {
+        run_instructions(assemble(source).unwrap())
+    }
+
+    // installs a handler that saves mcause, mepc and mtval in s0, s1 and s2,
+    // counts traps in s3, and skips over the instruction that trapped.
+    const HANDLER: &str = "
+        j    end
+        handler:
+        csrr s0, mcause
+        csrr s1, mepc
+        csrr s2, mtval
+        addi s3, s3, 1
+        addi t6, s1, 4
+        csrw mepc, t6
+        mret
+        end:";
+
+    fn run_with_handler(body: &str) -> Simulator {
+        run(&format!(
+            "la t0, handler
+             csrw mtvec, t0
+             {}
+             {}",
+            body, HANDLER
+        ))
+    }
+
+    #[test]
+    fn illegal_instruction() {
+        // 0: auipc, 4: addi, 8: csrw, 12: li, 16: the bad word
+        let sim = run_with_handler(
+            "li   a0, 1
+             .word 0xffffffff
+             addi a0, a0, 1
+             addi a0, a0, 1",
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.registers()[8], CAUSE_ILLEGAL_INSTRUCTION);
+        assert_eq!(sim.registers()[9], 16);
+        assert_eq!(sim.registers()[18], 0xffffffff);
+        assert_eq!(sim.registers()[19], 1);
+        assert_eq!(sim.registers()[10], 3); //each add happened exactly once
+    }
+
+    #[test]
+    fn bad_fields_are_illegal() {
+        for word in [
+            0x40001013u32, // slli with a funct7
+            0x40002033,    // "sub" with funct3 2
+            0x04000033,    // funct7 2 isn't anything
+            0x00003003,    // ld, which is RV64
+            0x00003023,    // sd
+            0x0000206b,    // unknown opcode
+            0xc0051073,    // csrw cycle, a0: cycle is read-only
+            0x7c002573,    // csrr a0, 0x7c0: no such CSR
+        ] {
+            let sim = run_with_handler(&format!(".word {:#x}", word));
+            assert_eq!(sim.registers()[8], CAUSE_ILLEGAL_INSTRUCTION, "{:#x}", word);
+            assert_eq!(sim.registers()[18], word);
+            assert_eq!(sim.registers()[19], 1);
+        }
+        // reading a read-only CSR is fine, and so is csrrs with x0, which doesn't write.
+        let sim = run_with_handler("csrr a0, cycle\n csrrs a1, mhartid, zero");
+        assert_eq!(sim.registers()[19], 0);
+    }
+
+    #[test]
+    fn bad_loads_and_stores() {
+        let sim = run_with_handler(
+            "li   t1, 0x100
+             li   t2, 0x55
+             sw   t2, 2(t1)
+             mv   s4, s0
+             mv   s5, s2
+             lh   a0, 1(t1)
+             mv   s6, s0
+             mv   s7, s2
+             sb   t2, 0(zero)
+             sh   t2, 2(t1)
+             lhu  a1, 2(t1)
+             lb   a2, 3(t1)",
+        );
+        assert_eq!(sim.registers()[20], CAUSE_MISALIGNED_STORE);
+        assert_eq!(sim.registers()[21], 0x102);
+        assert_eq!(sim.registers()[22], CAUSE_MISALIGNED_LOAD);
+        assert_eq!(sim.registers()[23], 0x101);
+        assert_eq!(sim.registers()[8], CAUSE_STORE_ACCESS_FAULT);
+        assert_eq!(sim.registers()[18], 0);
+        assert_eq!(sim.registers()[19], 3);
+        assert_eq!(sim.registers()[10], 0); //the faulting load never wrote its register
+                                            // the faulting stores changed nothing, the good ones went through.
+        assert_eq!(sim.read_word(0), sim.program().memory.read_word(0));
+        assert_eq!(sim.read_word(0x100), 0x00550000);
+        assert_eq!(sim.registers()[11], 0x55);
+        assert_eq!(sim.registers()[12], 0);
+    }
+
+    #[test]
+    fn jumps_to_halfwords() {
+        // with compressed instructions, anything 2-byte aligned can be jumped to, so no jump is misaligned anymore.
+        // 0: auipc, 4: addi, 8: csrw, 12: auipc, 16: jalr, 20: c.nop, 22: c.li a0, 7
+        let sim = run_with_handler(
+            "auipc t1, 0
+             jalr ra, 10(t1)
+             .word 0x451d0001",
+        );
+        assert_eq!(sim.registers()[19], 0);
+        assert_eq!(sim.registers()[1], 20);
+        assert_eq!(sim.registers()[10], 7);
+    }
+
+    #[test]
+    fn ecall_and_ebreak_go_to_the_handler() {
+        let sim = run_with_handler(
+            "li   a7, 93
+             ecall
+             mv   s4, s0
+             mv   s5, s2
+             ebreak",
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.registers()[20], CAUSE_ECALL_FROM_M);
+        assert_eq!(sim.registers()[8], CAUSE_BREAKPOINT);
+        assert_eq!(sim.registers()[21], 0); //ecall has no mtval
+        assert_eq!(sim.registers()[18], sim.registers()[9]); //ebreak's is its address
+        assert_eq!(sim.registers()[19], 2);
+    }
+
+    #[test]
+    fn mret_restores_interrupt_enable() {
+        let sim = run("la    t0, handler
+             csrw  mtvec, t0
+             csrsi mstatus, 8
+             .word 0
+             ecall
+             csrr  a1, mstatus
+             j     end
+             handler:
+             csrr  a0, mstatus
+             csrr  t1, mepc
+             addi  t1, t1, 4
+             csrw  mepc, t1
+             mret
+             end:");
+        // in the handler: MIE off, MPIE holding the old MIE.
+        assert_eq!(sim.registers()[10], MSTATUS_MPIE | MSTATUS_MPP);
+        // after: MIE back on, MPIE set.
+        assert_eq!(
+            sim.registers()[11],
+            MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP
+        );
+    }
+
+    #[test]
+    fn unhandled_traps_stop_the_run() {
+        let sim = run("li   a0, 5
+             lw   a1, 2(zero)
+             li   a0, 6");
+        let trap = Trap {
+            cause: CAUSE_MISALIGNED_LOAD,
+            epc: 4,
+            tval: 2,
+        };
+        assert_eq!(sim.stop_reason(), Some(StopReason::UnhandledTrap(trap)));
+        assert_eq!(sim.stop_reason().unwrap().exit_code(), EXIT_TRAP);
+        assert_eq!(
+            sim.stop_reason().unwrap().describe(),
+            "unhandled trap: misaligned load at 0x00000004 (mtval 0x00000002)"
+        );
+        assert_eq!(sim.registers()[10], 5); //nothing after it ran
+        assert_eq!(sim.state().csr.mcause, 0); //and nothing was written, since there is no handler
+
+        let sim = run("sw a0, 0(zero)");
+        assert!(matches!(
+            sim.stop_reason(),
+            Some(StopReason::UnhandledTrap(Trap {
+                cause: CAUSE_STORE_ACCESS_FAULT,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn assembles_and_shows_mret() {
+        assert_eq!(assemble("mret\nwfi").unwrap(), vec![0x30200073, 0x10500073]);
+        assert!(display_instruction(&0x30200073).starts_with("mret-"));
+        assert!(display_instruction(&0x10500073).starts_with("wfi-"));
+    }
+}
+
+#[cfg(test)]
+pub mod simulator_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn load(source: &str, config: Config) -> Simulator {
+        let mut sim = Simulator::new(config);
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim
+    }
+
+    #[test]
+    fn run_until_and_reset() {
+        let mut sim = load(
+            "        li   t0, 3
+             loop:   addi a0, a0, 1
+                     addi t0, t0, -1
+                     bnez t0, loop
+                     sw   a0, 0x40(zero)",
+            Config::default(),
+        );
+        // stops as soon as the condition holds, with the program still running.
+        assert_eq!(sim.run_until(|sim| sim.register(10) == 2), None);
+        assert_eq!(sim.register(10), 2);
+        assert_eq!(sim.stop_reason(), None);
+
+        assert_eq!(sim.run(), StopReason::EndOfProgram);
+        assert_eq!(sim.read_word(0x40), 3);
+        assert_eq!(sim.step(), Some(StopReason::EndOfProgram)); //nothing left to do
+        let cycles = sim.stats().cycles;
+
+        // back to how it was after loading, and it runs the same way again.
+        sim.reset();
+        assert_eq!(sim.pc(), 0);
+        assert_eq!(sim.registers(), &[0; 32][..]);
+        assert_eq!(sim.read_word(0x40), 0);
+        assert_eq!(sim.stats().cycles, 0);
+        assert_eq!(sim.run(), StopReason::EndOfProgram);
+        assert_eq!(sim.stats().cycles, cycles);
+    }
+
+    #[test]
+    fn registers_memory_and_config() {
+        let mut sim = load(
+            "lw   a1, 0x80(zero)
+             add  a2, a0, a1
+             csrr a3, mscratch
+             loop: j loop",
+            Config {
+                max_cycles: Some(50),
+                ..Config::default()
+            },
+        );
+        sim.set_register(10, 5);
+        sim.set_register(0, 7); //ignored
+        sim.write_word(0x80, 10);
+        assert_eq!(sim.run(), StopReason::CycleLimit(50));
+        assert!(sim.is_over());
+        assert_eq!(sim.register(0), 0);
+        assert_eq!(sim.register(12), 15);
+        assert_eq!(sim.csr(csr::MSCRATCH), Some(0));
+        assert_eq!(sim.csr(0x7c0), None);
+        assert!(sim
+            .report(report::ReportFormat::Text)
+            .contains("cycle limit (50 cycles)"));
+    }
+}
+
+#[cfg(test)]
+pub mod hazard_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str) -> Simulator {
+        run_with_forwarding(source, Forwarding::all())
+    }
+
+    fn run_with_forwarding(source: &str, forwarding: Forwarding) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            forwarding,
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.write_word(0x100, 40);
+        sim.write_word(0x104, 0x104);
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn load_use_stalls_once() {
+        // each of these needs the loaded value in EX right behind the load.
+        let sim = run("lw   a0, 0x100(zero)
+                       add  a1, zero, a0     # as r2
+                       lw   a2, 0x104(zero)
+                       lw   a3, 0(a2)        # as a load address
+                       lw   a4, 0x104(zero)
+                       sw   a0, 0(a4)        # as a store address
+                       lw   a5, 0x100(zero)
+                       beq  a5, a1, skip     # as a branch operand
+                       li   s0, 1
+                       skip:");
+        assert_eq!(sim.register(11), 40);
+        assert_eq!(sim.register(13), 0x104);
+        assert_eq!(sim.read_word(0x104), 40);
+        assert_eq!(sim.register(8), 0);
+        assert_eq!(sim.stats().stall_cycles, 4);
+    }
+
+    #[test]
+    fn no_stall_without_a_dependency() {
+        // the addi's immediate has a0 (10) in its r2 bits, but it doesn't read r2.
+        // the store only needs a0 as data, and MEM-MEM forwarding covers that.
+        let sim = run("lw   a0, 0x100(zero)
+                       addi a1, zero, 10
+                       lw   a2, 0x100(zero)
+                       sw   a2, 0x108(zero)
+                       lw   zero, 0x100(zero)
+                       add  a3, zero, zero
+                       lw   a4, 0x100(zero)
+                       nop
+                       add  a5, a4, a4");
+        assert_eq!(sim.register(11), 10);
+        assert_eq!(sim.read_word(0x108), 40);
+        assert_eq!(sim.register(13), 0);
+        assert_eq!(sim.register(15), 80);
+        assert_eq!(sim.stats().stall_cycles, 0);
+    }
+
+    fn stalls(source: &str, forwarding: &str) -> u64 {
+        run_with_forwarding(source, Forwarding::from_names(forwarding).unwrap())
+            .stats()
+            .stall_cycles
+    }
+
+    #[test]
+    fn stalls_for_each_missing_path() {
+        let back_to_back = "li a0, 5\n addi a1, a0, 1";
+        assert_eq!(stalls(back_to_back, "all"), 0);
+        assert_eq!(stalls(back_to_back, "mem-ex,mem-mem"), 1); //waits for MEM-EX instead
+        assert_eq!(stalls(back_to_back, "none"), 2); //waits for the register file
+
+        let one_apart = "li a0, 5\n nop\n addi a1, a0, 1";
+        assert_eq!(stalls(one_apart, "ex-ex,mem-mem"), 1);
+        assert_eq!(stalls(one_apart, "mem-ex"), 0);
+
+        let load_use = "lw a0, 0x100(zero)\n add a1, a0, a0";
+        assert_eq!(stalls(load_use, "all"), 1);
+        assert_eq!(stalls(load_use, "ex-ex,mem-mem"), 2);
+
+        let load_store = "lw a0, 0x100(zero)\n sw a0, 0x108(zero)";
+        assert_eq!(stalls(load_store, "all"), 0);
+        assert_eq!(stalls(load_store, "ex-ex,mem-ex"), 1);
+        assert_eq!(stalls(load_store, "none"), 2);
+    }
+
+    #[test]
+    fn same_results_with_or_without_forwarding() {
+        let source = "        li   t0, 4
+                              li   a0, 0
+                      loop:   lw   t1, 0x100(zero)
+                              add  a0, a0, t1
+                              sw   a0, 0x108(zero)
+                              lw   t2, 0x108(zero)
+                              sw   t2, 0x10c(zero)
+                              addi t0, t0, -1
+                              bnez t0, loop
+                              add  a1, a0, t0";
+        let mut stall_counts = Vec::new();
+        for paths in ["all", "ex-ex", "mem-ex", "mem-mem", "ex-ex,mem-ex", "none"] {
+            let sim = run_with_forwarding(source, Forwarding::from_names(paths).unwrap());
+            assert_eq!(sim.register(10), 160, "{}", paths);
+            assert_eq!(sim.register(11), 160, "{}", paths);
+            assert_eq!(sim.read_word(0x10c), 160, "{}", paths);
+            assert_eq!(sim.stats().retired, 2 + 4 * 7 + 1, "{}", paths);
+            stall_counts.push(sim.stats().stall_cycles);
+        }
+        // taking paths away only ever adds stalls.
+        assert!(stall_counts[0] < stall_counts[4]);
+        assert!(stall_counts[4] < stall_counts[5]);
+        assert!(stall_counts.iter().all(|stalls| *stalls <= stall_counts[5]));
+    }
+}
+
+#[cfg(test)]
+pub mod model_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str, model: Model) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            model,
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.write_word(0x100, 40);
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn same_results_on_every_model() {
+        let source = "        li   t0, 4
+                              li   a0, 0
+                      loop:   lw   t1, 0x100(zero)
+                              add  a0, a0, t1
+                              sw   a0, 0x108(zero)
+                              jal  ra, count
+                              addi t0, t0, -1
+                              bnez t0, loop
+                              j    end
+                      count:  addi a1, a1, 1
+                              ret
+                      end:    csrr a2, instret";
+        let pipelined = run(source, Model::Pipelined);
+        let single = run(source, Model::SingleCycle);
+        let multi = run(source, Model::MultiCycle);
+        for sim in [&pipelined, &single, &multi] {
+            assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+            assert_eq!(sim.registers(), pipelined.registers());
+            assert_eq!(sim.read_word(0x108), 160);
+            assert_eq!(sim.stats().retired, 2 + 4 * 8 + 2);
+        }
+        assert_eq!(pipelined.register(11), 4);
+
+        // one cycle per instruction, and no overlap to need stalls or bubbles.
+        assert_eq!(single.stats().cycles, single.stats().retired);
+        assert_eq!(single.stats().bubbles, 0);
+        // li x2, then per loop: lw 5, add 4, sw 4, jal 4, addi 4, ret 4, addi 4, branch 3. then j 4, csrr 4.
+        assert_eq!(multi.stats().cycles, 2 * 4 + 4 * 32 + 4 + 4);
+        assert_eq!(multi.stats().bubbles, 0);
+        assert!(single.stats().cycles < pipelined.stats().cycles);
+        assert!(pipelined.stats().cycles < multi.stats().cycles);
+    }
+
+    #[test]
+    fn steps_a_cycle_at_a_time() {
+        let source = "li a0, 1\n li a1, 2\n add a2, a0, a1\n sw a2, 0x10c(zero)";
+        let mut sim = Simulator::new(Config {
+            model: Model::SingleCycle,
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        // each step fetches the next instruction and finishes off the one before.
+        for cycle in 1..=4 {
+            assert_eq!(sim.step(), None);
+            assert_eq!(sim.stats().cycles, cycle);
+            assert_eq!(sim.stats().retired, cycle - 1);
+        }
+        assert_eq!(sim.step(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.stats().cycles, 4);
+        assert_eq!(sim.read_word(0x10c), 3);
+
+        // the counters the program sees go by the model's cycles too.
+        let source = "li a1, 1\n li a2, 2\n csrr a0, cycle";
+        assert_eq!(run(source, Model::SingleCycle).register(10), 3);
+        assert_eq!(run(source, Model::MultiCycle).register(10), 4 + 4 + 3);
+    }
+
+    #[test]
+    fn traps_and_syscalls_on_every_model() {
+        let source = "        la    t0, handler
+                              csrw  mtvec, t0
+                              .word 0xffffffff
+                              li    a0, 7
+                              li    a7, 93
+                              ecall
+                      handler:
+                              csrr  s0, mcause
+                              csrr  t1, mepc
+                              addi  t1, t1, 4
+                              csrw  mepc, t1
+                              csrw  mtvec, zero
+                              mret";
+        for model in [Model::Pipelined, Model::SingleCycle, Model::MultiCycle] {
+            let sim = run(source, model);
+            assert_eq!(sim.register(8), CAUSE_ILLEGAL_INSTRUCTION, "{:?}", model);
+            assert_eq!(sim.stop_reason(), Some(StopReason::Exit(7)), "{:?}", model);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod predictor_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str, predictor: Predictor, btb_entries: usize) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            predictor,
+            btb_entries,
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.run();
+        sim
+    }
+
+    const NESTED_LOOPS: &str = "        li   s0, 3
+                                outer:  li   t0, 4
+                                inner:  addi a0, a0, 1
+                                        addi t0, t0, -1
+                                        bnez t0, inner
+                                        addi s0, s0, -1
+                                        bnez s0, outer";
+
+    #[test]
+    fn each_predictor_on_nested_loops() {
+        // the inner branch is taken 9 times out of 12, the outer one 2 out of 3.
+        let expected = [
+            (Predictor::NotTaken, 11),
+            (Predictor::BackwardTaken, 4), //only the loop exits
+            (Predictor::OneBit, 8),        //the first time round each loop, and the exits
+            (Predictor::TwoBit, 6),        //only the first time round, then just the exits
+        ];
+        for (predictor, mispredictions) in expected {
+            let sim = run(NESTED_LOOPS, predictor, 0);
+            assert_eq!(sim.register(10), 12, "{:?}", predictor);
+            assert_eq!(sim.stats().branches, 15, "{:?}", predictor);
+            assert_eq!(
+                sim.stats().mispredictions,
+                mispredictions,
+                "{:?}",
+                predictor
+            );
+            // every misprediction costs two bubbles, and nothing else does here.
+            assert_eq!(sim.stats().bubbles, 2 * mispredictions, "{:?}", predictor);
+        }
+        // gshare is still filling its table with this few branches, so only check it gets the same results.
+        let gshare = run(NESTED_LOOPS, Predictor::Gshare, 0);
+        assert_eq!(gshare.register(10), 12);
+        assert_eq!(gshare.stats().branches, 15);
+    }
+
+    #[test]
+    fn wrong_path_is_squashed() {
+        // BTFNT guesses the backward branch is taken, so the addi at back gets fetched. it mustn't happen.
+        let sim = run(
+            "        j    start
+             back:   addi a1, a1, 100
+                     j    end
+             start:  li   t0, 1
+                     beqz t0, back
+                     addi a2, a2, 1
+             end:",
+            Predictor::BackwardTaken,
+            0,
+        );
+        assert_eq!(sim.register(11), 0);
+        assert_eq!(sim.register(12), 1);
+        assert_eq!(sim.stats().mispredictions, 1);
+    }
+
+    #[test]
+    fn btb_predicts_returns() {
+        let source = "        li   s0, 5
+                      loop:   jal  ra, func
+                              addi s0, s0, -1
+                              bnez s0, loop
+                              j    end
+                      func:   addi a0, a0, 1
+                              ret
+                      end:";
+        // without a BTB the targets come from decoding the jump, which doesn't work for ret.
+        let sim = run(source, Predictor::TwoBit, 0);
+        assert_eq!(sim.register(10), 5);
+        assert_eq!(sim.stats().branches, 16);
+        assert_eq!(sim.stats().mispredictions, 5 + 2);
+        // with one, everything misses the first time, and then only the loop exit is wrong.
+        let sim = run(source, Predictor::TwoBit, 64);
+        assert_eq!(sim.register(10), 5);
+        assert_eq!(sim.stats().mispredictions, 4 + 1);
+        // predicting nothing taken makes the BTB useless.
+        let sim = run(source, Predictor::NotTaken, 64);
+        assert_eq!(sim.stats().mispredictions, 15);
+    }
+
+    #[test]
+    fn link_register_is_forwarded() {
+        // a correctly predicted JAL is right in front of the first instruction of what it calls.
+        let source = "jal ra, f\n j end\n f: addi a0, ra, 0\n end:";
+        for predictor in [Predictor::NotTaken, Predictor::TwoBit] {
+            assert_eq!(run(source, predictor, 0).register(10), 4, "{:?}", predictor);
+        }
+    }
+
+    #[test]
+    fn counters_and_history() {
+        let mut two_bit = TwoBit::new(16);
+        assert!(!two_bit.predict(0x40, 0, false));
+        assert!(two_bit.predict(0x40, 0, true)); //jumps always go
+        two_bit.update(0x40, true);
+        two_bit.update(0x40, true);
+        two_bit.update(0x40, false);
+        assert!(two_bit.predict(0x40, 0, false)); //one not-taken isn't enough to change its mind
+        assert!(!two_bit.predict(0x44, 0, false));
+
+        // the same branch, alternating: gshare learns the pattern, a plain counter can't.
+        let mut gshare = Gshare::new(64, 4);
+        let mut counter = TwoBit::new(64);
+        let (mut gshare_right, mut counter_right) = (0, 0);
+        for i in 0..100 {
+            let taken = i % 2 == 0;
+            gshare_right += (gshare.predict(0x80, 0, false) == taken) as u32;
+            counter_right += (counter.predict(0x80, 0, false) == taken) as u32;
+            gshare.update(0x80, taken);
+            counter.update(0x80, taken);
+        }
+        assert!(gshare_right > 90);
+        assert!(counter_right <= 50);
+
+        let mut btb = Btb::new(4);
+        assert_eq!(btb.lookup(0x10), None);
+        btb.insert(0x10, 0x100);
+        assert_eq!(btb.lookup(0x10), Some(0x100));
+        assert_eq!(btb.lookup(0x20), None); //same entry, different branch
+    }
+
+    #[test]
+    fn accuracy_in_the_report() {
+        let sim = run(NESTED_LOOPS, Predictor::TwoBit, 32);
+        let text = sim.report(report::ReportFormat::Text);
+        assert!(text.contains("branch predictor:     2-bit (1024 entries), 32-entry BTB"));
+        assert!(text.contains("branches:             15 ("));
+        let json = sim.report(report::ReportFormat::Json);
+        assert!(json.contains("\"branches\": 15, "));
+
+        let mut sim = Simulator::new(Config::default());
+        sim.set_branch_predictor(Box::new(BackwardTaken));
+        sim.load_program(Program::from_instructions(assemble(NESTED_LOOPS).unwrap()));
+        sim.run();
+        assert_eq!(sim.stats().mispredictions, 4);
+        assert!((sim.stats().accuracy() - 11.0 / 15.0).abs() < 1e-9);
+    }
+}
+
+#[cfg(test)]
+pub mod early_branch_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str, early_branches: bool, forwarding: &str) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            early_branches,
+            forwarding: Forwarding::from_names(forwarding).unwrap(),
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.write_word(0x100, 3);
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn one_bubble_per_taken_branch() {
+        // nothing the branch reads is written right before it.
+        let source = "        li   t0, 10
+                              li   a0, 0
+                      loop:   addi t0, t0, -1
+                              add  a0, a0, t0
+                              bnez t0, loop";
+        let late = run(source, false, "all");
+        let early = run(source, true, "all");
+        assert_eq!(early.register(10), 45);
+        assert_eq!(late.stats().bubbles, 9 * 2);
+        assert_eq!(early.stats().bubbles, 9);
+        assert_eq!(early.stats().stall_cycles, 0);
+        assert_eq!(early.stats().cycles, late.stats().cycles - 9);
+    }
+
+    #[test]
+    fn stalls_for_values_not_ready_in_id() {
+        let stalls = |source| run(source, true, "all").stats().stall_cycles;
+        // an ALU result right before the branch is still in EX.
+        assert_eq!(stalls("li t0, 1\n bnez t0, end\n li a0, 1\n end:"), 1);
+        // one instruction later it gets forwarded from EX-MEM.
+        assert_eq!(stalls("li t0, 1\n nop\n bnez t0, end\n li a0, 1\n end:"), 0);
+        // a load has to get all the way to MEM-WB.
+        assert_eq!(
+            stalls("lw t0, 0x100(zero)\n bnez t0, end\n li a0, 1\n end:"),
+            2
+        );
+        assert_eq!(stalls("lw t0, 0x100(zero)\n nop\n bnez t0, end\n end:"), 1);
+        // JALR needs its register too. JAL doesn't need any.
+        assert_eq!(stalls("la t0, end\n jr t0\n li a0, 1\n end:"), 1);
+        assert_eq!(stalls("li ra, 5\n jal end\n li a0, 1\n end:"), 0);
+
+        // without EX-EX forwarding, there's no EX-MEM to ID path either. the branch waits for the register file,
+        // just like one resolved in EX would.
+        for early_branches in [false, true] {
+            let stalls = |source| run(source, early_branches, "none").stats().stall_cycles;
+            assert_eq!(stalls("li t0, 1\n bnez t0, end\n li a0, 1\n end:"), 2);
+            assert_eq!(stalls("li t0, 1\n nop\n bnez t0, end\n li a0, 1\n end:"), 1);
+            assert_eq!(stalls("li t0, 1\n nop\n nop\n bnez t0, end\n end:"), 0);
+        }
+    }
+
+    #[test]
+    fn greater_or_equal_with_equal_operands() {
+        // BGE and BGEU are taken when the two are the same, whichever stage resolves them.
+        let source = "        li   t0, -5
+                              li   t1, -5
+                              li   a0, 0
+                              bge  t0, t1, signed
+                              li   a0, 1
+                      signed: bgeu t0, t1, unsigned
+                              li   a0, 2
+                    unsigned: bge  t0, zero, end
+                              li   a1, 3
+                      end:    nop";
+        for early_branches in [false, true] {
+            let sim = run(source, early_branches, "all");
+            assert_eq!(sim.registers()[10..12], [0, 3], "{}", early_branches);
+        }
+        for (funct3, taken) in [(0b101, true), (0b111, true), (0b100, false), (0b110, false)] {
+            assert_eq!(branch_comparator(funct3, 7, 7), taken, "{:03b}", funct3);
+        }
+    }
+
+    #[test]
+    fn same_results_either_way() {
+        let source = "        lw   t0, 0x100(zero)
+                              li   a0, 0
+                      loop:   jal  ra, add_it
+                              addi t0, t0, -1
+                              bnez t0, loop
+                              j    end
+                      add_it: lw   t1, 0x100(zero)
+                              beq  t1, zero, skip
+                              add  a0, a0, t1
+                      skip:   ret
+                      end:    sw   a0, 0x104(zero)";
+        for forwarding in ["all", "none", "ex-ex", "mem-ex,mem-mem"] {
+            let late = run(source, false, forwarding);
+            let early = run(source, true, forwarding);
+            for sim in [&late, &early] {
+                assert_eq!(sim.register(10), 9, "{}", forwarding);
+                assert_eq!(sim.read_word(0x104), 9, "{}", forwarding);
+                assert_eq!(sim.stats().retired, late.stats().retired, "{}", forwarding);
+            }
+            assert!(
+                early.stats().bubbles < late.stats().bubbles,
+                "{}",
+                forwarding
+            );
+        }
+        // and with a predictor. a misprediction only has the one wrong-path instruction to throw away.
+        let mut sim = Simulator::new(Config {
+            early_branches: true,
+            predictor: Predictor::TwoBit,
+            btb_entries: 16,
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.write_word(0x100, 3);
+        sim.run();
+        assert_eq!(sim.register(10), 9);
+        assert_eq!(
+            sim.stats().bubbles,
+            sim.stats().mispredictions + sim.stats().stall_cycles
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod cache_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn cache(spec: &str) -> Cache {
+        Cache::new(CacheConfig::from_spec(spec).unwrap())
+    }
+
+    //the latency of each access, through the given levels, with memory taking 10 cycles.
+    fn accesses(levels: &mut [Cache], addresses: &[(u32, bool)]) -> Vec<u64> {
+        addresses
+            .iter()
+            .map(|&(address, write)| cache::access(levels, 10, address, write))
+            .collect()
+    }
+
+    #[test]
+    fn associativity() {
+        // 0 and 64 go in the same set of a 64-byte direct-mapped cache, and keep throwing each other out.
+        let mut levels = [cache("size=64,ways=1,line=16")];
+        let reads = [(0, false), (64, false), (0, false), (4, false)];
+        assert_eq!(accesses(&mut levels, &reads), vec![11, 11, 11, 1]);
+        assert_eq!(levels[0].stats.misses, 3);
+        // with two ways they both fit.
+        let mut levels = [cache("size=64,ways=2,line=16")];
+        assert_eq!(accesses(&mut levels, &reads), vec![11, 11, 1, 1]);
+        assert_eq!(levels[0].stats.hits, 2);
+    }
+
+    #[test]
+    fn replacement_policies() {
+        // one set of two lines. A, B, A, C, A: LRU throws out B for C, FIFO throws out A.
+        let reads = [(0, false), (16, false), (0, false), (32, false), (0, false)];
+        let mut lru = [cache("size=32,ways=2,line=16,policy=lru")];
+        accesses(&mut lru, &reads);
+        assert_eq!(lru[0].stats.hits, 2);
+        let mut fifo = [cache("size=32,ways=2,line=16,policy=fifo")];
+        accesses(&mut fifo, &reads);
+        assert_eq!(fifo[0].stats.hits, 1);
+        let mut random = [cache("size=32,ways=2,line=16,policy=random")];
+        accesses(&mut random, &reads);
+        assert_eq!(random[0].stats.hits + random[0].stats.misses, 5);
+    }
+
+    #[test]
+    fn write_policies() {
+        // write-back: the store only dirties the line, which gets written back when it's thrown out.
+        let mut levels = [cache("size=64,line=16,write=back")];
+        let ops = [(0, true), (0, true), (64, false)];
+        assert_eq!(accesses(&mut levels, &ops), vec![11, 1, 1 + 10 + 10]);
+        assert_eq!(levels[0].stats.writebacks, 1);
+        // write-through: every store goes to memory, so nothing is ever dirty.
+        let mut levels = [cache("size=64,line=16,write=through")];
+        assert_eq!(accesses(&mut levels, &ops), vec![21, 11, 11]);
+        assert_eq!(levels[0].stats.writebacks, 0);
+        // no write-allocate: a store that misses doesn't bring the line in.
+        let mut levels = [cache("size=64,line=16,write=through,allocate=no")];
+        assert_eq!(
+            accesses(&mut levels, &[(0, true), (0, false)]),
+            vec![11, 11]
+        );
+        assert_eq!(levels[0].stats.misses, 2);
+    }
+
+    #[test]
+    fn levels() {
+        // L1 conflict misses get caught by L2.
+        let mut levels = [
+            cache("size=64,line=16"),
+            cache("size=1k,ways=4,line=16,latency=4"),
+        ];
+        let reads = [(0, false), (64, false), (0, false), (64, false)];
+        assert_eq!(accesses(&mut levels, &reads), vec![15, 15, 5, 5]);
+        assert_eq!(levels[0].stats.misses, 4);
+        assert_eq!((levels[1].stats.hits, levels[1].stats.misses), (2, 2));
+    }
+
+    #[test]
+    fn bad_settings() {
+        assert!(CacheConfig::from_spec("size=100").is_err());
+        assert!(CacheConfig::from_spec("size=64,ways=8,line=16").is_err());
+        assert!(CacheConfig::from_spec("line=3").is_err());
+        assert!(CacheConfig::from_spec("policy=lfu").is_err());
+        assert!(CacheConfig::from_spec("colour=blue").is_err());
+        let config = CacheConfig::from_spec("size=32k,ways=8,line=64,write=through").unwrap();
+        assert_eq!((config.size, config.sets()), (32768, 64));
+        assert!(!config.write_back);
+    }
+
+    fn run(caches: &[&str]) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            memory: MemoryConfig {
+                caches: caches
+                    .iter()
+                    .map(|spec| CacheConfig::from_spec(spec).unwrap())
+                    .collect(),
+                memory_latency: 10,
+                ..MemoryConfig::default()
+            },
+            ..Config::default()
+        });
+        // sums 8 words, copying each one as it goes.
+        let source = "        li   t0, 0x100
+                              li   t1, 8
+                      loop:   lw   t2, 0(t0)
+                              sw   t2, 0x40(t0)
+                              add  a0, a0, t2
+                              addi t0, t0, 4
+                              addi t1, t1, -1
+                              bnez t1, loop
+                              sw   a0, 0x80(zero)";
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        for i in 0..8 {
+            sim.write_word(0x100 + 4 * i, i + 1);
+        }
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn misses_stall_mem() {
+        let uncached = run(&[]);
+        let cached = run(&["size=256,ways=2,line=16,latency=1"]);
+        for sim in [&uncached, &cached] {
+            assert_eq!(sim.register(10), 36);
+            assert_eq!(sim.read_word(0x80), 36);
+            assert_eq!(sim.read_word(0x15c), 8);
+        }
+        // 2 lines loaded from, 2 stored to, and the final store: 5 misses at 10 extra cycles each.
+        let l1 = cached.caches()[0].stats;
+        assert_eq!((l1.hits, l1.misses), (17 - 5, 5));
+        assert_eq!(
+            cached.stats().stall_cycles,
+            uncached.stats().stall_cycles + 5 * 10
+        );
+        assert_eq!(cached.stats().cycles, uncached.stats().cycles + 5 * 10);
+
+        let text = cached.report(report::ReportFormat::Text);
+        assert!(text.contains("L1 cache:             12 hits, 5 misses"));
+        let json = cached.report(report::ReportFormat::Json);
+        assert!(json.contains("\"caches\": [{\"hits\": 12, \"misses\": 5, \"writebacks\": 0}]"));
+    }
+
+    #[test]
+    fn same_results_with_every_setup() {
+        for caches in [
+            vec!["size=16,line=16,write=through,allocate=no"],
+            vec!["size=32,ways=2,line=4,policy=fifo", "size=128,latency=3"],
+            vec![
+                "size=16,line=4",
+                "size=32,line=8",
+                "size=64,line=16,policy=random",
+            ],
+        ] {
+            let sim = run(&caches);
+            assert_eq!(sim.register(10), 36, "{:?}", caches);
+            assert_eq!(sim.read_word(0x15c), 8, "{:?}", caches);
+            assert_eq!(sim.stats().retired, 2 + 8 * 6 + 1, "{:?}", caches);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod icache_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str, model: Model, icache: Option<&str>) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            model,
+            memory: MemoryConfig {
+                icache: icache.map(|spec| CacheConfig::from_spec(spec).unwrap()),
+                icache_miss_penalty: 10,
+                ..MemoryConfig::default()
+            },
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.run();
+        sim
+    }
+
+    // 8 instructions that don't depend on each other, so nothing else stalls.
+    const STRAIGHT: &str = "addi a0, zero, 1
+                            addi a1, zero, 2
+                            addi a2, zero, 3
+                            addi a3, zero, 4
+                            addi a4, zero, 5
+                            addi a5, zero, 6
+                            addi a6, zero, 7
+                            addi a7, zero, 8";
+
+    #[test]
+    fn misses_stall_fetch() {
+        let uncached = run(STRAIGHT, Model::Pipelined, None);
+        let cached = run(STRAIGHT, Model::Pipelined, Some("size=256,line=16"));
+        assert_eq!(cached.registers(), uncached.registers());
+        // one miss per 16-byte line, and each one holds the PC for the miss penalty.
+        let icache = cached.icache().unwrap().stats;
+        assert_eq!((icache.hits, icache.misses), (6, 2));
+        assert_eq!(cached.stats().cycles, uncached.stats().cycles + 2 * 10);
+        assert_eq!(
+            cached.stats().stall_cycles,
+            uncached.stats().stall_cycles + 2 * 10
+        );
+        assert_eq!(cached.stats().bubbles, uncached.stats().bubbles + 2 * 10);
+        assert!(uncached.icache().is_none());
+
+        let text = cached.report(report::ReportFormat::Text);
+        assert!(text.contains("I-cache:              6 hits, 2 misses (75.0% hit rate)"));
+        let json = cached.report(report::ReportFormat::Json);
+        assert!(json.contains("\"icache\": {\"hits\": 6, \"misses\": 2}"));
+        let json = uncached.report(report::ReportFormat::Json);
+        assert!(json.contains("\"icache\": null"));
+    }
+
+    #[test]
+    fn loop_size() {
+        // a 4-instruction loop body fits in one 16-byte line. a 12-instruction one needs 3, and keeps
+        // throwing its own lines out of a cache that only holds 2.
+        let small = "        li   t0, 20
+                      loop: addi a0, a0, 1
+                            addi t0, t0, -1
+                            bnez t0, loop
+                            nop";
+        let big = "        li   t0, 20
+                      loop: addi a0, a0, 1
+                            addi a1, a1, 1
+                            addi a2, a2, 1
+                            addi a3, a3, 1
+                            addi a4, a4, 1
+                            addi a5, a5, 1
+                            addi a6, a6, 1
+                            addi a7, a7, 1
+                            addi s2, s2, 1
+                            addi t0, t0, -1
+                            bnez t0, loop
+                            nop";
+        let tiny = Some("size=32,line=16");
+        let roomy = Some("size=256,line=16");
+
+        let fits = run(small, Model::Pipelined, tiny);
+        assert_eq!(fits.register(10), 20);
+        assert!(fits.icache().unwrap().stats.misses <= 3);
+
+        let thrashes = run(big, Model::Pipelined, tiny);
+        let doesnt = run(big, Model::Pipelined, roomy);
+        assert_eq!(thrashes.registers(), doesnt.registers());
+        assert_eq!(thrashes.register(18), 20);
+        assert!(doesnt.icache().unwrap().stats.misses <= 4);
+        assert!(thrashes.icache().unwrap().stats.misses >= 20);
+        assert!(thrashes.stats().cpi() > doesnt.stats().cpi() + 1.0);
+    }
+
+    #[test]
+    fn other_models() {
+        let cache = Some("size=256,line=16");
+        // the multi-cycle model's IF takes as long as the miss does.
+        let uncached = run(STRAIGHT, Model::MultiCycle, None);
+        let cached = run(STRAIGHT, Model::MultiCycle, cache);
+        assert_eq!(cached.registers(), uncached.registers());
+        assert_eq!(cached.stats().cycles, uncached.stats().cycles + 2 * 10);
+        assert_eq!(cached.stats().bubbles, 0);
+        // the single-cycle model's one cycle is as long as it needs to be.
+        let uncached = run(STRAIGHT, Model::SingleCycle, None);
+        let cached = run(STRAIGHT, Model::SingleCycle, cache);
+        assert_eq!(cached.registers(), uncached.registers());
+        assert_eq!(cached.stats().cycles, uncached.stats().cycles);
+        assert_eq!(cached.icache().unwrap().stats.misses, 2);
+    }
+}
+
+#[cfg(test)]
+pub mod memory_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str, map: &str) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            memory: MemoryConfig {
+                map: MemoryMap::from_spec(map).unwrap(),
+                ..MemoryConfig::default()
+            },
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.run();
+        sim
+    }
+
+    fn trap_cause(sim: &Simulator) -> Option<u32> {
+        match sim.stop_reason() {
+            Some(StopReason::UnhandledTrap(trap)) => Some(trap.cause),
+            _ => None,
+        }
+    }
+
+    const MAP: &str = "text=0:4k,data=0x10000:4k,stack=0x20000:4k,mmio=0x30000:4k";
+
+    #[test]
+    fn paged_memory() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.read_word(0x1234), 0);
+        assert_eq!(memory.pages(), 0);
+
+        memory.write_word(0x100, 0x12345678);
+        assert_eq!(memory.read_byte(0x100), 0x78); //little-endian
+        assert_eq!(memory.read(0x102, 2), 0x1234);
+        memory.write(0x101, 1, 0xab);
+        assert_eq!(memory.read_word(0x100), 0x1234ab78);
+
+        // a word that straddles two pages, and one at the very top of memory.
+        memory.write_word(PAGE_SIZE - 2, 0xaabbccdd);
+        assert_eq!(memory.read(PAGE_SIZE, 2), 0xaabb);
+        memory.write_word(0xfffffffc, 7);
+        assert_eq!(memory.read_word(0xfffffffc), 7);
+        assert_eq!(memory.pages(), 3);
+
+        // copies share pages until one of them writes.
+        let copy = memory.clone();
+        memory.write_word(0x100, 0);
+        assert_eq!(copy.read_word(0x100), 0x1234ab78);
+        assert_eq!(memory.read_word(0x100), 0);
+    }
+
+    #[test]
+    fn code_and_data_share_memory() {
+        // the constant after the code gets loaded straight out of .text.
+        let sim = run(
+            "lw   a0, 16(zero)
+             lb   a1, 19(zero)
+             lw   a2, 0(zero)
+             j    end
+             .word 0x87654321
+        end: nop",
+            MAP,
+        );
+        assert_eq!(sim.register(10), 0x87654321);
+        assert_eq!(sim.register(11), 0xffffff87);
+        assert_eq!(sim.register(12), assemble("lw a0, 16(zero)").unwrap()[0]);
+
+        // and code can be written like data. the addi at 28 gets replaced before it's fetched.
+        let addi_7 = assemble("addi a0, zero, 7").unwrap()[0];
+        let sim = run(
+            &format!(
+                "li   t0, {}
+                 sw   t0, 28(zero)
+                 nop
+                 nop
+                 nop
+                 nop
+                 addi a0, zero, 1",
+                addi_7
+            ),
+            MAP,
+        );
+        assert_eq!(sim.register(10), 7);
+    }
+
+    #[test]
+    fn memory_map() {
+        let map = MemoryMap::from_spec(MAP).unwrap();
+        assert_eq!(map.regions.len(), 4);
+        assert_eq!(map.kind(0x10ffc), Some(RegionKind::Data));
+        assert_eq!(map.kind(0x11000), None);
+        assert!(map.is_executable(0x40));
+        assert!(map.is_mmio(0x30010));
+        assert_eq!(map.stack_top(), Some(0x20ff0));
+        assert_eq!(
+            MemoryMap::default().stack_top(),
+            Some(loader::DEFAULT_STACK_TOP)
+        );
+
+        assert!(MemoryMap::from_spec("text=0:4k,data=0x800:4k").is_err()); //overlapping
+        assert!(MemoryMap::from_spec("rom=0:4k").is_err());
+        assert!(MemoryMap::from_spec("text=0:0").is_err());
+        assert!(MemoryMap::from_spec("text=2:4k").is_err());
+        assert!(MemoryMap::from_spec("data=0xfffff000:8k").is_err());
+        assert!(MemoryMap::from_spec("text=0").is_err());
+    }
+
+    #[test]
+    fn unmapped_accesses_fault() {
+        let sim = run("li t0, 0x11000\n lw a0, 0(t0)", MAP);
+        assert_eq!(trap_cause(&sim), Some(CAUSE_LOAD_ACCESS_FAULT));
+        let sim = run("li t0, 0x40000\n sw a0, 0(t0)", MAP);
+        assert_eq!(trap_cause(&sim), Some(CAUSE_STORE_ACCESS_FAULT));
+        // the stack is mapped, but isn't somewhere code can run from.
+        let sim = run("li t0, 0x20000\n jalr ra, 0(t0)\n li a0, 1", MAP);
+        assert_eq!(trap_cause(&sim), Some(CAUSE_FETCH_ACCESS_FAULT));
+        assert_eq!(sim.register(10), 0);
+
+        let sim = run(
+            "li t0, 0x20000
+             li t1, 5
+             sw t1, -4(t0)",
+            MAP,
+        );
+        assert_eq!(trap_cause(&sim), Some(CAUSE_STORE_ACCESS_FAULT));
+        let sim = run(
+            "li t0, 0x20000
+             li t1, 5
+             sw t1, 4(t0)
+             lw a0, 4(t0)",
+            MAP,
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.register(10), 5);
+
+        // running straight on past the end of the text region faults too, on the first instruction outside it.
+        let sim = run(
+            "li a0, 1
+             li a1, 2
+             li a2, 3",
+            "text=0:8,data=8:4k",
+        );
+        match sim.stop_reason() {
+            Some(StopReason::UnhandledTrap(trap)) => {
+                assert_eq!(
+                    (trap.cause, trap.epc, trap.tval),
+                    (CAUSE_FETCH_ACCESS_FAULT, 8, 8)
+                )
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(sim.registers()[10..13], [1, 2, 0]);
+    }
+
+    #[test]
+    fn mmio_isnt_memory() {
+        // with nothing attached, stores to MMIO go nowhere and loads get 0.
+        let sim = run(
+            "li t0, 0x30000
+             li t1, 5
+             sw t1, 0(t0)
+             lw a0, 0(t0)",
+            MAP,
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.register(10), 0);
+        assert_eq!(sim.memory().pages(), 1); //just the code
+    }
+
+    #[test]
+    fn reset_vector() {
+        let mut sim = Simulator::new(Config {
+            reset_vector: Some(8),
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(
+            assemble("li a0, 1\n li a1, 2\n li a2, 3").unwrap(),
+        ));
+        assert_eq!(sim.pc(), 8);
+        sim.run();
+        assert_eq!(sim.registers()[10..13], [0, 0, 3]);
+    }
+}
+
+#[cfg(test)]
+pub mod device_tests {
+    use crate::assembler::*;
+    use crate::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    //a program that sends every byte it receives back, until it gets a 0.
+    const ECHO: &str = "
+            li   t0, 0xf0000000
+            li   t1, 7
+            sb   t1, 2(t0)      # FCR: FIFOs on
+    wait:   lbu  t1, 5(t0)      # LSR
+            andi t1, t1, 1      # data ready?
+            beqz t1, wait
+            lbu  t1, 0(t0)      # RBR
+            sb   t1, 0(t0)      # THR, with the loaded byte MEM-MEM forwarded
+            bnez t1, wait";
+
+    fn run_echo(config: Config, input: &[u8]) -> (Simulator, Rc<RefCell<Vec<u8>>>) {
+        let mut sim = Simulator::new(config);
+        sim.load_program(Program::from_instructions(assemble(ECHO).unwrap()));
+        let (uart, output) = Uart::captured(input);
+        sim.attach_device(UART_BASE, Box::new(uart)).unwrap();
+        sim.run();
+        (sim, output)
+    }
+
+    #[test]
+    fn uart_echo() {
+        for model in [Model::Pipelined, Model::SingleCycle, Model::MultiCycle] {
+            let config = Config {
+                model,
+                ..Config::default()
+            };
+            let (sim, output) = run_echo(config, b"hi!\0");
+            assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+            assert_eq!(output.borrow().as_slice(), b"hi!\0");
+        }
+        // MMIO goes around the caches, so they don't change what happens.
+        let config = Config {
+            memory: MemoryConfig {
+                caches: vec![CacheConfig::default()],
+                ..MemoryConfig::default()
+            },
+            ..Config::default()
+        };
+        let (sim, output) = run_echo(config, b"ok\0");
+        assert_eq!(output.borrow().as_slice(), b"ok\0");
+        assert_eq!(sim.caches()[0].stats, CacheStats::default());
+    }
+
+    #[test]
+    fn uart_registers() {
+        let (mut uart, output) = Uart::captured(b"x");
+        assert_eq!(
+            uart.read(UART_LSR, 1) as u8,
+            LSR_DATA_READY | LSR_THR_EMPTY | LSR_TX_EMPTY
+        );
+        // reading RBR doesn't take the byte until the load actually happens.
+        assert_eq!(uart.read(UART_RBR, 1), 'x' as u32);
+        assert_eq!(uart.read(UART_RBR, 1), 'x' as u32);
+        uart.read_done(UART_RBR, 1);
+        assert_eq!(uart.read(UART_LSR, 1) as u8 & LSR_DATA_READY, 0);
+
+        // with DLAB set, offsets 0 and 1 are the divisor, and nothing gets sent.
+        uart.write(UART_LCR, 1, LCR_DLAB as u32 | 3);
+        uart.write(UART_THR, 1, 0x01);
+        uart.write(UART_IER, 1, 0x02);
+        assert_eq!(uart.read(UART_RBR, 1), 0x01);
+        assert_eq!(uart.read(UART_IER, 1), 0x02);
+        uart.write(UART_LCR, 1, 3);
+        assert_eq!(uart.read(UART_IER, 1), 0);
+        uart.write(UART_SCR, 1, 0x5a);
+        assert_eq!(uart.read(UART_SCR, 1), 0x5a);
+        assert!(output.borrow().is_empty());
+        uart.write(UART_THR, 4, 0x1234_5641); //only the low byte
+        assert_eq!(output.borrow().as_slice(), b"A");
+    }
+
+    //counts how many loads and stores it gets.
+    #[derive(Clone, Default)]
+    struct Counter {
+        loads: u32,
+        stores: u32,
+    }
+
+    impl Device for Counter {
+        fn name(&self) -> String {
+            "counter".to_owned()
+        }
+
+        fn size(&self) -> u32 {
+            8
+        }
+
+        fn read(&self, offset: u32, _size: u32) -> u32 {
+            if offset == 0 {
+                self.loads
+            } else {
+                self.stores
+            }
+        }
+
+        fn read_done(&mut self, _offset: u32, _size: u32) {
+            self.loads += 1;
+        }
+
+        fn write(&mut self, _offset: u32, _size: u32, _value: u32) {
+            self.stores += 1;
+        }
+
+        fn clone_box(&self) -> Box<dyn Device> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    fn devices_on_the_bus() {
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(Program::from_instructions(
+            assemble(
+                "li   t0, 0xf0001000
+                 lw   a0, 0(t0)
+                 lw   a1, 0(t0)
+                 add  a1, a1, a1  # load-use stall. the load still only happens once
+                 sw   a1, 4(t0)
+                 sw   a1, 4(t0)
+                 lw   a2, 4(t0)
+                 lw   a3, 0(t0)",
+            )
+            .unwrap(),
+        ));
+        sim.attach_device(0xf000_1000, Box::new(Counter::default()))
+            .unwrap();
+        sim.run();
+        assert_eq!(sim.registers()[10..14], [0, 2, 2, 3]);
+
+        // every reset starts over with the device as it was attached.
+        sim.reset();
+        sim.run();
+        assert_eq!(sim.registers()[10..14], [0, 2, 2, 3]);
+        assert_eq!(sim.bus().devices().count(), 1);
+
+        // devices have to be somewhere in an MMIO region, and not on top of each other.
+        assert!(sim
+            .attach_device(0x1000, Box::new(Counter::default()))
+            .is_err());
+        assert!(sim
+            .attach_device(0xf000_1004, Box::new(Counter::default()))
+            .is_err());
+        assert!(sim
+            .attach_device(0xfffffffc, Box::new(Counter::default()))
+            .is_err());
+        assert!(sim
+            .attach_device(0xf000_1008, Box::new(Counter::default()))
+            .is_ok());
+    }
+}
+
+#[cfg(test)]
+pub mod interrupt_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn run(source: &str, model: Model) -> Simulator {
+        let mut sim = Simulator::new(Config {
+            model,
+            max_cycles: Some(10_000),
+            ..Config::default()
+        });
+        sim.load_program(Program::from_instructions(assemble(source).unwrap()));
+        sim.attach_device(CLINT_BASE, Box::new(Clint::new()))
+            .unwrap();
+        sim.run();
+        sim
+    }
+
+    const MODELS: [Model; 3] = [Model::Pipelined, Model::SingleCycle, Model::MultiCycle];
+
+    // raises the software interrupt in front of three adds. the handler saves mcause and mepc in s0 and s1,
+    // counts interrupts in s3, and clears msip again.
+    const SOFTWARE: &str = "
+            la    t0, handler
+            csrw  mtvec, t0
+            li    s4, 0xf2000000
+            la    s5, after
+            li    t1, 8
+            csrw  mie, t1
+            csrsi mstatus, 8
+            li    t1, 1
+            sw    t1, 0(s4)
+    after:  addi  a0, a0, 1
+            addi  a0, a0, 1
+            addi  a0, a0, 1
+            j     end
+    handler:
+            csrr  s0, mcause
+            csrr  s1, mepc
+            sw    zero, 0(s4)
+            addi  s3, s3, 1
+            mret
+    end:";
+
+    #[test]
+    fn software_interrupt_is_precise() {
+        for model in MODELS {
+            let sim = run(SOFTWARE, model);
+            assert_eq!(
+                sim.stop_reason(),
+                Some(StopReason::EndOfProgram),
+                "{:?}",
+                model
+            );
+            assert_eq!(sim.register(8), CAUSE_SOFTWARE_INTERRUPT, "{:?}", model);
+            // taken right behind the store that raised it, and nothing got done twice or skipped.
+            assert_eq!(sim.register(9), sim.register(21), "{:?}", model);
+            assert_eq!(sim.register(19), 1, "{:?}", model);
+            assert_eq!(sim.register(10), 3, "{:?}", model);
+            assert_eq!(sim.stats().interrupts, 1, "{:?}", model);
+            assert_eq!(sim.csr(MSTATUS).unwrap() & MSTATUS_MIE, MSTATUS_MIE);
+        }
+    }
+
+    #[test]
+    fn timer_interrupt() {
+        let source = "
+                la    t0, handler
+                csrw  mtvec, t0
+                li    s4, 0xf2000000
+                li    t1, 0x4000
+                add   s5, s4, t1
+                li    t1, 300
+                sw    t1, 0(s5)       # mtimecmp, low word first
+                sw    zero, 4(s5)
+                li    t1, 128
+                csrw  mie, t1
+                csrsi mstatus, 8
+        spin:   addi  a0, a0, 1
+                beqz  s3, spin
+                j     end
+        handler:
+                csrr  s0, mcause
+                csrr  s1, mepc
+                li    t1, -1
+                sw    t1, 4(s5)       # pushes mtimecmp way off into the future
+                lw    s2, 0(s4)       # mtime isn't
+                addi  s3, s3, 1
+                mret
+        end:    lw    s6, 0(s5)";
+        for model in MODELS {
+            let sim = run(source, model);
+            assert_eq!(
+                sim.stop_reason(),
+                Some(StopReason::EndOfProgram),
+                "{:?}",
+                model
+            );
+            assert_eq!(sim.register(8), CAUSE_TIMER_INTERRUPT, "{:?}", model);
+            assert_eq!(sim.register(19), 1, "{:?}", model);
+            assert!(sim.register(10) > 0, "{:?}", model);
+            assert_eq!(sim.register(22), 300, "{:?}", model);
+            assert_eq!(sim.register(18), 0, "{:?}", model); //msip reads as 0
+            assert!(sim.stats().cycles >= 300, "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn masked_interrupts_wait() {
+        // pending, and enabled in mie, but mstatus.MIE is off until the nops are done.
+        let source = "
+                la    t0, handler
+                csrw  mtvec, t0
+                li    s4, 0xf2000000
+                li    t1, 8
+                csrw  mie, t1
+                li    t1, 1
+                sw    t1, 0(s4)
+                csrr  a1, mip
+                csrw  mip, zero       # can't be cleared from here
+                nop
+                nop
+                nop
+                nop
+                csrr  a2, mip
+                csrsi mstatus, 8
+                addi  a0, a0, 1
+                j     end
+        handler:
+                sw    zero, 0(s4)
+                addi  s3, s3, 1
+                mret
+        end:    csrr  a3, mip";
+        let sim = run(source, Model::Pipelined);
+        assert_eq!(sim.registers()[11..14], [MIP_MSIP, MIP_MSIP, 0]);
+        assert_eq!(sim.register(19), 1);
+        assert_eq!(sim.register(10), 1);
+        assert_eq!(sim.stats().interrupts, 1);
+        assert!(sim.stats().interrupt_latency >= 6);
+
+        // with nothing enabled in mie, it never gets taken at all.
+        let sim = run(
+            &source.replace("li    t1, 8", "li    t1, 0"),
+            Model::Pipelined,
+        );
+        assert_eq!(sim.register(19), 0);
+        assert_eq!(sim.stats().interrupts, 0);
+        assert_eq!(sim.register(13), MIP_MSIP);
+    }
+
+    #[test]
+    fn vectored_interrupts() {
+        // interrupts go to mtvec's base + 4 * cause, and exceptions still go to the base.
+        let source = "
+                la    t0, table
+                ori   t0, t0, 1
+                csrw  mtvec, t0
+                li    s4, 0xf2000000
+                li    t1, 8
+                csrw  mie, t1
+                csrsi mstatus, 8
+                li    t1, 1
+                sw    t1, 0(s4)
+                addi  a0, a0, 1
+                ecall
+                j     end
+        table:  j     exception
+                j     wrong
+                j     wrong
+                j     software
+        wrong:  li    s6, 1
+                mret
+        exception:
+                addi  s2, s2, 1
+                csrr  t1, mepc
+                addi  t1, t1, 4
+                csrw  mepc, t1
+                mret
+        software:
+                sw    zero, 0(s4)
+                addi  s3, s3, 1
+                mret
+        end:";
+        let sim = run(source, Model::Pipelined);
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.register(22), 0);
+        assert_eq!(sim.register(19), 1);
+        assert_eq!(sim.register(18), 1);
+        assert_eq!(sim.register(10), 1);
+    }
+
+    #[test]
+    fn clint_registers() {
+        let mut clint = Clint::new();
+        assert_eq!(clint.interrupts(), 0);
+        clint.write(CLINT_MSIP, 4, 3);
+        assert_eq!(clint.read(CLINT_MSIP, 4), 1);
+        assert_eq!(clint.interrupts(), MIP_MSIP);
+        clint.write(CLINT_MSIP, 4, 0);
+
+        clint.write(CLINT_MTIMECMP, 4, 2);
+        clint.write(CLINT_MTIMECMP + 4, 4, 0);
+        assert_eq!(clint.mtimecmp, 2);
+        clint.tick();
+        assert_eq!(clint.interrupts(), 0);
+        clint.tick();
+        assert_eq!(clint.interrupts(), MIP_MTIP);
+        assert_eq!(clint.read(CLINT_MTIME, 4), 2);
+
+        // smaller accesses only touch their own bytes.
+        clint.write(CLINT_MTIME + 5, 1, 0xab);
+        assert_eq!(clint.mtime, 0x0000_ab00_0000_0002);
+        assert_eq!(clint.read(CLINT_MTIME + 4, 2), 0xab00);
+        assert_eq!(clint.read(CLINT_MTIME + 5, 1), 0xab);
+    }
+}
+
+#[cfg(test)]
+pub mod muldiv_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn latencies(mul: u64, div: u64) -> Config {
+        Config {
+            latencies: Latencies {
+                mul,
+                div,
+                ..Latencies::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn results() {
+        let min = i32::MIN as u32;
+        let cases = [
+            // (funct3, op1, op2, result)
+            (0b000, 6, 7, 42),
+            (0b000, -3i32 as u32, 5, -15i32 as u32),
+            (0b000, 0x10000, 0x10000, 0), //only the low 32 bits
+            (0b001, -1i32 as u32, -1i32 as u32, 0),
+            (0b001, min, min, 0x40000000),
+            (0b001, -2i32 as u32, 3, u32::MAX), //-6: the high half is all ones
+            (0b010, -1i32 as u32, u32::MAX, u32::MAX),
+            (0b010, 2, u32::MAX, 1),
+            (0b011, u32::MAX, u32::MAX, 0xfffffffe),
+            (0b100, -7i32 as u32, 2, -3i32 as u32), //rounds toward zero
+            (0b100, 7, 0, u32::MAX),
+            (0b100, min, -1i32 as u32, min), //overflow
+            (0b101, u32::MAX, 2, 0x7fffffff),
+            (0b101, 7, 0, u32::MAX),
+            (0b110, -7i32 as u32, 2, -1i32 as u32), //the sign of what was divided
+            (0b110, 7, -2i32 as u32, 1),
+            (0b110, -7i32 as u32, 0, -7i32 as u32),
+            (0b110, min, -1i32 as u32, 0),
+            (0b111, 7, 3, 1),
+            (0b111, 7, 0, 7),
+        ];
+        for (funct3, op1, op2, result) in cases {
+            assert_eq!(
+                muldiv(funct3, op1, op2),
+                result,
+                "{:03b} {:#x} {:#x}",
+                funct3,
+                op1,
+                op2
+            );
+        }
+    }
+
+    #[test]
+    fn every_instruction_on_every_model() {
+        let source = "li     a0, -20
+                      li     a1, 3
+                      mul    s0, a0, a1
+                      mulh   s1, a0, a1
+                      mulhsu s2, a0, a1
+                      mulhu  s3, a0, a1
+                      div    s4, a0, a1
+                      divu   s5, a0, a1
+                      rem    s6, a0, a1
+                      remu   s7, a0, a1
+                      div    s8, a0, zero
+                      rem    s9, a0, zero";
+        for model in [Model::Pipelined, Model::SingleCycle, Model::MultiCycle] {
+            let sim = run_source(
+                source,
+                Config {
+                    model,
+                    ..Config::default()
+                },
+            );
+            assert_eq!(
+                sim.stop_reason(),
+                Some(StopReason::EndOfProgram),
+                "{:?}",
+                model
+            );
+            let a = -20i32 as u32;
+            let expected: Vec<u32> = vec![
+                muldiv(0b000, a, 3),
+                muldiv(0b001, a, 3),
+                muldiv(0b010, a, 3),
+                muldiv(0b011, a, 3),
+                -6i32 as u32,
+                a / 3,
+                -2i32 as u32,
+                a % 3,
+                u32::MAX,
+                a,
+            ];
+            assert_eq!(sim.registers()[8..10], expected[0..2], "{:?}", model);
+            assert_eq!(sim.registers()[18..26], expected[2..10], "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn latency_holds_ex() {
+        // a multiply takes its latency in EX, and so does a divide. both get forwarded to the add right behind.
+        let source = "li  a0, 6
+                      li  a1, 7
+                      mul a2, a0, a1
+                      add a3, a2, a2
+                      div a4, a3, a0
+                      add a5, a4, a4";
+        let fast = run_source(source, latencies(1, 1));
+        let slow = run_source(source, latencies(4, 10));
+        for sim in [&fast, &slow] {
+            assert_eq!(sim.registers()[12..16], [42, 84, 14, 28]);
+        }
+        assert_eq!(slow.stats().cycles - fast.stats().cycles, 3 + 9);
+        assert_eq!(slow.stats().stall_cycles - fast.stats().stall_cycles, 3 + 9);
+        assert_eq!(slow.stats().retired, fast.stats().retired);
+
+        // the multi-cycle model's EX takes as long too. the single-cycle model's one cycle covers it.
+        for (model, extra) in [(Model::MultiCycle, 12), (Model::SingleCycle, 0)] {
+            let config = |mul, div| Config {
+                model,
+                ..latencies(mul, div)
+            };
+            let fast = run_source(source, config(1, 1));
+            let slow = run_source(source, config(4, 10));
+            assert_eq!(slow.registers()[12..16], [42, 84, 14, 28], "{:?}", model);
+            assert_eq!(
+                slow.stats().cycles - fast.stats().cycles,
+                extra,
+                "{:?}",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn structural_hazard() {
+        // back to back, the second multiply has to wait for the first to be done with EX.
+        let source = "li  a0, 3
+                      mul a1, a0, a0
+                      mul a2, a1, a0
+                      mul a3, a0, a0
+                      mul a4, a3, a2";
+        let fast = run_source(source, latencies(1, 1));
+        let slow = run_source(source, latencies(5, 1));
+        assert_eq!(slow.registers()[11..15], [9, 27, 9, 243]);
+        assert_eq!(slow.stats().cycles - fast.stats().cycles, 4 * 4);
+
+        // with early branches, a branch on the result waits in ID without going anywhere early.
+        let source = "li   a0, 3
+                      mul  a1, a0, a0
+                      li   t0, 9
+                      beq  a1, t0, yes
+                      li   a2, 1
+                      j    end
+                yes:  li   a2, 2
+                end:  nop";
+        for early_branches in [false, true] {
+            let sim = run_source(
+                source,
+                Config {
+                    early_branches,
+                    ..latencies(6, 1)
+                },
+            );
+            assert_eq!(sim.register(12), 2, "{}", early_branches);
+        }
+    }
+
+    #[test]
+    fn behind_a_cache_miss() {
+        // the multiply waits in EX while the load in front of it misses, and its operand comes from the load.
+        let source = "li   t0, 0x10000000
+                      li   t1, 5
+                      sw   t1, 0(t0)
+                      lw   a0, 64(t0)
+                      lw   a1, 0(t0)
+                      mul  a2, a1, t1
+                      mul  a3, a2, a2";
+        let config = Config {
+            memory: MemoryConfig {
+                caches: vec![CacheConfig::default()],
+                ..MemoryConfig::default()
+            },
+            ..latencies(3, 1)
+        };
+        let sim = run_source(source, config);
+        assert_eq!(sim.registers()[11..14], [5, 25, 625]);
+    }
+
+    #[test]
+    fn assembles_and_disassembles() {
+        let instructions = assemble(
+            "mul a0, a1, a2
+             mulh a0, a1, a2
+             mulhsu a0, a1, a2
+             mulhu a0, a1, a2
+             div a0, a1, a2
+             divu a0, a1, a2
+             rem a0, a1, a2
+             remu a0, a1, a2",
+        )
+        .unwrap();
+        assert_eq!(instructions[0], 0x02c58533);
+        assert_eq!(instructions[7], 0x02c5f533);
+        for instr in instructions {
+            assert!(riscv_psim::isa::isa::is_legal(instr));
+            let text = display_instruction(&instr);
+            let text = text.trim_end_matches('-');
+            assert_eq!(assemble(text).unwrap(), vec![instr], "{}", text);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod atomic_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn models() -> Vec<Config> {
+        let mut configs: Vec<Config> = [Model::Pipelined, Model::SingleCycle, Model::MultiCycle]
+            .into_iter()
+            .map(|model| Config {
+                model,
+                ..Config::default()
+            })
+            .collect();
+        configs.push(Config {
+            forwarding: Forwarding::none(),
+            ..Config::default()
+        });
+        configs.push(Config {
+            early_branches: true,
+            ..Config::default()
+        });
+        configs.push(Config {
+            memory: MemoryConfig {
+                caches: vec![CacheConfig::default()],
+                ..MemoryConfig::default()
+            },
+            ..Config::default()
+        });
+        configs
+    }
+
+    #[test]
+    fn read_modify_write() {
+        // each one gets the old word in its rd, and leaves the new one in memory. a1 is -8 all the way through.
+        let amos = [
+            ("amoswap.w", 5, -8i32 as u32),
+            ("amoadd.w", 5, -3i32 as u32),
+            ("amoxor.w", 5, 5 ^ -8i32 as u32),
+            ("amoand.w", 5, 0),
+            ("amoor.w", 5, 5 | -8i32 as u32),
+            ("amomin.w", 5, -8i32 as u32),
+            ("amomax.w", 5, 5),
+            ("amominu.w", 5, 5),
+            ("amomaxu.w", 5, -8i32 as u32),
+        ];
+        for config in models() {
+            for (amo, old, new) in amos {
+                let source = format!(
+                    "li   t0, 0x10000000
+                     li   a0, 5
+                     li   a1, -8
+                     sw   a0, 0(t0)
+                     {} a2, a1, (t0)
+                     lw   a3, 0(t0)",
+                    amo
+                );
+                let sim = run_source(&source, config.clone());
+                assert_eq!(sim.register(12), old, "{} {:?}", amo, config.model);
+                assert_eq!(sim.register(13), new, "{} {:?}", amo, config.model);
+                assert_eq!(sim.read_word(0x10000000), new, "{}", amo);
+            }
+        }
+    }
+
+    #[test]
+    fn load_reserved_store_conditional() {
+        for config in models() {
+            let sim = run_source(
+                "li   t0, 0x10000000
+                 li   t1, 7
+                 sc.w s0, t1, (t0)      # nothing reserved yet: fails
+                 lr.w s1, (t0)
+                 sc.w s2, t1, (t0)      # works
+                 sc.w s3, t1, (t0)      # the last one gave up the reservation
+                 lr.w s4, (t0)
+                 addi t2, t0, 4
+                 sc.w s5, t1, (t2)      # a different word
+                 sc.w s6, t1, (t0)      # and that one still gave it up
+                 lr.w s7, (t0)
+                 amoadd.w s8, t1, (t0)  # AMOs don't touch the reservation
+                 sc.w s9, t1, (t0)",
+                config.clone(),
+            );
+            assert_eq!(sim.registers()[8..10], [1, 0], "{:?}", config.model);
+            assert_eq!(sim.registers()[18..26], [0, 1, 7, 1, 1, 7, 7, 0]);
+            assert_eq!(sim.read_word(0x10000000), 7);
+            assert_eq!(sim.read_word(0x10000004), 0);
+        }
+    }
+
+    #[test]
+    fn loaded_value_is_forwarded() {
+        // everything right behind an atomic needs what it loaded: an ALU op, a store's data, an AMO's rs2, and a branch.
+        for config in models() {
+            let sim = run_source(
+                "li   t0, 0x10000000
+                 addi t3, t0, 4
+                 li   t1, 10
+                 sw   t1, 0(t0)
+                 sw   t1, 8(t0)
+                 li   t2, 1
+                 amoadd.w a0, t2, (t0)
+                 addi a1, a0, 1
+                 amoswap.w a2, t2, (t0)
+                 sw   a2, 4(t0)
+                 lr.w a3, (t0)
+                 amoadd.w a4, a3, (t3)
+                 sc.w a5, a3, (t0)
+                 bnez a5, fail
+                 amoadd.w zero, t2, (t0)
+                 sw   zero, 8(t0)
+                 li   s0, 1
+                 j    end
+           fail: li   s0, 2
+            end: nop",
+                config.clone(),
+            );
+            assert_eq!(
+                sim.registers()[10..16],
+                [10, 11, 11, 1, 11, 0],
+                "{:?}",
+                config.model
+            );
+            assert_eq!(sim.register(8), 1);
+            assert_eq!(sim.read_word(0x10000000), 2);
+            assert_eq!(sim.read_word(0x10000004), 12);
+            assert_eq!(sim.read_word(0x10000008), 0);
+        }
+    }
+
+    #[test]
+    fn spinlock_and_counter() {
+        // takes a lock with LR/SC, bumps a counter, and lets the lock go. ten times over.
+        let source = "li   t0, 0x10000000     # the lock
+                      li   t1, 0x10000004     # the counter
+                      li   s1, 0x1000000c     # a counter that doesn't need the lock
+                      li   s0, 10
+                loop: li   t2, 1
+                lock: lr.w t3, (t0)
+                      bnez t3, lock
+                      sc.w t4, t2, (t0)
+                      bnez t4, lock
+                      lw   t5, 0(t1)
+                      addi t5, t5, 1
+                      sw   t5, 0(t1)
+                      amoswap.w.rl zero, zero, (t0)
+                      li   t6, 1
+                      amoadd.w.aqrl a0, t6, (s1)
+                      addi s0, s0, -1
+                      bnez s0, loop";
+        for config in models() {
+            let sim = run_source(source, config);
+            assert_eq!(sim.read_word(0x10000000), 0);
+            assert_eq!(sim.read_word(0x10000004), 10);
+            assert_eq!(sim.read_word(0x1000000c), 10);
+            assert_eq!(sim.register(10), 9);
+        }
+    }
+
+    #[test]
+    fn a_trap_breaks_the_reservation() {
+        let sim = run_source(
+            "la   t0, handler
+             csrw mtvec, t0
+             li   t0, 0x10000000
+             lr.w a0, (t0)
+             ecall
+             sc.w a1, t0, (t0)
+             j    end
+    handler: csrr t1, mepc
+             addi t1, t1, 4
+             csrw mepc, t1
+             mret
+        end: nop",
+            Config::default(),
+        );
+        assert_eq!(sim.register(11), 1);
+        assert_eq!(sim.read_word(0x10000000), 0);
+    }
+
+    #[test]
+    fn bad_addresses_trap() {
+        for (source, cause, address) in [
+            (
+                "li t0, 0x10000002\n lr.w a0, (t0)",
+                CAUSE_MISALIGNED_LOAD,
+                0x10000002,
+            ),
+            (
+                "li t0, 0x10000001\n amoadd.w a0, a0, (t0)",
+                CAUSE_MISALIGNED_STORE,
+                0x10000001,
+            ),
+            (
+                "li t0, 0x10000002\n sc.w a0, a0, (t0)",
+                CAUSE_MISALIGNED_STORE,
+                0x10000002,
+            ),
+            (
+                "li t0, 0xf0000000\n lr.w a0, (t0)",
+                CAUSE_LOAD_ACCESS_FAULT,
+                0xf0000000,
+            ),
+            (
+                "li t0, 0xf0000000\n amoor.w a0, a0, (t0)",
+                CAUSE_STORE_ACCESS_FAULT,
+                0xf0000000,
+            ),
+        ] {
+            let sim = run_source(source, Config::default());
+            match sim.stop_reason() {
+                Some(StopReason::UnhandledTrap(trap)) => {
+                    assert_eq!((trap.cause, trap.tval), (cause, address), "{}", source)
+                }
+                other => panic!("{}: {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn assembles_and_disassembles() {
+        let instructions = assemble(
+            "lr.w a0, (a1)
+             sc.w a0, a2, (a1)
+             amoswap.w.aq a0, a2, (a1)
+             amoadd.w.rl a0, a2, 0(a1)
+             amoxor.w.aqrl a0, a2, (a1)
+             amoand.w a0, a2, (a1)
+             amoor.w a0, a2, (a1)
+             amomin.w a0, a2, (a1)
+             amomax.w a0, a2, (a1)
+             amominu.w a0, a2, (a1)
+             amomaxu.w a0, a2, (a1)",
+        )
+        .unwrap();
+        assert_eq!(instructions[0], 0x1005a52f);
+        assert_eq!(instructions[1], 0x18c5a52f);
+        assert_eq!(instructions[4], 0x26c5a52f);
+        for instr in instructions {
+            assert!(riscv_psim::isa::isa::is_legal(instr));
+            let text = display_instruction(&instr);
+            let text = text.trim_end_matches('-');
+            assert_eq!(assemble(text).unwrap(), vec![instr], "{}", text);
+        }
+        assert!(assemble("amoadd.w a0, a2, 4(a1)").is_err());
+        assert!(assemble("lr.w a0, a2, (a1)").is_err());
+        // LR with an rs2, and a doubleword AMO, aren't anything here.
+        assert!(!riscv_psim::isa::isa::is_legal(0x1005a52f | 1 << 20));
+        assert!(!riscv_psim::isa::isa::is_legal(0x0005b52f));
+    }
+}
+
+#[cfg(test)]
+pub mod compressed_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    // packs a mix of 16-bit parcels and 32-bit instructions into words, the way they'd be laid out in memory.
+    fn pack(instructions: &[u32]) -> Vec<u32> {
+        let mut bytes = Vec::new();
+        for instr in instructions {
+            let size = instruction_size(*instr) as usize;
+            bytes.extend_from_slice(&instr.to_le_bytes()[..size]);
+        }
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    fn run(words: Vec<u32>, config: Config) -> Simulator {
+        let mut sim = Simulator::new(config);
+        sim.load_program(Program::from_instructions(words));
+        sim.run();
+        sim
+    }
+
+    #[test]
+    fn expands() {
+        for (parcel, name, expanded) in [
+            (0x0808, "c.addi4spn", "addi a0, sp, 16"),
+            (0x414c, "c.lw", "lw a1, 4(a0)"),
+            (0xc50c, "c.sw", "sw a1, 8(a0)"),
+            (0x0001, "c.nop", "addi zero, zero, 0"),
+            (0x157d, "c.addi", "addi a0, a0, -1"),
+            (0x2021, "c.jal", "jal ra, 8"),
+            (0x451d, "c.li", "addi a0, zero, 7"),
+            (0x713d, "c.addi16sp", "addi sp, sp, -32"),
+            (0x6785, "c.lui", "lui a5, 1"),
+            (0x800d, "c.srli", "srli s0, s0, 3"),
+            (0x8491, "c.srai", "srai s1, s1, 4"),
+            (0x9961, "c.andi", "andi a0, a0, -8"),
+            (0x8c05, "c.sub", "sub s0, s0, s1"),
+            (0x8c25, "c.xor", "xor s0, s0, s1"),
+            (0x8c45, "c.or", "or s0, s0, s1"),
+            (0x8c65, "c.and", "and s0, s0, s1"),
+            (0xbff5, "c.j", "jal zero, -4"),
+            (0xc501, "c.beqz", "beq a0, zero, 8"),
+            (0xfcfd, "c.bnez", "bne s1, zero, -2"),
+            (0x050a, "c.slli", "slli a0, a0, 2"),
+            (0x40b2, "c.lwsp", "lw ra, 12(sp)"),
+            (0x8082, "c.jr", "jalr zero, 0(ra)"),
+            (0x852e, "c.mv", "add a0, zero, a1"),
+            (0x9002, "c.ebreak", "ebreak"),
+            (0x9782, "c.jalr", "jalr ra, 0(a5)"),
+            (0x952e, "c.add", "add a0, a0, a1"),
+            (0xc606, "c.swsp", "sw ra, 12(sp)"),
+        ] {
+            assert!(is_compressed(parcel));
+            assert_eq!(expand(parcel), asm(expanded), "{}", name);
+            assert_eq!(compressed::decompress(parcel as u16).unwrap().0, name);
+            assert_eq!(
+                display_instruction(&parcel)
+                    .trim_end_matches('-')
+                    .split(' ')
+                    .next(),
+                Some(name)
+            );
+        }
+        assert_eq!(
+            display_instruction(&0x8082).trim_end_matches('-'),
+            "c.jr $r1"
+        );
+        assert_eq!(
+            display_instruction(&0x414c).trim_end_matches('-'),
+            "c.lw $r11, 0x4($r10)"
+        );
+        assert_eq!(
+            display_instruction(&0x713d).trim_end_matches('-'),
+            "c.addi16sp $r2, 0xffffffe0"
+        );
+
+        // reserved encodings, and the double-precision ones, aren't anything. they stay as they are, and are illegal.
+        for parcel in [0x0008, 0x4002, 0x8002, 0x2000, 0x8000, 0x1002, 0x6081] {
+            assert_eq!(compressed::decompress(parcel as u16), None, "{:#x}", parcel);
+            assert_eq!(expand(parcel), parcel);
+            assert!(!riscv_psim::isa::isa::is_legal(parcel));
+            assert!(display_instruction(&parcel).starts_with(".word"));
+        }
+        assert_eq!(instruction_size(0), 4);
+        assert_eq!(instruction_size(0x00a00513), 4);
+        assert_eq!(instruction_size(0x0513451d), 2);
+    }
+
+    // a loop, a call and a return, and loads and stores, mostly compressed. the 32-bit ones all straddle two words.
+    fn mixed_program() -> Vec<u32> {
+        vec![
+            0x451d,                    // 0: c.li a0, 7
+            asm("addi a1, zero, 100"), // 2
+            0x4401,                    // 6: c.li s0, 0
+            0x0405,                    // 8: loop: c.addi s0, 1
+            0x962a,                    // 10: c.add a2, a0
+            asm("blt s0, a0, -4"),     // 12: back to loop
+            0x2021,                    // 16: c.jal func
+            0x8686,                    // 18: c.mv a3, ra
+            0xa021,                    // 20: c.j end
+            0x4705,                    // 22: c.li a4, 1 (jumped over)
+            0x0606,                    // 24: func: c.slli a2, 1
+            0x8082,                    // 26: c.jr ra
+            0x6785,                    // 28: end: c.lui a5, 1
+            asm("sw a2, 0(a5)"),       // 30
+            0x4398,                    // 34: c.lw a4, 0(a5)
+        ]
+    }
+
+    #[test]
+    fn runs_on_every_model() {
+        let mut configs: Vec<Config> = [Model::Pipelined, Model::SingleCycle, Model::MultiCycle]
+            .into_iter()
+            .map(|model| Config {
+                model,
+                ..Config::default()
+            })
+            .collect();
+        configs.push(Config {
+            early_branches: true,
+            predictor: Predictor::Gshare,
+            btb_entries: 16,
+            ..Config::default()
+        });
+        configs.push(Config {
+            predictor: Predictor::TwoBit,
+            memory: MemoryConfig {
+                icache: Some(CacheConfig::from_spec("size=64,line=16").unwrap()),
+                icache_miss_penalty: 10,
+                ..MemoryConfig::default()
+            },
+            ..Config::default()
+        });
+        for config in configs {
+            let sim = run(pack(&mixed_program()), config.clone());
+            assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+            let registers = sim.registers();
+            assert_eq!(
+                registers[10..16],
+                [7, 100, 98, 18, 98, 0x1000],
+                "{:?}",
+                config
+            );
+            assert_eq!(registers[8], 7);
+            assert_eq!(registers[1], 18); //c.jal links to the instruction 2 bytes after it
+            assert_eq!(sim.read_word(0x1000), 98);
+            assert_eq!(sim.stats().retired, 3 + 7 * 3 + 8);
+        }
+    }
+
+    #[test]
+    fn fetch_across_cache_lines() {
+        // seven c.nops, and then a 32-bit instruction in the last two bytes of the first line and the first two of the next.
+        let mut program = vec![0x0001; 7];
+        program.push(asm("addi a0, zero, 5"));
+        let sim = run(
+            pack(&program),
+            Config {
+                memory: MemoryConfig {
+                    icache: Some(CacheConfig::from_spec("size=64,line=16").unwrap()),
+                    icache_miss_penalty: 10,
+                    ..MemoryConfig::default()
+                },
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.register(10), 5);
+        // the instruction at 14 hits the first line and misses the second. (the last hit is the fetch past the end.)
+        let icache = sim.icache().unwrap().stats;
+        assert_eq!((icache.hits, icache.misses), (8, 2));
+    }
+
+    #[test]
+    fn illegal_parcels_trap() {
+        // the handler skips the 2-byte instruction that trapped.
+        let sim = run(
+            assemble(
+                "la   t0, handler
+                 csrw mtvec, t0
+                 .word 0x451d8002   # an illegal c.jr x0, then c.li a0, 7
+                 li   a1, 1
+                 j    end
+        handler: csrr s0, mcause
+                 csrr s1, mepc
+                 csrr s2, mtval
+                 addi t1, s1, 2
+                 csrw mepc, t1
+                 mret
+            end: nop",
+            )
+            .unwrap(),
+            Config::default(),
+        );
+        assert_eq!(sim.registers()[8..10], [CAUSE_ILLEGAL_INSTRUCTION, 12]);
+        assert_eq!(sim.register(18), 0x8002);
+        assert_eq!(sim.registers()[10..12], [7, 1]);
+    }
+
+    #[test]
+    fn disassembles() {
+        let program = Program::from_instructions(pack(&mixed_program()[..5]));
+        let mut out = Vec::new();
+        disassemble(&program, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "       0:  451d      c.li $r10, 0x7
+       2:  06400593  addi $r11, $r0, 0x64
+       6:  4401      c.li $r8, 0x0
+       8:  0405      c.addi $r8, 0x1
+       a:  962a      c.add $r12, $r10
+"
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod float_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    fn latencies(fadd: u64, fmul: u64, fdiv: u64) -> Config {
+        Config {
+            latencies: Latencies {
+                fadd,
+                fmul,
+                fdiv,
+                ..Latencies::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    fn bits(x: f32) -> u32 {
+        x.to_bits()
+    }
+
+    #[test]
+    fn results() {
+        let snan = 0x7f800001;
+        let cases = [
+            // (instruction, a, b, c, result, flags)
+            ("fadd.s f0, f1, f2", bits(1.0), bits(2.0), 0, bits(3.0), 0),
+            (
+                "fadd.s f0, f1, f2",
+                bits(0.1),
+                bits(0.2),
+                0,
+                bits(0.1 + 0.2),
+                NX,
+            ),
+            ("fsub.s f0, f1, f2", bits(1.0), bits(1.0), 0, bits(0.0), 0),
+            (
+                "fsub.s f0, f1, f2, rdn",
+                bits(1.0),
+                bits(1.0),
+                0,
+                bits(-0.0),
+                0,
+            ), //exact zeros are -0 rounding down
+            (
+                "fadd.s f0, f1, f2",
+                bits(f32::INFINITY),
+                bits(f32::NEG_INFINITY),
+                0,
+                CANONICAL_NAN,
+                NV,
+            ),
+            (
+                "fmul.s f0, f1, f2",
+                bits(1e30),
+                bits(1e30),
+                0,
+                bits(f32::INFINITY),
+                OF | NX,
+            ),
+            (
+                "fmul.s f0, f1, f2, rtz",
+                bits(1e30),
+                bits(1e30),
+                0,
+                bits(f32::MAX),
+                OF | NX,
+            ),
+            (
+                "fmul.s f0, f1, f2",
+                bits(1e-30),
+                bits(1e-30),
+                0,
+                bits(0.0),
+                UF | NX,
+            ),
+            (
+                "fmul.s f0, f1, f2",
+                bits(f32::MIN_POSITIVE),
+                bits(0.5),
+                0,
+                bits(f32::MIN_POSITIVE / 2.0),
+                0,
+            ), //exact, so no UF
+            (
+                "fdiv.s f0, f1, f2",
+                bits(1.0),
+                bits(0.0),
+                0,
+                bits(f32::INFINITY),
+                DZ,
+            ),
+            (
+                "fdiv.s f0, f1, f2",
+                bits(0.0),
+                bits(0.0),
+                0,
+                CANONICAL_NAN,
+                NV,
+            ),
+            (
+                "fdiv.s f0, f1, f2, rdn",
+                bits(1.0),
+                bits(3.0),
+                0,
+                0x3eaaaaaa,
+                NX,
+            ),
+            (
+                "fdiv.s f0, f1, f2, rup",
+                bits(1.0),
+                bits(3.0),
+                0,
+                0x3eaaaaab,
+                NX,
+            ),
+            ("fsqrt.s f0, f1", bits(2.0), 0, 0, bits(2f32.sqrt()), NX),
+            ("fsqrt.s f0, f1", bits(-1.0), 0, 0, CANONICAL_NAN, NV),
+            ("fsqrt.s f0, f1", bits(-0.0), 0, 0, bits(-0.0), 0),
+            (
+                "fmadd.s f0, f1, f2, f3",
+                bits(1.5),
+                bits(2.0),
+                bits(3.0),
+                bits(6.0),
+                0,
+            ),
+            (
+                "fmsub.s f0, f1, f2, f3",
+                bits(1.5),
+                bits(2.0),
+                bits(3.0),
+                bits(0.0),
+                0,
+            ),
+            (
+                "fnmsub.s f0, f1, f2, f3",
+                bits(1.5),
+                bits(2.0),
+                bits(3.0),
+                bits(0.0),
+                0,
+            ),
+            (
+                "fnmadd.s f0, f1, f2, f3",
+                bits(1.5),
+                bits(2.0),
+                bits(3.0),
+                bits(-6.0),
+                0,
+            ),
+            (
+                "fmadd.s f0, f1, f2, f3",
+                bits(f32::INFINITY),
+                bits(0.0),
+                CANONICAL_NAN,
+                CANONICAL_NAN,
+                NV,
+            ),
+            (
+                "fmin.s f0, f1, f2",
+                CANONICAL_NAN,
+                bits(1.0),
+                0,
+                bits(1.0),
+                0,
+            ),
+            ("fmin.s f0, f1, f2", snan, bits(1.0), 0, bits(1.0), NV),
+            ("fmin.s f0, f1, f2", bits(0.0), bits(-0.0), 0, bits(-0.0), 0),
+            ("fmax.s f0, f1, f2", bits(-0.0), bits(0.0), 0, bits(0.0), 0),
+            (
+                "fmax.s f0, f1, f2",
+                CANONICAL_NAN,
+                snan,
+                0,
+                CANONICAL_NAN,
+                NV,
+            ),
+            (
+                "fsgnj.s f0, f1, f2",
+                bits(2.0),
+                bits(-1.0),
+                0,
+                bits(-2.0),
+                0,
+            ),
+            (
+                "fsgnjn.s f0, f1, f2",
+                bits(2.0),
+                bits(-1.0),
+                0,
+                bits(2.0),
+                0,
+            ),
+            (
+                "fsgnjx.s f0, f1, f2",
+                bits(-2.0),
+                bits(-1.0),
+                0,
+                bits(2.0),
+                0,
+            ),
+            ("feq.s a0, f1, f2", bits(1.0), bits(1.0), 0, 1, 0),
+            ("feq.s a0, f1, f2", CANONICAL_NAN, bits(1.0), 0, 0, 0),
+            ("feq.s a0, f1, f2", snan, bits(1.0), 0, 0, NV),
+            ("flt.s a0, f1, f2", CANONICAL_NAN, bits(1.0), 0, 0, NV),
+            ("fle.s a0, f1, f2", bits(-0.0), bits(0.0), 0, 1, 0),
+            ("fcvt.w.s a0, f1", bits(-2.5), 0, 0, -2i32 as u32, NX),
+            ("fcvt.w.s a0, f1, rmm", bits(-2.5), 0, 0, -3i32 as u32, NX),
+            ("fcvt.w.s a0, f1, rup", bits(-2.5), 0, 0, -2i32 as u32, NX),
+            ("fcvt.w.s a0, f1, rdn", bits(-2.5), 0, 0, -3i32 as u32, NX),
+            ("fcvt.w.s a0, f1, rtz", bits(-2.5), 0, 0, -2i32 as u32, NX),
+            ("fcvt.w.s a0, f1", bits(3e9), 0, 0, i32::MAX as u32, NV),
+            ("fcvt.w.s a0, f1", CANONICAL_NAN, 0, 0, i32::MAX as u32, NV),
+            (
+                "fcvt.w.s a0, f1",
+                bits(f32::NEG_INFINITY),
+                0,
+                0,
+                i32::MIN as u32,
+                NV,
+            ),
+            ("fcvt.wu.s a0, f1", bits(3e9), 0, 0, 3_000_000_000, 0),
+            ("fcvt.wu.s a0, f1", bits(-1.0), 0, 0, 0, NV),
+            ("fcvt.wu.s a0, f1", bits(-0.5), 0, 0, 0, NX), //rounds to 0, which fits
+            ("fcvt.s.w f0, a1", 16777217, 0, 0, bits(16777216.0), NX),
+            ("fcvt.s.w f0, a1", -5i32 as u32, 0, 0, bits(-5.0), 0),
+            ("fcvt.s.wu f0, a1", u32::MAX, 0, 0, bits(4294967296.0), NX),
+            ("fmv.x.w a0, f1", bits(-1.0), 0, 0, 0xbf800000, 0),
+            ("fmv.w.x f0, a1", 0x7f800001, 0, 0, 0x7f800001, 0), //moves don't touch NaNs
+            ("fclass.s a0, f1", bits(f32::NEG_INFINITY), 0, 0, 1 << 0, 0),
+            ("fclass.s a0, f1", bits(-0.0), 0, 0, 1 << 3, 0),
+            ("fclass.s a0, f1", 1, 0, 0, 1 << 5, 0), //positive subnormal
+            ("fclass.s a0, f1", snan, 0, 0, 1 << 8, 0),
+            ("fclass.s a0, f1", CANONICAL_NAN, 0, 0, 1 << 9, 0),
+        ];
+        for (text, a, b, c, result, flags) in cases {
+            assert_eq!(
+                fp_execute(asm(text), a, b, c, RNE),
+                (result, flags),
+                "{} {:#x} {:#x} {:#x}",
+                text,
+                a,
+                b,
+                c
+            );
+        }
+    }
+
+    #[test]
+    fn rounds_like_the_host() {
+        // with round to nearest, the host's f32 arithmetic is the reference.
+        let values = [
+            1.0f32, -1.0, 0.1, 3.0, 1e-3, 7e7, -123.456, 1e-40, 3.4e38, 1.0000001, 0.99999994,
+            2e-38, -5e-39,
+        ];
+        for x in values {
+            assert_eq!(
+                fp_execute(asm("fsqrt.s f0, f1"), bits(x.abs()), 0, 0, RNE).0,
+                bits(x.abs().sqrt())
+            );
+            for y in values {
+                let ops: [(&str, f32); 4] = [
+                    ("fadd.s f0, f1, f2", x + y),
+                    ("fsub.s f0, f1, f2", x - y),
+                    ("fmul.s f0, f1, f2", x * y),
+                    ("fdiv.s f0, f1, f2", x / y),
+                ];
+                for (text, expected) in ops {
+                    assert_eq!(
+                        fp_execute(asm(text), bits(x), bits(y), 0, RNE).0,
+                        bits(expected),
+                        "{} {} {}",
+                        text,
+                        x,
+                        y
+                    );
+                }
+                for z in values {
+                    let (result, _) = fp_execute(
+                        asm("fmadd.s f0, f1, f2, f3"),
+                        bits(x),
+                        bits(y),
+                        bits(z),
+                        RNE,
+                    );
+                    assert_eq!(result, bits(x.mul_add(y, z)), "{} {} {}", x, y, z);
+                }
+            }
+        }
+    }
+
+    const PROGRAM: &str = "li       t0, 3
+                           fcvt.s.w fa0, t0
+                           li       t1, 2
+                           fcvt.s.w fa1, t1
+                           fdiv.s   fa2, fa0, fa1
+                           fadd.s   fa3, fa2, fa1
+                           fmul.s   fa4, fa3, fa3
+                           fmadd.s  fa5, fa2, fa1, fa0
+                           fsqrt.s  fa6, fa4
+                           fsub.s   fa7, fa1, fa0
+                           fcvt.w.s a0, fa4
+                           flt.s    a1, fa7, fa1
+                           fmv.x.w  a2, fa6
+                           li       sp, 0x10000000
+                           fsw      fa5, 0(sp)
+                           flw      ft0, 0(sp)
+                           fneg.s   ft1, ft0
+                           fmax.s   ft2, ft1, fa7
+                           frflags  a3";
+
+    #[test]
+    fn every_model() {
+        let mut configs: Vec<Config> = [Model::Pipelined, Model::SingleCycle, Model::MultiCycle]
+            .into_iter()
+            .map(|model| Config {
+                model,
+                ..Config::default()
+            })
+            .collect();
+        configs.push(Config {
+            forwarding: Forwarding::none(),
+            ..Config::default()
+        });
+        configs.push(Config {
+            early_branches: true,
+            ..latencies(2, 3, 5)
+        });
+        configs.push(latencies(1, 1, 1));
+        for config in configs {
+            let sim = run_source(PROGRAM, config.clone());
+            assert_eq!(
+                sim.stop_reason(),
+                Some(StopReason::EndOfProgram),
+                "{:?}",
+                config
+            );
+            assert_eq!(
+                sim.fp_registers()[10..18],
+                [
+                    bits(3.0),
+                    bits(2.0),
+                    bits(1.5),
+                    bits(3.5),
+                    bits(12.25),
+                    bits(6.0),
+                    bits(3.5),
+                    bits(-1.0)
+                ],
+                "{:?}",
+                config
+            );
+            assert_eq!(
+                sim.fp_registers()[0..3],
+                [bits(6.0), bits(-6.0), bits(-1.0)],
+                "{:?}",
+                config
+            );
+            assert_eq!(
+                sim.registers()[10..14],
+                [12, 1, bits(3.5), NX as u32],
+                "{:?}",
+                config
+            );
+            assert_eq!(sim.read_word(0x10000000), bits(6.0));
+            assert_eq!(sim.stats().retired, 20);
+        }
+    }
+
+    #[test]
+    fn latency_and_pipelining() {
+        // each instruction in the chain waits for the one before it to come out of the FPU, and gets it EX-EX forwarded.
+        let chain = "li       t0, 3
+                     fcvt.s.w f1, t0
+                     fadd.s   f2, f1, f1
+                     fadd.s   f3, f2, f2
+                     fmul.s   f4, f3, f3";
+        let fast = run_source(chain, latencies(1, 1, 1));
+        let slow = run_source(chain, latencies(4, 7, 1));
+        for sim in [&fast, &slow] {
+            assert_eq!(sim.fp_register(4), bits(144.0));
+        }
+        assert_eq!(slow.stats().cycles - fast.stats().cycles, 3 * 3 + 6);
+        assert_eq!(slow.stats().stall_cycles - fast.stats().stall_cycles, 3 * 3);
+
+        // the adder's pipelined, so independent adds go in one after another. only the last one's latency shows.
+        let independent = "fadd.s f2, f1, f1
+                           fadd.s f3, f1, f1
+                           fadd.s f4, f1, f1
+                           fadd.s f5, f1, f1";
+        let fast = run_source(independent, latencies(1, 1, 1));
+        let slow = run_source(independent, latencies(4, 1, 1));
+        assert_eq!(slow.stats().cycles - fast.stats().cycles, 3);
+        assert_eq!(slow.stats().stall_cycles, 0);
+
+        // the divider isn't, so each divide waits for the one before it.
+        let divides = "fdiv.s f2, f1, f1
+                       fdiv.s f3, f1, f1
+                       fdiv.s f4, f1, f1";
+        let fast = run_source(divides, latencies(1, 1, 1));
+        let slow = run_source(divides, latencies(1, 1, 10));
+        assert_eq!(slow.stats().cycles - fast.stats().cycles, 3 * 9);
+
+        // the multi-cycle model's EX takes as long as the latency. the single-cycle model's one cycle covers it.
+        for (model, extra) in [(Model::MultiCycle, 3 * 3 + 6), (Model::SingleCycle, 0)] {
+            let config = |fadd, fmul| Config {
+                model,
+                ..latencies(fadd, fmul, 1)
+            };
+            let fast = run_source(chain, config(1, 1));
+            let slow = run_source(chain, config(4, 7));
+            assert_eq!(slow.fp_register(4), bits(144.0), "{:?}", model);
+            assert_eq!(
+                slow.stats().cycles - fast.stats().cycles,
+                extra,
+                "{:?}",
+                model
+            );
+        }
+    }
+
+    #[test]
+    fn hazards() {
+        // WAW: the add has to wait for the divide, or the divide would come out after it and overwrite f2.
+        let sim = run_source(
+            "li       t0, 3
+             fcvt.s.w f1, t0
+             fdiv.s   f2, f1, f1
+             fadd.s   f2, f1, f1
+             fmv.x.w  a0, f2",
+            latencies(4, 7, 20),
+        );
+        assert_eq!(sim.register(10), bits(6.0));
+
+        // structural: the third addi would get to MEM in the same cycle as the add comes out of the FPU.
+        let source = "fadd.s f2, f1, f1
+                      addi   a0, zero, 1
+                      addi   a1, zero, 2
+                      addi   a2, zero, 3
+                      addi   a3, zero, 4";
+        let sim = run_source(source, latencies(4, 7, 20));
+        assert_eq!(sim.registers()[10..14], [1, 2, 3, 4]);
+        assert_eq!(sim.stats().stall_cycles, 1);
+        // ...and a multiply issued right after the add would come out with it too, so it waits.
+        let sim = run_source(
+            "fadd.s f2, f1, f1
+             fmul.s f3, f1, f1",
+            latencies(4, 3, 20),
+        );
+        assert_eq!(sim.stats().stall_cycles, 1);
+
+        // an integer instruction reading an FP result waits for it too, as does a store of it.
+        let sim = run_source(
+            "li       t0, 7
+             fcvt.s.w f1, t0
+             fmul.s   f2, f1, f1
+             fcvt.w.s a0, f2
+             li       t1, 0x10000000
+             fdiv.s   f3, f2, f1
+             fsw      f3, 0(t1)
+             lw       a1, 0(t1)",
+            Config::default(),
+        );
+        assert_eq!(sim.registers()[10..12], [49, bits(7.0)]);
+
+        // an integer instruction writing the same register number as an FP one doesn't wait. they're different files.
+        let source = "fdiv.s f10, f1, f1
+                      addi   a0, zero, 5";
+        let sim = run_source(source, latencies(4, 7, 20));
+        assert_eq!(sim.register(10), 5);
+        assert_eq!(sim.stats().stall_cycles, 0);
+    }
+
+    #[test]
+    fn behind_a_cache_miss() {
+        // the store misses, which holds the add in the FPU, but not the multiply in EX. so the multiply's done
+        // in time to go into EX-MEM with the add, and has to wait a cycle for it.
+        let sim = run_source(
+            "lui    x10, 0x10000
+             addi   x6, x0, 3
+             addi   x7, x0, 5
+             fadd.s f1, f2, f3
+             addi   x8, x0, 1
+             sw     x8, 0(x10)
+             mul    x5, x6, x7
+             addi   x9, x0, 2",
+            Config {
+                memory: MemoryConfig {
+                    caches: vec![
+                        CacheConfig::from_spec("size=1k,ways=1,line=16,latency=1").unwrap()
+                    ],
+                    ..MemoryConfig::default()
+                },
+                ..Config::default()
+            },
+        );
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.registers()[5..10], [15, 3, 5, 1, 2]);
+        assert_eq!(sim.read_word(0x10000000), 1);
+        assert_eq!(sim.stats().retired, 8);
+    }
+
+    #[test]
+    fn fcsr() {
+        let sim = run_source(
+            "li       t0, 1
+             fsrm     t0              # round towards zero
+             li       t1, 1
+             li       t2, 3
+             fcvt.s.w f1, t1
+             fcvt.s.w f2, t2
+             fdiv.s   f3, f1, f2      # dyn, so towards zero
+             fdiv.s   f4, f1, f2, rne
+             frcsr    a0
+             fsflags  zero
+             frflags  a1
+             csrr     a2, fcsr
+             li       t0, 0xff
+             csrw     fcsr, t0
+             frrm     a3
+             frflags  a4",
+            Config::default(),
+        );
+        assert_eq!(sim.fp_registers()[3..5], [0x3eaaaaaa, 0x3eaaaaab]);
+        assert_eq!(
+            sim.registers()[10..15],
+            [RTZ as u32 * 32 + NX as u32, 0, 32, 7, 31]
+        );
+
+        // a dyn instruction with frm set to a mode that doesn't exist is illegal. one with its own mode is fine.
+        let sim = run_source(
+            "la       t0, handler
+             csrw     mtvec, t0
+             li       t0, 5
+             fsrm     t0
+             fadd.s   f1, f1, f1, rne
+             fadd.s   f1, f1, f1
+             li       a0, 1
+             j        end
+    handler: csrr     s0, mcause
+             csrr     s1, mtval
+         end: nop",
+            Config::default(),
+        );
+        assert_eq!(
+            sim.registers()[8..10],
+            [CAUSE_ILLEGAL_INSTRUCTION, asm("fadd.s f1, f1, f1")]
+        );
+        assert_eq!(sim.register(10), 0);
+    }
+
+    #[test]
+    fn compressed_loads_and_stores() {
+        for (parcel, name, expanded) in [
+            (0x6144, "c.flw", "flw fs1, 4(a0)"),
+            (0xe504, "c.fsw", "fsw fs1, 8(a0)"),
+            (0x6532, "c.flwsp", "flw fa0, 12(sp)"),
+            (0xe62a, "c.fswsp", "fsw fa0, 12(sp)"),
+        ] {
+            assert_eq!(expand(parcel), asm(expanded), "{}", name);
+            assert_eq!(compressed::decompress(parcel as u16).unwrap().0, name);
+        }
+        assert_eq!(
+            display_instruction(&0x6144).trim_end_matches('-'),
+            "c.flw $f9, 0x4($r10)"
+        );
+
+        // c.fsw fs1, 8(a0), then c.flwsp fa0, 8(sp), c.fswsp fa0, 12(sp) and c.flw fs0, 12(a0).
+        let mut words = assemble(
+            "lui      a0, 0x10000
+             mv       sp, a0
+             li       t0, 5
+             fcvt.s.w fs1, t0",
+        )
+        .unwrap();
+        words.push(0x6522e504);
+        words.push(0x6540e62a);
+        let mut sim = Simulator::new(Config::default());
+        sim.load_program(Program::from_instructions(words));
+        sim.run();
+        assert_eq!(sim.stop_reason(), Some(StopReason::EndOfProgram));
+        assert_eq!(sim.fp_registers()[8..11], [bits(5.0), bits(5.0), bits(5.0)]);
+        assert_eq!(sim.read_word(0x1000000c), bits(5.0));
+    }
+
+    #[test]
+    fn assembles_and_disassembles() {
+        let instructions = assemble(
+            "fadd.s  fa0, fa1, fa2
+             fadd.s  ft0, fs0, ft11, rtz
+             fsub.s  f1, f2, f3, rmm
+             fmul.s  f1, f2, f3
+             fdiv.s  f1, f2, f3, rup
+             fsqrt.s f1, f2
+             fmadd.s f1, f2, f3, f4
+             fmsub.s f1, f2, f3, f4, rdn
+             fnmsub.s f1, f2, f3, f4
+             fnmadd.s f1, f2, f3, f4
+             fsgnj.s f1, f2, f3
+             fsgnjn.s f1, f2, f3
+             fsgnjx.s f1, f2, f3
+             fmin.s  f1, f2, f3
+             fmax.s  f1, f2, f3
+             fcvt.w.s a0, f1, rtz
+             fcvt.wu.s a0, f1
+             fcvt.s.w f1, a0
+             fcvt.s.wu f1, a0
+             fmv.x.w a0, f1
+             fmv.w.x f1, a0
+             feq.s   a0, f1, f2
+             flt.s   a0, f1, f2
+             fle.s   a0, f1, f2
+             fclass.s a0, f1
+             flw     f1, -4(sp)
+             fsw     f1, 8(sp)",
+        )
+        .unwrap();
+        assert_eq!(instructions[0], 0x00c5f553);
+        assert_eq!(instructions[1], 0x01f41053);
+        assert_eq!(instructions[6], 0x203170c3);
+        assert_eq!(instructions[25], 0xffc12087);
+        assert_eq!(instructions[26], 0x00112427);
+        for instr in instructions {
+            assert!(riscv_psim::isa::isa::is_legal(instr), "{:#x}", instr);
+            let text = display_instruction(&instr);
+            let text = text.trim_end_matches('-');
+            assert_eq!(assemble(text).unwrap(), vec![instr], "{}", text);
+        }
+        assert_eq!(
+            display_instruction(&0x01f41053).trim_end_matches('-'),
+            "fadd.s $f0, $f8, $f31, rtz"
+        );
+
+        // the pseudo-instructions.
+        for (pseudo, real) in [
+            ("fmv.s f1, f2", "fsgnj.s f1, f2, f2"),
+            ("fneg.s f1, f2", "fsgnjn.s f1, f2, f2"),
+            ("fabs.s f1, f2", "fsgnjx.s f1, f2, f2"),
+            ("frcsr a0", "csrrs a0, fcsr, zero"),
+            ("fscsr a0, a1", "csrrw a0, fcsr, a1"),
+            ("fsrm a1", "csrrw zero, frm, a1"),
+            ("frflags a0", "csrrs a0, fflags, zero"),
+        ] {
+            assert_eq!(asm(pseudo), asm(real), "{}", pseudo);
+        }
+
+        // funct7s, rounding modes and fmt fields that aren't anything.
+        for instr in [0x00c5d553, 0x02c5f553, 0x7c000053, 0xe0101553] {
+            assert!(!riscv_psim::isa::isa::is_legal(instr), "{:#x}", instr);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod bitmanip_tests {
+    use crate::assembler::*;
+    use crate::*;
+
+    #[test]
+    fn results() {
+        let cases = [
+            // (instruction, op1, op2, result)
+            ("sh1add a0, a1, a2", 5, 100, 110),
+            ("sh2add a0, a1, a2", 5, 100, 120),
+            ("sh3add a0, a1, a2", 0x20000001, 100, 108), //the shift drops the top bits
+            ("andn a0, a1, a2", 0xff, 0x0f, 0xf0),
+            ("orn a0, a1, a2", 0xf0, 0xffff0fff, 0xf0f0),
+            ("xnor a0, a1, a2", 0xff00ff00, 0xffff0000, 0xff0000ff),
+            ("min a0, a1, a2", -1i32 as u32, 1, -1i32 as u32),
+            ("minu a0, a1, a2", -1i32 as u32, 1, 1),
+            ("max a0, a1, a2", -1i32 as u32, 1, 1),
+            ("maxu a0, a1, a2", -1i32 as u32, 1, u32::MAX),
+            ("rol a0, a1, a2", 0x80000001, 33, 0x00000003), //only the low 5 bits of rs2
+            ("ror a0, a1, a2", 0x80000001, 1, 0xc0000000),
+            ("bset a0, a1, a2", 0, 31, 0x80000000),
+            ("bclr a0, a1, a2", u32::MAX, 4, 0xffffffef),
+            ("binv a0, a1, a2", 0b101, 0, 0b100),
+            ("bext a0, a1, a2", 0b100, 2, 1),
+            ("zext.h a0, a1", 0x12345678, 0, 0x5678),
+            ("clz a0, a1", 0x00010000, 0, 15),
+            ("clz a0, a1", 0, 0, 32),
+            ("ctz a0, a1", 0x00010000, 0, 16),
+            ("ctz a0, a1", 0, 0, 32),
+            ("cpop a0, a1", 0xf0f00001, 0, 9),
+            ("sext.b a0, a1", 0x1280, 0, 0xffffff80),
+            ("sext.h a0, a1", 0x12347fff, 0, 0x7fff),
+            ("sext.h a0, a1", 0x8000, 0, 0xffff8000),
+            ("rev8 a0, a1", 0x12345678, 0, 0x78563412),
+            ("orc.b a0, a1", 0x00120300, 0, 0x00ffff00),
+        ];
+        for (text, op1, op2, result) in cases {
+            assert_eq!(bitmanip(asm(text), op1, op2), result, "{}", text);
+        }
+        // the immediate versions take the shamt from op2, which is the immediate.
+        for (text, op1, result) in [
+            ("rori a0, a1, 4", 0x12345678, 0x81234567),
+            ("bseti a0, a1, 3", 0, 8),
+            ("bclri a0, a1, 0", 3, 2),
+            ("binvi a0, a1, 31", 0, 0x80000000),
+            ("bexti a0, a1, 31", 0x80000000, 1),
+        ] {
+            let instruction = asm(text);
+            assert_eq!(
+                bitmanip(instruction, op1, (instruction as i32 >> 20) as u32),
+                result,
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn names_match_encodings() {
+        // the disassembler's names and EX's decoding are two tables, so make sure they agree on what's one of these.
+        for opcode in [0b0010011, 0b0110011] {
+            for fields in 0..(1 << 15) {
+                let funct7 = fields >> 8;
+                let funct3 = (fields >> 5) & 0b111;
+                let rs2 = fields & 0b11111;
+                let instruction = (funct7 << 25) | (rs2 << 20) | (funct3 << 12) | opcode;
+                assert_eq!(
+                    is_bitmanip(instruction),
+                    bitmanip_name(instruction).is_some(),
+                    "{:08x}",
+                    instruction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_model() {
+        // each result feeds the next instruction, so they all get forwarded.
+        let source = "li     a0, 0x00f0
+                      li     a1, 3
+                      sh2add a2, a1, a0        # 0xfc
+                      clz    a3, a2            # 24
+                      rori   a4, a2, 4         # 0xc000000f
+                      andn   a5, a4, a0        # 0xc000000f
+                      bseti  a6, a5, 8         # 0xc000010f
+                      cpop   a7, a6            # 7
+                      rev8   s0, a6            # 0x0f0100c0
+                      orc.b  s1, s0            # 0xffff00ff
+                      max    s2, a4, a1        # 3
+                      minu   s3, a4, a1        # 3
+                      sext.b s4, a2            # 0xfffffffc
+                      bext   s5, a6, a1        # 1
+                      xnor   s6, s5, zero      # 0xfffffffe";
+        for config in [
+            Config::default(),
+            Config {
+                model: Model::SingleCycle,
+                ..Config::default()
+            },
+            Config {
+                model: Model::MultiCycle,
+                ..Config::default()
+            },
+            Config {
+                forwarding: Forwarding::none(),
+                ..Config::default()
+            },
+        ] {
+            let sim = run_source(source, config.clone());
+            assert_eq!(
+                sim.stop_reason(),
+                Some(StopReason::EndOfProgram),
+                "{:?}",
+                config
+            );
+            assert_eq!(
+                sim.registers()[12..18],
+                [0xfc, 24, 0xc000000f, 0xc000000f, 0xc000010f, 7],
+                "{:?}",
+                config
+            );
+            assert_eq!(
+                sim.registers()[8..10],
+                [0x0f0100c0, 0xffff00ff],
+                "{:?}",
+                config
+            );
+            assert_eq!(
+                sim.registers()[18..23],
+                [3, 3, 0xfffffffc, 1, 0xfffffffe],
+                "{:?}",
+                config
+            );
+        }
+        // with every forwarding path, they're as quick as adds.
+        let sim = run_source(source, Config::default());
+        assert_eq!(sim.stats().stall_cycles, 0);
+    }
+
+    #[test]
+    fn assembles_and_disassembles() {
+        let instructions = assemble(
+            "sh1add a0, a1, a2
+             sh2add a0, a1, a2
+             sh3add a0, a1, a2
+             andn   a0, a1, a2
+             orn    a0, a1, a2
+             xnor   a0, a1, a2
+             min    a0, a1, a2
+             minu   a0, a1, a2
+             max    a0, a1, a2
+             maxu   a0, a1, a2
+             rol    a0, a1, a2
+             ror    a0, a1, a2
+             bset   a0, a1, a2
+             bclr   a0, a1, a2
+             binv   a0, a1, a2
+             bext   a0, a1, a2
+             rori   a0, a1, 7
+             bseti  a0, a1, 7
+             bclri  a0, a1, 7
+             binvi  a0, a1, 7
+             bexti  a0, a1, 7
+             clz    a0, a1
+             ctz    a0, a1
+             cpop   a0, a1
+             sext.b a0, a1
+             sext.h a0, a1
+             zext.h a0, a1
+             rev8   a0, a1
+             orc.b  a0, a1",
+        )
+        .unwrap();
+        // the encodings from the spec's tables.
+        for (instr, expected) in instructions.iter().zip([
+            0x20c5a533, 0x20c5c533, 0x20c5e533, 0x40c5f533, 0x40c5e533, 0x40c5c533, 0x0ac5c533,
+            0x0ac5d533, 0x0ac5e533, 0x0ac5f533, 0x60c59533, 0x60c5d533, 0x28c59533, 0x48c59533,
+            0x68c59533, 0x48c5d533, 0x6075d513, 0x28759513, 0x48759513, 0x68759513, 0x4875d513,
+            0x60059513, 0x60159513, 0x60259513, 0x60459513, 0x60559513, 0x0805c533, 0x6985d513,
+            0x2875d513,
+        ]) {
+            assert_eq!(*instr, expected, "{:#x}", expected);
+        }
+        for instr in instructions {
+            assert!(riscv_psim::isa::isa::is_legal(instr), "{:#x}", instr);
+            let text = display_instruction(&instr);
+            let text = text.trim_end_matches('-');
+            assert_eq!(assemble(text).unwrap(), vec![instr], "{}", text);
+        }
+        assert_eq!(
+            display_instruction(&0x6075d513).trim_end_matches('-'),
+            "rori $r10, $r11, 7"
+        );
+        assert_eq!(
+            display_instruction(&0x6985d513).trim_end_matches('-'),
+            "rev8 $r10, $r11"
+        );
+
+        // the gaps between them are still illegal: clz's neighbours, zext.h with an rs2, rev8 with the wrong shamt,
+        // and a funct7 nothing uses.
+        for instr in [0x60359513, 0x08c5c533, 0x6995d513, 0x7ec5c533] {
+            assert!(!riscv_psim::isa::isa::is_legal(instr), "{:#x}", instr);
+        }
+    }
 }
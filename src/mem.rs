@@ -0,0 +1,282 @@
+use std::collections::hash_map::*;
+use std::rc::Rc;
+
+// Memory. One byte-addressable, little-endian address space that holds the program's code and its data both,
+// so loads can read constants out of .text, and stores could even change the code (a von Neumann machine).
+// It's sparse: split into pages that only get made the first time something is written to them.
+// Reading from anywhere that hasn't been written to gives 0.
+
+pub const PAGE_SIZE: u32 = 4096;
+
+type Page = [u8; PAGE_SIZE as usize];
+
+//The pages are shared between copies of the memory, and only get copied when one of them writes to it.
+// The stepper keeps a copy of the whole CPU for every cycle it can go back to, so this keeps those cheap.
+#[derive(Clone, Default)]
+pub struct Memory {
+    pages: HashMap<u32, Rc<Page>>, //keyed by address / PAGE_SIZE
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory::default()
+    }
+
+    pub fn read_byte(&self, address: u32) -> u8 {
+        match self.pages.get(&(address / PAGE_SIZE)) {
+            Some(page) => page[(address % PAGE_SIZE) as usize],
+            None => 0,
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u32, byte: u8) {
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| Rc::new([0; PAGE_SIZE as usize]));
+        Rc::make_mut(page)[(address % PAGE_SIZE) as usize] = byte;
+    }
+
+    //`size` bytes (1, 2 or 4) starting at `address`, little-endian. wraps around at the top of memory.
+    pub fn read(&self, address: u32, size: u32) -> u32 {
+        (0..size.min(4)).fold(0, |value, i| {
+            value | (self.read_byte(address.wrapping_add(i)) as u32) << (8 * i)
+        })
+    }
+
+    //writes the lowest `size` bytes (1, 2 or 4) of `value`, little-endian.
+    pub fn write(&mut self, address: u32, size: u32, value: u32) {
+        for i in 0..size.min(4) {
+            self.write_byte(address.wrapping_add(i), (value >> (8 * i)) as u8);
+        }
+    }
+
+    pub fn read_word(&self, address: u32) -> u32 {
+        self.read(address, 4)
+    }
+
+    pub fn write_word(&mut self, address: u32, value: u32) {
+        self.write(address, 4, value);
+    }
+
+    pub fn read_bytes(&self, address: u32, count: u32) -> Vec<u8> {
+        (0..count)
+            .map(|i| self.read_byte(address.wrapping_add(i)))
+            .collect()
+    }
+
+    pub fn write_bytes(&mut self, address: u32, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), *byte);
+        }
+    }
+
+    //how many pages have been written to.
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+// =======================
+// The memory map
+// =======================
+
+//What a region of the address space is for. Code can only be run from text, and the other kinds can only be
+// loaded from and stored to. Anything outside every region is unmapped, and touching it is an access fault.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionKind {
+    Text,
+    Data,
+    Stack,
+    Mmio, //devices. not cached, and not backed by memory
+}
+
+impl RegionKind {
+    pub fn from_name(name: &str) -> Option<RegionKind> {
+        match name.to_lowercase().as_str() {
+            "text" | "code" => Some(RegionKind::Text),
+            "data" => Some(RegionKind::Data),
+            "stack" => Some(RegionKind::Stack),
+            "mmio" | "io" => Some(RegionKind::Mmio),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegionKind::Text => "text",
+            RegionKind::Data => "data",
+            RegionKind::Stack => "stack",
+            RegionKind::Mmio => "mmio",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub base: u32,
+    pub size: u32,
+}
+
+impl Region {
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.base && address - self.base < self.size
+    }
+
+    //one past the last address in it. (a u64, since a region can go right up to the top of memory.)
+    pub fn end(&self) -> u64 {
+        self.base as u64 + self.size as u64
+    }
+}
+
+//Where everything goes in the address space.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+}
+
+//The default layout. Programs that were written for the separate instruction and data memories put their
+// data right next to their code, near address 0, so text can be written to as well as run.
+// Bare-metal programs (riscv-tests, and most embedded linker scripts) put code and data both at 0x80000000,
+// so from there up to MMIO is text too.
+impl Default for MemoryMap {
+    fn default() -> MemoryMap {
+        let region = |kind, base, size| Region { kind, base, size };
+        MemoryMap {
+            regions: vec![
+                region(RegionKind::Text, 0x0000_0000, 0x1000_0000),
+                region(RegionKind::Data, 0x1000_0000, 0x6000_0000),
+                region(RegionKind::Stack, 0x7000_0000, 0x1000_0000),
+                region(RegionKind::Text, 0x8000_0000, 0x7000_0000),
+                region(RegionKind::Mmio, 0xf000_0000, 0x1000_0000),
+            ],
+        }
+    }
+}
+
+impl MemoryMap {
+    //a map from a list of regions like "text=0x0:64k,data=0x10000000:1m,stack=0x7ff00000:1m,mmio=0xf0000000:4k".
+    // each one is kind=base:size. the list replaces the whole default map.
+    pub fn from_spec(spec: &str) -> Result<MemoryMap, String> {
+        let mut regions = Vec::new();
+        for entry in spec.split(',').filter(|entry| !entry.is_empty()) {
+            let (kind, range) = entry.split_once('=').ok_or(format!(
+                "memory region '{}' needs to be kind=base:size",
+                entry
+            ))?;
+            let kind = RegionKind::from_name(kind.trim()).ok_or(format!(
+                "unknown memory region '{}' (expected text, data, stack or mmio)",
+                kind
+            ))?;
+            let (base, size) = range.split_once(':').ok_or(format!(
+                "memory region '{}' needs to be kind=base:size",
+                entry
+            ))?;
+            let number =
+                |text: &str| parse_number(text).ok_or(format!("bad address or size '{}'", text));
+            regions.push(Region {
+                kind,
+                base: number(base)?,
+                size: number(size)?,
+            });
+        }
+        let map = MemoryMap { regions };
+        map.check()?;
+        Ok(map)
+    }
+
+    //makes sure the regions fit in the address space without overlapping.
+    pub fn check(&self) -> Result<(), String> {
+        for (i, region) in self.regions.iter().enumerate() {
+            if region.size == 0 || region.end() > 1 << 32 {
+                return Err(format!(
+                    "the {} region at {:#x} doesn't fit in the address space",
+                    region.kind.name(),
+                    region.base
+                ));
+            }
+            // word-aligned, so a load or store can never be half in one region and half in another.
+            if !region.base.is_multiple_of(4) || !region.size.is_multiple_of(4) {
+                return Err(format!(
+                    "the {} region at {:#x} needs to start and end on a word boundary",
+                    region.kind.name(),
+                    region.base
+                ));
+            }
+            if let Some(other) = self.regions[..i].iter().find(|other| {
+                (region.base as u64) < other.end() && (other.base as u64) < region.end()
+            }) {
+                return Err(format!(
+                    "the {} region at {:#x} overlaps the {} region at {:#x}",
+                    region.kind.name(),
+                    region.base,
+                    other.kind.name(),
+                    other.base
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn region(&self, address: u32) -> Option<&Region> {
+        self.regions.iter().find(|region| region.contains(address))
+    }
+
+    pub fn kind(&self, address: u32) -> Option<RegionKind> {
+        self.region(address).map(|region| region.kind)
+    }
+
+    pub fn is_mapped(&self, address: u32) -> bool {
+        self.region(address).is_some()
+    }
+
+    pub fn is_executable(&self, address: u32) -> bool {
+        self.kind(address) == Some(RegionKind::Text)
+    }
+
+    pub fn is_mmio(&self, address: u32) -> bool {
+        self.kind(address) == Some(RegionKind::Mmio)
+    }
+
+    //where $sp starts for programs that need one: the top of the (first) stack region, 16-byte aligned.
+    pub fn stack_top(&self) -> Option<u32> {
+        let stack = self
+            .regions
+            .iter()
+            .find(|region| region.kind == RegionKind::Stack)?;
+        Some(((stack.end() - 16) & !15) as u32)
+    }
+
+    pub fn describe(&self) -> String {
+        self.regions
+            .iter()
+            .map(|region| {
+                format!(
+                    "{} {:#010x}-{:#010x}",
+                    region.kind.name(),
+                    region.base,
+                    region.end() - 1
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+//an address or size: decimal, or hex with 0x. sizes can end in k or m.
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim().to_lowercase();
+    let (digits, scale) = match text.strip_suffix('k') {
+        Some(digits) => (digits, 1024),
+        None => match text.strip_suffix('m') {
+            Some(digits) => (digits, 1024 * 1024),
+            None => (text.as_str(), 1),
+        },
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16).ok()?,
+        None => digits.parse::<u32>().ok()?,
+    };
+    value.checked_mul(scale)
+}
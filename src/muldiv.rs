@@ -0,0 +1,39 @@
+// RV32M: multiply and divide. They're R-type instructions with funct7 1, and the ALU works them out like
+// any other, but a real multiplier or divider takes more than one cycle. So they hold EX, and everything
+// behind them, for as many cycles as the config says, while MEM gets bubbles. That's a structural hazard:
+// there's only the one EX stage, so the instructions behind have to wait their turn for it.
+// Dividing by zero and overflowing don't trap. They give the results the spec says instead.
+
+pub const FUNCT7_MULDIV: u8 = 0b0000001;
+
+pub fn is_muldiv(opcode: u8, funct7: u8) -> bool {
+    opcode == 0b0110011 && funct7 == FUNCT7_MULDIV
+}
+
+//divides are the ones with the top bit of funct3 set.
+pub fn is_divide(funct3: u8) -> bool {
+    funct3 & 0b100 != 0
+}
+
+//the result of the multiply or divide with this funct3.
+pub fn muldiv(funct3: u8, op1: u32, op2: u32) -> u32 {
+    let signed1 = op1 as i32 as i64;
+    let signed2 = op2 as i32 as i64;
+    match funct3 {
+        0b000 => op1.wrapping_mul(op2),                 //MUL, the low 32 bits
+        0b001 => ((signed1 * signed2) >> 32) as u32,    //MULH, the high 32 bits of signed * signed
+        0b010 => ((signed1 * op2 as i64) >> 32) as u32, //MULHSU, signed * unsigned
+        0b011 => ((op1 as u64 * op2 as u64) >> 32) as u32, //MULHU, unsigned * unsigned
+        // dividing by zero gives all ones (-1), and the remainder is what was being divided.
+        // the one signed division that overflows, -2^31 / -1, gives -2^31 back, with a remainder of 0.
+        0b100 if op2 == 0 => u32::MAX, //DIV
+        0b100 => (op1 as i32).wrapping_div(op2 as i32) as u32,
+        0b101 if op2 == 0 => u32::MAX, //DIVU
+        0b101 => op1 / op2,
+        0b110 if op2 == 0 => op1, //REM, with the sign of what was divided
+        0b110 => (op1 as i32).wrapping_rem(op2 as i32) as u32,
+        0b111 if op2 == 0 => op1, //REMU
+        0b111 => op1 % op2,
+        _ => 0, //can't happen, funct3 is 3 bits
+    }
+}
@@ -0,0 +1,311 @@
+use crate::components::*;
+
+// Branch prediction. The IF stage asks the predictor about every branch and jump it fetches, and goes
+// wherever it says straight away. The branch is still resolved in EX like before (or in ID, with early branches):
+// if the PC the IF stage went to was the right one, nothing happens, and if it wasn't, the wrong-path
+// instructions behind it get bubbled and the PC is sent to the right place (like every taken branch used to be).
+// Where a taken branch goes comes from the branch target buffer if there is one. Without one, the IF stage
+// works it out from the instruction itself, which works for branches and JAL, but not for JALR.
+
+//Guesses which way branches go. Jumps always go, so most predictors just say yes to them.
+pub trait BranchPredictor {
+    //for the report.
+    fn name(&self) -> String;
+    //whether the branch at `pc`, which goes to `target` when taken, is taken. `unconditional` for JAL and JALR.
+    fn predict(&self, pc: u32, target: u32, unconditional: bool) -> bool;
+    //what a conditional branch actually did, once EX has worked it out.
+    fn update(&mut self, pc: u32, taken: bool);
+    //needed so the CPU can still be cloned for snapshots.
+    fn clone_box(&self) -> Box<dyn BranchPredictor>;
+}
+
+impl Clone for Box<dyn BranchPredictor> {
+    fn clone(&self) -> Box<dyn BranchPredictor> {
+        self.clone_box()
+    }
+}
+
+//Predicts nothing is ever taken, jumps included. The same as having no predictor at all.
+#[derive(Clone)]
+pub struct StaticNotTaken;
+
+impl BranchPredictor for StaticNotTaken {
+    fn name(&self) -> String {
+        "static not-taken".to_owned()
+    }
+
+    fn predict(&self, _pc: u32, _target: u32, _unconditional: bool) -> bool {
+        false
+    }
+
+    fn update(&mut self, _pc: u32, _taken: bool) {}
+
+    fn clone_box(&self) -> Box<dyn BranchPredictor> {
+        Box::new(self.clone())
+    }
+}
+
+//Backward taken, forward not taken: branches back up are probably loops, so they're taken.
+#[derive(Clone)]
+pub struct BackwardTaken;
+
+impl BranchPredictor for BackwardTaken {
+    fn name(&self) -> String {
+        "backward taken, forward not taken".to_owned()
+    }
+
+    fn predict(&self, pc: u32, target: u32, unconditional: bool) -> bool {
+        unconditional || target <= pc
+    }
+
+    fn update(&mut self, _pc: u32, _taken: bool) {}
+
+    fn clone_box(&self) -> Box<dyn BranchPredictor> {
+        Box::new(self.clone())
+    }
+}
+
+//A branch history table of 1-bit entries: each branch does whatever it did last time.
+#[derive(Clone)]
+pub struct OneBit {
+    table: Vec<bool>,
+}
+
+impl OneBit {
+    pub fn new(entries: usize) -> OneBit {
+        OneBit {
+            table: vec![false; entries.max(1)],
+        }
+    }
+}
+
+impl BranchPredictor for OneBit {
+    fn name(&self) -> String {
+        format!("1-bit ({} entries)", self.table.len())
+    }
+
+    fn predict(&self, pc: u32, _target: u32, unconditional: bool) -> bool {
+        unconditional || self.table[index(pc, self.table.len())]
+    }
+
+    fn update(&mut self, pc: u32, taken: bool) {
+        let entry = index(pc, self.table.len());
+        self.table[entry] = taken;
+    }
+
+    fn clone_box(&self) -> Box<dyn BranchPredictor> {
+        Box::new(self.clone())
+    }
+}
+
+//A branch history table of 2-bit saturating counters. 0 and 1 predict not taken, 2 and 3 taken,
+// so a loop's branch only gets mispredicted once when the loop ends, instead of twice.
+#[derive(Clone)]
+pub struct TwoBit {
+    table: Vec<u8>,
+}
+
+impl TwoBit {
+    pub fn new(entries: usize) -> TwoBit {
+        TwoBit {
+            table: vec![1; entries.max(1)], //weakly not taken
+        }
+    }
+}
+
+impl BranchPredictor for TwoBit {
+    fn name(&self) -> String {
+        format!("2-bit ({} entries)", self.table.len())
+    }
+
+    fn predict(&self, pc: u32, _target: u32, unconditional: bool) -> bool {
+        unconditional || self.table[index(pc, self.table.len())] >= 2
+    }
+
+    fn update(&mut self, pc: u32, taken: bool) {
+        let entry = index(pc, self.table.len());
+        self.table[entry] = count(self.table[entry], taken);
+    }
+
+    fn clone_box(&self) -> Box<dyn BranchPredictor> {
+        Box::new(self.clone())
+    }
+}
+
+//2-bit counters indexed by the PC XORed with the last few branches' outcomes,
+// so the same branch can be predicted differently depending on how it got there.
+// The history only gets updated once a branch is resolved, not when it's predicted.
+#[derive(Clone)]
+pub struct Gshare {
+    table: Vec<u8>,
+    history: u32,
+    history_bits: u32,
+}
+
+impl Gshare {
+    pub fn new(entries: usize, history_bits: u32) -> Gshare {
+        Gshare {
+            table: vec![1; entries.max(1)],
+            history: 0,
+            history_bits: history_bits.min(31),
+        }
+    }
+
+    fn entry(&self, pc: u32) -> usize {
+        ((pc / 2) ^ self.history) as usize % self.table.len()
+    }
+}
+
+impl BranchPredictor for Gshare {
+    fn name(&self) -> String {
+        format!(
+            "gshare ({} entries, {} bits of history)",
+            self.table.len(),
+            self.history_bits
+        )
+    }
+
+    fn predict(&self, pc: u32, _target: u32, unconditional: bool) -> bool {
+        unconditional || self.table[self.entry(pc)] >= 2
+    }
+
+    fn update(&mut self, pc: u32, taken: bool) {
+        let entry = self.entry(pc);
+        self.table[entry] = count(self.table[entry], taken);
+        self.history = ((self.history << 1) | taken as u32) & ((1 << self.history_bits) - 1);
+    }
+
+    fn clone_box(&self) -> Box<dyn BranchPredictor> {
+        Box::new(self.clone())
+    }
+}
+
+//which table entry a branch uses. instructions are at least 2 bytes, so the lowest bit is skipped.
+fn index(pc: u32, entries: usize) -> usize {
+    (pc / 2) as usize % entries
+}
+
+//a 2-bit saturating counter, counting up when taken and down when not.
+fn count(counter: u8, taken: bool) -> u8 {
+    if taken {
+        (counter + 1).min(3)
+    } else {
+        counter.saturating_sub(1)
+    }
+}
+
+//The branch target buffer: where the branch at each address went the last time it was taken.
+// Direct-mapped, and tagged with the whole address, so a hit is always for the right instruction.
+#[derive(Clone)]
+pub struct Btb {
+    entries: Vec<Option<(u32, u32)>>, //(branch address, target)
+}
+
+impl Btb {
+    pub fn new(entries: usize) -> Btb {
+        Btb {
+            entries: vec![None; entries.max(1)],
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn lookup(&self, pc: u32) -> Option<u32> {
+        match self.entries[index(pc, self.entries.len())] {
+            Some((address, target)) if address == pc => Some(target),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, pc: u32, target: u32) {
+        let entry = index(pc, self.entries.len());
+        self.entries[entry] = Some((pc, target));
+    }
+}
+
+//Where the IF stage should go after fetching `instruction` from `pc`, if it's a branch or jump predicted taken.
+pub fn predict(state: &Registers, pc: u32, instruction: u32) -> Option<u32> {
+    let opcode = instruction & 0b1111111;
+    if opcode != 0b1100011 && opcode != 0b1101111 && opcode != 0b1100111 {
+        return None;
+    }
+    let target = match &state.btb {
+        Some(btb) => btb.lookup(pc)?,
+        None if opcode == 0b1100111 => return None, //JALR's target depends on a register
+        None => pc.wrapping_add(target_offset(instruction)),
+    };
+    // a misaligned target traps, which is EX's job. so don't go there early.
+    if !target.is_multiple_of(2) {
+        return None;
+    }
+    let unconditional = opcode != 0b1100011;
+    if state.predictor.predict(pc, target, unconditional) {
+        Some(target)
+    } else {
+        None
+    }
+}
+
+//the offset of a branch (B-type) or JAL (J-type), sign-extended.
+pub fn target_offset(instruction: u32) -> u32 {
+    let sign = ((instruction as i32) >> 31) as u32;
+    if instruction & 0b1111111 == 0b1101111 {
+        (sign << 20)
+            | (instruction & 0x000ff000) //imm[19:12]
+            | ((instruction >> 9) & 0x800) //imm[11]
+            | ((instruction >> 20) & 0x7fe) //imm[10:1]
+    } else {
+        (sign << 12)
+            | ((instruction << 4) & 0x800) //imm[11]
+            | ((instruction >> 20) & 0x7e0) //imm[10:5]
+            | ((instruction >> 7) & 0x1e) //imm[4:1]
+    }
+}
+
+//Tells the predictor and the BTB what the branch or jump being resolved on this clock edge actually did,
+// and counts how often it was predicted right. That's the one leaving EX, or with early branches, ID.
+pub fn train_predictor(state: &mut Registers, logic: &Logic) {
+    if state.model != Model::Pipelined {
+        return;
+    }
+    let (pc, opcode, taken, target) = if state.early_branches {
+        // a branch that's stalled in ID hasn't been resolved yet.
+        if !logic.decode.branch_resolved || state.ifid.id_stall == 1 {
+            return;
+        }
+        let opcode = logic.decode.decode_opcode;
+        let pc = state.ifid.base_pc;
+        (
+            pc,
+            opcode,
+            logic.decode.branch_taken,
+            logic.decode.branch_target,
+        )
+    } else {
+        // nor is one that's stuck in EX behind a cache miss.
+        let opcode = state.idex.opcode;
+        if state.idex.instruction == 0
+            || state.idex.ex_stall == 1
+            || !matches!(opcode, 0b1100011 | 0b1101111 | 0b1100111)
+        {
+            return;
+        }
+        let pc = state.idex.base_pc;
+        (pc, opcode, logic.fetch.jumped, logic.execute.alu_output)
+    };
+    state.stats.branches += 1;
+    if logic.fetch.redirected {
+        state.stats.mispredictions += 1;
+    }
+
+    if opcode == 0b1100011 {
+        state.predictor.update(pc, taken);
+    }
+    if taken {
+        if let Some(btb) = &mut state.btb {
+            btb.insert(pc, target);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::components::Registers;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+//Counters that get bumped as the CPU runs. Kept in Registers, so rewinding rewinds them too.
+#[derive(Clone, Default)]
+pub struct Stats {
+    pub cycles: u64,
+    pub retired: u64, //instructions that made it through WB. bubbles don't count.
+    pub stall_cycles: u64, //cycles where the PC was frozen
+    pub bubbles: u64, //how many bubbles were put into the pipeline
+    pub branches: u64, //branches and jumps resolved in EX
+    pub mispredictions: u64, //...that the IF stage didn't predict right
+    pub interrupts: u64, //interrupts taken
+    pub interrupt_latency: u64, //cycles from each one being raised to it being taken, all added up
+    pub touched: BTreeSet<u32>, //data memory words that were loaded from or stored to, by address / 4
+}
+
+impl Stats {
+    //cycles per instruction. 0 if nothing has retired yet.
+    pub fn cpi(&self) -> f64 {
+        if self.retired == 0 {
+            0.0
+        } else {
+            self.cycles as f64 / self.retired as f64
+        }
+    }
+
+    //how many cycles interrupts waited to be taken, on average. 0 if there weren't any.
+    pub fn average_interrupt_latency(&self) -> f64 {
+        if self.interrupts == 0 {
+            0.0
+        } else {
+            self.interrupt_latency as f64 / self.interrupts as f64
+        }
+    }
+
+    //the fraction of branches and jumps that went where they were predicted to. 1 if there weren't any.
+    pub fn accuracy(&self) -> f64 {
+        if self.branches == 0 {
+            1.0
+        } else {
+            1.0 - self.mispredictions as f64 / self.branches as f64
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+impl ReportFormat {
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name.to_lowercase().as_str() {
+            "text" => Some(ReportFormat::Text),
+            "json" => Some(ReportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+//The end-of-run report: the counters, the register file, and every data memory word the program used.
+pub fn make_report(state: &Registers, format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Text => text_report(state),
+        ReportFormat::Json => json_report(state),
+    }
+}
+
+//the value of a touched word, as it is now.
+fn touched_words(state: &Registers) -> Vec<(u32, u32)> {
+    state
+        .stats
+        .touched
+        .iter()
+        .map(|which_word| (which_word * 4, state.mem.read_word(which_word * 4)))
+        .collect()
+}
+
+fn stop_reason(state: &Registers) -> String {
+    match state.stop {
+        Some(reason) => reason.describe(),
+        None => "still running".to_owned(),
+    }
+}
+
+fn predictor_name(state: &Registers) -> String {
+    match &state.btb {
+        Some(btb) => format!("{}, {}-entry BTB", state.predictor.name(), btb.capacity()),
+        None => state.predictor.name(),
+    }
+}
+
+fn text_report(state: &Registers) -> String {
+    let stats = &state.stats;
+    let mut out = String::new();
+
+    // writing into a String can't fail, so the results are ignored.
+    let _ = writeln!(out, "stopped by:           {}", stop_reason(state));
+    let _ = writeln!(out, "model:                {}", state.model.name());
+    let _ = writeln!(out, "cycles:               {}", stats.cycles);
+    let _ = writeln!(out, "instructions retired: {}", stats.retired);
+    let _ = writeln!(out, "CPI:                  {:.3}", stats.cpi());
+    let _ = writeln!(out, "stall cycles:         {}", stats.stall_cycles);
+    let _ = writeln!(out, "bubbles:              {}", stats.bubbles);
+    let _ = writeln!(out, "branch predictor:     {}", predictor_name(state));
+    let _ = writeln!(
+        out,
+        "branches:             {} ({} mispredicted, {:.1}% accuracy)",
+        stats.branches,
+        stats.mispredictions,
+        stats.accuracy() * 100.0
+    );
+    let _ = writeln!(
+        out,
+        "interrupts:           {} ({:.1} cycles average latency)",
+        stats.interrupts,
+        stats.average_interrupt_latency()
+    );
+    for (level, cache) in state.caches.iter().enumerate() {
+        let stats = &cache.stats;
+        let _ = writeln!(
+            out,
+            "L{} cache:             {} hits, {} misses ({:.1}% hit rate), {} write-backs",
+            level + 1,
+            stats.hits,
+            stats.misses,
+            stats.hit_rate() * 100.0,
+            stats.writebacks
+        );
+        let _ = writeln!(out, "                      ({})", cache.config.describe());
+    }
+    if let Some(icache) = &state.icache {
+        let _ = writeln!(
+            out,
+            "I-cache:              {} hits, {} misses ({:.1}% hit rate)",
+            icache.stats.hits,
+            icache.stats.misses,
+            icache.stats.hit_rate() * 100.0
+        );
+        let _ = writeln!(out, "                      ({})", icache.config.describe());
+    }
+
+    let _ = writeln!(out, "\nregisters:");
+    for r in 0..32 {
+        let _ = write!(out, "$r{:#02}: {:#010x}   ", r, state.reg_mem[r]);
+        if (r + 1) % 8 == 0 {
+            let _ = writeln!(out);
+        }
+    }
+    // the FP registers only once anything has used them.
+    if state.fp_reg_mem.iter().any(|value| *value != 0) {
+        let _ = writeln!(out, "\nFP registers:");
+        for r in 0..32 {
+            let value = state.fp_reg_mem[r];
+            let _ = write!(
+                out,
+                "$f{:#02}: {:#010x} ({:<12?})   ",
+                r,
+                value,
+                f32::from_bits(value)
+            );
+            if (r + 1) % 4 == 0 {
+                let _ = writeln!(out);
+            }
+        }
+    }
+
+    let _ = writeln!(out, "\ndata memory (touched words):");
+    let words = touched_words(state);
+    if words.is_empty() {
+        let _ = writeln!(out, "    (none)");
+    }
+    for (address, value) in words {
+        let _ = writeln!(out, "    {:#010x}: {:#010x}", address, value);
+    }
+    out
+}
+
+fn json_report(state: &Registers) -> String {
+    let stats = &state.stats;
+
+    let registers: Vec<String> = state.reg_mem.iter().map(|r| r.to_string()).collect();
+    let fp_registers: Vec<String> = state.fp_reg_mem.iter().map(|r| r.to_string()).collect();
+    let memory: Vec<String> = touched_words(state)
+        .iter()
+        .map(|(address, value)| format!("\"{:#010x}\": {}", address, value))
+        .collect();
+
+    let caches: Vec<String> = state
+        .caches
+        .iter()
+        .map(|cache| {
+            format!(
+                "{{\"hits\": {}, \"misses\": {}, \"writebacks\": {}}}",
+                cache.stats.hits, cache.stats.misses, cache.stats.writebacks
+            )
+        })
+        .collect();
+    let icache = match &state.icache {
+        Some(icache) => format!(
+            "{{\"hits\": {}, \"misses\": {}}}",
+            icache.stats.hits, icache.stats.misses
+        ),
+        None => "null".to_owned(),
+    };
+
+    format!(
+        "{{\"stop_reason\": \"{}\", \"exit_code\": {}, \"model\": \"{}\", \"cycles\": {}, \"retired\": {}, \"cpi\": {:.3}, \"stall_cycles\": {}, \"bubbles\": {}, \"predictor\": \"{}\", \"branches\": {}, \"mispredictions\": {}, \"interrupts\": {}, \"interrupt_latency\": {}, \"caches\": [{}], \"icache\": {}, \"registers\": [{}], \"fp_registers\": [{}], \"memory\": {{{}}}}}\n",
+        stop_reason(state),
+        state.stop.map_or(0, |reason| reason.exit_code()),
+        state.model.name(),
+        stats.cycles,
+        stats.retired,
+        stats.cpi(),
+        stats.stall_cycles,
+        stats.bubbles,
+        predictor_name(state),
+        stats.branches,
+        stats.mispredictions,
+        stats.interrupts,
+        stats.interrupt_latency,
+        caches.join(", "),
+        icache,
+        registers.join(", "),
+        fp_registers.join(", "),
+        memory.join(", ")
+    )
+}
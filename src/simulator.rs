@@ -0,0 +1,295 @@
+use crate::components::*;
+use crate::config::Config;
+use crate::loader::Program;
+use crate::report::{self, ReportFormat};
+use std::rc::Rc;
+
+// The whole simulator behind one type: the CPU (Registers and Logic), the program loaded into it, and its Config.
+// The binary drives everything through this, and so can anything else that wants to run RISC-V programs.
+
+#[derive(Clone)]
+pub struct Simulator {
+    config: Config,
+    program: Rc<Program>, //kept so reset can load it again. shared, since snapshots clone the whole simulator
+    syscalls: Option<Box<dyn SyscallHandler>>, //None uses NewlibSyscalls
+    predictor: Option<Box<dyn BranchPredictor>>, //None uses the one in the config
+    devices: Bus, //the devices as they were attached. every reset starts from copies of them
+    state: Registers,
+    logic: Logic,
+}
+
+impl Simulator {
+    //a simulator with nothing loaded yet.
+    pub fn new(config: Config) -> Simulator {
+        let mut simulator = Simulator {
+            config,
+            program: Rc::new(Program::default()),
+            syscalls: None,
+            predictor: None,
+            devices: Bus::new(),
+            state: Registers::new(Memory::new()),
+            logic: Logic::default(),
+        };
+        simulator.reset();
+        simulator
+    }
+
+    //replaces whatever was loaded with a new program, and starts it from the beginning.
+    pub fn load_program(&mut self, program: Program) {
+        self.program = Rc::new(program);
+        self.reset();
+    }
+
+    //the ECALL handler to use from now on, and after every reset.
+    pub fn set_syscall_handler(&mut self, handler: Box<dyn SyscallHandler>) {
+        self.state.syscalls = handler.clone();
+        self.syscalls = Some(handler);
+    }
+
+    //the branch predictor to use from now on, instead of the config's. every reset starts from a copy of this one,
+    // so whatever it has learned so far gets thrown away too.
+    pub fn set_branch_predictor(&mut self, predictor: Box<dyn BranchPredictor>) {
+        self.state.predictor = predictor.clone();
+        self.predictor = Some(predictor);
+    }
+
+    //attaches a device to the bus at `base`, from now on and after every reset.
+    // It has to be inside one of the memory map's MMIO regions, and not overlap another device.
+    pub fn attach_device(&mut self, base: u32, device: Box<dyn Device>) -> Result<(), String> {
+        let fits = match self.config.memory.map.region(base) {
+            Some(region) => {
+                region.kind == RegionKind::Mmio
+                    && base as u64 + device.size() as u64 <= region.end()
+            }
+            None => false,
+        };
+        if !fits {
+            return Err(format!(
+                "the {} at {:#x} isn't inside an mmio region of the memory map",
+                device.name(),
+                base
+            ));
+        }
+        self.devices.attach(base, device.clone())?;
+        self.state.bus.attach(base, device)
+    }
+
+    //puts the CPU back how it was right after the program was loaded.
+    pub fn reset(&mut self) {
+        let program = &self.program;
+        let mut state = Registers::new(program.memory.clone());
+        state.text_end = program.text_end;
+        state.memory_map = self.config.memory.map.clone();
+        state.bus = self.devices.clone();
+        state.termination = Termination {
+            halt_address: self.config.halt_address,
+            tohost: self.config.tohost.or(program.symbol_address("tohost")),
+        };
+        state.syscalls = match &self.syscalls {
+            Some(handler) => handler.clone(),
+            None => Box::new(NewlibSyscalls::new(heap_start(program))),
+        };
+        state.forwarding = self.config.forwarding;
+        state.model = self.config.model;
+        state.predictor = match &self.predictor {
+            Some(predictor) => predictor.clone(),
+            None => self.config.predictor.build(),
+        };
+        state.early_branches = self.config.early_branches;
+        state.caches = self
+            .config
+            .memory
+            .caches
+            .iter()
+            .cloned()
+            .map(Cache::new)
+            .collect();
+        state.memory_latency = self.config.memory.memory_latency;
+        state.icache = self.config.memory.icache.clone().map(Cache::new);
+        state.icache_miss_penalty = self.config.memory.icache_miss_penalty;
+        state.latencies = self.config.latencies;
+        if self.config.btb_entries > 0 {
+            state.btb = Some(Btb::new(self.config.btb_entries));
+        }
+        state.pc = self.config.reset_vector.unwrap_or(program.entry);
+        if let Some(stack_top) = program.stack_top {
+            // at the top of the memory map's stack region, if it has one.
+            state.reg_mem[2] = self.config.memory.map.stack_top().unwrap_or(stack_top);
+        }
+
+        self.state = state;
+        self.logic = Logic::default();
+        //the wires need to reflect the starting state before the first cycle, or the PC gets lost.
+        settle(&mut self.state, &mut self.logic);
+    }
+
+    //runs one cycle of whichever model is being run. Returns why the run ended, once it has.
+    pub fn step(&mut self) -> Option<StopReason> {
+        if self.is_over() {
+            return self.state.stop;
+        }
+        if self.config.max_cycles == Some(self.state.stats.cycles) {
+            let cycles = self.state.stats.cycles;
+            self.state.stop = Some(StopReason::CycleLimit(cycles));
+            return self.state.stop;
+        }
+        // the non-pipelined models take a step per stage, and not every step is a cycle of their own.
+        // keep stepping until one is.
+        let cycles = self.state.stats.cycles;
+        loop {
+            step(&mut self.state, &mut self.logic);
+            if self.state.stats.cycles != cycles || finished(&self.state) {
+                break;
+            }
+        }
+        if self.is_over() {
+            self.state.stop
+        } else {
+            None
+        }
+    }
+
+    //steps until `condition` is true after a cycle, or the run ends.
+    // Returns why the run ended, or None if it was the condition that stopped it.
+    pub fn run_until<F: FnMut(&Simulator) -> bool>(
+        &mut self,
+        mut condition: F,
+    ) -> Option<StopReason> {
+        loop {
+            if let Some(reason) = self.step() {
+                return Some(reason);
+            }
+            if condition(self) {
+                return None;
+            }
+        }
+    }
+
+    //runs the program to the end.
+    pub fn run(&mut self) -> StopReason {
+        loop {
+            if let Some(reason) = self.step() {
+                return reason;
+            }
+        }
+    }
+
+    //ends the run early, as if the user had quit.
+    pub fn quit(&mut self) {
+        self.state.stop.get_or_insert(StopReason::Quit);
+    }
+
+    //true once the run has ended. Programs that end themselves first let the pipeline drain,
+    // but the watchdog and the user quitting stop everything where it is.
+    pub fn is_over(&self) -> bool {
+        finished(&self.state)
+            || matches!(
+                self.state.stop,
+                Some(StopReason::CycleLimit(_)) | Some(StopReason::Quit)
+            )
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.state.stop
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.state.pc
+    }
+
+    pub fn registers(&self) -> &[u32] {
+        &self.state.reg_mem
+    }
+
+    pub fn register(&self, index: u8) -> u32 {
+        self.state.reg_mem[index as usize]
+    }
+
+    //f0-f31, as bits. (see float.rs)
+    pub fn fp_registers(&self) -> &[u32] {
+        &self.state.fp_reg_mem
+    }
+
+    pub fn fp_register(&self, index: u8) -> u32 {
+        self.state.fp_reg_mem[index as usize]
+    }
+
+    //writes to $r0 are ignored, like they are for instructions.
+    pub fn set_register(&mut self, index: u8, value: u32) {
+        if index != 0 {
+            self.state.reg_mem[index as usize] = value;
+        }
+    }
+
+    //the memory word that holds this byte address.
+    pub fn read_word(&self, address: u32) -> u32 {
+        self.state.mem.read_word(address & !3)
+    }
+
+    pub fn write_word(&mut self, address: u32, value: u32) {
+        self.state.mem.write_word(address & !3, value);
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.state.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.state.mem
+    }
+
+    //the devices, as they are now.
+    pub fn bus(&self) -> &Bus {
+        &self.state.bus
+    }
+
+    pub fn csr(&self, csr: u16) -> Option<u32> {
+        self.state.csr.read(csr)
+    }
+
+    pub fn stats(&self) -> &Stats {
+        &self.state.stats
+    }
+
+    //the data caches, L1 first, with their hit and miss counts.
+    pub fn caches(&self) -> &[Cache] {
+        &self.state.caches
+    }
+
+    //the instruction cache, if there is one.
+    pub fn icache(&self) -> Option<&Cache> {
+        self.state.icache.as_ref()
+    }
+
+    pub fn report(&self, format: ReportFormat) -> String {
+        report::make_report(&self.state, format)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    //the CPU itself, for displays and anything else the accessors above don't cover.
+    pub fn state(&self) -> &Registers {
+        &self.state
+    }
+
+    pub fn logic(&self) -> &Logic {
+        &self.logic
+    }
+}
+
+//where brk starts the heap: the linker's end symbol if there is one, otherwise past everything loaded.
+pub fn heap_start(program: &Program) -> u32 {
+    if let Some(end) = program
+        .symbol_address("_end")
+        .or(program.symbol_address("end"))
+    {
+        return end;
+    }
+    program.end.saturating_add(15) & !15
+}
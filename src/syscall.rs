@@ -0,0 +1,182 @@
+use crate::components::*;
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+// ECALL.
+// The syscall number goes in a7 and the arguments in a0-a5, like Linux and newlib's libgloss expect.
+// It gets handled once the ECALL has made it past EX, which is as late as it can be before the next
+// instruction's memory access. The result comes back in a0 through the normal writeback path,
+// so the usual forwarding gets it to the instructions behind it.
+
+pub const SYS_CLOSE: u32 = 57;
+pub const SYS_READ: u32 = 63;
+pub const SYS_WRITE: u32 = 64;
+pub const SYS_EXIT: u32 = 93;
+pub const SYS_EXIT_GROUP: u32 = 94;
+pub const SYS_BRK: u32 = 214;
+
+//The most one read or write moves. The count is whatever the program had in a2, so it could be anything,
+// but like a real kernel's, these can come back short, and programs (newlib's printf included) loop until it's all done.
+pub const MAX_TRANSFER: u32 = 64 * 1024;
+
+const ENOSYS: i32 = 38;
+const EBADF: i32 = 9;
+
+//What a syscall handler wants to happen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyscallResult {
+    Return(u32), //put this in a0 and carry on
+    Exit(u32),   //end the program with this exit code
+}
+
+//Anything that can handle ECALLs. `memory` is the CPU's memory, for syscalls that take pointers.
+pub trait SyscallHandler {
+    fn syscall(&mut self, number: u32, args: [u32; 6], memory: &mut Memory) -> SyscallResult;
+
+    //Registers gets cloned for rewinding, so the handler has to be clonable too.
+    fn clone_box(&self) -> Box<dyn SyscallHandler>;
+}
+
+impl Clone for Box<dyn SyscallHandler> {
+    fn clone(&self) -> Box<dyn SyscallHandler> {
+        self.clone_box()
+    }
+}
+
+//The default: enough of Linux for newlib's libgloss. exit, read, write, and brk.
+#[derive(Clone)]
+pub struct NewlibSyscalls {
+    pub brk: u32,                         //the current end of the heap
+    input: Option<Rc<RefCell<Vec<u8>>>>,  //None reads the real stdin
+    output: Option<Rc<RefCell<Vec<u8>>>>, //None writes to the real stdout/stderr
+}
+
+impl NewlibSyscalls {
+    pub fn new(heap_start: u32) -> NewlibSyscalls {
+        NewlibSyscalls {
+            brk: heap_start,
+            input: None,
+            output: None,
+        }
+    }
+
+    //reads come from `input`, and stdout and stderr both get collected into the returned buffer.
+    pub fn captured(heap_start: u32, input: &[u8]) -> (NewlibSyscalls, Rc<RefCell<Vec<u8>>>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let handler = NewlibSyscalls {
+            brk: heap_start,
+            input: Some(Rc::new(RefCell::new(input.to_vec()))),
+            output: Some(output.clone()),
+        };
+        (handler, output)
+    }
+
+    fn write(&mut self, fd: u32, bytes: &[u8]) -> u32 {
+        if let Some(output) = &self.output {
+            if fd != 1 && fd != 2 {
+                return error_code(EBADF);
+            }
+            output.borrow_mut().extend_from_slice(bytes);
+            return bytes.len() as u32;
+        }
+        let result = match fd {
+            1 => io::stdout()
+                .write_all(bytes)
+                .and_then(|_| io::stdout().flush()),
+            2 => io::stderr().write_all(bytes),
+            _ => return error_code(EBADF),
+        };
+        match result {
+            Ok(_) => bytes.len() as u32,
+            Err(_) => error_code(EBADF),
+        }
+    }
+
+    fn read(&mut self, fd: u32, count: usize) -> Result<Vec<u8>, u32> {
+        if fd != 0 {
+            return Err(error_code(EBADF));
+        }
+        if let Some(input) = &self.input {
+            let mut input = input.borrow_mut();
+            let count = count.min(input.len());
+            return Ok(input.drain(..count).collect());
+        }
+        let mut buffer = vec![0; count];
+        match io::stdin().read(&mut buffer) {
+            Ok(read) => {
+                buffer.truncate(read);
+                Ok(buffer)
+            }
+            Err(_) => Err(error_code(EBADF)),
+        }
+    }
+}
+
+impl SyscallHandler for NewlibSyscalls {
+    fn syscall(&mut self, number: u32, args: [u32; 6], memory: &mut Memory) -> SyscallResult {
+        match number {
+            SYS_EXIT | SYS_EXIT_GROUP => SyscallResult::Exit(args[0]),
+            SYS_WRITE => {
+                // write(fd, buf, count)
+                let bytes = memory.read_bytes(args[1], args[2].min(MAX_TRANSFER));
+                SyscallResult::Return(self.write(args[0], &bytes))
+            }
+            SYS_READ => {
+                // read(fd, buf, count)
+                match self.read(args[0], args[2].min(MAX_TRANSFER) as usize) {
+                    Ok(bytes) => {
+                        memory.write_bytes(args[1], &bytes);
+                        SyscallResult::Return(bytes.len() as u32)
+                    }
+                    Err(code) => SyscallResult::Return(code),
+                }
+            }
+            SYS_BRK => {
+                // brk(0) asks where the heap ends, anything else moves it. there's no limit.
+                if args[0] != 0 {
+                    self.brk = args[0];
+                }
+                SyscallResult::Return(self.brk)
+            }
+            SYS_CLOSE => SyscallResult::Return(0),
+            _ => SyscallResult::Return(error_code(ENOSYS)),
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn SyscallHandler> {
+        Box::new(self.clone())
+    }
+}
+
+//syscalls return errors as negative numbers.
+fn error_code(errno: i32) -> u32 {
+    (-errno) as u32
+}
+
+//Handles an ECALL that just got into the EX-MEM latch. Returns true if it did anything,
+// since the wires then need updating.
+pub fn handle_ecall(state: &mut Registers, logic: &Logic) -> bool {
+    if state.exmem.instruction != ECALL || state.stop.is_some() {
+        return false;
+    }
+
+    let number = read_register(state, logic, 17);
+    let mut args = [0; 6];
+    for (i, arg) in args.iter_mut().enumerate() {
+        *arg = read_register(state, logic, 10 + i as u8);
+    }
+
+    match state.syscalls.syscall(number, args, &mut state.mem) {
+        SyscallResult::Return(value) => {
+            // the ECALL now writes a0, like any other instruction would.
+            state.exmem.rd_index = 10;
+            state.exmem.alu_output = value;
+        }
+        SyscallResult::Exit(code) => {
+            state.stop = Some(StopReason::Exit(code));
+            squash_younger(state);
+        }
+    }
+    true
+}
@@ -0,0 +1,128 @@
+use crate::components::*;
+
+// How a program ends.
+// ECALL exit, EBREAK, unhandled traps (see trap.rs), the halt address and tohost are all noticed when the instruction responsible
+// makes it past EX, where it can't be on a wrong path anymore. Everything younger gets thrown away,
+// fetching stops, and the run is over once the older instructions have drained out of the pipeline.
+
+pub const ECALL: u32 = 0x00000073;
+pub const EBREAK: u32 = 0x00100073;
+
+//Why a run ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Exit(u32),           //ECALL with a7 = 93. holds a0, the exit code
+    Ebreak,              //EBREAK instruction
+    HaltAddress(u32),    //the instruction at the halt address was reached. it does not run
+    ToHost(u32),         //a non-zero value was stored to the tohost address. holds the value
+    CycleLimit(u64),     //the watchdog went off after this many cycles
+    EndOfProgram,        //ran off the end of the program's code and the pipeline emptied out
+    UnhandledTrap(Trap), //an exception happened with no trap handler installed
+    Quit,                //the user quit before the program ended
+}
+
+impl StopReason {
+    pub fn describe(&self) -> String {
+        match self {
+            StopReason::Exit(code) => format!("exit({})", *code as i32),
+            StopReason::Ebreak => "ebreak".to_owned(),
+            StopReason::HaltAddress(address) => format!("halt address {:#010x}", address),
+            StopReason::ToHost(value) => format!("tohost = {:#x}", value),
+            StopReason::CycleLimit(cycles) => format!("cycle limit ({} cycles)", cycles),
+            StopReason::EndOfProgram => "end of program".to_owned(),
+            StopReason::UnhandledTrap(trap) => format!("unhandled trap: {}", trap.describe()),
+            StopReason::Quit => "quit".to_owned(),
+        }
+    }
+
+    // what the simulator itself should exit with.
+    // programs that exit get their own exit code passed along, like a real process would.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StopReason::Exit(code) => (*code & 0xff) as i32,
+            // riscv-tests style: (code << 1) | 1, where code 0 is a pass.
+            StopReason::ToHost(value) => ((*value >> 1) & 0xff) as i32,
+            StopReason::CycleLimit(_) => EXIT_CYCLE_LIMIT,
+            StopReason::UnhandledTrap(_) => EXIT_TRAP,
+            _ => 0,
+        }
+    }
+}
+
+//exit code for when the watchdog stopped the program before it finished.
+pub const EXIT_CYCLE_LIMIT: i32 = 3;
+//exit code for when the program crashed: an exception with nowhere to go.
+pub const EXIT_TRAP: i32 = 4;
+
+//The optional ways for a program to end. ECALL exit and EBREAK always work.
+#[derive(Clone, Copy, Default)]
+pub struct Termination {
+    pub halt_address: Option<u32>,
+    pub tohost: Option<u32>, //byte address of the tohost word
+}
+
+//true once a stop was asked for and the pipeline has emptied out.
+pub fn finished(state: &Registers) -> bool {
+    state.stop.is_some() && pipeline_empty(state)
+}
+
+pub fn pipeline_empty(state: &Registers) -> bool {
+    state.ifid.instruction == 0
+        && state.idex.instruction == 0
+        && state.exmem.instruction == 0
+        && state.memwb.instruction == 0
+        && state.fpu.is_empty()
+}
+
+//Checks if anything in the pipeline just ended the program. Returns true if the pipeline was changed,
+// in which case the logic needs updating again.
+pub fn check_termination(state: &mut Registers) -> bool {
+    if state.stop.is_some() {
+        return false; //already stopping
+    }
+
+    // tohost: the store that was in MEM has just happened, and is now in the MEM-WB latch, along with what it stored.
+    // (reading it back wouldn't work if tohost is on a device)
+    if let Some(tohost) = state.termination.tohost {
+        if state.memwb.opcode == 0b0100011 && state.memwb.alu_output / 4 == tohost / 4 {
+            let value = state.memwb.store_data;
+            if value != 0 {
+                state.exmem.bubble();
+                add_bubbles(state, 1);
+                squash_younger(state);
+                state.stop = Some(StopReason::ToHost(value));
+                return true;
+            }
+        }
+    }
+
+    // everything else looks at the instruction that just got into the EX-MEM latch.
+    // bubbles are all zeroes, but a zero word fetched from past the end of the program still has an address.
+    let entered = state.exmem.instruction != 0 || state.exmem.base_pc != 0;
+    if entered && state.termination.halt_address == Some(state.exmem.base_pc) {
+        state.stop = Some(StopReason::HaltAddress(state.exmem.base_pc));
+        state.exmem.bubble();
+        add_bubbles(state, 1);
+        squash_younger(state);
+        return true;
+    }
+    // (ECALL exit is handled along with the other syscalls, in syscall.rs)
+    if state.exmem.instruction == EBREAK {
+        squash_younger(state);
+        state.stop = Some(StopReason::Ebreak);
+        return true;
+    }
+
+    // nothing left to fetch, and nothing left in flight.
+    if state.pc >= state.text_end && pipeline_empty(state) {
+        state.stop = Some(StopReason::EndOfProgram);
+    }
+    false
+}
+
+//throws away the instructions in IF-ID and ID-EX.
+pub fn squash_younger(state: &mut Registers) {
+    state.ifid.bubble();
+    state.idex.bubble();
+    add_bubbles(state, 2);
+}
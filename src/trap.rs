@@ -0,0 +1,286 @@
+use crate::components::*;
+
+// Machine-mode traps.
+// An exception is taken when the instruction that caused it makes it past EX, the same place ECALL and the
+// CSR instructions get done. Everything older has been through MEM by then, and nothing younger has done
+// anything yet, so throwing away the faulting instruction and everything behind it is all it takes to be precise.
+// Loads and stores already have their address at that point, so bad ones are caught before they touch memory.
+// So are jumps and branches to somewhere outside the text regions of the memory map. Those get reported on the
+// jump, like misaligned targets are, instead of on the fetch from the bad address.
+// With no handler installed (mtvec is 0) there's nowhere to go, so the run stops instead.
+// Interrupts get taken at the same place: just before the instruction that just got into the EX-MEM latch,
+// which gets thrown away with everything behind it, and runs again after MRET. mepc points at it.
+// That's the first instruction that hasn't done anything yet, so the interrupted program never notices.
+
+pub const CAUSE_MISALIGNED_FETCH: u32 = 0;
+pub const CAUSE_FETCH_ACCESS_FAULT: u32 = 1;
+pub const CAUSE_ILLEGAL_INSTRUCTION: u32 = 2;
+pub const CAUSE_BREAKPOINT: u32 = 3;
+pub const CAUSE_MISALIGNED_LOAD: u32 = 4;
+pub const CAUSE_LOAD_ACCESS_FAULT: u32 = 5;
+pub const CAUSE_MISALIGNED_STORE: u32 = 6;
+pub const CAUSE_STORE_ACCESS_FAULT: u32 = 7;
+pub const CAUSE_ECALL_FROM_M: u32 = 11;
+
+// interrupts have the top bit of mcause set, and the rest is which bit of mip they are.
+pub const CAUSE_INTERRUPT: u32 = 1 << 31;
+pub const CAUSE_SOFTWARE_INTERRUPT: u32 = CAUSE_INTERRUPT | 3;
+pub const CAUSE_TIMER_INTERRUPT: u32 = CAUSE_INTERRUPT | 7;
+pub const CAUSE_EXTERNAL_INTERRUPT: u32 = CAUSE_INTERRUPT | 11;
+
+//A trap, and what goes into mcause, mepc and mtval when it is taken.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Trap {
+    pub cause: u32,
+    pub epc: u32,  //address of the instruction that caused it
+    pub tval: u32, //the bad address, or the bad instruction
+}
+
+impl Trap {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} at {:#010x} (mtval {:#010x})",
+            cause_name(self.cause),
+            self.epc,
+            self.tval
+        )
+    }
+}
+
+pub fn cause_name(cause: u32) -> &'static str {
+    match cause {
+        CAUSE_MISALIGNED_FETCH => "misaligned instruction address",
+        CAUSE_FETCH_ACCESS_FAULT => "instruction access fault",
+        CAUSE_ILLEGAL_INSTRUCTION => "illegal instruction",
+        CAUSE_BREAKPOINT => "breakpoint",
+        CAUSE_MISALIGNED_LOAD => "misaligned load",
+        CAUSE_LOAD_ACCESS_FAULT => "load access fault",
+        CAUSE_MISALIGNED_STORE => "misaligned store",
+        CAUSE_STORE_ACCESS_FAULT => "store access fault",
+        CAUSE_ECALL_FROM_M => "ecall",
+        CAUSE_SOFTWARE_INTERRUPT => "software interrupt",
+        CAUSE_TIMER_INTERRUPT => "timer interrupt",
+        CAUSE_EXTERNAL_INTERRUPT => "external interrupt",
+        _ => "unknown trap",
+    }
+}
+
+//The exception the instruction that just got into the EX-MEM latch causes, if any.
+// `jumped` says if it jumped or took a branch, in which case the ALU output is where it went.
+pub fn exception(state: &Registers, jumped: bool) -> Option<Trap> {
+    let instr = state.exmem.instruction;
+    let address = state.exmem.alu_output;
+    let trap = |cause, tval| {
+        Some(Trap {
+            cause,
+            epc: state.exmem.base_pc,
+            tval,
+        })
+    };
+
+    // an instruction the PC carried on into, past the end of a text region. (jumps and branches get checked below)
+    if !state.memory_map.is_executable(state.exmem.base_pc) {
+        return trap(CAUSE_FETCH_ACCESS_FAULT, state.exmem.base_pc);
+    }
+    // illegal instructions. an FP instruction with a dynamic rounding mode is one too, when frm doesn't hold a real one. (see float.rs)
+    let bad_rounding_mode =
+        uses_rounding_mode(instr) && rounding_mode(instr, state.csr.frm()).is_none();
+    if !isa::is_legal(instr) || illegal_csr_access(state) || bad_rounding_mode {
+        return trap(CAUSE_ILLEGAL_INSTRUCTION, instr);
+    }
+    // the lowest two bits of funct3 are the access size: byte, half-word, word.
+    let size = 1 << (state.exmem.funct3 & 0b11);
+    let map = &state.memory_map;
+    match state.exmem.opcode {
+        // (FLW and FSW are word loads and stores, like LW and SW.)
+        0b0000011 | OPCODE_LOAD_FP if !address.is_multiple_of(size) => {
+            trap(CAUSE_MISALIGNED_LOAD, address)
+        }
+        0b0000011 | OPCODE_LOAD_FP if !map.is_mapped(address) => {
+            trap(CAUSE_LOAD_ACCESS_FAULT, address)
+        }
+        0b0100011 | OPCODE_STORE_FP if address == 0 => trap(CAUSE_STORE_ACCESS_FAULT, address),
+        0b0100011 | OPCODE_STORE_FP if !address.is_multiple_of(size) => {
+            trap(CAUSE_MISALIGNED_STORE, address)
+        }
+        0b0100011 | OPCODE_STORE_FP if !map.is_mapped(address) => {
+            trap(CAUSE_STORE_ACCESS_FAULT, address)
+        }
+        // atomics are always a word, and the devices can't do them. LR is a load, and the rest count as stores.
+        OPCODE_ATOMIC => {
+            let lr = is_load_reserved(instr);
+            if !address.is_multiple_of(4) {
+                trap(
+                    if lr {
+                        CAUSE_MISALIGNED_LOAD
+                    } else {
+                        CAUSE_MISALIGNED_STORE
+                    },
+                    address,
+                )
+            } else if !map.is_mapped(address) || map.is_mmio(address) {
+                trap(
+                    if lr {
+                        CAUSE_LOAD_ACCESS_FAULT
+                    } else {
+                        CAUSE_STORE_ACCESS_FAULT
+                    },
+                    address,
+                )
+            } else {
+                None
+            }
+        }
+        0b1101111 | 0b1100111 | 0b1100011 if jumped && !address.is_multiple_of(2) => {
+            trap(CAUSE_MISALIGNED_FETCH, address)
+        }
+        0b1101111 | 0b1100111 | 0b1100011 if jumped && !map.is_executable(address) => {
+            trap(CAUSE_FETCH_ACCESS_FAULT, address)
+        }
+        _ => None,
+    }
+}
+
+//The interrupt to take now, if any: one that's pending in mip, enabled in mie, with mstatus.MIE on.
+// If there's more than one, external goes first, then software, then timer.
+pub fn pending_interrupt(csr: &CsrFile) -> Option<u32> {
+    if csr.mstatus & MSTATUS_MIE == 0 {
+        return None;
+    }
+    let pending = csr.mip & csr.mie;
+    [MIP_MEIP, MIP_MSIP, MIP_MTIP]
+        .into_iter()
+        .find(|bit| pending & bit != 0)
+        .map(|bit| CAUSE_INTERRUPT | bit.trailing_zeros())
+}
+
+//Puts whatever the devices are raising into mip, and notes when an enabled interrupt started pending,
+// so the report can say how long they waited to be taken.
+pub fn update_interrupts(state: &mut Registers) {
+    state.csr.mip = state.bus.interrupts();
+    if state.csr.mip & state.csr.mie == 0 {
+        state.interrupt_raised = None;
+    } else if state.interrupt_raised.is_none() {
+        state.interrupt_raised = Some(state.stats.cycles);
+    }
+}
+
+//CSR instructions on CSRs that don't exist, or that write to read-only ones.
+fn illegal_csr_access(state: &Registers) -> bool {
+    let instr = state.exmem.instruction;
+    let funct3 = (instr >> 12) & 0b111;
+    if instr & 0b1111111 != 0b1110011 || funct3 & 0b11 == 0 {
+        return false;
+    }
+    let csr = (instr >> 20) as u16;
+    let rs1 = (instr >> 15) & 0b11111;
+    // CSRRW(I) always writes, CSRRS(I) and CSRRC(I) only when rs1 (or the immediate) isn't 0.
+    let writes = funct3 & 0b11 == 0b01 || rs1 != 0;
+    state.csr.read(csr).is_none() || (writes && is_read_only(csr))
+}
+
+//Takes any trap caused by the instruction that just got into the EX-MEM latch, and does MRET.
+// Returns true if the pipeline was changed, in which case the logic needs updating again.
+pub fn handle_traps(state: &mut Registers, jumped: bool) -> bool {
+    let instr = state.exmem.instruction;
+    if instr == 0 {
+        return false;
+    }
+    if state.stop.is_some() {
+        // the run is over and whatever is left is draining out. nothing gets to trap anymore,
+        // so an instruction that would have just gets dropped.
+        if exception(state, jumped).is_some() {
+            state.exmem.bubble();
+            add_bubbles(state, 1);
+            return true;
+        }
+        return false;
+    }
+
+    // interrupts come before anything the instruction would do itself, since it hasn't done it yet.
+    // one that's still waiting on the caches has started its access, though, so it gets to finish first.
+    // so does one just out of the FPU, since younger instructions have already gone past it. (see fpu.rs)
+    if state.mem_wait == 0 && !state.exmem.from_fpu {
+        if let Some(cause) = pending_interrupt(&state.csr) {
+            let raised = state.interrupt_raised.take().unwrap_or(state.stats.cycles);
+            state.stats.interrupts += 1;
+            state.stats.interrupt_latency += state.stats.cycles - raised;
+            take_trap(
+                state,
+                Trap {
+                    cause,
+                    epc: state.exmem.base_pc,
+                    tval: 0,
+                },
+            );
+            return true;
+        }
+    }
+
+    // ECALL and EBREAK only trap once the program has a handler to take them.
+    // Until then the simulator handles them itself, as syscalls and as the end of the program.
+    let handler_installed = state.csr.mtvec != 0;
+    let epc = state.exmem.base_pc;
+    let trap = match exception(state, jumped) {
+        Some(trap) => trap,
+        None if instr == ECALL && handler_installed => Trap {
+            cause: CAUSE_ECALL_FROM_M,
+            epc,
+            tval: 0,
+        },
+        None if instr == EBREAK && handler_installed => Trap {
+            cause: CAUSE_BREAKPOINT,
+            epc,
+            tval: epc,
+        },
+        None if instr == isa::MRET => {
+            return_from_trap(state);
+            return true;
+        }
+        None => return false,
+    };
+    take_trap(state, trap);
+    true
+}
+
+//Throws away the faulting (or interrupted) instruction and everything behind it, and jumps to the handler.
+pub fn take_trap(state: &mut Registers, trap: Trap) {
+    state.exmem.bubble();
+    add_bubbles(state, 1);
+    squash_younger(state);
+    state.reservation = None; //(see atomic.rs)
+
+    if state.csr.mtvec == 0 {
+        state.stop = Some(StopReason::UnhandledTrap(trap));
+        return;
+    }
+    state.csr.mepc = trap.epc;
+    state.csr.mcause = trap.cause;
+    state.csr.mtval = trap.tval;
+    // interrupts get turned off while in the handler, and MRET puts them back how they were.
+    let mie = state.csr.mstatus & MSTATUS_MIE != 0;
+    state.csr.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+    if mie {
+        state.csr.mstatus |= MSTATUS_MPIE;
+    }
+    // exceptions always go to the base address, but in vectored mode (1), interrupts go to base + 4 * cause.
+    let base = state.csr.mtvec & !0b11;
+    state.pc = if trap.cause & CAUSE_INTERRUPT != 0 && state.csr.mtvec & 0b11 == 1 {
+        base.wrapping_add(4 * (trap.cause & !CAUSE_INTERRUPT))
+    } else {
+        base
+    };
+}
+
+//MRET: back to mepc, with interrupts enabled again if they were before the trap.
+// MRET itself carries on and retires like any other instruction, only the ones behind it get thrown away.
+fn return_from_trap(state: &mut Registers) {
+    let mpie = state.csr.mstatus & MSTATUS_MPIE != 0;
+    state.csr.mstatus &= !MSTATUS_MIE;
+    if mpie {
+        state.csr.mstatus |= MSTATUS_MIE;
+    }
+    state.csr.mstatus |= MSTATUS_MPIE;
+    squash_younger(state);
+    state.pc = state.csr.mepc;
+}